@@ -1,5 +1,7 @@
 pub enum ExtractType {
     Instance(GenericType),
+    RequestId,
+    PeerAddr,
     Method,
     Path(GenericType),
     Query(GenericType),
@@ -10,6 +12,8 @@ pub enum ExtractType {
 
 impl ExtractType {
     pub const INSTANCE: &'static Self = &Self::Instance(GenericType::T);
+    pub const REQUEST_ID: &'static Self = &Self::RequestId;
+    pub const PEER_ADDR: &'static Self = &Self::PeerAddr;
     pub const METHOD: &'static Self = &Self::Method;
     pub const PATH: &'static Self = &Self::Path(GenericType::A(ExtractTrait::ToPath));
     pub const QUERY: &'static Self = &Self::Query(GenericType::B(ExtractTrait::ToQuery));
@@ -19,6 +23,8 @@ impl ExtractType {
     const fn identity_name(&self) -> &'static str {
         match self {
             Self::Instance(_) => "instance",
+            Self::RequestId => "request_id",
+            Self::PeerAddr => "peer_addr",
             Self::Method => "method",
             Self::Path(_) => "path",
             Self::Query(_) => "query",
@@ -31,6 +37,8 @@ impl ExtractType {
     const fn type_no_trait(&self) -> &'static str {
         match self {
             Self::Instance(_) => "Instance<T>",
+            Self::RequestId => "RequestId",
+            Self::PeerAddr => "PeerAddr",
             Self::Method => "Method",
             Self::Path(_) => "Path<A>",
             Self::Query(_) => "Query<B>",
@@ -86,7 +94,7 @@ impl<{}: Send + Sync {}> Extract<{}, InstanceRequest<{}>, Self> for ({}) {{
         )
     }
 
-    pub fn make_combinations(choices: [&'static Self; 7]) -> String {
+    pub fn make_combinations(choices: [&'static Self; 9]) -> String {
         let mut result = String::new();
         let n = choices.len();
 
@@ -109,9 +117,11 @@ impl<{}: Send + Sync {}> Extract<{}, InstanceRequest<{}>, Self> for ({}) {{
         result
     }
 
-    pub const fn all_choices() -> [&'static Self; 7] {
+    pub const fn all_choices() -> [&'static Self; 9] {
         [
             Self::INSTANCE,
+            Self::REQUEST_ID,
+            Self::PEER_ADDR,
             Self::METHOD,
             Self::PATH,
             Self::QUERY,
@@ -126,6 +136,8 @@ impl std::fmt::Display for ExtractType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Instance(g) => write!(f, "Instance<{}>", g),
+            Self::RequestId => write!(f, "RequestId"),
+            Self::PeerAddr => write!(f, "PeerAddr"),
             Self::Method => write!(f, "Method"),
             Self::Path(g) => write!(f, "Path<{}>", g),
             Self::Query(g) => write!(f, "Query<{}>", g),
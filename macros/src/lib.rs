@@ -5,7 +5,7 @@ use crate::{
     extract_macro::ExtractType,
     token_parser::{Struct, TokenParser},
 };
-use proc_macro::{TokenStream, TokenTree};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
 use uuid::UUID;
 
 #[proc_macro]
@@ -98,6 +98,9 @@ fn parse_attrs(attrs: TokenStream) -> Result<String, ()> {
 #[proc_macro]
 pub fn html(item: TokenStream) -> TokenStream {
     let mut parser = TokenParser::new(item);
+    if !parser.has_tokens_left() {
+        return "::zero::html::Markup::None".parse().unwrap();
+    }
 
     let mut tokens = String::new();
     while parser.has_tokens_left() {
@@ -114,7 +117,7 @@ pub fn html(item: TokenStream) -> TokenStream {
                     .expect("Failed to parse inner HTML");
             }
             Some(t) => panic!("Expected TagType, found {:#?}", t),
-            None => return "()".parse().unwrap(),
+            None => return "::zero::html::Markup::None".parse().unwrap(),
         };
 
         if parser.is_any_ident() {
@@ -178,24 +181,32 @@ fn parse_deserialize_struct(mut parser: TokenParser, is_public: bool) -> TokenSt
         .map(|(name, field_data)| {
             format!(
                 "{}: match dh.remove(\"{}\") {{
-                    Some(dh) => <{}>::deserialize(dh)?,
-                    None => return Err(())
+                    Some(dh) => <{}>::deserialize(dh).map_err(|e| e.in_field(\"{}\"))?,
+                    None => match <{}>::deserialize_missing() {{
+                        Some(v) => v,
+                        None => return Err(::zero::errors::DeserializeError::missing(\"{}\")),
+                    }}
                 }},",
                 name,
                 name,
-                field_data.ty_str()
+                field_data.ty_str(),
+                name,
+                field_data.ty_str(),
+                name
             )
         })
         .collect();
 
     let output = format!(
         r#"impl ::zero::serializer::Deserialize for {} {{
-    fn deserialize(dh: ::zero::serializer::DataHolder) -> Result<Self, ()> {{
+    fn deserialize(dh: ::zero::serializer::DataHolder) -> Result<Self, ::zero::errors::DeserializeError> {{
         match dh {{
             ::zero::serializer::DataHolder::Struct(mut dh) => Ok(Self {{
                 {}
             }}),
-            _ => Err(())
+            _ => Err(::zero::errors::DeserializeError::new(
+                ::zero::errors::DeserializeReason::ExpectedStruct
+            ))
         }}
     }}
 }}"#,
@@ -208,6 +219,59 @@ fn parse_deserialize_struct(mut parser: TokenParser, is_public: bool) -> TokenSt
     output.parse().unwrap()
 }
 
+fn parse_serialize_struct(mut parser: TokenParser, is_public: bool) -> TokenStream {
+    let data_struct = parser.consume_struct(is_public).expect("a valid struct");
+
+    let struct_name = data_struct.name();
+    let fields: String = data_struct
+        .fields()
+        .keys()
+        .map(|name| {
+            format!(
+                "if let Some(dh) = ::zero::serializer::Serialize::serialize_field(self.{}) {{
+                    map.insert(String::from(\"{}\"), dh);
+                }}",
+                name, name
+            )
+        })
+        .collect();
+
+    let output = format!(
+        r#"impl ::zero::serializer::Serialize for {} {{
+    fn serialize(self) -> ::zero::serializer::DataHolder {{
+        let mut map = ::std::collections::HashMap::new();
+        {}
+        ::zero::serializer::DataHolder::Struct(map)
+    }}
+}}"#,
+        struct_name, fields
+    );
+
+    output.parse().unwrap()
+}
+
+/// Fields that serialize to nothing, i.e. `None` options, are left out of the output.
+#[proc_macro_derive(Serialize)]
+pub fn derive_serialize(items: TokenStream) -> TokenStream {
+    let mut parser = TokenParser::new(items);
+
+    let is_pub = parser.is_ident("pub");
+    if is_pub {
+        parser.consume();
+    }
+
+    match parser.consume_if(|p| p.is_ident("struct")) {
+        Ok(_) => parse_serialize_struct(parser, is_pub),
+        Err(_) => match parser.consume_if(|p| p.is_ident("enum")) {
+            Ok(_) => {
+                unimplemented!("Enum serialization is not supported at this time")
+            }
+
+            Err(_) => panic!("Expected a struct or enum"),
+        },
+    }
+}
+
 #[proc_macro_derive(Deserialize)]
 pub fn derive_deserialize(items: TokenStream) -> TokenStream {
     let mut parser = TokenParser::new(items);
@@ -229,12 +293,57 @@ pub fn derive_deserialize(items: TokenStream) -> TokenStream {
     }
 }
 
+/// Builds a `compile_error!` invocation with all of its tokens set to `span`
+/// so the error gets reported at the offending tokens instead of the derive.
+fn compile_error_at(msg: &str, span: Span) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut lit = Literal::string(msg);
+    lit.set_span(span);
+    let mut group = Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(lit)),
+    );
+    group.set_span(span);
+    let mut semi = Punct::new(';', Spacing::Alone);
+    semi.set_span(span);
+
+    [
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group),
+        TokenTree::Punct(semi),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Table fields are serialized by value, so borrowed fields can't be supported.
+fn reject_reference_fields(data_struct: &Struct) -> Option<TokenStream> {
+    data_struct.fields().values().find_map(|field| {
+        field.reference_span().map(|span| {
+            compile_error_at(
+                &format!(
+                    "`{}` cannot derive ToDatabaseBytes/ZeroTable: field `{}` is a reference. Table fields must be owned types (e.g. `String` instead of `&str`)",
+                    data_struct.name(),
+                    field.name(),
+                ),
+                span,
+            )
+        })
+    })
+}
+
 fn parse_db_bytes_struct(
     other_traits: String,
     mut parser: TokenParser,
     is_public: bool,
     data_struct: Struct,
 ) -> TokenStream {
+    if let Some(err) = reject_reference_fields(&data_struct) {
+        return err;
+    }
+
     let struct_name = data_struct.name();
     let generic_idents: String = data_struct
         .generic_idents()
@@ -264,6 +373,8 @@ fn parse_db_bytes_struct(
             )
         })
         .collect();
+    // `DatabaseBytes::consume_layout` pops from the back, so fields are decoded in
+    // the reverse of the order they were pushed.
     let reversed: String = to_reverse.into_iter().rev().collect();
 
     let output = format!(
@@ -287,6 +398,11 @@ fn parse_db_bytes_struct(
     output.parse().unwrap()
 }
 
+/// Derives `ToDatabaseBytes` for a struct of owned fields.
+///
+/// `to_db_bytes` pushes the fields in declaration order and `from_db_bytes` reads
+/// them back last field first, since `DatabaseBytes` is a stack of layouts. Hand
+/// written impls have to follow the same LIFO order to nest inside derived ones.
 #[proc_macro_derive(ToDatabaseBytes)]
 pub fn derive_to_db_bytes(items: TokenStream) -> TokenStream {
     let mut parser = TokenParser::new(items);
@@ -310,10 +426,52 @@ pub fn derive_to_db_bytes(items: TokenStream) -> TokenStream {
         },
     }
 }
-#[proc_macro_derive(ZeroTable)]
+/// Tables listed in a `#[zero_table(previous(A, B))]` attribute, as type tokens.
+fn previous_versions(attr: TokenStream) -> Vec<String> {
+    let mut parser = TokenParser::new(attr);
+    if parser.consume_if(|p| p.is_ident("zero_table")).is_err() {
+        return Vec::new();
+    }
+    let Some(TokenTree::Group(args)) = parser.consume() else {
+        panic!("Expected `#[zero_table(previous(..))]`");
+    };
+    let mut parser = TokenParser::new(args.stream());
+    parser
+        .consume_if(|p| p.is_ident("previous"))
+        .expect("Expected `previous(..)` in `#[zero_table]`");
+    let Some(TokenTree::Group(tables)) = parser.consume() else {
+        panic!("Expected `previous(..)` in `#[zero_table]`");
+    };
+
+    let mut parser = TokenParser::new(tables.stream());
+    let mut previous = Vec::new();
+    while parser.has_tokens_left() {
+        let table = parser.consume_while(|p| !p.is_punct(","));
+        let _ = parser.consume();
+        if !table.is_empty() {
+            previous.push(TokenParser::to_token_stream(table).to_string());
+        }
+    }
+    previous
+}
+
+/// Derives `ZeroTable` along with `ToDatabaseBytes`.
+///
+/// Earlier versions the table migrates from are listed with
+/// `#[zero_table(previous(UserV1))]`, each of which needs a `TableVersion` impl. They
+/// make up the table's lineage registered by `PageMap::register_table`.
+#[proc_macro_derive(ZeroTable, attributes(zero_table))]
 pub fn derive_zero_table(items: TokenStream) -> TokenStream {
     let mut parser = TokenParser::new(items);
 
+    let mut previous = Vec::new();
+    while parser.consume_if(|p| p.is_punct("#")).is_ok() {
+        match parser.consume() {
+            Some(TokenTree::Group(attr)) => previous.extend(previous_versions(attr.stream())),
+            _ => panic!("Expected an attribute"),
+        }
+    }
+
     let is_pub = parser.is_ident("pub");
     if is_pub {
         parser.consume();
@@ -340,6 +498,9 @@ pub fn derive_zero_table(items: TokenStream) -> TokenStream {
                     fn table_version_hash() -> ::zero::UUID {{
                         ::zero::{}
                     }}
+                    fn previous_versions() -> ::std::vec::Vec<(&'static str, ::zero::UUID)> {{
+                        ::std::vec![{}]
+                    }}
                 }}"#,
                 traits,
                 data_struct.name(),
@@ -348,6 +509,20 @@ pub fn derive_zero_table(items: TokenStream) -> TokenStream {
                 UUID::from_table_hash(data_struct.struct_signature())
                     .expect("Failed to build table uuid")
                     .as_token_string(),
+                previous
+                    .iter()
+                    .map(|old| format!(
+                        r#"{{
+                            // only tables this one migrates from can be listed
+                            let _ = <Self as ::zero::db::system_tables::TableVersion<{0}>>::map_to_current;
+                            (
+                                <{0} as ::zero::db::ZeroTable>::table_name(),
+                                <{0} as ::zero::db::ZeroTable>::table_version_hash(),
+                            )
+                        }},"#,
+                        old
+                    ))
+                    .collect::<String>(),
             );
 
             let name = data_struct.name().clone();
@@ -1,4 +1,4 @@
-use proc_macro::{TokenStream, TokenTree, token_stream::IntoIter};
+use proc_macro::{Span, TokenStream, TokenTree, token_stream::IntoIter};
 use std::{collections::BTreeMap, sync::Arc};
 
 #[derive(Debug)]
@@ -12,6 +12,18 @@ impl StructField {
     pub fn ty_str(&self) -> String {
         self.ty.iter().map(|t| t.to_string()).collect()
     }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    /// Span of the leading `&` if this field is a borrowed type
+    pub fn reference_span(&self) -> Option<Span> {
+        match self.ty.first() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '&' => Some(p.span()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
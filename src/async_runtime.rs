@@ -1,18 +1,47 @@
 use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
     future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
     sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
     },
     task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    thread::{self, JoinHandle, Thread},
+    time::{Duration, Instant},
 };
 
 /// Shared wake flag
+///
+/// Flags of spawned tasks also push the task onto its executor's ready queue the
+/// first time they are woken, so `run` only polls tasks that can make progress.
+/// Waking also unparks the thread driving `run`, in case it is waiting for work.
 struct WakeFlag {
     woke: AtomicBool,
+    task: Option<(TaskId, Arc<ReadyQueue>)>,
+    executor: Thread,
+}
+
+impl WakeFlag {
+    fn wake(&self) {
+        if !self.woke.swap(true, Ordering::AcqRel)
+            && let Some((id, ready)) = &self.task
+        {
+            match ready.lock() {
+                Ok(mut ready) => ready.push_back(*id),
+                Err(poisoned) => poisoned.into_inner().push_back(*id),
+            }
+        }
+        self.executor.unpark();
+    }
 }
 
 type WakerData = Arc<WakeFlag>;
+type TaskId = usize;
+type ReadyQueue = Mutex<VecDeque<TaskId>>;
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
 
 static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop_waker);
 
@@ -25,36 +54,444 @@ unsafe fn clone(data: *const ()) -> RawWaker {
 
 unsafe fn wake(data: *const ()) {
     let arc = unsafe { WakerData::from_raw(data as *const WakeFlag) };
-    arc.woke.store(true, Ordering::Release);
+    arc.wake();
 }
 
 unsafe fn wake_by_ref(data: *const ()) {
     let arc = unsafe { &*(data as *const WakeFlag) };
-    arc.woke.store(true, Ordering::Release);
+    arc.wake();
 }
 
 unsafe fn drop_waker(data: *const ()) {
     drop(unsafe { WakerData::from_raw(data as *const WakeFlag) });
 }
 
-/// Minimal executor
+fn waker_for(flag: &WakerData) -> Waker {
+    let raw_waker = RawWaker::new(Arc::into_raw(flag.clone()) as *const (), &VTABLE);
+    unsafe { Waker::from_raw(raw_waker) }
+}
+
+thread_local! {
+    /// Tasks spawned on this thread that no `run` has picked up yet
+    static SPAWNED: RefCell<Vec<Task>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `future` alongside the future passed to `run`.
+///
+/// The task is picked up by the `run` driving the current thread, or by the next one
+/// started on it when called outside of `run`.
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    SPAWNED.with(|spawned| spawned.borrow_mut().push(Box::pin(future)));
+}
+
+/// Minimal executor, parking the thread while nothing is ready to be polled
+///
+/// Drives `future` along with every task `spawn`ed while it runs, returning the
+/// output of `future` once it and all of the spawned tasks have completed. Spawned
+/// tasks are only polled again after their waker has been woken.
 pub fn run<F: Future>(future: F) -> F::Output {
     let wake_flag = Arc::new(WakeFlag {
         woke: AtomicBool::new(true), // start "woken"
+        task: None,
+        executor: thread::current(),
     });
-
-    let raw_waker = RawWaker::new(Arc::into_raw(wake_flag.clone()) as *const (), &VTABLE);
-    let waker = unsafe { Waker::from_raw(raw_waker) };
+    let waker = waker_for(&wake_flag);
     let mut cx = Context::from_waker(&waker);
 
     let mut future = Box::pin(future);
+    let mut output = None;
+
+    let ready: Arc<ReadyQueue> = Arc::new(Mutex::new(VecDeque::new()));
+    let mut tasks: HashMap<TaskId, (Task, WakerData, Waker)> = HashMap::new();
+    let mut next_id: TaskId = 0;
+
+    loop {
+        for task in SPAWNED.with(|spawned| std::mem::take(&mut *spawned.borrow_mut())) {
+            let flag = Arc::new(WakeFlag {
+                woke: AtomicBool::new(false),
+                task: Some((next_id, ready.clone())),
+                executor: thread::current(),
+            });
+            flag.wake();
+            let task_waker = waker_for(&flag);
+            tasks.insert(next_id, (task, flag, task_waker));
+            next_id += 1;
+        }
+
+        if output.is_none()
+            && wake_flag.woke.swap(false, Ordering::Acquire)
+            && let Poll::Ready(val) = future.as_mut().poll(&mut cx)
+        {
+            output = Some(val);
+        }
+
+        loop {
+            let id = match ready.lock() {
+                Ok(mut ready) => ready.pop_front(),
+                Err(poisoned) => poisoned.into_inner().pop_front(),
+            };
+            let Some(id) = id else {
+                break;
+            };
+            let Some((task, flag, task_waker)) = tasks.get_mut(&id) else {
+                continue;
+            };
+            // cleared before polling so a wake during the poll queues the task again
+            flag.woke.store(false, Ordering::Release);
+            let mut task_cx = Context::from_waker(task_waker);
+            if task.as_mut().poll(&mut task_cx).is_ready() {
+                tasks.remove(&id);
+            }
+        }
+
+        if tasks.is_empty()
+            && SPAWNED.with(|spawned| spawned.borrow().is_empty())
+            && let Some(val) = output.take()
+        {
+            return val;
+        }
+
+        // Nothing was woken, so wait for a waker instead of spinning on the flags. An
+        // idle `run` uses no CPU, and an `unpark` sent while we were still polling is
+        // kept by the thread, so the `park` below returns right away instead of
+        // missing it. Spurious wake ups only cost another pass over the flags.
+        let idle = !wake_flag.woke.load(Ordering::Acquire)
+            && match ready.lock() {
+                Ok(ready) => ready.is_empty(),
+                Err(poisoned) => poisoned.into_inner().is_empty(),
+            }
+            && SPAWNED.with(|spawned| spawned.borrow().is_empty());
+        if idle {
+            thread::park();
+        }
+    }
+}
+
+/// Future returned by `catch_unwind`
+pub struct CatchUnwind<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(val)) => Poll::Ready(Ok(val)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+/// Wraps a future so a panic while polling it resolves to `Err` with the
+/// panic payload instead of unwinding through the executor.
+pub fn catch_unwind<F: Future>(future: F) -> CatchUnwind<F> {
+    CatchUnwind {
+        future: Box::pin(future),
+    }
+}
+
+/// Best effort message from a panic payload
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown panic payload"
+    }
+}
 
+/// Future returned by `sleep`
+pub struct Sleep {
+    deadline: Instant,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Instant::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            Timer::register(self.deadline, cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Completes once `duration` has passed.
+///
+/// Deadlines are tracked by a single timer thread shared by every `run`, started on
+/// the first `sleep` and stopped by `shutdown_timer`.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+    }
+}
+
+/// Runs `op` until it succeeds, at most `attempts` times, sleeping between attempts.
+///
+/// The first retry waits `backoff`, every later one twice as long as the one before.
+/// Returns the last error once all attempts failed. `op` always runs at least once.
+pub async fn retry<T, E, F, Fut>(attempts: u32, backoff: Duration, mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = backoff;
+    let mut attempt = 1;
     loop {
-        if wake_flag.woke.swap(false, Ordering::Acquire) {
-            match future.as_mut().poll(&mut cx) {
-                Poll::Ready(val) => return val,
-                Poll::Pending => {}
+        match op().await {
+            Ok(t) => return Ok(t),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(_) => {}
+        }
+        sleep(delay).await;
+        delay = delay.saturating_mul(2);
+        attempt += 1;
+    }
+}
+
+/// Stops the timer thread and waits for it to exit. Pending sleeps are woken and
+/// start a new timer thread if they are polled again.
+pub fn shutdown_timer() {
+    let timer = match TIMER.lock() {
+        Ok(mut timer) => timer.take(),
+        Err(poisoned) => poisoned.into_inner().take(),
+    };
+    if let Some(timer) = timer {
+        timer.shared.lock().shutdown = true;
+        timer.shared.wake.notify_one();
+        let _ = timer.thread.join();
+    }
+}
+
+static TIMER: Mutex<Option<Timer>> = Mutex::new(None);
+static TIMER_THREADS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+struct Timer {
+    shared: Arc<TimerShared>,
+    thread: JoinHandle<()>,
+}
+
+struct TimerShared {
+    state: Mutex<TimerState>,
+    wake: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    entries: Vec<(Instant, Waker)>,
+    shutdown: bool,
+}
+
+impl TimerShared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, TimerState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Timer {
+    fn register(deadline: Instant, waker: Waker) {
+        let mut timer = match TIMER.lock() {
+            Ok(timer) => timer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let timer = timer.get_or_insert_with(Timer::start);
+        timer.shared.lock().entries.push((deadline, waker));
+        timer.shared.wake.notify_one();
+    }
+
+    fn start() -> Timer {
+        let shared = Arc::new(TimerShared {
+            state: Mutex::new(TimerState::default()),
+            wake: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name(String::from("zero-timer"))
+            .spawn(move || Self::tick(&thread_shared))
+            .expect("failed to spawn timer thread");
+        TIMER_THREADS_STARTED.fetch_add(1, Ordering::Relaxed);
+
+        Timer { shared, thread }
+    }
+
+    /// Wakes sleeps as their deadlines pass until told to shut down
+    fn tick(shared: &TimerShared) {
+        let mut state = shared.lock();
+        loop {
+            if state.shutdown {
+                for (_, waker) in state.entries.drain(..) {
+                    waker.wake();
+                }
+                return;
+            }
+
+            let now = Instant::now();
+            let mut next: Option<Instant> = None;
+            state.entries.retain(|(deadline, waker)| {
+                if *deadline <= now {
+                    waker.wake_by_ref();
+                    false
+                } else {
+                    next = Some(next.map_or(*deadline, |n| n.min(*deadline)));
+                    true
+                }
+            });
+
+            state = match next {
+                Some(next) => match shared.wake.wait_timeout(state, next - now) {
+                    Ok((state, _)) => state,
+                    Err(poisoned) => poisoned.into_inner().0,
+                },
+                None => match shared.wake.wait(state) {
+                    Ok(state) => state,
+                    Err(poisoned) => poisoned.into_inner(),
+                },
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pending until polled `polls` times, waking itself each time
+    struct Yield {
+        polls: usize,
+    }
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.polls == 0 {
+                return Poll::Ready(());
+            }
+            self.polls -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    /// Pending on its first poll, woken from another thread a little later
+    struct PendingOnce {
+        polls: Arc<AtomicUsize>,
+    }
+
+    impl Future for PendingOnce {
+        type Output = usize;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<usize> {
+            let polls = self.polls.fetch_add(1, Ordering::SeqCst) + 1;
+            if polls > 1 {
+                return Poll::Ready(polls);
             }
+            let waker = cx.waker().clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                waker.wake();
+            });
+            Poll::Pending
         }
     }
+
+    #[test]
+    fn test_run_parks_until_woken() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let total = run(PendingOnce {
+            polls: polls.clone(),
+        });
+        // `run` parks while the future is pending instead of spinning on the wake flag,
+        // burning no CPU, so it is only polled again once the other thread wakes it
+        assert_eq!(total, 2);
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let total = run({
+            let count = count.clone();
+            async move {
+                for i in 0..3 {
+                    let count = count.clone();
+                    spawn(async move {
+                        Yield { polls: i }.await;
+                        count.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+                "main done"
+            }
+        });
+
+        // run only returns once the spawned tasks are done too
+        assert_eq!(total, "main done");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        // tasks spawned outside of run are picked up by the next one
+        let count_outside = count.clone();
+        spawn(async move {
+            count_outside.fetch_add(1, Ordering::SeqCst);
+        });
+        run(async {});
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_sleep_single_timer_thread() {
+        shutdown_timer();
+        let started = TIMER_THREADS_STARTED.load(Ordering::Relaxed);
+
+        let begin = Instant::now();
+        for _ in 0..20 {
+            run(async {
+                for _ in 0..5 {
+                    sleep(Duration::from_millis(1)).await;
+                }
+            });
+        }
+        assert!(begin.elapsed() >= Duration::from_millis(100));
+        assert_eq!(TIMER_THREADS_STARTED.load(Ordering::Relaxed) - started, 1);
+
+        shutdown_timer();
+        assert!(TIMER.lock().unwrap().is_none());
+        run(sleep(Duration::from_millis(1)));
+        assert_eq!(TIMER_THREADS_STARTED.load(Ordering::Relaxed) - started, 2);
+        shutdown_timer();
+    }
+
+    #[test]
+    fn test_retry() {
+        let attempts = Mutex::new(Vec::new());
+        let result = run(retry(5, Duration::from_millis(10), || {
+            let mut attempts = attempts.lock().unwrap();
+            attempts.push(Instant::now());
+            let n = attempts.len();
+            async move { if n < 3 { Err(n) } else { Ok(n) } }
+        }));
+        assert_eq!(result, Ok(3));
+
+        let attempts = attempts.into_inner().unwrap();
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts[1] - attempts[0] >= Duration::from_millis(10));
+        assert!(attempts[2] - attempts[1] >= Duration::from_millis(20));
+
+        let calls = AtomicUsize::new(0);
+        let result: Result<(), usize> = run(retry(3, Duration::from_millis(1), || {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Err(n) }
+        }));
+        // the error of the last attempt is returned
+        assert_eq!(result, Err(3));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
 }
@@ -6,15 +6,19 @@ pub mod system_tables;
 //
 use uuid::UUID;
 
-use crate::{ToDatabaseBytes, db::system_tables::User, stream_writer::StreamWritable};
+use crate::{
+    ToDatabaseBytes, db::system_tables::User, errors::DbError, stream_writer::StreamWritable,
+};
 use std::{
     cmp::Ordering,
     collections::{BTreeMap, HashMap, VecDeque},
     fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Read, Seek, Write},
+    io::{BufReader, BufWriter, Read, Write},
     os::unix::fs::FileExt,
     path::Path,
     sync::{Arc, RwLock, atomic::AtomicBool},
+    thread,
+    time::{Duration, Instant},
 };
 
 pub type PageAddress = usize;
@@ -68,172 +72,285 @@ impl WalPageNumber {
 
 pub type Page = [u8; 4096];
 
+/// Reusable page buffers for `BufferedRW`'s read path.
+///
+/// Pages dropped from the read buffer are handed back through `release`. A page still
+/// shared with a caller waits in `pending` until every other `Arc` is dropped, then it
+/// can be handed out again by `take` without allocating.
+#[derive(Debug, Default)]
+struct PagePool {
+    free: Vec<Arc<Page>>,
+    pending: Vec<Arc<Page>>,
+    /// Pages allocated because nothing could be reused
+    allocated: usize,
+}
+
+impl PagePool {
+    /// Pages kept in each of `free` and `pending`, anything past this is dropped
+    const MAX_POOLED: usize = 64;
+
+    /// A page nothing else references. Its contents are left over from the last use.
+    fn take(&mut self) -> Arc<Page> {
+        if let Some(page) = self.free.pop() {
+            return page;
+        }
+        if let Some(idx) = self
+            .pending
+            .iter()
+            .position(|page| Arc::strong_count(page) == 1)
+        {
+            return self.pending.swap_remove(idx);
+        }
+        self.allocated += 1;
+        Arc::new([0_u8; 4096])
+    }
+
+    fn release(&mut self, page: Arc<Page>) {
+        let pool = if Arc::strong_count(&page) == 1 {
+            &mut self.free
+        } else {
+            &mut self.pending
+        };
+        if pool.len() < Self::MAX_POOLED {
+            pool.push(page);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BufferedRW {
     db_file: File,
     wal_file: File,
     update_ledger: HashMap<PageAddress, Arc<Page>>,
     read_buffer: HashMap<PageAddress, Arc<Page>>,
+    page_pool: PagePool,
     ledger_version: usize,
     commit: usize,
+    lock_timeout: Duration,
 }
 
 impl BufferedRW {
     pub const MAX_BUF: usize = 1000;
-    pub fn new(path: &str) -> Result<Self, ()> {
+    /// Size of the WAL header, the `commit` followed by the `ledger_version`
+    const WAL_HEADER: u64 = 16;
+    /// Size of a WAL entry, a page address followed by the page
+    const WAL_ENTRY: u64 = 8 + 4096;
+    /// How long acquiring the WAL lock may take before giving up, see `with_lock_timeout`
+    pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Pages `write_stream` collects before handing them to `write_pages`
+    pub const STREAM_BATCH: usize = 16;
+    pub fn new(path: &str) -> Result<Self, DbError> {
         let path = Path::new(path);
         let wal_file = OpenOptions::new()
             .write(true)
             .read(true)
             .create(true)
-            .open(path.with_extension("zero_wal"))
-            .map_err(|_| ())?;
-        wal_file.unlock().map_err(|_| ())?;
+            .open(path.with_extension("zero_wal"))?;
+        wal_file.unlock()?;
 
         let db_file = OpenOptions::new()
             .write(true)
             .read(true)
             .create(true)
-            .open(path)
-            .map_err(|_| ())?;
-        db_file.unlock().map_err(|_| ())?;
+            .open(path)?;
+        db_file.unlock()?;
 
-        Ok(BufferedRW {
+        let mut rw = BufferedRW {
             db_file,
             wal_file,
             update_ledger: HashMap::new(),
             read_buffer: HashMap::new(),
+            page_pool: PagePool::default(),
             ledger_version: 0,
             commit: 0,
-        })
+            lock_timeout: Self::DEFAULT_LOCK_TIMEOUT,
+        };
+        rw.recover()?;
+        Ok(rw)
     }
 
-    fn wal_read<T, F: Fn(&Self) -> Result<T, ()>>(&self, f: F) -> Result<T, ()> {
-        self.wal_file.lock_shared().map_err(|_| ())?;
-        let t = f(self);
-        self.wal_file.unlock().map_err(|_| ())?;
-        Ok(t?)
-    }
-    fn wal_read_mut<T, F: Fn(&mut Self) -> Result<T, ()>>(&mut self, f: F) -> Result<T, ()> {
-        self.wal_file.lock_shared().map_err(|_| ())?;
-        let t = f(self);
-        self.wal_file.unlock().map_err(|_| ())?;
-        Ok(t?)
-    }
-    fn wal_write_mut<T, F: Fn(&mut Self) -> Result<T, ()>>(&mut self, f: F) -> Result<T, ()> {
-        self.wal_file.lock().map_err(|_| ())?;
-        let t = f(self);
-        self.wal_file.unlock().map_err(|_| ())?;
-        Ok(t?)
+    /// Replays pages left in the WAL by a handle that never flushed them, e.g. after a
+    /// crash, into the db file and empties the WAL. Returns the number of pages replayed.
+    ///
+    /// Only the `ledger_version` pages published in the WAL header for its current
+    /// `commit` are replayed. A page whose append was cut short was never published, so
+    /// it is rolled back. Other handles open on the same files stay valid, they see the
+    /// new `commit` and reload their state before their next read or write.
+    pub fn recover(&mut self) -> Result<usize, DbError> {
+        self.wal_write_mut(|s| {
+            s.load_wal()?;
+            let replayed = s.ledger_version;
+            if replayed > 0 {
+                s.checkpoint()?;
+            } else if s.wal_file.metadata()?.len() > Self::WAL_HEADER {
+                s.wal_file.set_len(Self::WAL_HEADER)?;
+            }
+            Ok(replayed)
+        })
     }
 
-    fn db_read_mut<T, F: Fn(&mut Self) -> Result<T, ()>>(&mut self, f: F) -> Result<T, ()> {
-        self.wal_file.lock_shared().map_err(|_| ())?;
-        let t = f(self);
-        self.wal_file.unlock().map_err(|_| ())?;
-        Ok(t?)
-    }
-    fn db_write_mut<T, F: Fn(&mut Self) -> Result<T, ()>>(&mut self, f: F) -> Result<T, ()> {
-        self.wal_file.lock().map_err(|_| ())?;
-        let t = f(self);
-        self.wal_file.unlock().map_err(|_| ())?;
-        Ok(t?)
-    }
+    /// Writes the pages loaded from the WAL into the db file, then starts a new
+    /// `commit` with an empty WAL.
+    ///
+    /// The db file is synced before the header is, so a crash part way through replays
+    /// the same pages again. Must be called while holding the WAL write lock, after
+    /// `load_wal`.
+    fn checkpoint(&mut self) -> Result<(), DbError> {
+        let pages = std::mem::take(&mut self.update_ledger);
+        for (address, page) in &pages {
+            self.db_file.write_all_at(&**page, *address as u64)?;
+        }
+        if !pages.is_empty() {
+            self.db_file.sync_data()?;
+        }
 
-    fn fetch_file_version(&self) -> Result<(usize, usize), ()> {
-        self.wal_read(|s| {
-            let mut buf = [0_8; 8];
-            let len_read = s.wal_file.read_at(&mut buf, 0).map_err(|_| ())?;
-            if len_read != 8 {
-                return Err(());
-            }
-            let commit = usize::from_le_bytes(buf);
-            let len_read = s.wal_file.read_at(&mut buf, 1).map_err(|_| ())?;
-            if len_read != 8 {
-                return Err(());
-            }
-            let ledger_version = usize::from_le_bytes(buf);
-            Ok((commit, ledger_version))
-        })
+        // other handles see the new commit and drop what they loaded from the WAL
+        self.commit += 1;
+        self.ledger_version = 0;
+        self.write_wal_header()?;
+        self.wal_file.set_len(Self::WAL_HEADER)?;
+        Ok(())
     }
 
-    fn sync_wal(&mut self) -> Result<(), ()> {
-        self.wal_read_mut(|s| {
-            let mut commit = [0_u8; 8];
-            let bytes_read = s.wal_file.read_at(&mut commit, 0).map_err(|e| ())?;
-            if bytes_read != 8 {
-                return Err(());
-            }
+    /// `commit` and `ledger_version` from the WAL header, zeros for a WAL too short to
+    /// have one yet.
+    fn read_wal_header(&self) -> Result<(usize, usize), DbError> {
+        if self.wal_file.metadata()?.len() < Self::WAL_HEADER {
+            return Ok((0, 0));
+        }
+        let mut word = [0_u8; 8];
+        self.wal_file.read_exact_at(&mut word, 0)?;
+        let commit = usize::from_le_bytes(word);
+        self.wal_file.read_exact_at(&mut word, 8)?;
+        Ok((commit, usize::from_le_bytes(word)))
+    }
 
-            let mut ledger_version = [0_u8; 8];
-            let bytes_read = s.wal_file.read_at(&mut ledger_version, 8).map_err(|_| ())?;
-            if bytes_read != 8 {
-                return Err(());
+    /// Brings this handle up to date with the WAL, loading the pages other handles
+    /// published since it last looked.
+    ///
+    /// A `commit` other than the one this handle loaded means the WAL was checkpointed
+    /// and emptied, so everything loaded from it is dropped and it is read from the
+    /// start. Must be called while holding the WAL lock.
+    fn load_wal(&mut self) -> Result<(), DbError> {
+        let (commit, ledger_version) = self.read_wal_header()?;
+        if commit != self.commit {
+            self.update_ledger.clear();
+            for (_, page) in self.read_buffer.drain() {
+                self.page_pool.release(page);
             }
+            self.commit = commit;
+            self.ledger_version = 0;
+        }
 
-            let commit = usize::from_le_bytes(commit);
-            let ledger_version = usize::from_le_bytes(ledger_version);
-
-            if commit > s.commit {
-                s.update_ledger.clear();
-                s.read_buffer.clear();
-                s.ledger_version = 0;
-                s.wal_file
-                    .seek(std::io::SeekFrom::Start(16))
-                    .map_err(|_| ())?;
-                while s.ledger_version < ledger_version {
-                    let mut page_address = [0_u8; 8];
-                    let bytes_read = s.wal_file.read(&mut page_address).map_err(|_| ())?;
-                    if bytes_read != 8 {
-                        return Err(());
-                    }
-
-                    let mut page = [0_u8; 4096];
-                    let bytes_read = s.wal_file.read(&mut page).map_err(|_| ())?;
-                    if bytes_read != 4096 {
-                        return Err(());
-                    }
+        // a WAL cut short loses the pages past its end, rather than failing every read
+        let len = self.wal_file.metadata()?.len();
+        let available = (len.saturating_sub(Self::WAL_HEADER) / Self::WAL_ENTRY) as usize;
+        while self.ledger_version < ledger_version.min(available) {
+            let offset = Self::wal_offset(self.ledger_version);
+            let mut word = [0_u8; 8];
+            self.wal_file.read_exact_at(&mut word, offset)?;
+            let mut page = [0_u8; 4096];
+            self.wal_file.read_exact_at(&mut page, offset + 8)?;
+
+            let page_address = usize::from_le_bytes(word);
+            let page = Arc::new(page);
+            self.update_ledger.insert(page_address, page.clone());
+            self.update_read_buf(page_address, page);
+            self.ledger_version += 1;
+        }
 
-                    let page_address = usize::from_le_bytes(page_address);
-                    let page = Arc::new(page);
+        Ok(())
+    }
 
-                    s.update_ledger.insert(page_address, page.clone());
-                    s.read_buffer.insert(page_address, page);
+    /// Position of the WAL entry for the page published as `ledger_version`
+    fn wal_offset(ledger_version: usize) -> u64 {
+        Self::WAL_HEADER + ledger_version as u64 * Self::WAL_ENTRY
+    }
 
-                    s.ledger_version += 1;
-                }
-            } else if s.ledger_version < ledger_version {
-                while s.ledger_version < ledger_version {
-                    let mut page_address = [0_u8; 8];
-                    let bytes_read = s.wal_file.read(&mut page_address).map_err(|_| ())?;
-                    if bytes_read != 8 {
-                        return Err(());
-                    }
+    /// Must be called while holding the WAL write lock
+    fn write_wal_header(&self) -> Result<(), DbError> {
+        self.wal_file.write_all_at(&self.commit.to_le_bytes(), 0)?;
+        self.wal_file
+            .write_all_at(&self.ledger_version.to_le_bytes(), 8)?;
+        Ok(())
+    }
 
-                    let mut page = [0_u8; 4096];
-                    let bytes_read = s.wal_file.read(&mut page).map_err(|_| ())?;
-                    if bytes_read != 4096 {
-                        return Err(());
-                    }
+    /// Sets how long to wait for another handle to release the WAL lock before failing
+    /// with `DbError::LockTimeout`, instead of blocking until it does.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
 
-                    let page_address = usize::from_le_bytes(page_address);
-                    let page = Arc::new(page);
+    /// Retries taking the WAL lock, backing off from 1ms up to 50ms between attempts,
+    /// until `lock_timeout` has passed.
+    fn lock_wal(&self, exclusive: bool) -> Result<(), DbError> {
+        let deadline = Instant::now() + self.lock_timeout;
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            let locked = if exclusive {
+                self.wal_file.try_lock()
+            } else {
+                self.wal_file.try_lock_shared()
+            };
+            match locked {
+                Ok(()) => return Ok(()),
+                Err(std::fs::TryLockError::WouldBlock) => {}
+                Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(DbError::LockTimeout);
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(Duration::from_millis(50));
+        }
+    }
 
-                    s.update_ledger.insert(page_address, page.clone());
-                    s.read_buffer.insert(page_address, page);
+    fn wal_read<T, F: Fn(&Self) -> Result<T, DbError>>(&self, f: F) -> Result<T, DbError> {
+        self.lock_wal(false)?;
+        let t = f(self);
+        self.wal_file.unlock()?;
+        t
+    }
+    fn wal_read_mut<T, F: Fn(&mut Self) -> Result<T, DbError>>(
+        &mut self,
+        f: F,
+    ) -> Result<T, DbError> {
+        self.lock_wal(false)?;
+        let t = f(self);
+        self.wal_file.unlock()?;
+        t
+    }
+    fn wal_write_mut<T, F: Fn(&mut Self) -> Result<T, DbError>>(
+        &mut self,
+        f: F,
+    ) -> Result<T, DbError> {
+        self.lock_wal(true)?;
+        let t = f(self);
+        self.wal_file.unlock()?;
+        t
+    }
 
-                    s.ledger_version += 1;
-                }
-            }
+    fn db_read_mut<T, F: Fn(&mut Self) -> Result<T, DbError>>(
+        &mut self,
+        f: F,
+    ) -> Result<T, DbError> {
+        self.lock_wal(false)?;
+        let t = f(self);
+        self.wal_file.unlock()?;
+        t
+    }
 
-            Ok(())
-        })
+    fn sync_wal(&mut self) -> Result<(), DbError> {
+        self.wal_read_mut(|s| s.load_wal())
     }
 
     fn update_read_buf(&mut self, page_address: PageAddress, page: Arc<Page>) {
         match self.read_buffer.get_mut(&page_address) {
             Some(found_page) => {
-                *found_page = page;
+                let old = std::mem::replace(found_page, page);
+                self.page_pool.release(old);
             }
 
             None => {
@@ -244,7 +361,9 @@ impl BufferedRW {
                         .next()
                         .expect("read buffer found none, this should be impossible");
 
-                    self.read_buffer.remove(&rand_key);
+                    if let Some(old) = self.read_buffer.remove(&rand_key) {
+                        self.page_pool.release(old);
+                    }
                 }
 
                 self.read_buffer.insert(page_address, page);
@@ -252,68 +371,146 @@ impl BufferedRW {
         };
     }
 
-    pub fn read_page(&mut self, page_address: &PageAddress) -> Result<Arc<Page>, ()> {
+    pub fn read_page(&mut self, page_address: &PageAddress) -> Result<Arc<Page>, DbError> {
         let page_address = (page_address >> 12) << 12;
         self.sync_wal()?;
         match self.read_buffer.get(&page_address) {
             Some(wal_page) => Ok(wal_page.clone()),
             None => self.db_read_mut(|s| {
-                let mut page = [0_u8; 4096];
-                match s.db_file.read_at(&mut page, page_address as u64) {
-                    Ok(_) => {
-                        let page = Arc::new(page);
+                let mut page = s.page_pool.take();
+                let buf = Arc::get_mut(&mut page).expect("pooled pages are never shared");
+                match s.db_file.read_at(buf, page_address as u64) {
+                    Ok(read) => {
+                        // pooled pages hold stale data, pages past the end of file read as zeros
+                        buf[read..].fill(0);
                         s.update_read_buf(page_address, page.clone());
                         Ok(page)
                     }
-                    Err(_) => Err(()),
+                    Err(e) => {
+                        s.page_pool.release(page);
+                        Err(e.into())
+                    }
                 }
             }),
         }
     }
 
-    pub fn write_page(&mut self, page_address: &PageAddress, page: Page) -> Result<(), ()> {
-        let page_address = (page_address >> 12) << 12;
+    pub fn write_page(&mut self, page_address: &PageAddress, page: Page) -> Result<(), DbError> {
+        self.write_pages(&[(*page_address, page)])
+    }
+
+    /// Writes a batch of pages to the WAL under a single lock.
+    ///
+    /// All pages are appended in one write and the ledger version in the WAL header is
+    /// only updated once, after every page has been appended.
+    pub fn write_pages(&mut self, pages: &[(PageAddress, Page)]) -> Result<(), DbError> {
         self.wal_write_mut(|s| {
-            let page = Arc::new(page);
-            s.update_read_buf(page_address, page.clone());
+            // appending after what other handles published, never over it
+            s.load_wal()?;
+
+            let mut entries = Vec::with_capacity(pages.len() * Self::WAL_ENTRY as usize);
+            for (page_address, page) in pages {
+                let page_address = (page_address >> 12) << 12;
+                entries.extend_from_slice(&page_address.to_le_bytes());
+                entries.extend_from_slice(page);
+
+                let page = Arc::new(*page);
+                s.update_read_buf(page_address, page.clone());
+                s.update_ledger.insert(page_address, page);
+            }
             s.wal_file
-                .write(&page_address.to_le_bytes())
-                .map_err(|_| ())?;
-            s.wal_file.write(&*page).map_err(|_| ())?;
-            s.ledger_version += 1;
-            let ledger_version = s.ledger_version.to_le_bytes();
-            s.update_ledger.insert(page_address, page);
-            if s.update_ledger.len() > Self::MAX_BUF {
-                s.read_buffer.clear();
-                s.ledger_version = 0;
-                s.commit = 0;
-                s.wal_file.set_len(16).map_err(|_| ())?;
-                s.wal_file
-                    .seek(std::io::SeekFrom::Start(0))
-                    .map_err(|_| ())?;
-                let commit = s.commit.to_le_bytes();
-                let ledger_version = s.ledger_version.to_le_bytes();
-                s.wal_file.write(&commit).map_err(|_| ())?;
-                s.wal_file.write(&ledger_version).map_err(|_| ())?;
-
-                s.flush_wal()?;
-            } else {
-                s.wal_file.write_at(&ledger_version, 8).map_err(|_| ())?;
+                .write_all_at(&entries, Self::wal_offset(s.ledger_version))?;
+            s.ledger_version += pages.len();
+            s.publish_ledger_version()
+        })
+    }
+
+    pub fn ledger_version(&self) -> usize {
+        self.ledger_version
+    }
+
+    /// Streams `reader` into consecutive pages starting at `start`, returning the number
+    /// of bytes written. The last page is zero padded.
+    ///
+    /// Only `STREAM_BATCH` pages are held at a time, each batch going through
+    /// `write_pages`, so uploads of any size are written without being buffered whole.
+    pub fn write_stream<R: Read>(
+        &mut self,
+        start: PageAddress,
+        mut reader: R,
+    ) -> Result<usize, DbError> {
+        let mut page_address = (start >> 12) << 12;
+        let mut batch: Vec<(PageAddress, Page)> = Vec::with_capacity(Self::STREAM_BATCH);
+        let mut written = 0;
+        loop {
+            let mut page = [0_u8; 4096];
+            let len = Self::fill_page(&mut reader, &mut page)?;
+            if len > 0 {
+                batch.push((page_address, page));
+                page_address += PageMap::PAGE_SIZE;
+                written += len;
+            }
+            if batch.len() == Self::STREAM_BATCH || (len < page.len() && !batch.is_empty()) {
+                self.write_pages(&batch)?;
+                batch.clear();
+            }
+            if len < page.len() {
+                return Ok(written);
             }
+        }
+    }
 
-            Ok(())
-        })
+    /// Writes `len` bytes stored by `write_stream` at `start` to `writer`, a page at a time.
+    pub fn read_stream<W: Write>(
+        &mut self,
+        start: PageAddress,
+        len: usize,
+        writer: &mut W,
+    ) -> Result<(), DbError> {
+        let mut page_address = (start >> 12) << 12;
+        let mut remaining = len;
+        while remaining > 0 {
+            let page = self.read_page(&page_address)?;
+            let n = remaining.min(page.len());
+            writer.write_all(&page[..n])?;
+            remaining -= n;
+            page_address += PageMap::PAGE_SIZE;
+        }
+        Ok(())
     }
 
-    pub fn flush_wal(&mut self) -> Result<(), ()> {
-        self.db_write_mut(|s| {
-            let mut map = HashMap::new();
-            std::mem::swap(&mut s.update_ledger, &mut map);
-            for (address, page) in map {
-                s.db_file.write_at(&*page, address as u64).map_err(|_| ())?;
+    /// Reads until `page` is full or `reader` is exhausted
+    fn fill_page<R: Read>(reader: &mut R, page: &mut Page) -> Result<usize, DbError> {
+        let mut len = 0;
+        while len < page.len() {
+            match reader.read(&mut page[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
             }
+        }
+        Ok(len)
+    }
+
+    /// Must be called while holding the WAL write lock
+    fn publish_ledger_version(&mut self) -> Result<(), DbError> {
+        self.write_wal_header()?;
+        if self.update_ledger.len() > Self::MAX_BUF {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
 
-            Ok(())
+    /// Writes every page published to the WAL, by any handle, into the db file and
+    /// empties the WAL.
+    pub fn flush_wal(&mut self) -> Result<(), DbError> {
+        self.wal_write_mut(|s| {
+            s.load_wal()?;
+            if s.ledger_version == 0 {
+                return Ok(());
+            }
+            s.checkpoint()
         })
     }
 }
@@ -331,33 +528,8 @@ pub struct PageMap {
     order_map: BTreeMap<UUID, PageAddress>,
     read_map: HashMap<UUID, PageAddress>,
     open_layouts: BTreeMap<usize, PageAddress>,
-    table_version_maps: HashMap<&'static str, Vec<&'static str>>,
-}
-
-impl ToDatabaseBytes for (UUID, PageAddress) {
-    fn to_db_bytes(self) -> DatabaseBytes {
-        DatabaseBytes::default().push_into(self.0).push_into(self.1)
-    }
-
-    fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
-        let page_address = <PageAddress>::from_db_bytes(bytes)?;
-        let uuid = <UUID>::from_db_bytes(bytes)?;
-
-        Ok((uuid, page_address))
-    }
-}
-
-impl ToDatabaseBytes for (usize, PageAddress) {
-    fn to_db_bytes(self) -> DatabaseBytes {
-        DatabaseBytes::default().push_into(self.0).push_into(self.1)
-    }
-
-    fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
-        let page_address = <PageAddress>::from_db_bytes(bytes)?;
-        let layout = <usize>::from_db_bytes(bytes)?;
-
-        Ok((layout, page_address))
-    }
+    /// Every known version of a table, by the name of its current version
+    table_version_maps: HashMap<&'static str, Vec<(&'static str, UUID)>>,
 }
 
 impl ToDatabaseBytes for PageMap {
@@ -385,12 +557,14 @@ impl ToDatabaseBytes for PageMap {
                 .map(|(k, v)| ((k.clone(), v.clone()), (k.clone(), v.clone())))
                 .collect();
 
-        Ok(PageMap {
+        let mut map = PageMap {
             order_map,
             read_map,
             open_layouts,
             table_version_maps: HashMap::new(),
-        })
+        };
+        map.register_system_tables();
+        Ok(map)
     }
 }
 
@@ -402,12 +576,18 @@ impl PageMap {
     pub const PAGE_SIZE: usize = 4096;
 
     pub fn new() -> Self {
-        PageMap {
+        let mut map = PageMap {
             order_map: BTreeMap::new(),
             read_map: HashMap::new(),
             open_layouts: BTreeMap::new(),
             table_version_maps: HashMap::new(),
-        }
+        };
+        map.register_system_tables();
+        map
+    }
+
+    fn register_system_tables(&mut self) {
+        self.register_table::<User>();
     }
 
     pub fn insert(&mut self) -> Result<UUID, ()> {
@@ -434,6 +614,28 @@ impl PageMap {
             _ => None,
         }
     }
+
+    /// Registers `T` as the current version of its table, along with the previous
+    /// versions its `ZeroTable` derive lists. System tables are registered up front.
+    pub fn register_table<T: ZeroTable>(&mut self) {
+        self.push_version(T::table_name(), T::table_name(), T::table_version_hash());
+        for (name, hash) in T::previous_versions() {
+            self.push_version(T::table_name(), name, hash);
+        }
+    }
+
+    fn push_version(&mut self, table: &'static str, name: &'static str, hash: UUID) {
+        let versions = self.table_version_maps.entry(table).or_default();
+        if !versions.iter().any(|(_, h)| *h == hash) {
+            versions.push((name, hash));
+        }
+    }
+
+    /// Names and version hashes of the registered versions of `table`, in the order
+    /// they were registered, or `None` for a table that was never registered.
+    pub fn table_versions(&self, table: &str) -> Option<&[(&'static str, UUID)]> {
+        self.table_version_maps.get(table).map(Vec::as_slice)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -474,6 +676,73 @@ impl DatabaseBytes {
     pub fn into_bytes(self) -> Vec<u8> {
         self.bytes
     }
+
+    /// Version of the record framing written by `to_record_bytes`. Bump this whenever
+    /// the layout or encoding scheme changes so old records are rejected instead of misread.
+    pub const FORMAT_VERSION: u16 = 3;
+
+    /// Flattens into a self describing record:
+    ///
+    /// ```text
+    /// record = format-version (u16) layout-count (u64) *layout (u64) bytes
+    /// ```
+    pub fn to_record_bytes(self) -> Vec<u8> {
+        self.encode_record(Self::FORMAT_VERSION)
+    }
+
+    pub fn from_record_bytes(raw: &[u8]) -> Result<Self, DbError> {
+        Self::decode_record(raw, Self::FORMAT_VERSION)
+    }
+
+    /// Same as `to_record_bytes`, with `len` more bytes read from `tail` pushed last the
+    /// way a `Vec<u8>` is, so the record decodes as `(T, Vec<u8>)` for the `T` that `self`
+    /// came from. The tail is only read as the record is, e.g. by
+    /// `BufferedRW::write_stream`, so it is never held in memory whole.
+    pub fn to_record_stream<R: Read>(self, len: usize, tail: R) -> impl Read {
+        let head = self
+            .push_db_bytes(DatabaseBytes::new(len, Vec::new()))
+            .to_record_bytes();
+        std::io::Cursor::new(head).chain(tail.take(len as u64))
+    }
+
+    fn encode_record(self, version: u16) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(10 + self.layouts.len() * 8 + self.bytes.len());
+        raw.extend_from_slice(&version.to_le_bytes());
+        raw.extend_from_slice(&(self.layouts.len() as u64).to_le_bytes());
+        for layout in self.layouts {
+            raw.extend_from_slice(&(layout as u64).to_le_bytes());
+        }
+        raw.extend_from_slice(&self.bytes);
+        raw
+    }
+
+    fn decode_record(raw: &[u8], expected: u16) -> Result<Self, DbError> {
+        let (version, raw) = raw.split_first_chunk::<2>().ok_or(DbError::InvalidRecord)?;
+        let found = u16::from_le_bytes(*version);
+        if found != expected {
+            return Err(DbError::UnsupportedFormatVersion { found, expected });
+        }
+
+        let (count, mut raw) = raw.split_first_chunk::<8>().ok_or(DbError::InvalidRecord)?;
+        let count = u64::from_le_bytes(*count) as usize;
+        let mut layouts = Vec::new();
+        let mut total = 0_usize;
+        for _ in 0..count {
+            let (layout, rest) = raw.split_first_chunk::<8>().ok_or(DbError::InvalidRecord)?;
+            let layout = u64::from_le_bytes(*layout) as usize;
+            total = total.checked_add(layout).ok_or(DbError::InvalidRecord)?;
+            layouts.push(layout);
+            raw = rest;
+        }
+        if total != raw.len() {
+            return Err(DbError::InvalidRecord);
+        }
+
+        Ok(Self {
+            layouts,
+            bytes: raw.to_vec(),
+        })
+    }
 }
 
 impl Default for DatabaseBytes {
@@ -485,23 +754,62 @@ impl Default for DatabaseBytes {
     }
 }
 
+/// Converts a type to and from its on-disk representation.
+///
+/// Fields are serialized by value, so deriving this (or `ZeroTable`) on a
+/// struct with borrowed fields is rejected at compile time:
+///
+/// ```compile_fail
+/// #[derive(zero::ToDatabaseBytes)]
+/// struct Borrowed<'a> {
+///     name: &'a str,
+/// }
+/// ```
 pub trait ToDatabaseBytes: Sized {
     fn to_db_bytes(self) -> DatabaseBytes;
     fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()>;
+
+    /// Encodes a `Vec<Self>`. Overridden by `u8` so byte vectors are stored as is
+    /// rather than encoded one element at a time.
+    ///
+    /// Each element keeps its own layouts, followed by the element count, so elements
+    /// don't need to share an encoded length (`None` next to `Some`, strings, tuples).
+    #[doc(hidden)]
+    fn vec_to_db_bytes(v: Vec<Self>) -> DatabaseBytes {
+        let len = v.len();
+        v.into_iter()
+            .fold(DatabaseBytes::default(), |bytes, t| bytes.push_into(t))
+            .push_into(len)
+    }
+
+    #[doc(hidden)]
+    fn vec_from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Vec<Self>, ()> {
+        let len = <usize>::from_db_bytes(bytes)?;
+        // a corrupt count shouldn't reserve more than there are layouts left
+        let mut v = Vec::with_capacity(len.min(bytes.layouts.len()));
+        for _ in 0..len {
+            v.push(Self::from_db_bytes(bytes)?);
+        }
+        // layouts pop from the back, so the elements come out last first
+        v.reverse();
+
+        Ok(v)
+    }
 }
 
 macro_rules! impl_to_db_bytes {
-    ($t: ty, $bytes: literal) => {
+    ($t: ty, $bytes: literal $(; $($vec_impl: tt)*)?) => {
         impl ToDatabaseBytes for $t {
             fn to_db_bytes(self) -> DatabaseBytes {
                 let b = self.to_le_bytes().to_vec();
                 DatabaseBytes::new(b.len(), b)
             }
+            $($($vec_impl)*)?
 
             fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
                 let bytes = bytes.consume_layout()?;
-                match bytes.split_first_chunk::<$bytes>() {
-                    Some((b, _)) => Ok(<$t>::from_le_bytes(*b)),
+                match <[u8; $bytes]>::try_from(bytes.as_slice()) {
+                    Ok(b) => Ok(<$t>::from_le_bytes(b)),
                     _ => Err(()),
                 }
             }
@@ -518,6 +826,7 @@ macro_rules! impl_to_db_bytes {
             }
 
             fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
+                // an empty array still takes up a (zero sized) layout
                 let raw = bytes.consume_layout()?;
 
                 if raw.len() != N * $bytes {
@@ -525,13 +834,8 @@ macro_rules! impl_to_db_bytes {
                 }
 
                 let mut out: [$t; N] = [0; N];
-                for i in 0..N {
-                    let mut buf = [0_u8; $bytes];
-                    let start = i * $bytes;
-                    for j in start..start + $bytes {
-                        buf[j - start] = raw[j];
-                    }
-                    out[i] = <$t>::from_le_bytes(buf);
+                for (o, chunk) in out.iter_mut().zip(raw.chunks_exact($bytes)) {
+                    *o = <$t>::from_le_bytes(chunk.try_into().map_err(|_| ())?);
                 }
 
                 Ok(out)
@@ -540,8 +844,15 @@ macro_rules! impl_to_db_bytes {
     };
 }
 
-//TODO: u8 needs manual impl for vec
-impl_to_db_bytes!(u8, 1);
+impl_to_db_bytes!(u8, 1;
+    fn vec_to_db_bytes(v: Vec<Self>) -> DatabaseBytes {
+        DatabaseBytes::new(v.len(), v)
+    }
+
+    fn vec_from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Vec<Self>, ()> {
+        bytes.consume_layout()
+    }
+);
 impl_to_db_bytes!(u16, 2);
 impl_to_db_bytes!(u32, 4);
 impl_to_db_bytes!(u64, 8);
@@ -596,71 +907,59 @@ impl<const N: usize> ToDatabaseBytes for [char; N] {
     }
 }
 
-struct DatabaseVec<T: ToDatabaseBytes> {
-    t_len: usize,
-    data: Vec<u8>,
-    _ty: std::marker::PhantomData<T>,
+impl ToDatabaseBytes for bool {
+    fn to_db_bytes(self) -> DatabaseBytes {
+        (self as u8).to_db_bytes()
+    }
+
+    fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
+        match <u8>::from_db_bytes(bytes)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(()),
+        }
+    }
 }
 
-impl<T: ToDatabaseBytes> From<Vec<T>> for DatabaseVec<Vec<T>> {
-    fn from(mut value: Vec<T>) -> Self {
-        if let Some(first) = value.pop() {
-            let first = first.to_db_bytes().into_bytes();
-            let t_len = first.len();
-            let end_data: Vec<u8> = value
-                .into_iter()
-                .map(|b| b.to_db_bytes().into_bytes())
-                .flatten()
-                .collect();
-            let data = [end_data, first].concat();
-            DatabaseVec {
-                t_len,
-                data,
-                _ty: std::marker::PhantomData,
+/// Tuples, e.g. composite keys like `(UUID, PageAddress)`, are written field by field.
+///
+/// Layouts are popped from the back, so fields are read in the reverse of the order
+/// they were pushed. The macro takes the fields in both orders to spell that out.
+macro_rules! impl_tuple_to_db_bytes {
+    ($($t:ident $v:ident),+ ; $($rv:ident $rt:ident),+) => {
+        impl<$($t: ToDatabaseBytes),+> ToDatabaseBytes for ($($t,)+) {
+            fn to_db_bytes(self) -> DatabaseBytes {
+                let ($($v,)+) = self;
+                DatabaseBytes::default()$(.push_into($v))+
             }
-        } else {
-            DatabaseVec {
-                t_len: 0,
-                data: Vec::new(),
-                _ty: std::marker::PhantomData,
+
+            fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
+                $(let $rv = <$rt>::from_db_bytes(bytes)?;)+
+
+                Ok(($($v,)+))
             }
         }
-    }
+    };
 }
 
-impl<T: ToDatabaseBytes> ToDatabaseBytes for DatabaseVec<T> {
-    fn to_db_bytes(self) -> DatabaseBytes {
-        DatabaseBytes::default()
-            .push_into(self.t_len)
-            .push_db_bytes(DatabaseBytes::new(self.data.len(), self.data))
-    }
+impl_tuple_to_db_bytes!(A a, B b; b B, a A);
+impl_tuple_to_db_bytes!(A a, B b, C c; c C, b B, a A);
+impl_tuple_to_db_bytes!(A a, B b, C c, D d; d D, c C, b B, a A);
+impl_tuple_to_db_bytes!(A a, B b, C c, D d, E e; e E, d D, c C, b B, a A);
+impl_tuple_to_db_bytes!(A a, B b, C c, D d, E e, F f; f F, e E, d D, c C, b B, a A);
+impl_tuple_to_db_bytes!(A a, B b, C c, D d, E e, F f, G g; g G, f F, e E, d D, c C, b B, a A);
+impl_tuple_to_db_bytes!(
+    A a, B b, C c, D d, E e, F f, G g, H h;
+    h H, g G, f F, e E, d D, c C, b B, a A
+);
 
-    fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
-        let data = bytes.consume_layout()?;
-        let t_len = <usize>::from_db_bytes(bytes)?;
-        Ok(DatabaseVec {
-            t_len,
-            data,
-            _ty: std::marker::PhantomData,
-        })
-    }
-}
 impl<A: ToDatabaseBytes> ToDatabaseBytes for Vec<A> {
     fn to_db_bytes(self) -> DatabaseBytes {
-        let db_vec: DatabaseVec<Vec<A>> = self.into();
-        db_vec.to_db_bytes()
+        A::vec_to_db_bytes(self)
     }
 
     fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
-        let db_vec = DatabaseVec::<Vec<A>>::from_db_bytes(bytes)?;
-
-        let mut v = Vec::new();
-        for chunk in db_vec.data.chunks(db_vec.t_len) {
-            let mut db_bytes = DatabaseBytes::new(db_vec.t_len, chunk.to_vec());
-            v.push(A::from_db_bytes(&mut db_bytes)?);
-        }
-
-        Ok(v)
+        A::vec_from_db_bytes(bytes)
     }
 }
 
@@ -679,32 +978,56 @@ impl ToDatabaseBytes for String {
     }
 }
 
+/// Options are written as the inner value followed by a presence byte (`0` for `None`,
+/// `1` for `Some`), so a `Some` holding a zero length value isn't read back as `None`.
 impl<T: ToDatabaseBytes> ToDatabaseBytes for Option<T> {
     fn to_db_bytes(self) -> DatabaseBytes {
         match self {
-            Some(t) => t.to_db_bytes(),
-            None => DatabaseBytes::new(0, vec![]),
+            Some(t) => t.to_db_bytes().push_into(1_u8),
+            None => DatabaseBytes::default().push_into(0_u8),
         }
     }
 
     fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
-        let bytes = bytes.consume_layout()?;
-        if bytes.len() == 0 {
-            Ok(None)
-        } else {
-            let mut bytes = DatabaseBytes::new(bytes.len(), bytes);
-            Ok(Some(T::from_db_bytes(&mut bytes)?))
+        match <u8>::from_db_bytes(bytes)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::from_db_bytes(bytes)?)),
+            _ => Err(()),
         }
     }
 }
-// impl<A: ToDatabaseBytes> ToDatabaseBytes for HashMap<A, B> {
-//     fn to_db_bytes(self) -> DatabaseBytes {
 
-//     }
-//     fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
+/// Maps are written as their entries, each key followed by its value, then the entry
+/// count. Entries are read back in the order they were written, which is whatever order
+/// the map iterated in, so nothing relies on it. A key found twice is an error.
+impl<K, V> ToDatabaseBytes for HashMap<K, V>
+where
+    K: ToDatabaseBytes + Eq + std::hash::Hash,
+    V: ToDatabaseBytes,
+{
+    fn to_db_bytes(self) -> DatabaseBytes {
+        let len = self.len();
+        self.into_iter()
+            .fold(DatabaseBytes::default(), |bytes, (k, v)| {
+                bytes.push_into(k).push_into(v)
+            })
+            .push_into(len)
+    }
 
-//     }
-// }
+    fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
+        let len = <usize>::from_db_bytes(bytes)?;
+        let mut map = HashMap::new();
+        for _ in 0..len {
+            let v = V::from_db_bytes(bytes)?;
+            let k = K::from_db_bytes(bytes)?;
+            if map.insert(k, v).is_some() {
+                return Err(());
+            }
+        }
+
+        Ok(map)
+    }
+}
 
 /// This is implemented manually to avoid circular dependency of trait and macro
 impl ToDatabaseBytes for UUID {
@@ -727,6 +1050,26 @@ impl ToDatabaseBytes for UUID {
 }
 // impl ToDatabaseBytes for UUID {}
 
+impl<const N: usize> ToDatabaseBytes for [UUID; N] {
+    fn to_db_bytes(self) -> DatabaseBytes {
+        self.into_iter()
+            .fold(DatabaseBytes::default(), |bytes, uuid| {
+                bytes.push_into(uuid)
+            })
+    }
+
+    fn from_db_bytes(bytes: &mut DatabaseBytes) -> Result<Self, ()> {
+        // layouts are popped from the back, so the last uuid comes out first
+        let mut uuids = Vec::with_capacity(N);
+        for _ in 0..N {
+            uuids.push(UUID::from_db_bytes(bytes)?);
+        }
+        uuids.reverse();
+
+        uuids.try_into().map_err(|_| ())
+    }
+}
+
 pub struct TableReference<T: ZeroTable> {
     z_uuid: UUID,
     _ty: std::marker::PhantomData<T>,
@@ -749,6 +1092,11 @@ impl<T: ZeroTable> ToDatabaseBytes for TableReference<T> {
 pub trait ZeroTable: ToDatabaseBytes {
     fn table_name() -> &'static str;
     fn table_version_hash() -> UUID;
+    /// Names and version hashes of the earlier versions this table migrates from, as
+    /// listed in the derive's `#[zero_table(previous(..))]`
+    fn previous_versions() -> Vec<(&'static str, UUID)> {
+        Vec::new()
+    }
 }
 
 impl<T: ZeroTable> ZeroTable for TableReference<T> {
@@ -759,6 +1107,10 @@ impl<T: ZeroTable> ZeroTable for TableReference<T> {
     fn table_version_hash() -> UUID {
         T::table_version_hash()
     }
+
+    fn previous_versions() -> Vec<(&'static str, UUID)> {
+        T::previous_versions()
+    }
 }
 
 #[derive(ToDatabaseBytes)]
@@ -771,6 +1123,22 @@ pub struct TableRecord<T: ToDatabaseBytes> {
     z_uuid: UUID,
 }
 
+impl<T: ToDatabaseBytes> TableRecord<T> {
+    pub fn row(&self) -> &T {
+        &self.row
+    }
+
+    /// Serializes the record with the `DatabaseBytes` format version header
+    pub fn to_record_bytes(self) -> Vec<u8> {
+        self.to_db_bytes().to_record_bytes()
+    }
+
+    pub fn from_record_bytes(raw: &[u8]) -> Result<Self, DbError> {
+        let mut bytes = DatabaseBytes::from_record_bytes(raw)?;
+        Self::from_db_bytes(&mut bytes).map_err(|_| DbError::InvalidRecord)
+    }
+}
+
 impl<T: ZeroTable> TableRecord<T> {
     pub fn new_system_record(row: T) -> Result<Self, ()> {
         let z_uuid = UUID::rand_v7()?;
@@ -800,4 +1168,549 @@ mod tests {
         let test_vec2 = <Vec<i32>>::from_db_bytes(&mut bytes).expect("Failed to parse db bytes");
         assert_eq!(test_vec, test_vec2);
     }
+
+    #[test]
+    fn test_vec_of_mixed_lengths_round_trip() {
+        let options = vec![Some(1_u32), None, Some(3), None, None, Some(u32::MAX)];
+        let raw = options.clone().to_db_bytes().to_record_bytes();
+        let mut bytes = DatabaseBytes::from_record_bytes(&raw).unwrap();
+        assert_eq!(<Vec<Option<u32>>>::from_db_bytes(&mut bytes), Ok(options));
+        assert!(bytes.into_bytes().is_empty());
+
+        let mut bytes = vec![None::<u32>].to_db_bytes();
+        assert_eq!(
+            <Vec<Option<u32>>>::from_db_bytes(&mut bytes),
+            Ok(vec![None])
+        );
+
+        let mut bytes = Vec::<Option<u32>>::new().to_db_bytes();
+        assert_eq!(<Vec<Option<u32>>>::from_db_bytes(&mut bytes), Ok(vec![]));
+
+        let names = vec![String::new(), String::from("a"), String::from("longer")];
+        let mut bytes = names.clone().to_db_bytes();
+        assert_eq!(<Vec<String>>::from_db_bytes(&mut bytes), Ok(names));
+
+        let pages = vec![
+            (UUID::default().encode_id(1), 0_usize),
+            (UUID::default().encode_id(2), PageMap::PAGE_SIZE),
+        ];
+        let mut bytes = (pages.clone(), 9_u32).to_db_bytes();
+        assert_eq!(
+            <(Vec<(UUID, PageAddress)>, u32)>::from_db_bytes(&mut bytes),
+            Ok((pages, 9))
+        );
+    }
+
+    #[test]
+    fn test_option_round_trip() {
+        for value in [Some(String::new()), None, Some("x".to_string())] {
+            let mut bytes = value.clone().to_db_bytes();
+            let read =
+                <Option<String>>::from_db_bytes(&mut bytes).expect("Failed to parse db bytes");
+            assert_eq!(read, value);
+        }
+
+        let mut bytes = DatabaseBytes::default()
+            .push_into(Some(String::new()))
+            .push_into(None::<String>)
+            .push_into(Some(7_u32));
+        assert_eq!(<Option<u32>>::from_db_bytes(&mut bytes), Ok(Some(7)));
+        assert_eq!(<Option<String>>::from_db_bytes(&mut bytes), Ok(None));
+        assert_eq!(
+            <Option<String>>::from_db_bytes(&mut bytes),
+            Ok(Some(String::new()))
+        );
+
+        #[derive(ToDatabaseBytes, Debug, PartialEq, Clone)]
+        struct Row {
+            a: u32,
+            b: Option<String>,
+            c: u32,
+        }
+        for b in [None, Some(String::new()), Some(String::from("middle"))] {
+            let row = Row { a: 1, b, c: 2 };
+            let raw = row.clone().to_db_bytes().to_record_bytes();
+            let mut bytes = DatabaseBytes::from_record_bytes(&raw).unwrap();
+            assert_eq!(Row::from_db_bytes(&mut bytes), Ok(row));
+            assert!(bytes.into_bytes().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_hash_map_round_trip() {
+        let names = HashMap::from([
+            (String::from("a"), String::new()),
+            (String::from("longer key"), String::from("value")),
+            (String::new(), String::from("empty key")),
+        ]);
+        let mut bytes = names.clone().to_db_bytes();
+        assert_eq!(
+            <HashMap<String, String>>::from_db_bytes(&mut bytes),
+            Ok(names)
+        );
+
+        let pages: HashMap<UUID, PageAddress> = (0..20_u64)
+            .map(|i| {
+                (
+                    UUID::default().encode_id(i),
+                    i as usize * PageMap::PAGE_SIZE,
+                )
+            })
+            .collect();
+        // followed by another field, as in a derived struct
+        let mut bytes = DatabaseBytes::default()
+            .push_into(pages.clone())
+            .push_into(HashMap::<u8, u8>::new());
+        assert_eq!(
+            <HashMap<u8, u8>>::from_db_bytes(&mut bytes),
+            Ok(HashMap::new())
+        );
+        assert_eq!(
+            <HashMap<UUID, PageAddress>>::from_db_bytes(&mut bytes),
+            Ok(pages)
+        );
+
+        #[derive(ToDatabaseBytes, Debug, PartialEq, Clone)]
+        struct Settings {
+            name: String,
+            values: HashMap<String, String>,
+            version: u32,
+        }
+        let settings = Settings {
+            name: String::from("site"),
+            values: HashMap::from([(String::from("theme"), String::from("dark"))]),
+            version: 2,
+        };
+        let mut bytes = settings.clone().to_db_bytes();
+        assert_eq!(Settings::from_db_bytes(&mut bytes), Ok(settings));
+
+        let mut duplicated = DatabaseBytes::default()
+            .push_into(1_u8)
+            .push_into(2_u8)
+            .push_into(1_u8)
+            .push_into(3_u8)
+            .push_into(2_usize);
+        assert_eq!(<HashMap<u8, u8>>::from_db_bytes(&mut duplicated), Err(()));
+    }
+
+    #[test]
+    fn test_record_format_version() {
+        let uuid = || UUID {
+            data_1: 1,
+            data_2: 2,
+            data_3: 3,
+            data_4: [4; 8],
+        };
+        let record = || TableRecord {
+            row: String::from("row"),
+            z_created_by: User::SYSTEM,
+            z_mod_count: 3,
+            z_updated_by: User::SYSTEM,
+            z_updated_on: 42,
+            z_uuid: uuid(),
+        };
+
+        let raw = record().to_record_bytes();
+        let read = TableRecord::<String>::from_record_bytes(&raw).unwrap();
+        assert_eq!(read.row, "row");
+        assert_eq!(read.z_mod_count, 3);
+        assert_eq!(read.z_updated_on, 42);
+        assert_eq!(read.z_uuid, uuid());
+
+        let bumped = DatabaseBytes::FORMAT_VERSION + 1;
+        assert_eq!(
+            DatabaseBytes::decode_record(&raw, bumped).map(|_| ()),
+            Err(DbError::UnsupportedFormatVersion {
+                found: DatabaseBytes::FORMAT_VERSION,
+                expected: bumped,
+            })
+        );
+        assert_eq!(
+            TableRecord::<String>::from_record_bytes(&raw[..raw.len() - 1]).map(|_| ()),
+            Err(DbError::InvalidRecord)
+        );
+    }
+
+    #[test]
+    fn test_uuid_keys() {
+        let uuid = |n: u32| UUID {
+            data_1: n,
+            data_2: n as u16,
+            data_3: 7,
+            data_4: [n as u8; 8],
+        };
+
+        let mut bytes = [uuid(1), uuid(2), uuid(3)].to_db_bytes();
+        let read = <[UUID; 3]>::from_db_bytes(&mut bytes).expect("Failed to parse db bytes");
+        assert_eq!(read, [uuid(1), uuid(2), uuid(3)]);
+
+        let mut bytes = (uuid(9), 42_u64).to_db_bytes();
+        let read = <(UUID, u64)>::from_db_bytes(&mut bytes).expect("Failed to parse db bytes");
+        assert_eq!(read, (uuid(9), 42));
+    }
+
+    #[test]
+    fn test_byte_vec_round_trip() {
+        let blob: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let bytes = blob.clone().to_db_bytes();
+        assert_eq!(bytes.layouts, vec![1000]);
+        assert_eq!(bytes.bytes.len(), 1000);
+
+        // version, layout count and a single layout on top of the data
+        let raw = bytes.to_record_bytes();
+        assert_eq!(raw.len(), 1000 + 2 + 8 + 8);
+        let mut bytes = DatabaseBytes::from_record_bytes(&raw).unwrap();
+        assert_eq!(<Vec<u8>>::from_db_bytes(&mut bytes), Ok(blob));
+
+        let mut bytes = (Vec::<u8>::new(), vec![1_u8], 3_u32).to_db_bytes();
+        assert_eq!(
+            <(Vec<u8>, Vec<u8>, u32)>::from_db_bytes(&mut bytes),
+            Ok((vec![], vec![1], 3))
+        );
+    }
+
+    #[test]
+    fn test_array_round_trip() {
+        let mut bytes = [0_u32; 0].to_db_bytes();
+        assert_eq!(bytes.layouts, vec![0]);
+        assert_eq!(<[u32; 0]>::from_db_bytes(&mut bytes), Ok([]));
+        assert!(bytes.layouts.is_empty());
+
+        let large: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let mut bytes = large.to_db_bytes();
+        assert_eq!(bytes.layouts, vec![256]);
+        assert_eq!(<[u8; 256]>::from_db_bytes(&mut bytes), Ok(large));
+
+        // empty arrays keep their place next to other fields
+        let mut bytes = (7_u32, [0_u64; 0], [1_i16, -2, 3]).to_db_bytes();
+        assert_eq!(
+            <(u32, [u64; 0], [i16; 3])>::from_db_bytes(&mut bytes),
+            Ok((7, [], [1, -2, 3]))
+        );
+
+        let raw = [5_u64; 3].to_db_bytes().to_record_bytes();
+        let mut bytes = DatabaseBytes::from_record_bytes(&raw).unwrap();
+        assert_eq!(<[u64; 3]>::from_db_bytes(&mut bytes), Ok([5; 3]));
+
+        // a layout of the wrong size is rejected instead of truncated
+        let mut bytes = [1_u16; 4].to_db_bytes();
+        assert!(<[u16; 3]>::from_db_bytes(&mut bytes).is_err());
+        let mut bytes = 1_u64.to_db_bytes();
+        assert!(u32::from_db_bytes(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_tuple_round_trip() {
+        let mut bytes = (7_u32, String::from("seven")).to_db_bytes();
+        assert_eq!(
+            <(u32, String)>::from_db_bytes(&mut bytes),
+            Ok((7, String::from("seven")))
+        );
+
+        let key = (UUID::default().encode_id(3), 4096_usize, true);
+        let mut bytes = key.clone().to_db_bytes();
+        assert_eq!(<(UUID, usize, bool)>::from_db_bytes(&mut bytes), Ok(key));
+
+        // fields of the same type come back in place rather than swapped
+        let layout = (1_usize, 2 * PageMap::PAGE_SIZE);
+        let mut bytes = layout.to_db_bytes();
+        assert_eq!(
+            <(usize, PageAddress)>::from_db_bytes(&mut bytes),
+            Ok(layout)
+        );
+
+        let wide = (
+            1_u8,
+            2_u16,
+            3_u32,
+            4_u64,
+            String::from("5"),
+            false,
+            7_i8,
+            '8',
+        );
+        let mut bytes = wide.clone().to_db_bytes();
+        assert_eq!(
+            <(u8, u16, u32, u64, String, bool, i8, char)>::from_db_bytes(&mut bytes),
+            Ok(wide)
+        );
+        assert_eq!(<bool>::from_db_bytes(&mut 2_u8.to_db_bytes()), Err(()));
+    }
+
+    #[test]
+    fn test_write_pages() {
+        let path = std::env::temp_dir().join(format!("zero_write_pages_{}", std::process::id()));
+        let path_str = path.to_str().expect("temp path should be utf-8");
+        let mut db = BufferedRW::new(path_str).expect("Failed to open db");
+
+        let pages: Vec<(PageAddress, Page)> = (0..100_usize)
+            .map(|i| {
+                let mut page = [0_u8; 4096];
+                page[..8].copy_from_slice(&i.to_le_bytes());
+                page[4095] = i as u8;
+                (i << 12, page)
+            })
+            .collect();
+
+        let before = db.ledger_version();
+        db.write_pages(&pages).expect("Failed to write pages");
+        assert_eq!(db.ledger_version() - before, 100);
+
+        for (address, page) in &pages {
+            let read = db.read_page(address).expect("Failed to read page");
+            assert_eq!(&*read, page);
+        }
+        assert_eq!(db.ledger_version() - before, 100);
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("zero_wal"));
+    }
+
+    #[test]
+    fn test_wal_recovery() {
+        let path = std::env::temp_dir().join(format!("zero_wal_recovery_{}", std::process::id()));
+        let path_str = path.to_str().expect("temp path should be utf-8");
+        let wal_path = path.with_extension("zero_wal");
+        let page = |i: u8| [i; 4096];
+
+        let mut db = BufferedRW::new(path_str).expect("Failed to open db");
+        db.write_page(&0, page(1)).expect("Failed to write page");
+        db.write_pages(&[(4096, page(2)), (2 * 4096, page(3))])
+            .expect("Failed to write pages");
+        db.write_page(&0, page(4)).expect("Failed to write page");
+        drop(db);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        // an append cut short by a crash, never published in the header
+        let mut wal = OpenOptions::new().append(true).open(&wal_path).unwrap();
+        wal.write_all(&[9; 100]).unwrap();
+        drop(wal);
+
+        let mut db = BufferedRW::new(path_str).expect("Failed to reopen db");
+        assert_eq!(db.ledger_version(), 0);
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 16);
+        assert_eq!(&*db.read_page(&0).unwrap(), &page(4));
+        assert_eq!(&*db.read_page(&4096).unwrap(), &page(2));
+        assert_eq!(&*db.read_page(&(2 * 4096)).unwrap(), &page(3));
+        let on_disk = std::fs::read(&path).unwrap();
+        assert_eq!(on_disk.len(), 3 * 4096);
+        assert_eq!(&on_disk[..4096], &page(4));
+        assert_eq!(db.recover(), Ok(0));
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&wal_path);
+    }
+
+    #[test]
+    fn test_wal_recovery_with_live_handle() {
+        let path = std::env::temp_dir().join(format!("zero_wal_live_{}", std::process::id()));
+        let path_str = path.to_str().expect("temp path should be utf-8");
+        let wal_path = path.with_extension("zero_wal");
+        let page = |i: u8| [i; 4096];
+
+        let mut first = BufferedRW::new(path_str).expect("Failed to open db");
+        first.write_page(&0, page(1)).expect("Failed to write page");
+        first
+            .write_page(&4096, page(2))
+            .expect("Failed to write page");
+
+        // opening recovers the WAL while the first handle is still live
+        let mut second = BufferedRW::new(path_str).expect("Failed to open db");
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 16);
+
+        first
+            .write_page(&(2 * 4096), page(3))
+            .expect("Failed to write page");
+        second
+            .write_page(&0, page(4))
+            .expect("Failed to write page");
+        first
+            .write_page(&4096, page(5))
+            .expect("Failed to write page");
+        assert_eq!(second.recover(), Ok(3));
+        first
+            .write_page(&(3 * 4096), page(6))
+            .expect("Failed to write page");
+
+        let expected = [page(4), page(5), page(3), page(6)];
+        for db in [&mut first, &mut second] {
+            for (i, expected) in expected.iter().enumerate() {
+                assert_eq!(&*db.read_page(&(i * 4096)).unwrap(), expected);
+            }
+        }
+        drop(first);
+        drop(second);
+
+        let mut db = BufferedRW::new(path_str).expect("Failed to reopen db");
+        for (i, expected) in expected.iter().enumerate() {
+            assert_eq!(&*db.read_page(&(i * 4096)).unwrap(), expected);
+        }
+        let on_disk = std::fs::read(&path).unwrap();
+        assert_eq!(&on_disk[3 * 4096..], &page(6));
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&wal_path);
+    }
+
+    #[test]
+    fn test_write_stream() {
+        /// Generates `len` bytes on the fly, tracking the largest read asked of it
+        struct Upload {
+            pos: usize,
+            len: usize,
+            largest_read: usize,
+        }
+        impl Read for Upload {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.largest_read = self.largest_read.max(buf.len());
+                let n = buf.len().min(self.len - self.pos);
+                for (i, b) in buf[..n].iter_mut().enumerate() {
+                    *b = ((self.pos + i) % 251) as u8;
+                }
+                self.pos += n;
+                Ok(n)
+            }
+        }
+        /// Compares written bytes against the same sequence without storing them
+        struct Check {
+            pos: usize,
+            mismatches: usize,
+        }
+        impl Write for Check {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                for b in buf {
+                    if *b != (self.pos % 251) as u8 {
+                        self.mismatches += 1;
+                    }
+                    self.pos += 1;
+                }
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("zero_write_stream_{}", std::process::id()));
+        let path_str = path.to_str().expect("temp path should be utf-8");
+        let mut db = BufferedRW::new(path_str).expect("Failed to open db");
+
+        let len = 1024 * 1024 + 17;
+        let mut upload = Upload {
+            pos: 0,
+            len,
+            largest_read: 0,
+        };
+        let written = db
+            .write_stream(1 << 12, &mut upload)
+            .expect("Failed to stream upload");
+        assert_eq!(written, len);
+        assert!(upload.largest_read <= 4096);
+
+        let mut check = Check {
+            pos: 0,
+            mismatches: 0,
+        };
+        db.read_stream(1 << 12, written, &mut check)
+            .expect("Failed to read upload back");
+        assert_eq!((check.pos, check.mismatches), (len, 0));
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("zero_wal"));
+    }
+
+    #[test]
+    fn test_page_pool_reuse() {
+        let path = std::env::temp_dir().join(format!("zero_page_pool_{}", std::process::id()));
+        let path_str = path.to_str().expect("temp path should be utf-8");
+        let mut db = BufferedRW::new(path_str).expect("Failed to open db");
+
+        let mut page = [0_u8; 4096];
+        page[0] = 42;
+        db.write_pages(&[(0, page)]).expect("Failed to write page");
+        db.flush_wal().expect("Failed to flush");
+        db.read_buffer.clear();
+
+        // held across evictions, so its buffer must not be handed out again. Evicted up
+        // front, as the read buffer picks what to evict in no particular order
+        let held = db.read_page(&0).expect("Failed to read page");
+        let evicted = db
+            .read_buffer
+            .remove(&0)
+            .expect("page 0 should be buffered");
+        db.page_pool.release(evicted);
+
+        let pages = BufferedRW::MAX_BUF + 200;
+        for _ in 0..2 {
+            for i in 1..pages {
+                let page = db.read_page(&(i << 12)).expect("Failed to read page");
+                assert!(page.iter().all(|b| *b == 0));
+            }
+        }
+
+        assert_eq!(held[0], 42);
+        // `held`, a full read buffer and the page taken by the first miss past it, after
+        // which every miss reuses the page its eviction released
+        assert_eq!(db.page_pool.allocated, BufferedRW::MAX_BUF + 2);
+
+        // once dropped, `held` is handed out again
+        let held_ptr = Arc::as_ptr(&held);
+        drop(held);
+        let taken = [db.page_pool.take(), db.page_pool.take()];
+        assert!(taken.iter().any(|page| Arc::as_ptr(page) == held_ptr));
+        assert_eq!(db.page_pool.allocated, BufferedRW::MAX_BUF + 2);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("zero_wal"));
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let path = std::env::temp_dir().join(format!("zero_lock_timeout_{}", std::process::id()));
+        let path_str = path.to_str().expect("temp path should be utf-8");
+        let first = BufferedRW::new(path_str).expect("Failed to open db");
+        let mut second = BufferedRW::new(path_str)
+            .expect("Failed to open db")
+            .with_lock_timeout(Duration::from_millis(50));
+
+        first.lock_wal(true).expect("Failed to lock");
+        let started = Instant::now();
+        assert_eq!(second.lock_wal(false), Err(DbError::LockTimeout));
+        assert_eq!(second.lock_wal(true), Err(DbError::LockTimeout));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        // the public calls report the timeout as is
+        assert_eq!(second.read_page(&0), Err(DbError::LockTimeout));
+
+        first.wal_file.unlock().expect("Failed to unlock");
+        assert_eq!(second.lock_wal(true), Ok(()));
+        second.wal_file.unlock().expect("Failed to unlock");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("zero_wal"));
+
+        let missing = path.join("missing").join("db");
+        let missing = missing.to_str().expect("temp path should be utf-8");
+        assert_eq!(
+            BufferedRW::new(missing).map(|_| ()),
+            Err(DbError::Io(std::io::ErrorKind::NotFound))
+        );
+    }
+
+    #[test]
+    fn test_table_versions() {
+        use crate::db::system_tables::UserV1;
+
+        // system tables are registered with the lineage their derive lists
+        let map = PageMap::new();
+        let versions = map
+            .table_versions("User")
+            .expect("User should be registered");
+        assert_eq!(
+            versions,
+            &[
+                ("User", User::table_version_hash()),
+                ("UserV1", UserV1::table_version_hash()),
+            ]
+        );
+        assert_ne!(versions[0].1, versions[1].1);
+        assert!(map.table_versions("UserV1").is_none());
+    }
 }
@@ -1,7 +1,8 @@
 use super::UUID;
 use crate::db::{TableReference, ZeroTable};
 
-#[derive(Debug, crate::ZeroTable)]
+#[derive(Debug, PartialEq, crate::ZeroTable)]
+#[zero_table(previous(UserV1))]
 pub struct User {
     first_name: String,
     last_name: String,
@@ -20,7 +21,7 @@ impl User {
     };
 }
 
-#[derive(Debug, crate::ZeroTable)]
+#[derive(Debug, PartialEq, crate::ZeroTable)]
 pub struct UserV1 {
     first_name: String,
     last_name: String,
@@ -42,3 +43,56 @@ impl TableVersion<UserV1> for User {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{TableRecord, ToDatabaseBytes};
+
+    fn round_trip<T: ToDatabaseBytes>(value: T) -> T {
+        let raw = value.to_db_bytes().to_record_bytes();
+        let mut bytes = crate::db::DatabaseBytes::from_record_bytes(&raw).unwrap();
+        let decoded = T::from_db_bytes(&mut bytes).unwrap();
+        assert!(bytes.into_bytes().is_empty());
+        decoded
+    }
+
+    fn user() -> User {
+        User {
+            first_name: String::from("Ada"),
+            last_name: String::from("Lovelace"),
+            email: String::from("ada@example.com"),
+        }
+    }
+
+    #[test]
+    fn test_user_round_trip() {
+        assert_eq!(round_trip(user()), user());
+        let old = UserV1 {
+            first_name: String::from("Ada"),
+            last_name: String::from("Lovelace"),
+        };
+        assert_eq!(
+            round_trip(old),
+            UserV1 {
+                first_name: String::from("Ada"),
+                last_name: String::from("Lovelace"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_table_record_round_trip() {
+        let record = TableRecord::new_system_record(user()).unwrap();
+        let z_uuid = record.z_uuid.clone();
+        let z_updated_on = record.z_updated_on;
+
+        let decoded = TableRecord::<User>::from_record_bytes(&record.to_record_bytes()).unwrap();
+        assert_eq!(decoded.row, user());
+        assert_eq!(decoded.z_created_by.z_uuid, User::SYSTEM.z_uuid);
+        assert_eq!(decoded.z_mod_count, 0);
+        assert_eq!(decoded.z_updated_by.z_uuid, User::SYSTEM.z_uuid);
+        assert_eq!(decoded.z_updated_on, z_updated_on);
+        assert_eq!(decoded.z_uuid, z_uuid);
+    }
+}
@@ -1,5 +1,121 @@
+use crate::http::request::Method;
+
 #[derive(Debug, Clone)]
 pub enum ZeroErr {
     FailedToOpen,
     FailedToRead,
+    FailedToSpawn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbError {
+    /// Bytes are too short or don't decode to the expected type
+    InvalidRecord,
+    /// Record was written with a `DatabaseBytes::FORMAT_VERSION` this build can't read
+    UnsupportedFormatVersion { found: u16, expected: u16 },
+    /// Another handle kept the WAL locked for longer than the lock timeout
+    LockTimeout,
+    /// Reading, writing or locking the db or WAL file failed
+    Io(std::io::ErrorKind),
+}
+
+impl From<std::io::Error> for DbError {
+    fn from(e: std::io::Error) -> Self {
+        DbError::Io(e.kind())
+    }
+}
+
+/// Routes of a `Router` that can't both be served, reported by `Router::build`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteConflict {
+    /// The same method and path were routed more than once, the last handler winning
+    Duplicate { method: Method, path: &'static str },
+    /// Both routes match some request path with the same priority, so which one handles
+    /// it is arbitrary
+    Ambiguous {
+        method: Method,
+        paths: (&'static str, &'static str),
+    },
+}
+
+impl std::fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Duplicate { method, path } => {
+                write!(f, "{} {} is routed more than once", method.as_str(), path)
+            }
+            Self::Ambiguous {
+                method,
+                paths: (a, b),
+            } => write!(
+                f,
+                "{} {} and {} {} match the same paths",
+                method.as_str(),
+                a,
+                method.as_str(),
+                b
+            ),
+        }
+    }
+}
+
+/// Why `Deserialize` failed and which field it failed on
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeserializeError {
+    /// Dotted path to the offending field, empty if the value itself was wrong
+    pub field: String,
+    pub reason: DeserializeReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeserializeReason {
+    MissingField,
+    /// Primitive didn't parse into the field's type
+    InvalidValue {
+        found: String,
+    },
+    ExpectedPrimitive,
+    ExpectedStruct,
+    ExpectedList,
+}
+
+impl DeserializeError {
+    pub fn new(reason: DeserializeReason) -> Self {
+        DeserializeError {
+            field: String::new(),
+            reason,
+        }
+    }
+
+    pub fn missing(field: &str) -> Self {
+        Self::new(DeserializeReason::MissingField).in_field(field)
+    }
+
+    /// Prefixes the field path with `name`, used as the error bubbles out of
+    /// nested structs.
+    pub fn in_field(mut self, name: &str) -> Self {
+        self.field = if self.field.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", name, self.field)
+        };
+        self
+    }
+}
+
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match &self.reason {
+            DeserializeReason::MissingField => String::from("missing"),
+            DeserializeReason::InvalidValue { found } => format!("invalid value '{}'", found),
+            DeserializeReason::ExpectedPrimitive => String::from("expected a value"),
+            DeserializeReason::ExpectedStruct => String::from("expected an object"),
+            DeserializeReason::ExpectedList => String::from("expected a list"),
+        };
+        if self.field.is_empty() {
+            write!(f, "{}", reason)
+        } else {
+            write!(f, "field '{}' {}", self.field, reason)
+        }
+    }
 }
@@ -1,10 +1,14 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 pub type HTML<'a> = Vec<Tag<'a>>;
 /// Hold html tag, attributes, inner content, etc
 #[derive(Debug)]
 pub struct Tag<'a> {
-    ty: TagType,
+    /// `None` for a bare text node, which renders only its content
+    ty: Option<TagType>,
     attrs: HashMap<Text<'a>, Text<'a>>,
     content: Markup<'a>,
 }
@@ -12,12 +16,21 @@ pub struct Tag<'a> {
 impl<'a> Tag<'a> {
     pub fn new(ty: TagType) -> Self {
         Tag {
-            ty,
+            ty: Some(ty),
             attrs: HashMap::new(),
             content: Markup::None,
         }
     }
 
+    /// Text node, used to place text alongside tags in an `HTML` vector
+    fn text(text: Text<'a>) -> Self {
+        Tag {
+            ty: None,
+            attrs: HashMap::new(),
+            content: Markup::Text(text),
+        }
+    }
+
     /// Sets current tag attributes
     ///
     /// This is mainly intended to be used by the html macro.
@@ -38,18 +51,146 @@ impl<'a> Tag<'a> {
     }
 }
 
+impl<'a> Tag<'a> {
+    fn fmt_open(&self, f: &mut std::fmt::Formatter<'_>, ty: &TagType) -> std::fmt::Result {
+        write!(f, "<{}", ty.as_str())?;
+        // sorted so the same tag always renders the same way
+        let mut attrs: Vec<(&Text<'a>, &Text<'a>)> = self.attrs.iter().collect();
+        attrs.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+        for (k, v) in attrs {
+            write!(f, " {}=\"{}\"", k, v)?;
+        }
+        write!(f, ">")
+    }
+
+    fn fmt_pretty(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let Some(ty) = &self.ty else {
+            return write!(f, "{}", self.content);
+        };
+        self.fmt_open(f, ty)?;
+        if ty.is_void() {
+            return Ok(());
+        }
+        match &self.content {
+            Markup::Html(tags) if !tags.is_empty() => {
+                for tag in tags {
+                    write!(f, "\n{:indent$}", "", indent = (depth + 1) * 2)?;
+                    tag.fmt_pretty(f, depth + 1)?;
+                }
+                write!(f, "\n{:indent$}", "", indent = depth * 2)?;
+            }
+            content => write!(f, "{}", content)?,
+        }
+        write!(f, "</{}>", ty.as_str())
+    }
+}
+
 /// Prints tag as html
+///
+/// The alternate flag (`{:#}`) pretty prints, putting nested tags on their own
+/// lines indented by two spaces per level. Tags holding only text stay on one line.
 impl<'a> std::fmt::Display for Tag<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let tag = &self.ty.as_str();
-        write!(f, "<{} ", tag)?;
-        for (k, v) in self.attrs.iter() {
-            eprint!("{}=\"{}\" ", k, v);
-            write!(f, "{}=\"{}\" ", k, v)?;
+        if f.alternate() {
+            return self.fmt_pretty(f, 0);
+        }
+        let Some(ty) = &self.ty else {
+            return self.content.fmt(f);
+        };
+        self.fmt_open(f, ty)?;
+        if ty.is_void() {
+            return Ok(());
         }
-        write!(f, ">")?;
         self.content.fmt(f)?;
-        write!(f, "</{}>", tag)
+        write!(f, "</{}>", ty.as_str())
+    }
+}
+
+/// Builder for a complete html document
+///
+/// Renders the doctype along with `<html>`, `<head>` and `<body>`. The head always
+/// starts with a utf-8 charset declaration followed by anything added through the
+/// builder methods, in the order they were added.
+///
+/// # Example Usage
+/// ```rust
+/// use zero::html;
+/// use zero::html::Page;
+///
+/// let page = Page::new()
+///     .title("Home")
+///     .stylesheet("/style.css")
+///     .body(html! { P(){ "hello" } });
+///
+/// assert!(page.render().starts_with("<!DOCTYPE html>"));
+/// ```
+#[derive(Debug)]
+pub struct Page<'a> {
+    head: HTML<'a>,
+    body: Markup<'a>,
+}
+
+impl<'a> Default for Page<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Page<'a> {
+    pub fn new() -> Self {
+        Page {
+            head: vec![Tag::new(TagType::META).set_attr("charset".into(), "utf-8".into())],
+            body: Markup::None,
+        }
+    }
+
+    pub fn title<T: Into<Text<'a>>>(mut self, title: T) -> Self {
+        let title = Markup::from(title.into());
+        self.head.push(Tag::new(TagType::TITLE).set_content(title));
+        self
+    }
+
+    pub fn meta<N: Into<Text<'a>>, C: Into<Text<'a>>>(mut self, name: N, content: C) -> Self {
+        self.head.push(
+            Tag::new(TagType::META)
+                .set_attr("name".into(), name.into())
+                .set_attr("content".into(), content.into()),
+        );
+        self
+    }
+
+    pub fn stylesheet<T: Into<Text<'a>>>(mut self, href: T) -> Self {
+        self.head.push(
+            Tag::new(TagType::LINK)
+                .set_attr("rel".into(), "stylesheet".into())
+                .set_attr("href".into(), href.into()),
+        );
+        self
+    }
+
+    pub fn script<T: Into<Text<'a>>>(mut self, src: T) -> Self {
+        self.head
+            .push(Tag::new(TagType::SCRIPT).set_attr("src".into(), src.into()));
+        self
+    }
+
+    pub fn body(mut self, body: Markup<'a>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Consumes the page into the `<html>` tag, without the doctype
+    pub fn into_markup(self) -> Markup<'a> {
+        let head = Tag::new(TagType::HEAD).set_content(Markup::Html(self.head));
+        let body = Tag::new(TagType::BODY).set_content(self.body);
+        Markup::Html(vec![
+            Tag::new(TagType::HTML).set_content(Markup::Html(vec![head, body])),
+        ])
+    }
+
+    /// Renders the full document, doctype included
+    pub fn render(self) -> String {
+        format!("<!DOCTYPE html>{}", self.into_markup())
     }
 }
 
@@ -67,6 +208,15 @@ impl<'a> std::fmt::Display for Markup<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Text(t) => t.fmt(f),
+            Self::Html(h) if f.alternate() => {
+                for (i, tag) in h.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    tag.fmt_pretty(f, 0)?;
+                }
+                Ok(())
+            }
             Self::Html(h) => {
                 for tag in h {
                     tag.fmt(f)?;
@@ -78,6 +228,41 @@ impl<'a> std::fmt::Display for Markup<'a> {
     }
 }
 
+impl<'a> Markup<'a> {
+    /// Joins markups in order, see `Add`
+    pub fn concat<I: IntoIterator<Item = Markup<'a>>>(markups: I) -> Self {
+        markups.into_iter().fold(Markup::None, |acc, m| acc + m)
+    }
+
+    fn into_tags(self) -> HTML<'a> {
+        match self {
+            Markup::Text(t) => vec![Tag::text(t)],
+            Markup::Html(h) => h,
+            Markup::None => Vec::new(),
+        }
+    }
+}
+
+/// Joins two markups, rendering `self` followed by `rhs`
+///
+/// `None` is dropped and two texts are joined into one text, anything else is
+/// flattened into a single `Markup::Html`.
+impl<'a> std::ops::Add for Markup<'a> {
+    type Output = Markup<'a>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        match (self, rhs) {
+            (Markup::None, m) | (m, Markup::None) => m,
+            (Markup::Text(a), Markup::Text(b)) => Markup::Text(Text::owned(format!("{}{}", a, b))),
+            (a, b) => {
+                let mut tags = a.into_tags();
+                tags.extend(b.into_tags());
+                Markup::Html(tags)
+            }
+        }
+    }
+}
+
 impl<'a> From<String> for Markup<'a> {
     fn from(value: String) -> Self {
         Markup::Text(Into::<Text<'a>>::into(value).to_escaped())
@@ -464,12 +649,94 @@ impl TagType {
     }
 }
 
+impl TagType {
+    /// Void elements have no content and no closing tag
+    pub const fn is_void(&self) -> bool {
+        matches!(
+            self,
+            TagType::AREA
+                | TagType::BASE
+                | TagType::BR
+                | TagType::COL
+                | TagType::EMBED
+                | TagType::HR
+                | TagType::IMG
+                | TagType::INPUT
+                | TagType::LINK
+                | TagType::META
+                | TagType::PARAM
+                | TagType::SOURCE
+                | TagType::TRACK
+                | TagType::WBR
+        )
+    }
+}
+
 impl From<TagType> for &'static str {
     fn from(value: TagType) -> Self {
         value.as_str()
     }
 }
 
+/// Markup rendered once and reused, for pages that rarely change.
+///
+/// `render` runs the first time the page is needed and again only after
+/// `invalidate`. Being `const`, it can live in a `static` shared by a route:
+///
+/// ```rust
+/// # use zero::html::{CachedMarkup, Markup};
+/// # use zero::http::routing::ResponseResult;
+/// fn render_home() -> Markup<'static> {
+///     zero::html! { P(){ "home" } }
+/// }
+/// static HOME: CachedMarkup = CachedMarkup::new(render_home);
+///
+/// async fn home() -> ResponseResult {
+///     Ok((&HOME).into())
+/// }
+/// ```
+pub struct CachedMarkup {
+    render: fn() -> Markup<'static>,
+    rendered: RwLock<Option<Arc<str>>>,
+}
+
+impl CachedMarkup {
+    pub const fn new(render: fn() -> Markup<'static>) -> Self {
+        CachedMarkup {
+            render,
+            rendered: RwLock::new(None),
+        }
+    }
+
+    /// Rendered markup, rendering it first if needed
+    pub fn get(&self) -> Arc<str> {
+        let cached = match self.rendered.read() {
+            Ok(rendered) => rendered.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        if let Some(rendered) = cached {
+            return rendered;
+        }
+
+        let mut rendered = match self.rendered.write() {
+            Ok(rendered) => rendered,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // another thread may have rendered it while we waited for the lock
+        rendered
+            .get_or_insert_with(|| (self.render)().to_string().into())
+            .clone()
+    }
+
+    /// Drops the rendered markup, so the next `get` renders it again
+    pub fn invalidate(&self) {
+        match self.rendered.write() {
+            Ok(mut rendered) => *rendered = None,
+            Err(poisoned) => *poisoned.into_inner() = None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -477,9 +744,100 @@ mod tests {
     #[test]
     fn test_macro() {
         Tag {
-            ty: TagType::P,
+            ty: Some(TagType::P),
             attrs: HashMap::new(),
             content: Markup::None,
         };
     }
+
+    #[test]
+    fn test_page() {
+        let page = Page::new()
+            .title("Home & Away")
+            .stylesheet("/style.css")
+            .body(vec![Tag::new(TagType::P).set_content("hello".into())].into());
+
+        assert_eq!(
+            page.render(),
+            concat!(
+                "<!DOCTYPE html>",
+                "<html><head>",
+                "<meta charset=\"utf-8\">",
+                "<title>Home &amp; Away</title>",
+                "<link href=\"/style.css\" rel=\"stylesheet\">",
+                "</head><body><p>hello</p></body></html>",
+            )
+        );
+    }
+
+    fn nested<'a>() -> Markup<'a> {
+        let list = Tag::new(TagType::UL).set_content(
+            vec![
+                Tag::new(TagType::LI).set_content("one".into()),
+                Tag::new(TagType::LI).set_content("two".into()),
+            ]
+            .into(),
+        );
+        let div = Tag::new(TagType::DIV)
+            .set_attr("class".into(), "list".into())
+            .set_content(vec![Tag::new(TagType::BR), list, Tag::new(TagType::DIV)].into());
+        vec![div, Tag::new(TagType::P)].into()
+    }
+
+    #[test]
+    fn test_compact_display() {
+        assert_eq!(
+            nested().to_string(),
+            concat!(
+                "<div class=\"list\"><br><ul><li>one</li><li>two</li></ul><div></div></div>",
+                "<p></p>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_pretty_display() {
+        assert_eq!(
+            format!("{:#}", nested()),
+            concat!(
+                "<div class=\"list\">\n",
+                "  <br>\n",
+                "  <ul>\n",
+                "    <li>one</li>\n",
+                "    <li>two</li>\n",
+                "  </ul>\n",
+                "  <div></div>\n",
+                "</div>\n",
+                "<p></p>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_markup_concat() {
+        let text: Markup = "a < b".into();
+        let html: Markup = vec![Tag::new(TagType::P).set_content("para".into())].into();
+        let joined = text + html + Markup::None;
+        assert!(matches!(&joined, Markup::Html(tags) if tags.len() == 2));
+        assert_eq!(joined.to_string(), "a &lt; b<p>para</p>");
+
+        let joined = Markup::concat([
+            Markup::None,
+            "one ".into(),
+            "two".into(),
+            vec![Tag::new(TagType::BR)].into(),
+        ]);
+        assert_eq!(joined.to_string(), "one two<br>");
+        assert_eq!(format!("{:#}", joined), "one two\n<br>");
+    }
+
+    #[test]
+    fn test_empty_html_macro() {
+        let m: Markup = crate::html! {};
+        assert!(matches!(m, Markup::None));
+        assert_eq!(m.to_string(), "");
+
+        let p: Markup = crate::html! { P(){} };
+        assert_eq!(p.to_string(), "<p></p>");
+    }
 }
@@ -1,3 +1,5 @@
+pub mod cors;
+pub mod rate_limit;
 pub mod request;
 pub mod response;
 pub mod routing;
@@ -5,19 +7,40 @@ pub mod server;
 pub mod uri;
 
 use crate::http::routing::ToQuery;
-use crate::http::uri::RequestQuery;
+use crate::http::uri::Params;
 use crate::parsing::StrParser;
 use crate::parsing::prelude::*;
 use crate::serializer::DataHolder;
-use crate::serializer::Deserialize;
+use crate::serializer::{Deserialize, Json};
 use crate::stream_writer::prelude::*;
-use request::RequestBody;
+use request::{BodyStream, RequestBody};
 pub use routing::Query;
 use std::collections::HashMap;
 use std::io::{Read, Write};
 
 pub struct Body<T: ToBody>(pub T);
 
+/// Either kind of HTTP message, for when it isn't known up front which one arrives.
+///
+/// Responses are told apart by their status line starting with `HTTP/`, everything
+/// else is parsed as a request.
+#[derive(Debug)]
+pub enum HttpMessage {
+    Request(request::Request),
+    Response(response::Response),
+}
+
+impl<R: Read> Parsable<R> for HttpMessage {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        // `HEAD` is the only method starting with the same letter as `HTTP/`
+        if parser.peek() == Some(b'H') && parser.peek_second() == Some(b'T') {
+            Ok(HttpMessage::Response(response::Response::parse(parser)?))
+        } else {
+            Ok(HttpMessage::Request(request::Request::parse(parser)?))
+        }
+    }
+}
+
 // impl std::fmt::Display for Body<String> {
 //     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 //         write!(f, "{}", self.0)
@@ -28,6 +51,40 @@ pub trait ToBody: Sized {
     fn into_body(body: RequestBody) -> Result<Body<Self>, ()>;
 }
 
+impl ToBody for RequestBody {
+    fn into_body(body: RequestBody) -> Result<Body<Self>, ()> {
+        Ok(Body(body))
+    }
+}
+
+/// The body as a reader, streamed off the connection on `Router::route_streaming` routes
+/// and over the already read bytes everywhere else. Form data has no raw form to read.
+impl ToBody for BodyStream {
+    fn into_body(body: RequestBody) -> Result<Body<Self>, ()> {
+        match body {
+            RequestBody::Stream(stream) => Ok(Body(stream)),
+            RequestBody::Plain(s) => Ok(Body(BodyStream::from_bytes(s.into_bytes()))),
+            RequestBody::Bytes(bytes) => Ok(Body(BodyStream::from_bytes(bytes))),
+            RequestBody::Empty => Ok(Body(BodyStream::from_bytes(Vec::new()))),
+            RequestBody::FormData(_) => Err(()),
+        }
+    }
+}
+
+/// Parses a plain text body as JSON, e.g. `Body<Json<Vec<T>>>` for a top-level array.
+impl<T: Deserialize> ToBody for Json<T> {
+    fn into_body(body: RequestBody) -> Result<Body<Self>, ()> {
+        let body = match body {
+            RequestBody::Plain(body) => body,
+            RequestBody::Bytes(bytes) => String::from_utf8(bytes).map_err(|_| ())?,
+            _ => return Err(()),
+        };
+        Json::parse(&mut StrParser::from_str(&body))
+            .map(Body)
+            .map_err(|_| ())
+    }
+}
+
 // impl<T: Deserialize> ToBody for T {
 //     fn into_body(body: RequestBody) -> Result<Body<Self>, ()> {
 //         let mut parser = StrParser::from_str(&body);
@@ -39,17 +96,6 @@ pub trait ToBody: Sized {
 //     }
 // }
 
-// TODO
-// impl<T: Deserialize> ToQuery for T {
-//     fn into_query(body: String) -> Result<Query<Self>, ()> {
-//         let s = query.parameters;
-//         match T::deserialize(s) {
-//             Ok(t) => Ok(Query(t)),
-//             Err(_) => Err(()),
-//         }
-//     }
-// }
-
 /// Based on rfc2616 Section 4.2
 ///
 /// # Augmented Backus-Naur Form
@@ -72,9 +118,60 @@ impl MessageHeader {
         self.name.as_str()
     }
 
+    pub fn value(&self) -> &str {
+        self.value.as_str()
+    }
+
     pub fn extract_name_val(self) -> (String, String) {
         (self.name, self.value)
     }
+
+    /// Elements of a comma separated value, see `split_list`
+    pub fn values(&self) -> Vec<String> {
+        Self::split_list(&self.value)
+    }
+
+    /// Splits a `#rule` list value, e.g. of `Accept`, `Cache-Control` or `Vary`, into
+    /// its elements.
+    ///
+    /// Commas inside quoted-strings don't split, and the whitespace around elements
+    /// is trimmed. Empty elements are dropped, as rfc2616 Section 2.1 allows them.
+    /// Quoted-strings are kept quoted, with only `"` and `\` escaped.
+    pub fn split_list(value: &str) -> Vec<String> {
+        let mut parser = StrParser::from_str(value);
+        let mut elements = Vec::new();
+        let mut element = String::new();
+        let mut push = |element: &mut String| {
+            let trimmed = element.trim_matches(|c| c == ' ' || c == '\t');
+            if !trimmed.is_empty() {
+                elements.push(trimmed.to_string());
+            }
+            element.clear();
+        };
+        loop {
+            match parser.peek() {
+                None => break,
+                Some(b',') => {
+                    parser.consume();
+                    push(&mut element);
+                }
+                Some(b'"') => {
+                    element.push('"');
+                    for c in parser.consume_str_lit().chars() {
+                        if c == '"' || c == '\\' {
+                            element.push('\\');
+                        }
+                        element.push(c);
+                    }
+                    element.push('"');
+                }
+                Some(_) => element
+                    .push_str(&parser.consume_while(|p| p.matches(|c| c != b',' && c != b'"'))),
+            }
+        }
+        push(&mut element);
+        elements
+    }
 }
 
 impl MessageHeader {
@@ -91,34 +188,32 @@ impl<R: Read> Parsable<R> for MessageHeader {
             return Err(ParseErr::BlankHeaderFieldName);
         }
         parser.skip_whitespace();
-        parser.consume_or_err(|c| c == b':')?;
-        parser.skip_whitespace();
-        let mut parts = String::new();
-        parts.push_str(
-            parser
-                .consume_while(|p| !p.matches(|c| c == b'\r'))
-                .as_str(),
-        );
-        parser.consume();
+        parser.consume_expect(b':', "header name separator")?;
+        parser.skip_linear_whitespace_and_folds();
 
-        while let Some(c) = parser.peek()
-            && c == b'\n'
-        {
-            parser.consume();
-            if parser.is_linear_whitespace() {
-                parser.skip_whitespace();
-                parts.push_str(
-                    parser
-                        .consume_while(|p| !p.matches(|c| c == b'\r'))
-                        .as_str(),
-                );
-                parser.consume();
+        // folds and the whitespace around them become a single space
+        let mut value = String::new();
+        loop {
+            if parser.peek_is_fold() {
+                let trimmed = value.trim_end_matches([' ', '\t']).len();
+                value.truncate(trimmed);
+                parser.skip_linear_whitespace_and_folds();
+                if !value.is_empty() && !parser.peek_is_crlf() {
+                    value.push(' ');
+                }
+            } else if parser.peek_is_crlf() {
+                parser.consume_line();
+                break;
+            } else if let Some(c) = parser.consume() {
+                value.push(c as char);
             } else {
                 break;
             }
         }
+        let trimmed = value.trim_end_matches([' ', '\t']).len();
+        value.truncate(trimmed);
 
-        Ok(MessageHeader { name, value: parts })
+        Ok(MessageHeader { name, value })
     }
 }
 impl<W: std::io::Write> StreamWritable<W> for MessageHeader {
@@ -128,21 +223,377 @@ impl<W: std::io::Write> StreamWritable<W> for MessageHeader {
         Ok(())
     }
 }
+/// Typed header block, keyed by lowercase header name.
+///
+/// Repeated headers keep every value in the order they were received.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Headers<T> {
+    entries: HashMap<String, Vec<T>>,
+}
+
+impl<T> Default for Headers<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Headers<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: String, header: T) {
+        self.entries.entry(name).or_default().push(header);
+    }
+
+    /// First value received for `name`
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.entries.get(name).and_then(|h| h.first())
+    }
+
+    /// Every value received for `name`
+    pub fn get_all(&self, name: &str) -> &[T] {
+        self.entries.get(name).map_or(&[], |h| h.as_slice())
+    }
+
+    /// Number of distinct header names
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.entries
+            .iter()
+            .flat_map(|(name, h)| h.iter().map(move |h| (name, h)))
+    }
+}
+
+impl<T> IntoIterator for Headers<T> {
+    type Item = (String, T);
+    type IntoIter = std::vec::IntoIter<(String, T)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries
+            .into_iter()
+            .flat_map(|(name, h)| h.into_iter().map(move |h| (name.clone(), h)))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl<T: FromMessageHeader> Headers<T> {
+    /// Reads header lines up to and including the blank line ending the block.
+    ///
+    /// Unlike looping on `MessageHeader::parse` until it fails, a malformed header or a
+    /// stream that ends before the blank line is an error.
+    pub fn parse_block<R: Read>(parser: &mut Parser<R>) -> ParseResult<Self> {
+        Self::parse_block_limited(parser, usize::MAX)
+    }
+
+    /// Same as `parse_block`, but errors with `ParseErr::TooManyHeaders` as soon as the
+    /// block has more than `max` header fields.
+    pub fn parse_block_limited<R: Read>(parser: &mut Parser<R>, max: usize) -> ParseResult<Self> {
+        let mut headers = Self::new();
+        let mut count = 0_usize;
+        while !parser.peek_is_crlf() {
+            if parser.peek().is_none() {
+                return Err(parser.eof_err());
+            }
+            if count == max {
+                return Err(ParseErr::TooManyHeaders { max });
+            }
+            let (name, header) = MessageHeader::parse(parser)?.into_header()?;
+            headers.insert(name, header);
+            count += 1;
+        }
+        parser.consume_line();
+
+        Ok(headers)
+    }
+}
+
+impl<R: Read, T: FromMessageHeader> Parsable<R> for Headers<T> {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        Self::parse_block(parser)
+    }
+}
+
+/// Based on rfc2616 Section 3.9
+///
+/// Held as thousandths so it can be compared exactly, e.g. `q=0.5` is `QValue(500)`.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// qvalue         = ( "0" [ "." 0*3DIGIT ] )
+///                | ( "1" [ "." 0*3("0") ] )
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct QValue(u16);
+
+impl QValue {
+    pub const MAX: QValue = QValue(1000);
+
+    pub fn thousandths(&self) -> u16 {
+        self.0
+    }
+
+    /// Parses the `;`-separated parameters following a list item, e.g. in `Accept` or
+    /// `TE`, returning its `q` parameter, `MAX` when it has none. Other parameters are
+    /// skipped.
+    pub fn parse_params<R: Read>(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let mut q = QValue::MAX;
+        parser.skip_whitespace();
+        if !parser.matches(|c| c == b';') {
+            return Ok(q);
+        }
+        parser.consume();
+        for (key, val) in Params::HEADER.parse_pairs(parser)? {
+            if key.eq_ignore_ascii_case("q") {
+                let mut val_parser = StrParser::from_str(&val);
+                q = QValue::parse(&mut val_parser)?;
+                if val_parser.peek().is_some() {
+                    return Err(ParseErr::InvalidQValue { found: val.clone() });
+                }
+            }
+        }
+        Ok(q)
+    }
+}
+
+impl Default for QValue {
+    fn default() -> Self {
+        Self::MAX
+    }
+}
+
+impl std::fmt::Display for QValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == 1000 {
+            write!(f, "1")
+        } else {
+            let decimals = format!("{:03}", self.0);
+            write!(f, "0.{}", decimals.trim_end_matches('0'))
+        }
+    }
+}
+
+impl<R: Read> Parsable<R> for QValue {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let whole = parser.consume_or_err(|c| c == b'0' || c == b'1')?;
+        let mut decimals = String::new();
+        if parser.matches(|c| c == b'.') {
+            parser.consume();
+            decimals = parser.consume_while(|p| p.is_digit());
+        }
+
+        let invalid = || ParseErr::InvalidQValue {
+            found: format!("{}.{}", whole as char, decimals),
+        };
+        if decimals.len() > 3 {
+            return Err(invalid());
+        }
+        let fraction = format!("{:0<3}", decimals);
+        let fraction = u16::from_str_radix(fraction.as_str(), 10).map_err(|_| invalid())?;
+        let value = (whole - b'0') as u16 * 1000 + fraction;
+        if value > 1000 {
+            return Err(invalid());
+        }
+
+        Ok(QValue(value))
+    }
+}
+
+/// Based on rfc2616 Section 3.7
+///
+/// Type, subtype and parameter names are lowercased, parameter values are kept as
+/// sent since some, like `boundary`, are case-sensitive.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// media-type     = type "/" subtype *( ";" parameter )
+/// parameter      = attribute "=" value
+/// value          = token | quoted-string
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MediaType {
+    pub ty: String,
+    pub subtype: String,
+    params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Value of the parameter called `name`, matched case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val.as_str())
+    }
+
+    /// Label of the `charset` parameter, see `Charset::from_media_type`.
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    /// Delimiter between the parts of a `multipart/*` body.
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary")
+    }
+
+    /// Whether bodies of this type are text to decode with `charset`, i.e. `text/*`
+    /// and url encoded forms.
+    pub fn is_text(&self) -> bool {
+        self.ty == "text" || (self.ty == "application" && self.subtype == "x-www-form-urlencoded")
+    }
+}
+
+impl<R: Read> Parsable<R> for MediaType {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        parser.skip_whitespace();
+        let ty = parser.consume_while_lower(|p| p.is_token_char());
+        parser.consume_expect(b'/', "media subtype")?;
+        let subtype = parser.consume_while_lower(|p| p.is_token_char());
+        if ty.is_empty() || subtype.is_empty() {
+            return Err(ParseErr::InvalidMediaType {
+                found: format!("{}/{}", ty, subtype),
+            });
+        }
+
+        parser.skip_whitespace();
+        let params = if parser.matches(|c| c == b';') {
+            parser.consume();
+            Params::HEADER
+                .parse_pairs(parser)?
+                .into_iter()
+                .map(|(key, val)| (key.to_ascii_lowercase(), val))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(MediaType {
+            ty,
+            subtype,
+            params,
+        })
+    }
+}
+
+/// Character sets request bodies can be decoded from.
+///
+/// Based on rfc2616 Section 3.4. Labels are matched case-insensitively and a
+/// missing charset defaults to UTF-8.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Charset {
+    Utf8,
+    Iso8859_1,
+    Windows1252,
+}
+
+impl Charset {
+    /// Code points for bytes `0x80..=0x9F` in windows-1252. Unassigned bytes map
+    /// to the matching C1 control, same as ISO-8859-1.
+    const WINDOWS_1252_HIGH: [char; 32] = [
+        '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}',
+        '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}',
+        '\u{2014}', '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}',
+        '\u{178}',
+    ];
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().trim_matches('"').to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Self::Utf8),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "l1" | "us-ascii" => Some(Self::Iso8859_1),
+            "windows-1252" | "cp1252" => Some(Self::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Picks the charset from the `charset` parameter of a `Content-Type` value.
+    pub fn from_content_type(content_type: &str) -> ParseResult<Self> {
+        match MediaType::parse(&mut StrParser::from_str(content_type)) {
+            Ok(media_type) => Self::from_media_type(&media_type),
+            Err(_) => Ok(Self::Utf8),
+        }
+    }
+
+    pub fn from_media_type(media_type: &MediaType) -> ParseResult<Self> {
+        match media_type.charset() {
+            Some(label) => Self::from_label(label).ok_or(ParseErr::UnsupportedCharset {
+                found: label.trim().to_string(),
+            }),
+            None => Ok(Self::Utf8),
+        }
+    }
+
+    pub fn decode(&self, bytes: Vec<u8>) -> ParseResult<String> {
+        match self {
+            Self::Utf8 => String::from_utf8(bytes).map_err(|_| ParseErr::InvalidUTF8),
+            Self::Iso8859_1 => Ok(bytes.into_iter().map(|b| b as char).collect()),
+            Self::Windows1252 => Ok(bytes
+                .into_iter()
+                .map(|b| match b {
+                    0x80..=0x9F => Self::WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect()),
+        }
+    }
+}
+
 /// Based on rfc2616 Section 3.1
 ///
 /// # Augmented Backus-Naur Form
 /// ```text
 /// HTTP-Version   = "HTTP" "/" 1*DIGIT "." 1*DIGIT
 /// ```
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct HTTPVersion {
     major: u8,
     minor: u8,
 }
 
+impl HTTPVersion {
+    pub const HTTP_10: HTTPVersion = HTTPVersion::new(1, 0);
+    pub const HTTP_11: HTTPVersion = HTTPVersion::new(1, 1);
+    pub const HTTP_2: HTTPVersion = HTTPVersion::new(2, 0);
+
+    pub const fn new(major: u8, minor: u8) -> Self {
+        HTTPVersion { major, minor }
+    }
+
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.minor
+    }
+
+    pub fn is_http_10(&self) -> bool {
+        *self == Self::HTTP_10
+    }
+
+    pub fn is_http_11(&self) -> bool {
+        *self == Self::HTTP_11
+    }
+
+    /// Whether this is `major.minor` or any later version
+    pub fn at_least(&self, major: u8, minor: u8) -> bool {
+        *self >= Self::new(major, minor)
+    }
+}
+
 impl Default for HTTPVersion {
     fn default() -> Self {
-        HTTPVersion { major: 1, minor: 1 }
+        Self::HTTP_11
     }
 }
 
@@ -156,7 +607,7 @@ impl<R: Read> Parsable<R> for HTTPVersion {
                 found: major_str,
                 radix: 10,
             })?;
-        parser.consume_or_err(|c| c == b'.')?;
+        parser.consume_expect(b'.', "version separator")?;
         let minor_str = parser.consume_while(|p| p.is_digit());
         let minor = if minor_str.is_empty() {
             0
@@ -192,7 +643,7 @@ pub enum EntityHeader {
     ContentLength(usize),                   // Section 14.13
     ContentLocation(String),                // Section 14.14
     ContentMD5(String),                     // Section 14.15
-    ContentRange(String),                   // Section 14.16
+    ContentRange(ContentRange),             // Section 14.16
     ContentType(String),                    // Section 14.17
     Expires(String),                        // Section 14.21
     LastModified(String),                   // Section 14.29
@@ -219,18 +670,7 @@ impl EntityHeader {
 
 impl FromMessageHeader for EntityHeader {
     fn can_convert(eh: &MessageHeader) -> bool {
-        let name = eh.name.as_str();
-        name == "allow"
-            || name == "content-encoding"
-            || name == "content-language"
-            || name == "content-length"
-            || name == "content-location"
-            || name == "content-md5"
-            || name == "content-range"
-            || name == "content-type"
-            || name == "expires"
-            || name == "last-modified"
-            || name == "content-disposition"
+        HeaderCategory::of(&eh.name) == HeaderCategory::Entity
     }
     fn from_extension_header(eh: MessageHeader) -> ParseResult<(String, Self)> {
         let val = eh.value;
@@ -250,7 +690,10 @@ impl FromMessageHeader for EntityHeader {
             }
             "content-location" => Self::ContentLocation(val),
             "content-md5" => Self::ContentMD5(val),
-            "content-range" => Self::ContentRange(val),
+            "content-range" => {
+                let mut s_parser = StrParser::from_str(&val);
+                Self::ContentRange(ContentRange::parse(&mut s_parser)?)
+            }
             "content-type" => Self::ContentType(val),
             "expires" => Self::Expires(val),
             "last-modified" => Self::LastModified(val),
@@ -276,7 +719,7 @@ impl ToMessageHeader for EntityHeader {
             EntityHeader::ContentLength(n) => n.to_string(),      // Section 14.13
             EntityHeader::ContentLocation(s) => s,                // Section 14.14
             EntityHeader::ContentMD5(s) => s,                     // Section 14.15
-            EntityHeader::ContentRange(s) => s,                   // Section 14.16
+            EntityHeader::ContentRange(s) => s.to_string(),       // Section 14.16
             EntityHeader::ContentType(s) => s,                    // Section 14.17
             EntityHeader::Expires(s) => s,                        // Section 14.21
             EntityHeader::LastModified(s) => s,                   // Section 14.29
@@ -291,6 +734,60 @@ impl ToMessageHeader for EntityHeader {
     }
 }
 
+/// Header group of rfc2616 Section 4.5, 5.3, 6.2 and 7.1 a field name belongs to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeaderCategory {
+    General,
+    Request,
+    Response,
+    Entity,
+    Extension,
+}
+
+impl HeaderCategory {
+    /// Classifies a lowercase field name with a single match, instead of trying each
+    /// group's names in turn. Unknown names are `Extension`.
+    pub fn of(name: &str) -> Self {
+        match name {
+            "cache-control" | "connection" | "date" | "pragma" | "trailer"
+            | "transfer-encoding" | "upgrade" | "via" | "warning" => Self::General,
+            "accept"
+            | "accept-charset"
+            | "accept-encoding"
+            | "accept-language"
+            | "authorization"
+            | "expect"
+            | "from"
+            | "host"
+            | "if-match"
+            | "if-modified-since"
+            | "if-none-match"
+            | "if-range"
+            | "if-unmodified-since"
+            | "max-forwards"
+            | "proxy-authorization"
+            | "range"
+            | "referer"
+            | "te"
+            | "user-agent" => Self::Request,
+            "accept-ranges" | "age" | "etag" | "location" | "proxy-authenticate"
+            | "retry-after" | "server" | "vary" | "www-authenticate" => Self::Response,
+            "allow"
+            | "content-encoding"
+            | "content-language"
+            | "content-length"
+            | "content-location"
+            | "content-md5"
+            | "content-range"
+            | "content-type"
+            | "expires"
+            | "last-modified"
+            | "content-disposition" => Self::Entity,
+            _ => Self::Extension,
+        }
+    }
+}
+
 pub trait FromMessageHeader: Sized {
     fn can_convert(eh: &MessageHeader) -> bool;
     fn from_extension_header(eh: MessageHeader) -> ParseResult<(String, Self)>;
@@ -309,7 +806,7 @@ pub enum GeneralHeader {
     Trailer(String),          // Section 14.40
     TransferEncoding(String), // Section 14.41
     Upgrade(String),          // Section 14.42
-    Via(String),              // Section 14.45
+    Via(Via),                 // Section 14.45
     Warning(String),          // Section 14.46
 }
 
@@ -331,16 +828,7 @@ impl GeneralHeader {
 
 impl FromMessageHeader for GeneralHeader {
     fn can_convert(eh: &MessageHeader) -> bool {
-        let name = eh.name.as_str();
-        name == "cache-control"
-            || name == "connection"
-            || name == "date"
-            || name == "pragma"
-            || name == "trailer"
-            || name == "transfer-encoding"
-            || name == "upgrade"
-            || name == "via"
-            || name == "warning"
+        HeaderCategory::of(&eh.name) == HeaderCategory::General
     }
 
     fn from_extension_header(eh: MessageHeader) -> ParseResult<(String, Self)> {
@@ -354,7 +842,7 @@ impl FromMessageHeader for GeneralHeader {
             "trailer" => Self::Trailer(val),
             "transfer-encoding" => Self::TransferEncoding(val),
             "upgrade" => Self::Upgrade(val),
-            "via" => Self::Via(val),
+            "via" => Self::Via(Via::parse(&mut StrParser::from_str(&val))?),
             "warning" => Self::Warning(val),
             _ => unreachable!(
                 "Failed to convert extension header. Perhaps can_convert was not checked"
@@ -368,15 +856,15 @@ impl FromMessageHeader for GeneralHeader {
 impl ToMessageHeader for GeneralHeader {
     fn consume_value_as_string(self) -> String {
         match self {
-            GeneralHeader::CacheControl(s) => s,     // Section 14.9
-            GeneralHeader::Connection(s) => s,       // Section 14.10
-            GeneralHeader::Date(s) => s,             // Section 14.18
-            GeneralHeader::Pragma(s) => s,           // Section 14.32
-            GeneralHeader::Trailer(s) => s,          // Section 14.40
-            GeneralHeader::TransferEncoding(s) => s, // Section 14.41
-            GeneralHeader::Upgrade(s) => s,          // Section 14.42
-            GeneralHeader::Via(s) => s,              // Section 14.45
-            GeneralHeader::Warning(s) => s,          // Section 14.46
+            GeneralHeader::CacheControl(s) => s,        // Section 14.9
+            GeneralHeader::Connection(s) => s,          // Section 14.10
+            GeneralHeader::Date(s) => s,                // Section 14.18
+            GeneralHeader::Pragma(s) => s,              // Section 14.32
+            GeneralHeader::Trailer(s) => s,             // Section 14.40
+            GeneralHeader::TransferEncoding(s) => s,    // Section 14.41
+            GeneralHeader::Upgrade(s) => s,             // Section 14.42
+            GeneralHeader::Via(via) => via.to_string(), // Section 14.45
+            GeneralHeader::Warning(s) => s,             // Section 14.46
         }
     }
     fn to_msg_header(self) -> MessageHeader {
@@ -387,6 +875,192 @@ impl ToMessageHeader for GeneralHeader {
     }
 }
 
+/// One proxy or gateway a message passed through, see `Via`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct ViaHop {
+    /// Omitted by the sender when it is `HTTP`
+    pub protocol: Option<String>,
+    pub version: String,
+    /// `host [":" port]` or a pseudonym
+    pub received_by: String,
+    pub comment: Option<String>,
+}
+
+impl std::fmt::Display for ViaHop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(protocol) = &self.protocol {
+            write!(f, "{}/", protocol)?;
+        }
+        write!(f, "{} {}", self.version, self.received_by)?;
+        if let Some(comment) = &self.comment {
+            write!(f, " ({})", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Based on rfc2616 Section 14.45
+///
+/// Hops are kept in the order they were added, oldest first. A header without any hop,
+/// or with anything but a comment after a hop's `received-by`, fails to parse.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// Via =  "Via" ":" 1#( received-protocol received-by [ comment ] )
+/// received-protocol = [ protocol-name "/" ] protocol-version
+/// protocol-name     = token
+/// protocol-version  = token
+/// received-by       = ( host [ ":" port ] ) | pseudonym
+/// pseudonym         = token
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct Via {
+    hops: Vec<ViaHop>,
+}
+
+impl Via {
+    pub fn hops(&self) -> &[ViaHop] {
+        &self.hops
+    }
+
+    /// Appends this server as the latest hop, for a message received over `version`.
+    pub fn push_hop(&mut self, version: HTTPVersion, received_by: impl Into<String>) {
+        self.hops.push(ViaHop {
+            protocol: None,
+            version: format!("{}.{}", version.major(), version.minor()),
+            received_by: received_by.into(),
+            comment: None,
+        });
+    }
+}
+
+impl std::fmt::Display for Via {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, hop) in self.hops.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", hop)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Parsable<R> for Via {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let mut via = Via::default();
+        loop {
+            while parser.is_linear_whitespace() || parser.matches(|c| c == b',') {
+                parser.consume();
+            }
+            if parser.peek().is_none() {
+                break;
+            }
+            let first = parser.consume_while(|p| p.is_token_char());
+            let (protocol, version) = if parser.matches(|c| c == b'/') {
+                parser.consume();
+                (Some(first), parser.consume_while(|p| p.is_token_char()))
+            } else {
+                (None, first)
+            };
+            if version.is_empty() || protocol.as_ref().is_some_and(|p| p.is_empty()) {
+                return Err(ParseErr::FailedToConsume {
+                    found: parser.peek(),
+                });
+            }
+            parser.skip_whitespace();
+            let received_by = parser.consume_while(|p| {
+                p.peek().is_some() && !p.is_linear_whitespace() && !p.matches(|c| c == b',')
+            });
+            if received_by.is_empty() {
+                return Err(ParseErr::FailedToConsume {
+                    found: parser.peek(),
+                });
+            }
+            parser.skip_whitespace();
+            let comment = if parser.matches(|c| c == b'(') {
+                parser.consume();
+                let comment = parser.consume_while(|p| p.peek().is_some_and(|c| c != b')'));
+                parser.consume_expect(b')', "end of comment")?;
+                Some(comment)
+            } else {
+                None
+            };
+            parser.skip_whitespace();
+            if !parser.matches(|c| c == b',') && parser.peek().is_some() {
+                return Err(ParseErr::FailedToConsume {
+                    found: parser.peek(),
+                });
+            }
+
+            via.hops.push(ViaHop {
+                protocol,
+                version,
+                received_by,
+                comment,
+            });
+        }
+
+        if via.hops.is_empty() {
+            return Err(ParseErr::FailedToConsume { found: None });
+        }
+        Ok(via)
+    }
+}
+
+/// Based on rfc2616 Section 14.32
+///
+/// HTTP/1.0 clients send `Pragma: no-cache` where HTTP/1.1 ones send
+/// `Cache-Control: no-cache`. Directives are kept as sent.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// Pragma            = "Pragma" ":" 1#pragma-directive
+/// pragma-directive  = "no-cache" | extension-pragma
+/// extension-pragma  = token [ "=" ( token | quoted-string ) ]
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Pragma {
+    directives: Vec<String>,
+}
+
+impl Pragma {
+    pub fn directives(&self) -> &[String] {
+        &self.directives
+    }
+
+    pub fn is_no_cache(&self) -> bool {
+        self.directives
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case("no-cache"))
+    }
+}
+
+impl std::fmt::Display for Pragma {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.directives.join(", "))
+    }
+}
+
+impl<R: Read> Parsable<R> for Pragma {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let mut pragma = Pragma::default();
+        loop {
+            while parser.is_linear_whitespace() || parser.matches(|c| c == b',') {
+                parser.consume();
+            }
+            let directive = parser.consume_while(|p| p.peek().is_some_and(|c| c != b','));
+            let directive = directive.trim_end();
+            if directive.is_empty() {
+                break;
+            }
+            pragma.directives.push(directive.to_string());
+        }
+
+        Ok(pragma)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum DispositionType {
     Inline,
@@ -440,21 +1114,439 @@ impl std::fmt::Display for ContentDisposition {
 impl<R: Read> Parsable<R> for ContentDisposition {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         let ty = DispositionType::parse(parser)?;
-        let mut params = HashMap::new();
+        parser.skip_whitespace();
+        let params = if parser.matches(|b| b == b';') {
+            parser.consume();
+            Params::HEADER.parse(parser)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self { ty, params })
+    }
+}
+
+/// Based on rfc2616 Section 14.16
+///
+/// Only the `bytes` unit with a satisfied range is supported.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// Content-Range = "Content-Range" ":" content-range-spec
+/// content-range-spec      = byte-content-range-spec
+/// byte-content-range-spec = bytes-unit SP
+///                           byte-range-resp-spec "/"
+///                           ( instance-length | "*" )
+/// byte-range-resp-spec = (first-byte-pos "-" last-byte-pos)
+///                                | "*"
+/// instance-length           = 1*DIGIT
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ContentRange {
+    pub start: u64,
+    pub end: u64,
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    pub fn new(start: u64, end: u64, total: Option<u64>) -> ParseResult<Self> {
+        if start > end || total.is_some_and(|total| end >= total) {
+            return Err(ParseErr::InvalidContentRange { start, end, total });
+        }
+        Ok(Self { start, end, total })
+    }
+
+    fn parse_num<R: Read>(parser: &mut Parser<R>) -> ParseResult<u64> {
+        let num = parser.consume_while(|p| p.is_digit());
+        u64::from_str_radix(num.as_str(), 10).map_err(|_| ParseErr::FailedToParseNum {
+            found: num,
+            radix: 10,
+        })
+    }
+}
 
-        while parser.matches(|b| b == b';') {
+impl std::fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bytes {}-{}/", self.start, self.end)?;
+        match self.total {
+            Some(total) => write!(f, "{}", total),
+            None => write!(f, "*"),
+        }
+    }
+}
+
+impl<R: Read> Parsable<R> for ContentRange {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        parser.expect_str("bytes")?;
+        parser.consume_expect(b' ', "range unit separator")?;
+        parser.skip_whitespace();
+        let start = Self::parse_num(parser)?;
+        parser.consume_expect(b'-', "range separator")?;
+        let end = Self::parse_num(parser)?;
+        parser.consume_expect(b'/', "complete length separator")?;
+        let total = if parser.matches(|c| c == b'*') {
             parser.consume();
-            let key = parser.consume_while(|p| p.is_token_char());
-            parser.expect_str("=");
-            let val = if parser.matches(|b| b == b'"') {
-                parser.consume_str_lit()
-            } else {
-                parser.consume_while(|p| p.is_token_char())
-            };
+            None
+        } else {
+            Some(Self::parse_num(parser)?)
+        };
+
+        Self::new(start, end, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_missing_colon() {
+        let mut parser = StrParser::from_str("Host localhost\r\n");
+        assert_eq!(
+            MessageHeader::parse(&mut parser),
+            Err(ParseErr::Expected {
+                expected: vec![b':'],
+                label: "header name separator",
+                found: Some(b'l'),
+                at: 5,
+            })
+        );
+
+        let mut parser = StrParser::from_str("x");
+        assert_eq!(
+            parser.expect_one_of(b"-/"),
+            Err(ParseErr::Expected {
+                expected: b"-/".to_vec(),
+                label: "one of",
+                found: Some(b'x'),
+                at: 0,
+            })
+        );
+        let mut parser = StrParser::from_str("/");
+        assert_eq!(parser.expect_one_of(b"-/"), Ok(b'/'));
+    }
+
+    #[test]
+    fn test_folded_header_value() {
+        let mut parser =
+            StrParser::from_str("X-Long: first part  \r\n   second part\r\nHost: a\r\n");
+        let header = MessageHeader::parse(&mut parser).unwrap();
+        assert_eq!(header.value(), "first part second part");
+        assert_eq!(MessageHeader::parse(&mut parser).unwrap().value(), "a");
 
-            params.insert(key, val);
+        let mut parser = StrParser::from_str("X-Empty:\r\n\tvalue\n\t\r\n");
+        assert_eq!(MessageHeader::parse(&mut parser).unwrap().value(), "value");
+        assert_eq!(parser.peek(), None);
+    }
+
+    #[test]
+    fn test_http_message() {
+        let mut parser = StrParser::from_str("HEAD /index.html HTTP/1.1\r\nHost: zero\r\n\r\n");
+        match HttpMessage::parse(&mut parser).unwrap() {
+            HttpMessage::Request(req) => assert_eq!(req.method(), &request::Method::Head),
+            HttpMessage::Response(_) => panic!("expected a request"),
         }
 
-        Ok(Self { ty, params })
+        let mut parser = StrParser::from_str("HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n");
+        match HttpMessage::parse(&mut parser).unwrap() {
+            HttpMessage::Response(res) => {
+                assert_eq!(res.status_code(), &response::StatusCode::NotFound)
+            }
+            HttpMessage::Request(_) => panic!("expected a response"),
+        }
+    }
+
+    #[test]
+    fn test_via() {
+        let mut parser = StrParser::from_str("1.1 proxy1, 1.0 proxy2:8080 (squid)");
+        let mut via = Via::parse(&mut parser).unwrap();
+        assert_eq!(via.hops().len(), 2);
+        assert_eq!(via.hops()[0].version, "1.1");
+        assert_eq!(via.hops()[0].received_by, "proxy1");
+        assert_eq!(via.hops()[1].received_by, "proxy2:8080");
+        assert_eq!(via.hops()[1].comment.as_deref(), Some("squid"));
+
+        via.push_hop(HTTPVersion::HTTP_11, "zero");
+        assert_eq!(via.hops().len(), 3);
+        assert_eq!(
+            via.to_string(),
+            "1.1 proxy1, 1.0 proxy2:8080 (squid), 1.1 zero"
+        );
+
+        let mut parser = StrParser::from_str("HTTP/2.0 edge");
+        let via = Via::parse(&mut parser).unwrap();
+        assert_eq!(via.hops()[0].protocol.as_deref(), Some("HTTP"));
+        assert_eq!(via.to_string(), "HTTP/2.0 edge");
+
+        for malformed in [
+            "",
+            " , ",
+            "@@@",
+            "1.1",
+            "/1.1 proxy",
+            "1.1 proxy junk",
+            "1.1 a (x",
+        ] {
+            let mut parser = StrParser::from_str(malformed);
+            assert!(Via::parse(&mut parser).is_err(), "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_header_category() {
+        use request::{RequestHeader, RequestHeaderType};
+        use response::{ResponseHeader, ResponseHeaderType};
+
+        let header = |raw: &str| MessageHeader::parse(&mut StrParser::from_str(raw)).unwrap();
+        let request = |raw: &str| header(raw).into_header::<RequestHeaderType>().unwrap().1;
+        let response = |raw: &str| header(raw).into_header::<ResponseHeaderType>().unwrap().1;
+
+        assert_eq!(
+            request("Cache-Control: no-cache\r\n"),
+            RequestHeaderType::GeneralHeader(GeneralHeader::CacheControl("no-cache".into()))
+        );
+        assert_eq!(
+            request("Host: example.com\r\n"),
+            RequestHeaderType::RequestHeader(RequestHeader::Host("example.com".into()))
+        );
+        assert_eq!(
+            request("Content-Length: 3\r\n"),
+            RequestHeaderType::EntityHeader(EntityHeader::ContentLength(3))
+        );
+        assert_eq!(
+            request("ETag: \"a\"\r\n"),
+            RequestHeaderType::ExtensionHeader("\"a\"".into())
+        );
+        assert_eq!(
+            request("X-Custom: 1\r\n"),
+            RequestHeaderType::ExtensionHeader("1".into())
+        );
+
+        assert_eq!(
+            response("Via: 1.1 proxy\r\n"),
+            ResponseHeaderType::GeneralHeader(GeneralHeader::Via(
+                Via::parse(&mut StrParser::from_str("1.1 proxy")).unwrap()
+            ))
+        );
+        assert!(
+            header("Via: @@@\r\n")
+                .into_header::<ResponseHeaderType>()
+                .is_err()
+        );
+        assert_eq!(
+            response("Server: zero\r\n"),
+            ResponseHeaderType::ResponseHeader(ResponseHeader::Server("zero".into()))
+        );
+        assert_eq!(
+            response("Content-Type: text/html\r\n"),
+            ResponseHeaderType::EntityHeader(EntityHeader::ContentType("text/html".into()))
+        );
+        assert_eq!(
+            response("Accept: */*\r\n"),
+            ResponseHeaderType::ExtensionHeader {
+                name: "accept".into(),
+                value: "*/*".into()
+            }
+        );
+
+        assert_eq!(HeaderCategory::of("te"), HeaderCategory::Request);
+        assert_eq!(HeaderCategory::of("age"), HeaderCategory::Response);
+        assert_eq!(HeaderCategory::of("Host"), HeaderCategory::Extension);
+    }
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(
+            MessageHeader::split_list("text/html ,\tapplication/json;q=0.9 , , */*"),
+            vec!["text/html", "application/json;q=0.9", "*/*"]
+        );
+        assert_eq!(
+            MessageHeader::split_list(r#"private="set-cookie, x-token", max-age=60"#),
+            vec![r#"private="set-cookie, x-token""#, "max-age=60"]
+        );
+        assert_eq!(
+            MessageHeader::split_list(r#"a="x\"y,z", b"#),
+            vec![r#"a="x\"y,z""#, "b"]
+        );
+        assert!(MessageHeader::split_list(" , ").is_empty());
+
+        let mut parser = StrParser::from_str("Vary: accept-encoding,  origin\r\n");
+        let header = MessageHeader::parse(&mut parser).unwrap();
+        assert_eq!(header.values(), vec!["accept-encoding", "origin"]);
+    }
+
+    #[test]
+    fn test_pragma() {
+        let mut parser = StrParser::from_str("No-Cache, foo=\"bar\"");
+        let pragma = Pragma::parse(&mut parser).unwrap();
+        assert!(pragma.is_no_cache());
+        assert_eq!(pragma.directives(), ["No-Cache", "foo=\"bar\""]);
+        assert_eq!(pragma.to_string(), "No-Cache, foo=\"bar\"");
+
+        let mut parser = StrParser::from_str("foo");
+        assert!(!Pragma::parse(&mut parser).unwrap().is_no_cache());
+    }
+
+    #[test]
+    fn test_content_range() {
+        let mut parser = StrParser::from_str("bytes 0-499/1234");
+        let range = ContentRange::parse(&mut parser).unwrap();
+        assert_eq!(range, ContentRange::new(0, 499, Some(1234)).unwrap());
+        assert_eq!(range.to_string(), "bytes 0-499/1234");
+
+        let mut parser = StrParser::from_str("bytes 500-999/*");
+        let range = ContentRange::parse(&mut parser).unwrap();
+        assert_eq!(range.total, None);
+        assert_eq!(range.to_string(), "bytes 500-999/*");
+
+        let mut parser = StrParser::from_str("bytes 500-499/1234");
+        assert_eq!(
+            ContentRange::parse(&mut parser),
+            Err(ParseErr::InvalidContentRange {
+                start: 500,
+                end: 499,
+                total: Some(1234)
+            })
+        );
+        assert!(ContentRange::new(0, 1234, Some(1234)).is_err());
+    }
+
+    #[test]
+    fn test_content_range_header() {
+        let header = MessageHeader {
+            name: String::from("content-range"),
+            value: String::from("bytes 0-499/1234"),
+        };
+        let (_, header) = EntityHeader::from_extension_header(header).unwrap();
+        assert_eq!(
+            header,
+            EntityHeader::ContentRange(ContentRange::new(0, 499, Some(1234)).unwrap())
+        );
+        assert_eq!(header.consume_value_as_string(), "bytes 0-499/1234");
+    }
+
+    #[test]
+    fn test_header_block() {
+        use request::RequestHeaderType;
+
+        let mut parser = StrParser::from_str(
+            "Host: localhost\r\nAccept: */*\r\nX-Custom: one\r\nX-Custom: two\r\n\r\nbody",
+        );
+        let headers = Headers::<RequestHeaderType>::parse_block(&mut parser).unwrap();
+
+        assert_eq!(headers.len(), 3);
+        assert!(headers.get("host").is_some());
+        assert!(headers.get("accept").is_some());
+        assert_eq!(
+            headers.get_all("x-custom"),
+            &[
+                RequestHeaderType::ExtensionHeader(String::from("one")),
+                RequestHeaderType::ExtensionHeader(String::from("two")),
+            ]
+        );
+        // the blank line is consumed, leaving the body
+        assert_eq!(parser.peek(), Some(b'b'));
+
+        let mut parser = StrParser::from_str("Host: localhost\r\n: no name\r\n\r\n");
+        assert_eq!(
+            Headers::<RequestHeaderType>::parse_block(&mut parser),
+            Err(ParseErr::BlankHeaderFieldName)
+        );
+        let mut parser = StrParser::from_str("Host: localhost\r\n");
+        assert!(Headers::<RequestHeaderType>::parse_block(&mut parser).is_err());
+    }
+
+    #[test]
+    fn test_media_type_params() {
+        let html = MediaType::parse(&mut StrParser::from_str("Text/HTML; Charset=UTF-8")).unwrap();
+        assert_eq!((html.ty.as_str(), html.subtype.as_str()), ("text", "html"));
+        assert_eq!(html.charset(), Some("UTF-8"));
+        assert_eq!(html.boundary(), None);
+        assert_eq!(Charset::from_media_type(&html), Ok(Charset::Utf8));
+
+        let form = MediaType::parse(&mut StrParser::from_str(
+            "multipart/form-data; charset=utf-8 ;boundary=\"----Zero Boundary\"",
+        ))
+        .unwrap();
+        assert_eq!(form.boundary(), Some("----Zero Boundary"));
+        assert_eq!(form.param("CHARSET"), Some("utf-8"));
+
+        let form =
+            MediaType::parse(&mut StrParser::from_str("multipart/mixed;boundary=AbC123")).unwrap();
+        assert_eq!(form.boundary(), Some("AbC123"));
+
+        // stray separators, e.g. a trailing `;`, are skipped instead of failing the
+        // parse, which would lose the charset
+        for raw in [
+            "text/plain; charset=ISO-8859-1;",
+            "text/plain;; charset=ISO-8859-1",
+            "text/plain; ; charset=ISO-8859-1 ; ",
+        ] {
+            let media_type = MediaType::parse(&mut StrParser::from_str(raw)).unwrap();
+            assert_eq!(media_type.charset(), Some("ISO-8859-1"), "{}", raw);
+            assert_eq!(Charset::from_content_type(raw), Ok(Charset::Iso8859_1));
+        }
+        let plain = MediaType::parse(&mut StrParser::from_str("text/plain;")).unwrap();
+        assert_eq!(plain.charset(), None);
+
+        assert!(MediaType::parse(&mut StrParser::from_str("text/")).is_err());
+        assert!(MediaType::parse(&mut StrParser::from_str("text; charset=utf-8")).is_err());
+    }
+
+    #[test]
+    fn test_charset_decode() {
+        let charset = Charset::from_content_type("text/plain; charset=ISO-8859-1").unwrap();
+        assert_eq!(charset, Charset::Iso8859_1);
+        assert_eq!(
+            charset.decode(vec![b'c', b'a', b'f', 0xE9]),
+            Ok(String::from("caf\u{e9}"))
+        );
+
+        let charset = Charset::from_content_type("text/plain;charset=\"windows-1252\"").unwrap();
+        assert_eq!(
+            charset.decode(vec![0x80, 0xE9]),
+            Ok(String::from("\u{20AC}\u{e9}"))
+        );
+
+        assert_eq!(Charset::from_content_type("text/plain"), Ok(Charset::Utf8));
+        assert_eq!(Charset::Utf8.decode(vec![0xE9]), Err(ParseErr::InvalidUTF8));
+        assert_eq!(
+            Charset::from_content_type("text/plain; charset=koi8-r"),
+            Err(ParseErr::UnsupportedCharset {
+                found: String::from("koi8-r")
+            })
+        );
+    }
+
+    #[test]
+    fn test_qvalue() {
+        let mut parser = StrParser::from_str("0.5");
+        assert_eq!(QValue::parse(&mut parser), Ok(QValue(500)));
+        let mut parser = StrParser::from_str("1");
+        assert_eq!(QValue::parse(&mut parser), Ok(QValue::MAX));
+        let mut parser = StrParser::from_str("0.125");
+        assert_eq!(QValue::parse(&mut parser).unwrap().to_string(), "0.125");
+        let mut parser = StrParser::from_str("1.5");
+        assert!(QValue::parse(&mut parser).is_err());
+        let mut parser = StrParser::from_str("0.1234");
+        assert!(QValue::parse(&mut parser).is_err());
+    }
+
+    #[test]
+    fn test_http_version_helpers() {
+        let parse = |s: &str| HTTPVersion::parse(&mut StrParser::from_str(s)).unwrap();
+        let v10 = parse("HTTP/1.0");
+        let v11 = parse("HTTP/1.1");
+        let v20 = parse("HTTP/2.0");
+
+        assert!(v10.is_http_10() && !v10.is_http_11());
+        assert!(v11.is_http_11() && !v11.is_http_10());
+        assert!(!v20.is_http_10() && !v20.is_http_11());
+        assert_eq!(v20, HTTPVersion::HTTP_2);
+
+        assert!(v10.at_least(1, 0) && !v10.at_least(1, 1));
+        assert!(v11.at_least(1, 0) && v11.at_least(1, 1) && !v11.at_least(2, 0));
+        assert!(v20.at_least(1, 1) && v20.at_least(2, 0) && !v20.at_least(2, 1));
+        assert_eq!((v20.major(), v20.minor()), (2, 0));
     }
 }
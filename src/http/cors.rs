@@ -0,0 +1,113 @@
+use super::{
+    request::Request,
+    response::{Response, StatusCode},
+};
+use std::collections::HashMap;
+
+/// Cross-origin resource sharing policy, see `Router::cors`.
+///
+/// By default any origin is allowed, and preflights get back whatever headers they
+/// ask for.
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    origins: &'static [&'static str],
+    headers: Option<&'static [&'static str]>,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allows requests from `origins`, e.g. `"https://example.com"`. An empty
+    /// list allows any origin.
+    pub fn allow_origins(mut self, origins: &'static [&'static str]) -> Self {
+        self.origins = origins;
+        self
+    }
+
+    /// Request headers allowed in cross-origin requests, instead of mirroring the
+    /// ones a preflight asks for.
+    pub fn allow_headers(mut self, headers: &'static [&'static str]) -> Self {
+        self.headers = Some(headers);
+        self
+    }
+
+    /// How long, in seconds, browsers may cache a preflight response.
+    pub fn max_age(mut self, secs: u64) -> Self {
+        self.max_age = Some(secs);
+        self
+    }
+
+    /// `Access-Control-Allow-Origin` value for `origin`, `None` if it isn't allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.is_empty() {
+            Some(String::from("*"))
+        } else if self.origins.contains(&origin) {
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Adds the `Access-Control-Allow-Origin` header to the response to a request sent
+    /// from `origin`.
+    pub fn apply(&self, origin: Option<&str>, response: &mut Response) {
+        let Some(allow_origin) = origin.and_then(|o| self.allow_origin(o)) else {
+            return;
+        };
+        let headers = response.headers_mut();
+        if allow_origin != "*" {
+            Self::vary_on(headers, "Origin");
+        }
+        headers.insert(String::from("access-control-allow-origin"), allow_origin);
+    }
+
+    /// Adds `name` to the `Vary` header, keeping the fields the handler already listed
+    fn vary_on(headers: &mut HashMap<String, String>, name: &str) {
+        match headers.get_mut("vary") {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .map(str::trim)
+                    .any(|v| v == "*" || v.eq_ignore_ascii_case(name)) => {}
+            Some(vary) if !vary.trim().is_empty() => {
+                vary.push_str(", ");
+                vary.push_str(name);
+            }
+            _ => {
+                headers.insert(String::from("vary"), name.to_string());
+            }
+        }
+    }
+
+    /// Answers a preflight `req` with `204 No Content`, allowing `methods`.
+    ///
+    /// A preflight from an origin that isn't allowed gets no `Access-Control-Allow-*`
+    /// headers, which the browser takes as a refusal.
+    pub fn preflight(&self, req: &Request, methods: String) -> Response {
+        let mut response = Response::new(StatusCode::NoContent, HashMap::new(), None);
+        self.apply(req.origin(), &mut response);
+        if !response
+            .headers()
+            .contains_key("access-control-allow-origin")
+        {
+            return response;
+        }
+
+        let headers = response.headers_mut();
+        headers.insert(String::from("access-control-allow-methods"), methods);
+        let allow_headers = match self.headers {
+            Some(allowed) => Some(allowed.join(", ")),
+            None => req.access_control_request_headers().map(str::to_string),
+        };
+        if let Some(allow_headers) = allow_headers.filter(|h| !h.is_empty()) {
+            headers.insert(String::from("access-control-allow-headers"), allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(String::from("access-control-max-age"), max_age.to_string());
+        }
+        response
+    }
+}
@@ -1,9 +1,19 @@
 use super::{
-    EntityHeader, FromMessageHeader, GeneralHeader, HTTPVersion, MessageHeader,
-    uri::{RequestQuery, URIPath},
+    Charset, EntityHeader, FromMessageHeader, GeneralHeader, HTTPVersion, HeaderCategory, Headers,
+    MediaType, MessageHeader, Pragma, QValue,
+    uri::{Params, RequestQuery, URIPath},
+};
+use crate::UUID;
+use crate::parsing::{StrParser, prelude::*};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
-use crate::parsing::prelude::*;
-use std::{collections::HashMap, io::Read};
 
 pub trait FromRequest: Sized {
     fn from_request(a: Request) -> Self;
@@ -26,7 +36,8 @@ pub trait FromRequest: Sized {
 /// extension-method = token
 /// ```
 ///
-/// Not supporting extension methods for now
+/// `PATCH` (rfc5789) gets a variant of its own, any other extension method is kept
+/// as is in `Extension`.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Method {
     Options,
@@ -37,23 +48,54 @@ pub enum Method {
     Delete,
     Trace,
     Connect,
+    Patch,
+    Extension(String),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Options => "OPTIONS",
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Patch => "PATCH",
+            Method::Extension(method) => method,
+        }
+    }
+}
+
+/// Methods are case-sensitive, so e.g. `"get"` is an extension method and not `Get`.
+impl From<&str> for Method {
+    fn from(token: &str) -> Self {
+        match token {
+            "OPTIONS" => Method::Options,
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "TRACE" => Method::Trace,
+            "CONNECT" => Method::Connect,
+            "PATCH" => Method::Patch,
+            _ => Method::Extension(token.to_string()),
+        }
+    }
 }
 
 impl<R: Read> Parsable<R> for Method {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         parser.skip_whitespace();
-        let token = parser.consume_while(|p| p.is_alpha());
-        match token.as_str() {
-            "OPTIONS" => Ok(Method::Options),
-            "GET" => Ok(Method::Get),
-            "HEAD" => Ok(Method::Head),
-            "POST" => Ok(Method::Post),
-            "PUT" => Ok(Method::Put),
-            "DELETE" => Ok(Method::Delete),
-            "TRACE" => Ok(Method::Trace),
-            "CONNECT" => Ok(Method::Connect),
-            _ => Err(ParseErr::InvalidRequestOption { found: token }),
+        let token =
+            parser.consume_while(|p| p.is_token_char() && p.peek().is_some_and(|c| c.is_ascii()));
+        if token.is_empty() {
+            return Err(ParseErr::InvalidRequestOption { found: token });
         }
+        Ok(Method::from(token.as_str()))
     }
 }
 
@@ -77,7 +119,7 @@ pub enum RequestHeader {
     ProxyAuthorization(String), // Section 14.34
     Range(String),              // Section 14.35
     Referer(String),            // Section 14.36
-    TE(String),                 // Section 14.39
+    TE(TE),                     // Section 14.39
     UserAgent(String),          // Section 14.43
 }
 
@@ -109,26 +151,7 @@ impl RequestHeader {
 
 impl FromMessageHeader for RequestHeader {
     fn can_convert(eh: &MessageHeader) -> bool {
-        let name = eh.name.as_str();
-        name == "accept"
-            || name == "accept-charset"
-            || name == "accept-encoding"
-            || name == "accept-language"
-            || name == "authorization"
-            || name == "expect"
-            || name == "from"
-            || name == "host"
-            || name == "if-match"
-            || name == "if-modified-since"
-            || name == "if-none-match"
-            || name == "if-range"
-            || name == "if-unmodified-since"
-            || name == "max-forwards"
-            || name == "proxy-authorization"
-            || name == "range"
-            || name == "referer"
-            || name == "te"
-            || name == "user-agent"
+        HeaderCategory::of(eh.name()) == HeaderCategory::Request
     }
     fn from_extension_header(eh: MessageHeader) -> ParseResult<(String, Self)> {
         let val = eh.value;
@@ -151,7 +174,10 @@ impl FromMessageHeader for RequestHeader {
             "proxy-authorization" => Self::ProxyAuthorization(val),
             "range" => Self::Range(val),
             "referer" => Self::Referer(val),
-            "te" => Self::TE(val),
+            "te" => {
+                let mut s_parser = StrParser::from_str(&val);
+                Self::TE(TE::parse(&mut s_parser)?)
+            }
             "user-agent" => Self::UserAgent(val),
             _ => unreachable!(
                 "Failed to convert extension header. Perhaps can_convert was not checked"
@@ -162,6 +188,227 @@ impl FromMessageHeader for RequestHeader {
     }
 }
 
+/// A transfer coding listed in a `TE` header along with its quality
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TransferCoding {
+    pub name: String,
+    pub q: QValue,
+}
+
+/// Based on rfc2616 Section 14.39
+///
+/// `chunked` is always acceptable to HTTP/1.1 clients, so it is never listed here.
+/// Trailers are only allowed in a chunked response when `accepts_trailers` is true.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// TE        = "TE" ":" #( t-codings )
+/// t-codings = "trailers" | ( transfer-extension [ accept-params ] )
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct TE {
+    codings: Vec<TransferCoding>,
+    trailers: bool,
+}
+
+impl TE {
+    pub fn accepts_trailers(&self) -> bool {
+        self.trailers
+    }
+
+    pub fn codings(&self) -> &[TransferCoding] {
+        &self.codings
+    }
+
+    /// Whether the client will take a response in the given coding
+    pub fn accepts(&self, coding: &str) -> bool {
+        coding.eq_ignore_ascii_case("chunked")
+            || self
+                .codings
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(coding) && c.q > QValue(0))
+    }
+}
+
+impl std::fmt::Display for TE {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut first = true;
+        if self.trailers {
+            write!(f, "trailers")?;
+            first = false;
+        }
+        for coding in self.codings.iter() {
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+            write!(f, "{}", coding.name)?;
+            if coding.q != QValue::MAX {
+                write!(f, ";q={}", coding.q)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Parsable<R> for TE {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let mut te = TE::default();
+        loop {
+            while parser.is_linear_whitespace() || parser.matches(|c| c == b',') {
+                parser.consume();
+            }
+            let name = parser.consume_while_lower(|p| p.is_token_char());
+            if name.is_empty() {
+                break;
+            }
+
+            let q = QValue::parse_params(parser)?;
+            if name == "trailers" {
+                te.trailers = true;
+            } else {
+                te.codings.push(TransferCoding { name, q });
+            }
+        }
+
+        Ok(te)
+    }
+}
+
+/// A media range listed in an `Accept` header along with its quality
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MediaRange {
+    pub ty: String,
+    pub subtype: String,
+    pub q: QValue,
+}
+
+impl MediaRange {
+    /// How specifically this range matches `ty/subtype`, `None` if it doesn't match at all
+    fn specificity(&self, ty: &str, subtype: &str) -> Option<u8> {
+        if self.ty == "*" && self.subtype == "*" {
+            Some(0)
+        } else if !self.ty.eq_ignore_ascii_case(ty) {
+            None
+        } else if self.subtype == "*" {
+            Some(1)
+        } else if self.subtype.eq_ignore_ascii_case(subtype) {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Based on rfc2616 Section 14.1
+///
+/// Media type parameters are accepted but not used when matching.
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// Accept         = "Accept" ":" #( media-range [ accept-params ] )
+/// media-range    = ( "*/*"
+///                  | ( type "/" "*" )
+///                  | ( type "/" subtype )
+///                  ) *( ";" parameter )
+/// accept-params  = ";" "q" "=" qvalue *( accept-extension )
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct AcceptList {
+    ranges: Vec<MediaRange>,
+}
+
+impl AcceptList {
+    pub fn ranges(&self) -> &[MediaRange] {
+        &self.ranges
+    }
+
+    /// Quality the client gives `media_type`, taken from the most specific range matching it
+    pub fn quality(&self, media_type: &str) -> QValue {
+        if self.ranges.is_empty() {
+            return QValue::MAX;
+        }
+        let media_type = media_type.split(';').next().unwrap_or("").trim();
+        let (ty, subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+        self.ranges
+            .iter()
+            .filter_map(|r| r.specificity(ty, subtype).map(|s| (s, r.q)))
+            .max_by_key(|(s, _)| *s)
+            .map_or(QValue(0), |(_, q)| q)
+    }
+
+    /// Picks the offered media type the client prefers, earlier offers win ties.
+    ///
+    /// Returns `None` when the client accepts none of them.
+    pub fn best_match<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let mut best: Option<(&'a str, QValue)> = None;
+        for media_type in offered {
+            let q = self.quality(media_type);
+            if q > QValue(0) && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((media_type, q));
+            }
+        }
+        best.map(|(media_type, _)| media_type)
+    }
+}
+
+impl<R: Read> Parsable<R> for AcceptList {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let mut accept = AcceptList::default();
+        loop {
+            while parser.is_linear_whitespace() || parser.matches(|c| c == b',') {
+                parser.consume();
+            }
+            let ty = parser.consume_while_lower(|p| p.is_token_char());
+            if ty.is_empty() {
+                break;
+            }
+            parser.consume_expect(b'/', "media subtype")?;
+            let subtype = parser.consume_while_lower(|p| p.is_token_char());
+
+            let q = QValue::parse_params(parser)?;
+
+            accept.ranges.push(MediaRange { ty, subtype, q });
+        }
+
+        Ok(accept)
+    }
+}
+
+/// Address of the client on the other end of the connection
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Identifies a request in logs, see `Request::ensure_request_id`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub const HEADER: &'static str = "x-request-id";
+    /// Incoming ids longer than this are replaced instead of being echoed back
+    pub const MAX_LEN: usize = 128;
+
+    /// New random id, backed by a v7 uuid so ids sort by creation time
+    pub fn generate() -> Self {
+        match UUID::rand_v7() {
+            Ok(uuid) => RequestId(uuid.to_string()),
+            // rand_v7 only fails if the clock is before the unix epoch
+            Err(_) => RequestId(String::from("unknown")),
+        }
+    }
+
+    /// Whether a client supplied id is safe to echo back in a header
+    fn is_valid(id: &str) -> bool {
+        !id.is_empty() && id.len() <= Self::MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// Based on RFC 2616 section 5
 ///
 /// # Augmented Backus-Naur Form
@@ -194,46 +441,255 @@ impl RequestHeaderMap {
     }
 }
 
+impl FromMessageHeader for RequestHeaderType {
+    fn can_convert(_eh: &MessageHeader) -> bool {
+        true
+    }
+
+    fn from_extension_header(header: MessageHeader) -> ParseResult<(String, Self)> {
+        match HeaderCategory::of(header.name()) {
+            HeaderCategory::General => {
+                let (name, header) = header.into_header()?;
+                Ok((name, RequestHeaderType::GeneralHeader(header)))
+            }
+            HeaderCategory::Request => {
+                let (name, header) = header.into_header()?;
+                Ok((name, RequestHeaderType::RequestHeader(header)))
+            }
+            HeaderCategory::Entity => {
+                let (name, header) = header.into_header()?;
+                Ok((name, RequestHeaderType::EntityHeader(header)))
+            }
+            // response headers mean nothing in a request
+            HeaderCategory::Response | HeaderCategory::Extension => {
+                let (name, value) = header.extract_name_val();
+                Ok((name, RequestHeaderType::ExtensionHeader(value)))
+            }
+        }
+    }
+}
+
 impl<R: Read> Parsable<R> for RequestHeaderMap {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
-        let header = MessageHeader::parse(parser);
-        let header = header?;
-        if GeneralHeader::can_convert(&header) {
-            let (name, header) = header.into_header()?;
-            Ok(Self {
-                name,
-                ty: RequestHeaderType::GeneralHeader(header),
-            })
-        } else if RequestHeader::can_convert(&header) {
-            let (name, header) = header.into_header()?;
-            Ok(Self {
-                name,
-                ty: RequestHeaderType::RequestHeader(header),
-            })
-        } else if EntityHeader::can_convert(&header) {
-            let (name, header) = header.into_header()?;
-            Ok(Self {
-                name,
-                ty: RequestHeaderType::EntityHeader(header),
-            })
-        } else {
-            let (name, value) = header.extract_name_val();
-            Ok(Self {
-                name,
-                ty: RequestHeaderType::ExtensionHeader(value),
-            })
-        }
+        let (name, ty) = MessageHeader::parse(parser)?.into_header()?;
+        Ok(Self { name, ty })
     }
 }
 
 pub type RequestHeaders = HashMap<String, RequestHeaderType>;
 #[derive(Debug, PartialEq, Eq)]
 pub enum RequestBody {
+    /// Fields of a `multipart/form-data` body, see `RequestBody::multipart_fields`
     FormData(HashMap<String, String>),
+    /// `text/*` and form bodies, decoded with the charset of their `Content-Type`
     Plain(String),
+    /// Any other body, kept as sent
+    Bytes(Vec<u8>),
+    /// Body of a `Router::route_streaming` route, still on the connection
+    Stream(BodyStream),
     Empty,
 }
 
+/// Request body read off the connection as the handler reads it, rather than before
+/// the handler runs, see `Router::route_streaming`.
+///
+/// Reading ends after the `Content-Length` the client sent, failing with
+/// `UnexpectedEof` if the connection closes first. Whatever the handler leaves unread is
+/// skipped by the server once the handler returns, and reads after that see an ended body.
+pub struct BodyStream {
+    source: Arc<Mutex<dyn Read + Send>>,
+    remaining: Arc<AtomicUsize>,
+}
+
+impl BodyStream {
+    /// Streams the next `len` bytes of `source`, shared with whoever reads on after the body
+    pub fn new(source: Arc<Mutex<dyn Read + Send>>, len: usize) -> Self {
+        BodyStream {
+            source,
+            remaining: Arc::new(AtomicUsize::new(len)),
+        }
+    }
+
+    /// Streams a body that was already read
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        Self::new(Arc::new(Mutex::new(Cursor::new(bytes))), len)
+    }
+
+    /// Bytes of the body not read yet
+    pub fn remaining(&self) -> usize {
+        self.remaining.load(Ordering::Acquire)
+    }
+
+    /// Counter of the bytes not read yet, kept by the server to skip them afterwards
+    pub(crate) fn remaining_handle(&self) -> Arc<AtomicUsize> {
+        self.remaining.clone()
+    }
+}
+
+impl Read for BodyStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let want = self.remaining().min(buf.len());
+        if want == 0 {
+            return Ok(0);
+        }
+
+        let read = match self.source.lock() {
+            Ok(mut source) => source.read(&mut buf[..want])?,
+            Err(poisoned) => poisoned.into_inner().read(&mut buf[..want])?,
+        };
+        if read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed before the whole body was sent",
+            ));
+        }
+        // the server may have given up on the rest in the meantime
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |r| {
+                Some(r.saturating_sub(read))
+            });
+
+        Ok(read)
+    }
+}
+
+impl std::fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+/// Streams are only equal to themselves
+impl PartialEq for BodyStream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.remaining, &other.remaining)
+    }
+}
+
+impl Eq for BodyStream {}
+
+impl RequestBody {
+    /// Splits a `multipart/form-data` body into its fields on the `boundary` of its
+    /// `Content-Type`, rfc7578.
+    ///
+    /// `None` when the body is malformed or not UTF-8, e.g. with a binary file upload,
+    /// in which case it is kept as sent.
+    ///
+    /// Based on rfc2046 Section 5.1.1
+    ///
+    /// # Augmented Backus-Naur Form
+    /// ```text
+    /// multipart-body := [preamble CRLF]
+    ///                   dash-boundary transport-padding CRLF
+    ///                   body-part *encapsulation
+    ///                   close-delimiter transport-padding
+    ///                   [CRLF epilogue]
+    /// encapsulation  := delimiter transport-padding
+    ///                   CRLF body-part
+    /// delimiter      := CRLF dash-boundary
+    /// ```
+    pub fn multipart_fields(bytes: &[u8], boundary: &str) -> Option<HashMap<String, String>> {
+        let body = format!("\r\n{}", std::str::from_utf8(bytes).ok()?);
+        let delimiter = format!("\r\n--{}", boundary);
+        let mut parts = body.split(delimiter.as_str());
+        // the preamble before the first delimiter is ignored
+        parts.next()?;
+
+        let mut fields = HashMap::new();
+        for part in parts {
+            if part.starts_with("--") {
+                return Some(fields);
+            }
+            let (_padding, part) = part.split_once("\r\n")?;
+            let (head, value) = match part.strip_prefix("\r\n") {
+                Some(value) => ("", value),
+                None => part.split_once("\r\n\r\n")?,
+            };
+            let name = head.split("\r\n").find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                if !name.trim().eq_ignore_ascii_case("content-disposition") {
+                    return None;
+                }
+                let (_disposition, params) = value.split_once(';')?;
+                Params::HEADER
+                    .parse(&mut StrParser::from_str(params))
+                    .ok()?
+                    .remove("name")
+            })?;
+            fields.insert(name, value.to_string());
+        }
+        // no close delimiter
+        None
+    }
+
+    /// Raw bytes of the body, e.g. to hand to `BufferedRW::write_stream` without copying.
+    /// `None` for form data, which no longer has a raw form, and for streamed bodies,
+    /// which have to be read instead.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            RequestBody::Plain(s) => Some(s.as_bytes()),
+            RequestBody::Bytes(b) => Some(b),
+            RequestBody::Empty => Some(&[]),
+            RequestBody::FormData(_) | RequestBody::Stream(_) => None,
+        }
+    }
+
+    /// Reads and throws away a chunked body, trailers included.
+    ///
+    /// Used to get a persistent connection back to the start of the next request when
+    /// nothing read the body. Returns the number of body bytes discarded, or
+    /// `ParseErr::BodyTooLarge` as soon as the chunks add up to more than `max`.
+    ///
+    /// Based on rfc2616 Section 3.6.1
+    ///
+    /// # Augmented Backus-Naur Form
+    /// ```text
+    /// Chunked-Body   = *chunk
+    ///                  last-chunk
+    ///                  trailer
+    ///                  CRLF
+    /// chunk          = chunk-size [ chunk-extension ] CRLF
+    ///                  chunk-data CRLF
+    /// last-chunk     = 1*("0") [ chunk-extension ] CRLF
+    /// ```
+    pub fn drain_chunked<R: Read>(parser: &mut Parser<R>, max: usize) -> ParseResult<usize> {
+        let mut total: usize = 0;
+        loop {
+            let line = parser.consume_line();
+            let size_str = line.split(';').next().unwrap_or_default().trim();
+            let size =
+                usize::from_str_radix(size_str, 16).map_err(|_| ParseErr::FailedToParseNum {
+                    found: size_str.to_string(),
+                    radix: 16,
+                })?;
+            if size == 0 {
+                break;
+            }
+
+            total = total.saturating_add(size);
+            if total > max {
+                return Err(ParseErr::BodyTooLarge {
+                    requested: total,
+                    max,
+                });
+            }
+            if parser.skip_n(size) < size {
+                return Err(parser.eof_err());
+            }
+            parser.expect_crlf()?;
+        }
+
+        // trailers run until the blank line
+        while !parser.consume_line().is_empty() {}
+
+        Ok(total)
+    }
+}
+
 /// Based on RFC 2616 section 5
 ///
 /// # Augmented Backus-Naur Form
@@ -253,6 +709,8 @@ pub struct Request {
     pub http_version: HTTPVersion,
     pub headers: RequestHeaders,
     pub body: RequestBody,
+    /// Set by the server for requests read off a socket, `None` otherwise
+    pub peer_addr: Option<SocketAddr>,
 }
 
 pub type RequestTuple = (
@@ -284,6 +742,7 @@ impl Request {
             http_version: r.3,
             headers: r.4,
             body: r.5,
+            peer_addr: None,
         }
     }
 
@@ -298,13 +757,286 @@ impl Request {
     pub fn method_path(&self) -> (&Method, &str) {
         (&self.method, self.path.entire_path().as_str())
     }
+
+    fn general_header_has_token(&self, name: &str, token: &str) -> bool {
+        let value = match self.headers.get(name) {
+            Some(RequestHeaderType::GeneralHeader(GeneralHeader::Connection(v))) => v,
+            Some(RequestHeaderType::GeneralHeader(GeneralHeader::TransferEncoding(v))) => v,
+            _ => return false,
+        };
+        value
+            .split(',')
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Whether the connection should stay open after this request.
+    ///
+    /// HTTP/1.1 connections persist unless the client sent `Connection: close`.
+    /// Older versions only persist with `Connection: keep-alive`.
+    pub fn is_persistent(&self) -> bool {
+        if self.http_version.at_least(1, 1) {
+            !self.general_header_has_token("connection", "close")
+        } else {
+            self.general_header_has_token("connection", "keep-alive")
+        }
+    }
+
+    /// Parsed `TE` header, if the client sent one
+    pub fn te(&self) -> Option<&TE> {
+        match self.headers.get("te") {
+            Some(RequestHeaderType::RequestHeader(RequestHeader::TE(te))) => Some(te),
+            _ => None,
+        }
+    }
+
+    /// The `X-Request-Id` sent by the client or set by `ensure_request_id`.
+    /// Generates a fresh id if neither happened.
+    pub fn request_id(&self) -> RequestId {
+        match self.headers.get(RequestId::HEADER) {
+            Some(RequestHeaderType::ExtensionHeader(id)) if RequestId::is_valid(id) => {
+                RequestId(id.clone())
+            }
+            _ => RequestId::generate(),
+        }
+    }
+
+    /// Keeps a valid incoming `X-Request-Id`, otherwise generates one and stores it in
+    /// the headers so every later `request_id` call sees the same id.
+    pub fn ensure_request_id(&mut self) -> RequestId {
+        let id = self.request_id();
+        self.headers.insert(
+            RequestId::HEADER.to_string(),
+            RequestHeaderType::ExtensionHeader(id.0.clone()),
+        );
+        id
+    }
+
+    /// Parsed `Pragma` header, if the client sent one
+    pub fn pragma(&self) -> Option<Pragma> {
+        match self.headers.get("pragma") {
+            Some(RequestHeaderType::GeneralHeader(GeneralHeader::Pragma(val))) => {
+                Pragma::parse(&mut StrParser::from_str(val)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the client asked not to be served from a cache.
+    ///
+    /// Checks the `no-cache` directive of `Cache-Control`, falling back to
+    /// `Pragma: no-cache` from HTTP/1.0 clients when there is no `Cache-Control`.
+    pub fn is_no_cache(&self) -> bool {
+        match self.headers.get("cache-control") {
+            Some(RequestHeaderType::GeneralHeader(GeneralHeader::CacheControl(val))) => {
+                MessageHeader::split_list(val)
+                    .iter()
+                    .filter_map(|d| d.split('=').next())
+                    .any(|d| d.trim().eq_ignore_ascii_case("no-cache"))
+            }
+            _ => self.pragma().is_some_and(|p| p.is_no_cache()),
+        }
+    }
+
+    /// Raw `If-None-Match` value, if the client sent one
+    pub fn if_none_match(&self) -> Option<&str> {
+        match self.headers.get("if-none-match") {
+            Some(RequestHeaderType::RequestHeader(RequestHeader::IfNoneMatch(val))) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Original client address from `X-Forwarded-For`, i.e. its first entry.
+    ///
+    /// The header is set by whatever is in front of the server, and by clients too, so
+    /// it is only trustworthy behind a proxy that overwrites it. See `client_ip` for
+    /// an address that can't be spoofed.
+    pub fn forwarded_for(&self) -> Option<&str> {
+        match self.headers.get("x-forwarded-for") {
+            Some(RequestHeaderType::ExtensionHeader(v)) => v
+                .split(',')
+                .next()
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty()),
+            _ => None,
+        }
+    }
+
+    /// Address of the client the request comes from.
+    ///
+    /// That is the peer the request was read from, unless the peer is one of
+    /// `trusted_proxies`. Then `X-Forwarded-For` is walked from its last entry, each
+    /// added by the hop before it, until an address that isn't a trusted proxy. An entry
+    /// that isn't an IP address stops the walk at the proxy that added it, as does
+    /// running out of entries.
+    pub fn client_ip(&self, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+        let mut client = self.peer_addr?.ip();
+        if !trusted_proxies.contains(&client) {
+            return Some(client);
+        }
+        let Some(forwarded) = self.extension_header("x-forwarded-for") else {
+            return Some(client);
+        };
+        for hop in forwarded.rsplit(',') {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !trusted_proxies.contains(&client) {
+                break;
+            }
+        }
+
+        Some(client)
+    }
+
+    fn extension_header(&self, name: &str) -> Option<&str> {
+        match self.headers.get(name) {
+            Some(RequestHeaderType::ExtensionHeader(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// `Origin` of a cross-origin request, if the client sent one
+    pub fn origin(&self) -> Option<&str> {
+        self.extension_header("origin")
+    }
+
+    /// Raw `Access-Control-Request-Headers` value of a CORS preflight
+    pub fn access_control_request_headers(&self) -> Option<&str> {
+        self.extension_header("access-control-request-headers")
+    }
+
+    /// Whether this is a CORS preflight, i.e. an `OPTIONS` request with an `Origin`
+    /// and the `Access-Control-Request-Method` the actual request will use.
+    pub fn is_cors_preflight(&self) -> bool {
+        self.method == Method::Options
+            && self.origin().is_some()
+            && self
+                .extension_header("access-control-request-method")
+                .is_some()
+    }
+
+    fn media_type(header: &RequestHeaderType) -> Option<MediaType> {
+        match header {
+            RequestHeaderType::EntityHeader(EntityHeader::ContentType(val)) => {
+                MediaType::parse(&mut StrParser::from_str(val)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Parsed `Content-Type` of the body, `None` if it is missing or malformed.
+    pub fn content_type(&self) -> Option<MediaType> {
+        self.headers.get("content-type").and_then(Self::media_type)
+    }
+
+    /// Media types the client accepts. A missing or malformed `Accept` header
+    /// accepts everything.
+    pub fn accept(&self) -> AcceptList {
+        match self.headers.get("accept") {
+            Some(RequestHeaderType::RequestHeader(RequestHeader::Accept(val))) => {
+                AcceptList::parse(&mut StrParser::from_str(val)).unwrap_or_default()
+            }
+            _ => AcceptList::default(),
+        }
+    }
+
+    /// Whether trailer fields may be sent after a chunked response body.
+    pub fn accepts_trailers(&self) -> bool {
+        self.te().is_some_and(|te| te.accepts_trailers())
+    }
+
+    /// Whether the body is sent with chunked transfer coding. Chunked bodies are
+    /// not read by `Request::parse`.
+    pub fn is_chunked(&self) -> bool {
+        self.general_header_has_token("transfer-encoding", "chunked")
+    }
 }
 
 impl<R: Read> Parsable<R> for Request {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        Self::parse_with_limits(parser, usize::MAX, usize::MAX)
+    }
+}
+
+impl Request {
+    /// Length of the body framed by `Content-Length`.
+    ///
+    /// Rejects the ambiguous framings used for request smuggling: a `Content-Length`
+    /// sent alongside `Transfer-Encoding`, and several `Content-Length` fields that
+    /// disagree. Repeated identical values are accepted, see rfc7230 section 3.3.2.
+    fn body_len(headers: &Headers<RequestHeaderType>) -> ParseResult<Option<usize>> {
+        let lengths: Vec<usize> = headers
+            .get_all("content-length")
+            .iter()
+            .filter_map(|h| match h {
+                RequestHeaderType::EntityHeader(EntityHeader::ContentLength(len)) => Some(*len),
+                _ => None,
+            })
+            .collect();
+        if lengths.is_empty() {
+            return Ok(None);
+        }
+        if headers.get("transfer-encoding").is_some() {
+            return Err(ParseErr::ContentLengthWithTransferEncoding);
+        }
+        if lengths.iter().any(|len| *len != lengths[0]) {
+            return Err(ParseErr::ConflictingContentLength { found: lengths });
+        }
+        Ok(Some(lengths[0]))
+    }
+
+    /// Parses a request, failing with `ParseErr::TooManyHeaders` if it has more than
+    /// `max_headers` header fields.
+    pub fn parse_with_max_headers<R: Read>(
+        parser: &mut Parser<R>,
+        max_headers: usize,
+    ) -> ParseResult<Self> {
+        Self::parse_with_limits(parser, max_headers, usize::MAX)
+    }
+
+    /// Same as `parse_with_max_headers`, also failing with `ParseErr::BodyTooLarge`
+    /// before reading a body whose `Content-Length` is over `max_body`.
+    pub fn parse_with_limits<R: Read>(
+        parser: &mut Parser<R>,
+        max_headers: usize,
+        max_body: usize,
+    ) -> ParseResult<Self> {
+        Self::parse_with_body_limit(parser, max_headers, |_, _| max_body)
+    }
+
+    /// Same as `parse_with_limits`, but the body limit is picked once the request line
+    /// is read, e.g. to give each route its own limit, see `Router::route_with_limit`.
+    pub fn parse_with_body_limit<R: Read>(
+        parser: &mut Parser<R>,
+        max_headers: usize,
+        max_body: impl FnOnce(&Method, &URIPath) -> usize,
+    ) -> ParseResult<Self> {
+        let (mut request, body_len) = Self::parse_head(parser, max_headers)?;
+        if let Some(body_len) = body_len {
+            let max_body = max_body(&request.method, &request.path);
+            request.read_body(parser, body_len, max_body)?;
+        }
+
+        Ok(request)
+    }
+
+    /// Parses the request line and headers, leaving the body on the connection.
+    ///
+    /// Returns the request with an `Empty` body, along with the length of the body from
+    /// its `Content-Length`, if it has one.
+    pub fn parse_head<R: Read>(
+        parser: &mut Parser<R>,
+        max_headers: usize,
+    ) -> ParseResult<(Self, Option<usize>)> {
         let method = Method::parse(parser)?;
         parser.skip_whitespace();
         let path = URIPath::parse(parser)?;
+        if path.is_asterisk() && method != Method::Options {
+            return Err(ParseErr::AsteriskFormNotOptions {
+                found: method.as_str().to_string(),
+            });
+        }
         let query = if parser.matches(|c| c == b'?') {
             parser.consume();
             RequestQuery::parse(parser)?
@@ -316,36 +1048,54 @@ impl<R: Read> Parsable<R> for Request {
         parser.skip_whitespace();
         parser.expect_crlf()?;
 
-        let mut headers = HashMap::new();
-        let mut body_len = None;
-
-        while let Ok(header) = RequestHeaderMap::parse(parser) {
-            let (name, ty) = header.extract_name_type();
-            if let RequestHeaderType::EntityHeader(EntityHeader::ContentLength(len)) = ty {
-                body_len = Some(len)
-            }
-            headers.insert(name, ty);
-        }
-
-        let body = match body_len {
-            Some(body_len) => {
-                parser.expect_crlf()?;
-                // eprintln!("{}", parser.peek().unwrap() as char);
-                // parser.consume_or_err(|c| c == b'\n')?;
-                // eprintln!("hit");
-                RequestBody::Plain(parser.consume_n(body_len))
-            }
-            None => RequestBody::Empty,
-        };
+        let headers = Headers::<RequestHeaderType>::parse_block_limited(parser, max_headers)?;
+        let body_len = Self::body_len(&headers)?;
+        let headers: RequestHeaders = headers.into_iter().collect();
 
-        Ok(Request {
+        let request = Request {
             method,
             path,
             query,
             http_version,
             headers,
-            body,
-        })
+            body: RequestBody::Empty,
+            peer_addr: None,
+        };
+        Ok((request, body_len))
+    }
+
+    /// Reads the `len` byte body following the head parsed by `parse_head`, failing with
+    /// `ParseErr::BodyTooLarge` without reading anything if `len` is over `max_body`.
+    pub fn read_body<R: Read>(
+        &mut self,
+        parser: &mut Parser<R>,
+        len: usize,
+        max_body: usize,
+    ) -> ParseResult<()> {
+        // only text is decoded, binary uploads are kept as they were sent, and form
+        // fields are split off the boundary of a multipart body
+        let media_type = self.content_type();
+        let charset = match &media_type {
+            Some(media_type) if media_type.is_text() => Some(Charset::from_media_type(media_type)?),
+            _ => None,
+        };
+        let boundary = media_type
+            .as_ref()
+            .filter(|media_type| {
+                (media_type.ty.as_str(), media_type.subtype.as_str()) == ("multipart", "form-data")
+            })
+            .and_then(MediaType::boundary);
+        let bytes = parser.consume_n_bytes_capped(len, max_body)?;
+        self.body = match (charset, boundary) {
+            (Some(charset), _) => RequestBody::Plain(charset.decode(bytes)?),
+            (None, Some(boundary)) => match RequestBody::multipart_fields(&bytes, boundary) {
+                Some(fields) => RequestBody::FormData(fields),
+                None => RequestBody::Bytes(bytes),
+            },
+            (None, None) => RequestBody::Bytes(bytes),
+        };
+
+        Ok(())
     }
 }
 
@@ -376,11 +1126,11 @@ mod tests {
     fn test_request() {
         let mut parser = StrParser::from_str("/somepath");
         let path = URIPath::parse(&mut parser).unwrap();
-        let mut parser = StrParser::from_str("?some=query");
+        let mut parser = StrParser::from_str("some=query");
         let query = RequestQuery::parse(&mut parser).unwrap();
 
         let mut parser = StrParser::from_str(
-            "GET /somepath?some=query HTTP/1.1\r\nHost: 127.0.0.1:8000\r\nUser-Agent: curl/8.14.1\r\nAccept: */*",
+            "GET /somepath?some=query HTTP/1.1\r\nHost: 127.0.0.1:8000\r\nUser-Agent: curl/8.14.1\r\nAccept: */*\r\n\r\n",
         );
         let mut headers = HashMap::new();
         headers.insert(
@@ -403,7 +1153,8 @@ mod tests {
                 query: query,
                 http_version: HTTPVersion { major: 1, minor: 1 },
                 headers,
-                body: RequestBody::Empty
+                body: RequestBody::Empty,
+                peer_addr: None,
             })
         );
     }
@@ -411,7 +1162,7 @@ mod tests {
     fn test_request_body() {
         let mut parser = StrParser::from_str("/somepath");
         let path = URIPath::parse(&mut parser).unwrap();
-        let mut parser = StrParser::from_str("?some=query");
+        let mut parser = StrParser::from_str("some=query");
         let query = RequestQuery::parse(&mut parser).unwrap();
 
         let mut parser = StrParser::from_str(
@@ -442,8 +1193,212 @@ mod tests {
                 query: query,
                 http_version: HTTPVersion { major: 1, minor: 1 },
                 headers,
-                body: RequestBody::Plain(String::from("this is a test"))
+                body: RequestBody::Bytes(b"this is a test".to_vec()),
+                peer_addr: None,
             })
         );
     }
+
+    #[test]
+    fn test_body_too_large() {
+        let raw = "POST /upload HTTP/1.1\r\nContent-Length: 999999999999\r\n\r\nsmall";
+        assert_eq!(
+            Request::parse_with_limits(&mut StrParser::from_str(raw), 100, 1024),
+            Err(ParseErr::BodyTooLarge {
+                requested: 999999999999,
+                max: 1024
+            })
+        );
+
+        let raw = "POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nsmall";
+        let request = Request::parse_with_limits(&mut StrParser::from_str(raw), 100, 5).unwrap();
+        assert_eq!(request.body, RequestBody::Bytes(b"small".to_vec()));
+    }
+
+    #[test]
+    fn test_drain_chunked() {
+        let mut parser = StrParser::from_str(
+            "4\r\nWiki\r\n7;ext=1\r\npedia i\r\n0\r\nExpires: never\r\n\r\nGET",
+        );
+        assert_eq!(RequestBody::drain_chunked(&mut parser, 64), Ok(11));
+        assert_eq!(parser.peek(), Some(b'G'));
+
+        let mut parser = StrParser::from_str("4\r\nWiki\r\n7\r\npedia i\r\n0\r\n\r\n");
+        assert_eq!(
+            RequestBody::drain_chunked(&mut parser, 8),
+            Err(ParseErr::BodyTooLarge {
+                requested: 11,
+                max: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_bare_lf_line_endings() {
+        let crlf = "POST /submit?x=1 HTTP/1.1\r\nHost: example.com\r\nX-Folded: a\r\n b\r\nContent-Length: 4\r\n\r\nbody";
+        let lf = crlf.replace("\r\n", "\n");
+        let expected = Request::parse(&mut StrParser::from_str(crlf)).unwrap();
+        let request = Request::parse(&mut StrParser::from_str(&lf)).unwrap();
+        assert_eq!(request.method, expected.method);
+        assert_eq!(request.path, expected.path);
+        assert_eq!(request.query, expected.query);
+        assert_eq!(request.headers, expected.headers);
+        assert_eq!(request.body, RequestBody::Bytes(b"body".to_vec()));
+
+        let mut parser = StrParser::from_str("4\nWiki\r\n0\n\nGET");
+        assert_eq!(RequestBody::drain_chunked(&mut parser, 64), Ok(4));
+        assert_eq!(parser.peek(), Some(b'G'));
+
+        let mut parser = StrParser::from_str("HTTP/1.1 404 Not Found\nContent-Length: 2\n\nno");
+        let response = crate::http::response::Response::parse(&mut parser).unwrap();
+        assert_eq!(
+            response.status_code(),
+            &crate::http::response::StatusCode::NotFound
+        );
+        assert_eq!(response.body(), Some("no"));
+    }
+
+    #[test]
+    fn test_request_body_charset() {
+        let mut raw = b"POST /form HTTP/1.1\r\nContent-Type: text/plain; charset=ISO-8859-1\r\nContent-Length: 4\r\n\r\ncaf".to_vec();
+        raw.push(0xE9);
+        let mut parser = Parser::from_stream(std::io::Cursor::new(raw));
+        let request = Request::parse(&mut parser).unwrap();
+        assert_eq!(request.body, RequestBody::Plain(String::from("caf\u{e9}")));
+        let content_type = request.content_type().unwrap();
+        assert_eq!(content_type.subtype, "plain");
+        assert_eq!(content_type.charset(), Some("ISO-8859-1"));
+
+        let mut raw =
+            b"POST /form HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\ncaf"
+                .to_vec();
+        raw.push(0xE9);
+        let mut parser = Parser::from_stream(std::io::Cursor::new(raw));
+        assert_eq!(Request::parse(&mut parser), Err(ParseErr::InvalidUTF8));
+
+        // binary uploads, and bodies of no declared type, are not decoded
+        for content_type in ["Content-Type: application/octet-stream\r\n", ""] {
+            let mut raw = format!(
+                "POST /upload HTTP/1.1\r\n{}Content-Length: 3\r\n\r\n",
+                content_type
+            )
+            .into_bytes();
+            raw.extend_from_slice(&[0xFF, 0x00, 0xE9]);
+            let mut parser = Parser::from_stream(std::io::Cursor::new(raw));
+            let request = Request::parse(&mut parser).unwrap();
+            assert_eq!(request.body, RequestBody::Bytes(vec![0xFF, 0x00, 0xE9]));
+        }
+
+        let raw = "POST /form HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 3\r\n\r\na=1";
+        let request = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+        assert_eq!(request.body, RequestBody::Plain(String::from("a=1")));
+    }
+
+    #[test]
+    fn test_multipart_form_data() {
+        let request = |body: &[u8]| {
+            let mut raw = format!(
+                "POST /form HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"--Zero Boundary\"\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            raw.extend_from_slice(body);
+            Request::parse(&mut Parser::from_stream(std::io::Cursor::new(raw))).unwrap()
+        };
+
+        let body = "preamble\r\n----Zero Boundary\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Hello\r\nworld\r\n\
+            ----Zero Boundary  \r\n\
+            content-disposition: form-data; name=\"note\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            hi\r\n\
+            ----Zero Boundary--\r\nepilogue";
+        assert_eq!(
+            request(body.as_bytes()).body,
+            RequestBody::FormData(HashMap::from([
+                (String::from("title"), String::from("Hello\r\nworld")),
+                (String::from("note"), String::from("hi")),
+            ]))
+        );
+
+        // binary uploads and malformed bodies are kept as sent
+        let mut binary =
+            b"----Zero Boundary\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\n".to_vec();
+        binary.extend_from_slice(&[0xFF, 0x00]);
+        binary.extend_from_slice(b"\r\n----Zero Boundary--");
+        assert_eq!(request(&binary).body, RequestBody::Bytes(binary.clone()));
+        let unclosed = b"----Zero Boundary\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\nx";
+        assert_eq!(
+            request(unclosed).body,
+            RequestBody::Bytes(unclosed.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_te() {
+        let mut parser = StrParser::from_str("trailers, deflate;q=0.5");
+        let te = TE::parse(&mut parser).unwrap();
+        assert!(te.accepts_trailers());
+        assert!(te.accepts("deflate"));
+        assert!(te.accepts("chunked"));
+        assert!(!te.accepts("gzip"));
+        assert_eq!(
+            te.codings(),
+            &[TransferCoding {
+                name: String::from("deflate"),
+                q: QValue::parse(&mut StrParser::from_str("0.5")).unwrap(),
+            }]
+        );
+        assert_eq!(te.to_string(), "trailers, deflate;q=0.5");
+
+        // quoted parameters and a Q key are handled like any other header parameters
+        let mut parser = StrParser::from_str("gzip ; level=\"a;b, c\" ; Q = 0 , deflate");
+        let te = TE::parse(&mut parser).unwrap();
+        assert_eq!(te.codings().len(), 2);
+        assert!(te.accepts("deflate"));
+        let mut parser = StrParser::from_str("gzip;q=0.5x");
+        assert!(TE::parse(&mut parser).is_err());
+
+        let mut parser = StrParser::from_str("gzip;q=0");
+        let te = TE::parse(&mut parser).unwrap();
+        assert!(!te.accepts_trailers());
+        assert!(!te.accepts("gzip"));
+
+        let mut parser = StrParser::from_str("GET / HTTP/1.1\r\nTE: deflate, trailers\r\n\r\n");
+        let request = Request::parse(&mut parser).unwrap();
+        assert!(request.accepts_trailers());
+    }
+
+    #[test]
+    fn test_accept_list() {
+        let mut parser =
+            StrParser::from_str("text/*;q=0.3, text/html;level=1, application/json;q=0");
+        let accept = AcceptList::parse(&mut parser).unwrap();
+        assert_eq!(accept.ranges().len(), 3);
+        assert_eq!(accept.quality("text/html"), QValue::MAX);
+        assert_eq!(accept.quality("text/plain").thousandths(), 300);
+        assert_eq!(accept.quality("application/json"), QValue(0));
+        assert_eq!(accept.best_match(&["application/json"]), None);
+        assert_eq!(
+            accept.best_match(&["text/plain", "text/html"]),
+            Some("text/html")
+        );
+
+        // quoted parameters and a Q key are handled like any other header parameters
+        let mut parser =
+            StrParser::from_str("text/html ; profile=\"a;b, c\" ; Q = 0.5 , image/png");
+        let accept = AcceptList::parse(&mut parser).unwrap();
+        assert_eq!(accept.ranges().len(), 2);
+        assert_eq!(accept.quality("text/html").thousandths(), 500);
+        assert_eq!(accept.quality("image/png"), QValue::MAX);
+
+        let mut parser = StrParser::from_str("text/html;q=0.5x");
+        assert!(AcceptList::parse(&mut parser).is_err());
+
+        assert_eq!(
+            AcceptList::default().best_match(&["application/json", "text/html"]),
+            Some("application/json")
+        );
+    }
 }
@@ -1,9 +1,15 @@
 use super::{
-    EntityHeader, FromMessageHeader, GeneralHeader, HTTPVersion, MessageHeader, ToMessageHeader,
+    EntityHeader, FromMessageHeader, GeneralHeader, HTTPVersion, HeaderCategory, Headers,
+    MessageHeader, ToMessageHeader,
 };
 use crate::parsing::prelude::*;
 use crate::stream_writer::{StreamResult, StreamWritable};
-use std::{collections::HashMap, io::Read};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 /// Based on RFC 2616 section 6.1.1
 ///
@@ -33,6 +39,7 @@ use std::{collections::HashMap, io::Read};
 ///           | "304"  ; Section 10.3.5: Not Modified
 ///           | "305"  ; Section 10.3.6: Use Proxy
 ///           | "307"  ; Section 10.3.8: Temporary Redirect
+///           | "308"  ; RFC 7538: Permanent Redirect
 ///           | "400"  ; Section 10.4.1: Bad Request
 ///           | "401"  ; Section 10.4.2: Unauthorized
 ///           | "402"  ; Section 10.4.3: Payment Required
@@ -51,6 +58,11 @@ use std::{collections::HashMap, io::Read};
 ///           | "415"  ; Section 10.4.16: Unsupported Media Type
 ///           | "416"  ; Section 10.4.17: Requested range not satisfiable
 ///           | "417"  ; Section 10.4.18: Expectation Failed
+///           | "421"  ; RFC 7540 Section 9.1.2: Misdirected Request
+///           | "426"  ; RFC 7231 Section 6.5.15: Upgrade Required
+///           | "429"  ; RFC 6585 Section 4: Too Many Requests
+///           | "431"  ; RFC 6585 Section 5: Request Header Fields Too Large
+///           | "451"  ; RFC 7725: Unavailable For Legal Reasons
 ///           | "500"  ; Section 10.5.1: Internal Server Error
 ///           | "501"  ; Section 10.5.2: Not Implemented
 ///           | "502"  ; Section 10.5.3: Bad Gateway
@@ -61,7 +73,7 @@ use std::{collections::HashMap, io::Read};
 ///
 ///       extension-code = 3DIGIT
 /// ```
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum StatusCode {
     Continue,                     // "100"  ; Section 10.1.1:
     SwitchingProtocols,           // "101"  ; Section 10.1.2:
@@ -79,6 +91,7 @@ pub enum StatusCode {
     NotModified,                  // "304"  ; Section 10.3.5:
     UseProxy,                     // "305"  ; Section 10.3.6:
     TemporaryRedirect,            // "307"  ; Section 10.3.8:
+    PermanentRedirect,            // "308"  ; RFC 7538:
     BadRequest,                   // "400"  ; Section 10.4.1:
     Unauthorized,                 // "401"  ; Section 10.4.2:
     PaymentRequired,              // "402"  ; Section 10.4.3:
@@ -97,6 +110,11 @@ pub enum StatusCode {
     UnsupportedMediaType,         // "415"  ; Section 10.4.16:
     RequestedRangeNotSatisfiable, // "416"  ; Section 10.4.17:
     ExpectationFailed,            // "417"  ; Section 10.4.18:
+    MisdirectedRequest,           // "421"  ; RFC 7540 Section 9.1.2:
+    UpgradeRequired,              // "426"  ; RFC 7231 Section 6.5.15:
+    TooManyRequests,              // "429"  ; RFC 6585 Section 4:
+    RequestHeaderFieldsTooLarge,  // "431"  ; RFC 6585 Section 5:
+    UnavailableForLegalReasons,   // "451"  ; RFC 7725:
     InternalServerError,          // "500"  ; Section 10.5.1:
     NotImplemented,               // "501"  ; Section 10.5.2:
     BadGateway,                   // "502"  ; Section 10.5.3:
@@ -126,6 +144,7 @@ impl StatusCode {
             Self::NotModified => Some("304"),        // "304"  ; Section 10.3.5:
             Self::UseProxy => Some("305"),           // "305"  ; Section 10.3.6:
             Self::TemporaryRedirect => Some("307"),  // "307"  ; Section 10.3.8:
+            Self::PermanentRedirect => Some("308"),  // "308"  ; RFC 7538:
             Self::BadRequest => Some("400"),         // "400"  ; Section 10.4.1:
             Self::Unauthorized => Some("401"),       // "401"  ; Section 10.4.2:
             Self::PaymentRequired => Some("402"),    // "402"  ; Section 10.4.3:
@@ -144,6 +163,11 @@ impl StatusCode {
             Self::UnsupportedMediaType => Some("415"), // "415"  ; Section 10.4.16:
             Self::RequestedRangeNotSatisfiable => Some("416"), // "416"  ; Section 10.4.17:
             Self::ExpectationFailed => Some("417"),  // "417"  ; Section 10.4.18:
+            Self::MisdirectedRequest => Some("421"), // "421"  ; RFC 7540 Section 9.1.2:
+            Self::UpgradeRequired => Some("426"),    // "426"  ; RFC 7231 Section 6.5.15:
+            Self::TooManyRequests => Some("429"),    // "429"  ; RFC 6585 Section 4:
+            Self::RequestHeaderFieldsTooLarge => Some("431"), // "431"  ; RFC 6585 Section 5:
+            Self::UnavailableForLegalReasons => Some("451"), // "451"  ; RFC 7725:
             Self::InternalServerError => Some("500"), // "500"  ; Section 10.5.1:
             Self::NotImplemented => Some("501"),     // "501"  ; Section 10.5.2:
             Self::BadGateway => Some("502"),         // "502"  ; Section 10.5.3:
@@ -153,6 +177,95 @@ impl StatusCode {
             Self::ExtensionCode(_) => None,
         }
     }
+    /// Numeric code, e.g. `404` for `NotFound`
+    pub const fn as_code(&self) -> u16 {
+        match (self, self.as_str()) {
+            (Self::ExtensionCode(n), _) => *n,
+            (_, Some(s)) => {
+                let b = s.as_bytes();
+                (b[0] - b'0') as u16 * 100 + (b[1] - b'0') as u16 * 10 + (b[2] - b'0') as u16
+            }
+            (_, None) => 0,
+        }
+    }
+    /// Reason phrase recommended by the RFC defining the code, empty for extension codes
+    pub const fn reason_phrase(&self) -> &'static str {
+        match self {
+            Self::Continue => "Continue",
+            Self::SwitchingProtocols => "Switching Protocols",
+            Self::OK => "OK",
+            Self::Created => "Created",
+            Self::Accepted => "Accepted",
+            Self::NonAuthoritativeInformation => "Non-Authoritative Information",
+            Self::NoContent => "No Content",
+            Self::ResetContent => "Reset Content",
+            Self::PartialContent => "Partial Content",
+            Self::MultipleChoices => "Multiple Choices",
+            Self::MovedPermanently => "Moved Permanently",
+            Self::Found => "Found",
+            Self::SeeOther => "See Other",
+            Self::NotModified => "Not Modified",
+            Self::UseProxy => "Use Proxy",
+            Self::TemporaryRedirect => "Temporary Redirect",
+            Self::PermanentRedirect => "Permanent Redirect",
+            Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
+            Self::PaymentRequired => "Payment Required",
+            Self::Forbidden => "Forbidden",
+            Self::NotFound => "Not Found",
+            Self::MethodNotAllowed => "Method Not Allowed",
+            Self::NotAcceptable => "Not Acceptable",
+            Self::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            Self::RequestTimeout => "Request Time-out",
+            Self::Conflict => "Conflict",
+            Self::Gone => "Gone",
+            Self::LengthRequired => "Length Required",
+            Self::PreconditionFailed => "Precondition Failed",
+            Self::RequestEntityTooLarge => "Request Entity Too Large",
+            Self::RequestUriTooLarge => "Request-URI Too Large",
+            Self::UnsupportedMediaType => "Unsupported Media Type",
+            Self::RequestedRangeNotSatisfiable => "Requested range not satisfiable",
+            Self::ExpectationFailed => "Expectation Failed",
+            Self::MisdirectedRequest => "Misdirected Request",
+            Self::UpgradeRequired => "Upgrade Required",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Self::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            Self::InternalServerError => "Internal Server Error",
+            Self::NotImplemented => "Not Implemented",
+            Self::BadGateway => "Bad Gateway",
+            Self::ServiceUnavailable => "Service Unavailable",
+            Self::GatewayTimeout => "Gateway Time-out",
+            Self::HTTPVersionNotSupported => "HTTP Version not supported",
+            Self::ExtensionCode(_) => "",
+        }
+    }
+    /// Whether a response with the code can carry a body. Informational (1xx), `204 No
+    /// Content` and `304 Not Modified` responses can't, see rfc7230 section 3.3.
+    pub const fn allows_body(&self) -> bool {
+        !matches!(self.as_code(), 100..200 | 204 | 304)
+    }
+    /// Whether the code redirects the client to the `Location` header
+    pub const fn is_redirect(&self) -> bool {
+        matches!(
+            self,
+            Self::MovedPermanently
+                | Self::Found
+                | Self::SeeOther
+                | Self::TemporaryRedirect
+                | Self::PermanentRedirect
+        )
+    }
+    /// Whether the code is a client (4xx) or server (5xx) error
+    pub fn is_error(&self) -> bool {
+        match self {
+            Self::ExtensionCode(code) => (400..600).contains(code),
+            _ => matches!(
+                self.as_str().and_then(|s| s.bytes().next()),
+                Some(b'4' | b'5')
+            ),
+        }
+    }
     pub const fn from_code(n: u16) -> Result<Self, ParseErr> {
         match n {
             100 => Ok(Self::Continue),                     // "100"  ; Section 10.1.1:
@@ -171,6 +284,7 @@ impl StatusCode {
             304 => Ok(Self::NotModified),                  // "304"  ; Section 10.3.5:
             305 => Ok(Self::UseProxy),                     // "305"  ; Section 10.3.6:
             307 => Ok(Self::TemporaryRedirect),            // "307"  ; Section 10.3.8:
+            308 => Ok(Self::PermanentRedirect),            // "308"  ; RFC 7538:
             400 => Ok(Self::BadRequest),                   // "400"  ; Section 10.4.1:
             401 => Ok(Self::Unauthorized),                 // "401"  ; Section 10.4.2:
             402 => Ok(Self::PaymentRequired),              // "402"  ; Section 10.4.3:
@@ -189,6 +303,11 @@ impl StatusCode {
             415 => Ok(Self::UnsupportedMediaType),         // "415"  ; Section 10.4.16:
             416 => Ok(Self::RequestedRangeNotSatisfiable), // "416"  ; Section 10.4.17:
             417 => Ok(Self::ExpectationFailed),            // "417"  ; Section 10.4.18:
+            421 => Ok(Self::MisdirectedRequest),           // "421"  ; RFC 7540 Section 9.1.2:
+            426 => Ok(Self::UpgradeRequired),              // "426"  ; RFC 7231 Section 6.5.15:
+            429 => Ok(Self::TooManyRequests),              // "429"  ; RFC 6585 Section 4:
+            431 => Ok(Self::RequestHeaderFieldsTooLarge),  // "431"  ; RFC 6585 Section 5:
+            451 => Ok(Self::UnavailableForLegalReasons),   // "451"  ; RFC 7725:
             500 => Ok(Self::InternalServerError),          // "500"  ; Section 10.5.1:
             501 => Ok(Self::NotImplemented),               // "501"  ; Section 10.5.2:
             502 => Ok(Self::BadGateway),                   // "502"  ; Section 10.5.3:
@@ -238,6 +357,7 @@ impl<R: Read> Parsable<R> for StatusCode {
             b"304" => Ok(Self::NotModified),        // "304"  ; Section 10.3.5:
             b"305" => Ok(Self::UseProxy),           // "305"  ; Section 10.3.6:
             b"307" => Ok(Self::TemporaryRedirect),  // "307"  ; Section 10.3.8:
+            b"308" => Ok(Self::PermanentRedirect),  // "308"  ; RFC 7538:
             b"400" => Ok(Self::BadRequest),         // "400"  ; Section 10.4.1:
             b"401" => Ok(Self::Unauthorized),       // "401"  ; Section 10.4.2:
             b"402" => Ok(Self::PaymentRequired),    // "402"  ; Section 10.4.3:
@@ -256,6 +376,11 @@ impl<R: Read> Parsable<R> for StatusCode {
             b"415" => Ok(Self::UnsupportedMediaType), // "415"  ; Section 10.4.16:
             b"416" => Ok(Self::RequestedRangeNotSatisfiable), // "416"  ; Section 10.4.17:
             b"417" => Ok(Self::ExpectationFailed),  // "417"  ; Section 10.4.18:
+            b"421" => Ok(Self::MisdirectedRequest), // "421"  ; RFC 7540 Section 9.1.2:
+            b"426" => Ok(Self::UpgradeRequired),    // "426"  ; RFC 7231 Section 6.5.15:
+            b"429" => Ok(Self::TooManyRequests),    // "429"  ; RFC 6585 Section 4:
+            b"431" => Ok(Self::RequestHeaderFieldsTooLarge), // "431"  ; RFC 6585 Section 5:
+            b"451" => Ok(Self::UnavailableForLegalReasons), // "451"  ; RFC 7725:
             b"500" => Ok(Self::InternalServerError), // "500"  ; Section 10.5.1:
             b"501" => Ok(Self::NotImplemented),     // "501"  ; Section 10.5.2:
             b"502" => Ok(Self::BadGateway),         // "502"  ; Section 10.5.3:
@@ -275,9 +400,7 @@ impl<R: Read> Parsable<R> for StatusCode {
 
 impl<W: std::io::Write> StreamWritable<W> for StatusCode {
     fn write_to_stream(self, stream: &mut W) -> StreamResult {
-        if let Some(s) = self.as_str() {
-            write!(stream, "{}", s)?;
-        }
+        write!(stream, "{:03}", self.as_code())?;
         Ok(())
     }
 }
@@ -298,7 +421,7 @@ impl<W: std::io::Write> StreamWritable<W> for StatusCode {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ResponseHeader {
     AcceptRanges(String),      // Section 14.5
-    Age(String),               // Section 14.6
+    Age(u64),                  // Section 14.6
     ETag(String),              // Section 14.19
     Location(String),          // Section 14.30
     ProxyAuthenticate(String), // Section 14.33
@@ -309,6 +432,24 @@ pub enum ResponseHeader {
 }
 
 impl ResponseHeader {
+    /// What a `delta-seconds` value too large to represent is taken as, per rfc7234
+    /// Section 1.2.1
+    pub const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+    /// Parses `delta-seconds`, i.e. `1*DIGIT`, clamping it to `MAX_DELTA_SECONDS`.
+    pub fn parse_delta_seconds(val: &str) -> ParseResult<u64> {
+        let val = val.trim();
+        if val.is_empty() || !val.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseErr::FailedToParseNum {
+                found: val.to_string(),
+                radix: 10,
+            });
+        }
+        Ok(val.parse::<u64>().map_or(Self::MAX_DELTA_SECONDS, |secs| {
+            secs.min(Self::MAX_DELTA_SECONDS)
+        }))
+    }
+
     pub const fn name(&self) -> &'static str {
         match self {
             Self::AcceptRanges(_) => "accept-ranges", // Section 14.5
@@ -326,16 +467,7 @@ impl ResponseHeader {
 
 impl FromMessageHeader for ResponseHeader {
     fn can_convert(eh: &MessageHeader) -> bool {
-        let name = eh.name.as_str();
-        name== "accept-ranges" // Section 14.5
-            ||name== "age"                    // Section 14.6
-            ||name== "etag"                  // Section 14.19
-            ||name== "location"          // Section 14.30
-            ||name== "proxy-authenticate" // Section 14.33
-            ||name== "retry-after"     // Section 14.37
-            ||name== "server"              // Section 14.38
-            ||name== "vary"                  // Section 14.44
-            ||name== "www-authenticate" // Section 14.47
+        HeaderCategory::of(eh.name()) == HeaderCategory::Response
     }
 
     fn from_extension_header(eh: MessageHeader) -> ParseResult<(String, Self)> {
@@ -343,7 +475,7 @@ impl FromMessageHeader for ResponseHeader {
         let name = eh.name.as_str();
         let header = match name {
             "accept-ranges" => Self::AcceptRanges(val), // Section 14.5
-            "age" => Self::Age(val),                    // Section 14.6
+            "age" => Self::Age(Self::parse_delta_seconds(&val)?), // Section 14.6
             "etag" => Self::ETag(val),                  // Section 14.19
             "location" => Self::Location(val),          // Section 14.30
             "proxy-authenticate" => Self::ProxyAuthenticate(val), // Section 14.33
@@ -363,15 +495,15 @@ impl FromMessageHeader for ResponseHeader {
 impl ToMessageHeader for ResponseHeader {
     fn consume_value_as_string(self) -> String {
         match self {
-            Self::AcceptRanges(s) => s,      // Section 14.5
-            Self::Age(s) => s,               // Section 14.6
-            Self::ETag(s) => s,              // Section 14.19
-            Self::Location(s) => s,          // Section 14.30
-            Self::ProxyAuthenticate(s) => s, // Section 14.33
-            Self::RetryAfter(s) => s,        // Section 14.37
-            Self::Server(s) => s,            // Section 14.38
-            Self::Vary(s) => s,              // Section 14.44
-            Self::WWWAuthenticate(s) => s,   // Section 14.47
+            Self::AcceptRanges(s) => s,          // Section 14.5
+            Self::Age(secs) => secs.to_string(), // Section 14.6
+            Self::ETag(s) => s,                  // Section 14.19
+            Self::Location(s) => s,              // Section 14.30
+            Self::ProxyAuthenticate(s) => s,     // Section 14.33
+            Self::RetryAfter(s) => s,            // Section 14.37
+            Self::Server(s) => s,                // Section 14.38
+            Self::Vary(s) => s,                  // Section 14.44
+            Self::WWWAuthenticate(s) => s,       // Section 14.47
         }
     }
     fn to_msg_header(self) -> MessageHeader {
@@ -431,10 +563,11 @@ impl StatusLine {
         }
     }
     pub fn new_simple(status_code: StatusCode) -> Self {
+        let reason_phrase = ReasonPhrase(status_code.reason_phrase().to_string());
         StatusLine {
             http_version: HTTPVersion::default(),
             status_code,
-            reason_phrase: ReasonPhrase(String::new()),
+            reason_phrase,
         }
     }
 }
@@ -459,10 +592,8 @@ impl<W: std::io::Write> StreamWritable<W> for StatusLine {
         self.http_version.write_to_stream(stream)?;
         write!(stream, " ")?;
         self.status_code.write_to_stream(stream)?;
-        if !self.reason_phrase.0.is_empty() {
-            write!(stream, " ")?;
-            self.reason_phrase.write_to_stream(stream)?;
-        }
+        write!(stream, " ")?;
+        self.reason_phrase.write_to_stream(stream)?;
         write!(stream, "\r\n")?;
 
         Ok(())
@@ -525,35 +656,41 @@ impl ResponseHeaderMap {
     }
 }
 
+impl FromMessageHeader for ResponseHeaderType {
+    fn can_convert(_eh: &MessageHeader) -> bool {
+        true
+    }
+
+    fn from_extension_header(header: MessageHeader) -> ParseResult<(String, Self)> {
+        match HeaderCategory::of(header.name()) {
+            HeaderCategory::General => {
+                let (name, header) = header.into_header()?;
+                Ok((name, ResponseHeaderType::GeneralHeader(header)))
+            }
+            HeaderCategory::Response => {
+                let (name, header) = header.into_header()?;
+                Ok((name, ResponseHeaderType::ResponseHeader(header)))
+            }
+            HeaderCategory::Entity => {
+                let (name, header) = header.into_header()?;
+                Ok((name, ResponseHeaderType::EntityHeader(header)))
+            }
+            // request headers mean nothing in a response
+            HeaderCategory::Request | HeaderCategory::Extension => {
+                let (name, value) = header.extract_name_val();
+                Ok((
+                    name.clone(),
+                    ResponseHeaderType::ExtensionHeader { name, value },
+                ))
+            }
+        }
+    }
+}
+
 impl<R: Read> Parsable<R> for ResponseHeaderMap {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
-        let header = MessageHeader::parse(parser);
-        let header = header?;
-        if GeneralHeader::can_convert(&header) {
-            let (name, header) = header.into_header()?;
-            Ok(Self {
-                name,
-                ty: ResponseHeaderType::GeneralHeader(header),
-            })
-        } else if ResponseHeader::can_convert(&header) {
-            let (name, header) = header.into_header()?;
-            Ok(Self {
-                name,
-                ty: ResponseHeaderType::ResponseHeader(header),
-            })
-        } else if EntityHeader::can_convert(&header) {
-            let (name, header) = header.into_header()?;
-            Ok(Self {
-                name,
-                ty: ResponseHeaderType::EntityHeader(header),
-            })
-        } else {
-            let (name, value) = header.extract_name_val();
-            Ok(Self {
-                name: name.clone(),
-                ty: ResponseHeaderType::ExtensionHeader { name, value },
-            })
-        }
+        let (name, ty) = MessageHeader::parse(parser)?.into_header()?;
+        Ok(Self { name, ty })
     }
 }
 /// Based on RFC 2616 section 6
@@ -571,7 +708,150 @@ impl<R: Read> Parsable<R> for ResponseHeaderMap {
 pub struct Response {
     status_line: StatusLine,
     headers: HashMap<String, String>,
-    body: Option<String>,
+    body: Option<ResponseBody>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResponseBody {
+    Text(String),
+    /// Streamed from disk when the response is written, see `Response::from_file`
+    File {
+        path: PathBuf,
+        len: u64,
+    },
+    /// Sent with chunked transfer coding, see `From<StreamingResponse>`
+    Chunked(Box<ChunkedBody>),
+}
+
+/// Body of a `StreamingResponse` turned into a `Response`, so handlers can return one.
+pub struct ChunkedBody {
+    chunks: Box<dyn Iterator<Item = Vec<u8>> + Send>,
+    trailers: Vec<(String, String)>,
+    accepts_trailers: bool,
+    /// Sent as is and ended by closing the connection, see `Response::close_delimit`
+    close_delimited: bool,
+}
+
+impl std::fmt::Debug for ChunkedBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkedBody")
+            .field("trailers", &self.trailers)
+            .field("accepts_trailers", &self.accepts_trailers)
+            .field("close_delimited", &self.close_delimited)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Chunks can't be compared without consuming them, two bodies are only equal when
+/// they produce their chunks from the same iterator.
+impl PartialEq for ChunkedBody {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self.chunks.as_ref(), other.chunks.as_ref())
+    }
+}
+
+impl Eq for ChunkedBody {}
+
+impl ChunkedBody {
+    /// Writes the `transfer-encoding` header, and the `trailer` header listing the
+    /// names of `trailers` when there are any.
+    fn write_head<W: std::io::Write>(
+        trailers: &[(String, String)],
+        stream: &mut W,
+    ) -> StreamResult {
+        write!(stream, "transfer-encoding:chunked\r\n")?;
+        if !trailers.is_empty() {
+            let names: Vec<&str> = trailers.iter().map(|(n, _)| n.as_str()).collect();
+            write!(stream, "trailer:{}\r\n", names.join(", "))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every chunk as it is produced, then the last chunk followed by `trailers`
+    fn write_chunks<W: std::io::Write, I: Iterator>(
+        chunks: I,
+        trailers: Vec<(String, String)>,
+        stream: &mut W,
+    ) -> StreamResult
+    where
+        I::Item: AsRef<[u8]>,
+    {
+        for chunk in chunks {
+            let chunk = chunk.as_ref();
+            // a zero length chunk would end the body early
+            if chunk.is_empty() {
+                continue;
+            }
+            write!(stream, "{:x}\r\n", chunk.len())?;
+            stream.write_all(chunk)?;
+            write!(stream, "\r\n")?;
+        }
+        write!(stream, "0\r\n")?;
+        for (name, val) in trailers {
+            write!(stream, "{}:{}\r\n", name, val)?;
+        }
+        write!(stream, "\r\n")?;
+
+        Ok(())
+    }
+}
+
+impl ResponseBody {
+    /// Size of the blocks file bodies are copied to the stream in
+    pub const BLOCK_SIZE: usize = 8 * 1024;
+
+    /// Length of the body, `0` for chunked bodies whose length isn't known up front
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Text(s) => s.len() as u64,
+            Self::File { len, .. } => *len,
+            Self::Chunked(_) => 0,
+        }
+    }
+
+    /// Chunked bodies never count as empty, they are only known once they are sent
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Chunked(_) => false,
+            _ => self.len() == 0,
+        }
+    }
+
+    /// Opens a file body, checking it still has the length sent as the content-length.
+    ///
+    /// Done before anything is written so a file that changed size fails the response
+    /// instead of sending headers for a body that doesn't match them.
+    fn open_file(path: &Path, len: u64) -> std::io::Result<File> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() != len {
+            return Err(std::io::Error::other(
+                "file changed size before it was sent",
+            ));
+        }
+
+        Ok(file)
+    }
+
+    /// Copies a file body opened by `open_file` in `BLOCK_SIZE` blocks.
+    fn write_file<W: std::io::Write>(mut file: File, len: u64, stream: &mut W) -> StreamResult {
+        let mut block = [0_u8; Self::BLOCK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let want = remaining.min(Self::BLOCK_SIZE as u64) as usize;
+            let read = file.read(&mut block[..want])?;
+            if read == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "file shrank while it was being sent",
+                ));
+            }
+            stream.write_all(&block[..read])?;
+            remaining -= read as u64;
+        }
+
+        Ok(())
+    }
 }
 
 impl Response {
@@ -579,14 +859,94 @@ impl Response {
         Self {
             status_line: StatusLine::new_simple(status),
             headers,
-            body,
+            body: body.map(ResponseBody::Text),
         }
     }
     pub fn new_simple(status: StatusCode, body: Option<String>) -> Self {
         Self {
             status_line: StatusLine::new_simple(status),
             headers: HashMap::new(),
-            body: body,
+            body: body.map(ResponseBody::Text),
+        }
+    }
+    /// 200 response whose body is read from `path` while it is being written.
+    ///
+    /// The file isn't loaded into memory, the content-length is taken from its metadata.
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let metadata = std::fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(std::io::Error::other("not a file"));
+        }
+        let len = metadata.len();
+
+        let mut headers = HashMap::new();
+        headers.insert(String::from("content-length"), len.to_string());
+        Ok(Self {
+            status_line: StatusLine::new_simple(StatusCode::OK),
+            headers,
+            body: Some(ResponseBody::File {
+                path: path.to_path_buf(),
+                len,
+            }),
+        })
+    }
+    pub fn status_code(&self) -> &StatusCode {
+        &self.status_line.status_code
+    }
+    /// Replaces the status code, along with its reason phrase
+    pub fn set_status(&mut self, status: StatusCode) {
+        let http_version = self.status_line.http_version;
+        self.status_line = StatusLine::new_simple(status);
+        self.status_line.http_version = http_version;
+    }
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+    pub fn headers_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut self.headers
+    }
+    /// Whether there is a non-empty body to send
+    pub fn has_body(&self) -> bool {
+        self.body.as_ref().is_some_and(|b| !b.is_empty())
+    }
+    /// Replaces the body, dropping any `content-length` set for the previous one
+    pub fn set_body(&mut self, body: String) {
+        self.headers.remove("content-length");
+        self.body = Some(ResponseBody::Text(body));
+    }
+    /// Lets a chunked body send its trailers, which are dropped unless the request
+    /// `accepts_trailers`. Other bodies have no trailers and are left as they are.
+    pub fn accept_trailers(&mut self, accepts: bool) {
+        if let Some(ResponseBody::Chunked(body)) = &mut self.body {
+            body.accepts_trailers = accepts;
+        }
+    }
+    /// Whether the body is sent with chunked transfer coding
+    pub fn is_chunked(&self) -> bool {
+        matches!(&self.body, Some(ResponseBody::Chunked(body)) if !body.close_delimited)
+    }
+    /// Sends a chunked body without the chunked coding, for HTTP/1.0 clients that can't
+    /// decode it. The body is ended by closing the connection instead, so the response
+    /// carries `Connection: close` and its trailers are dropped.
+    pub fn close_delimit(&mut self) {
+        if let Some(ResponseBody::Chunked(body)) = &mut self.body {
+            body.close_delimited = true;
+            self.headers
+                .insert(String::from("connection"), String::from("close"));
+        }
+    }
+    /// Seconds the response has been cached for according to its `Age` header, `None`
+    /// when the header is missing or malformed
+    pub fn age(&self) -> Option<u64> {
+        let age = self.headers.get("age")?;
+        ResponseHeader::parse_delta_seconds(age).ok()
+    }
+    /// Text body of the response, `None` for empty and file bodies
+    pub fn body(&self) -> Option<&str> {
+        match &self.body {
+            Some(ResponseBody::Text(s)) => Some(s),
+            _ => None,
         }
     }
     pub fn test_response() -> Response {
@@ -619,41 +979,40 @@ impl Response {
                 reason_phrase: ReasonPhrase(String::new()),
             },
             headers,
-            body: Some(msg),
+            body: Some(ResponseBody::Text(msg)),
         }
     }
 }
 
 impl<R: Read> Parsable<R> for Response {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        Self::parse_with_max_body(parser, usize::MAX)
+    }
+}
+
+impl Response {
+    /// Parses a response, failing with `ParseErr::BodyTooLarge` before reading a body
+    /// whose `Content-Length` is over `max_body`.
+    pub fn parse_with_max_body<R: Read>(
+        parser: &mut Parser<R>,
+        max_body: usize,
+    ) -> ParseResult<Self> {
         let status_line = StatusLine::parse(parser)?;
         parser.expect_crlf()?;
 
-        let mut headers = HashMap::new();
-        let mut body_len = None;
-
-        while let Ok(header) = ResponseHeaderMap::parse(parser) {
-            if let ResponseHeaderType::EntityHeader(EntityHeader::ContentLength(len)) = header.ty {
-                body_len = Some(len)
-            }
-            let (k, v) = header.ty.to_msg_header().extract_name_val();
-            headers.insert(k, v);
-        }
-
-        let body = match body_len {
-            Some(body_len) => {
-                if body_len > 0 {
-                    parser.expect_crlf()?;
-                    // eprintln!("{}", parser.peek().unwrap() as char);
-                    // parser.consume_or_err(|c| c == b'\n')?;
-                    // eprintln!("hit");
-                    Some(parser.consume_n(body_len))
-                } else {
-                    None
-                }
+        let headers = Headers::<ResponseHeaderType>::parse_block(parser)?;
+        let body = match headers.get("content-length") {
+            Some(ResponseHeaderType::EntityHeader(EntityHeader::ContentLength(len)))
+                if *len > 0 =>
+            {
+                Some(ResponseBody::Text(parser.consume_n_capped(*len, max_body)?))
             }
-            None => None,
+            _ => None,
         };
+        let headers = headers
+            .into_iter()
+            .map(|(_, ty)| ty.to_msg_header().extract_name_val())
+            .collect();
 
         Ok(Response {
             status_line,
@@ -664,18 +1023,182 @@ impl<R: Read> Parsable<R> for Response {
 }
 impl<W: std::io::Write> StreamWritable<W> for Response {
     fn write_to_stream(self, stream: &mut W) -> StreamResult {
+        let allows_body = self.status_line.status_code.allows_body();
+        let not_modified = self.status_line.status_code == StatusCode::NotModified;
+        let body = self.body.filter(|_| allows_body);
+        let file = match &body {
+            Some(ResponseBody::File { path, len }) => Some(ResponseBody::open_file(path, *len)?),
+            _ => None,
+        };
+        let mut headers = self.headers;
         self.status_line.write_to_stream(stream)?;
-        for (name, val) in self.headers.into_iter() {
+        match &body {
+            // rfc7230 section 3.3.2 forbids a content-length on 1xx and 204 responses, a
+            // 304 may only send the one of the representation it stands in for
+            _ if !allows_body => {
+                headers.remove("transfer-encoding");
+                headers.remove("trailer");
+                if !not_modified {
+                    headers.remove("content-length");
+                }
+            }
+            Some(ResponseBody::Chunked(body)) => {
+                // the chunks delimit the body, a content-length would contradict them
+                headers.remove("content-length");
+                headers.remove("transfer-encoding");
+                headers.remove("trailer");
+                let trailers: &[(String, String)] = if body.accepts_trailers {
+                    &body.trailers
+                } else {
+                    &[]
+                };
+                if !body.close_delimited {
+                    ChunkedBody::write_head(trailers, stream)?;
+                }
+            }
+            // the body length always has to be known so the connection can be reused
+            body if !headers.contains_key("content-length") => {
+                let len = body.as_ref().map_or(0, |b| b.len());
+                write!(stream, "content-length:{}\r\n", len)?;
+            }
+            _ => {}
+        }
+        for (name, val) in headers.into_iter() {
             write!(stream, "{}:{}\r\n", name, val)?;
         }
-        if let Some(body) = self.body {
-            write!(stream, "\r\n{}", body)?;
+        write!(stream, "\r\n")?;
+        match (body, file) {
+            (Some(ResponseBody::Text(body)), _) => write!(stream, "{}", body)?,
+            (Some(ResponseBody::File { len, .. }), Some(file)) => {
+                ResponseBody::write_file(file, len, stream)?
+            }
+            (Some(ResponseBody::Chunked(body)), _) if body.close_delimited => {
+                for chunk in body.chunks {
+                    stream.write_all(&chunk)?;
+                }
+            }
+            (Some(ResponseBody::Chunked(body)), _) => {
+                let trailers = if body.accepts_trailers {
+                    body.trailers
+                } else {
+                    Vec::new()
+                };
+                ChunkedBody::write_chunks(body.chunks, trailers, stream)?
+            }
+            _ => {}
         }
 
         Ok(())
     }
 }
 
+/// Response sent with chunked transfer coding, for bodies produced piece by piece.
+///
+/// Every item of `chunks` goes out as one chunk as soon as it is produced. Trailers
+/// are buffered and written after the terminating zero-length chunk, their names being
+/// listed in the `Trailer` header up front. They are only sent once `accepts_trailers`
+/// says the request allows them, which the server does for responses handlers return,
+/// and dropped otherwise.
+///
+/// Based on rfc2616 Section 3.6.1
+///
+/// # Augmented Backus-Naur Form
+/// ```text
+/// Chunked-Body   = *chunk
+///                  last-chunk
+///                  trailer
+///                  CRLF
+/// trailer        = *(entity-header CRLF)
+/// ```
+pub struct StreamingResponse<I: Iterator>
+where
+    I::Item: AsRef<[u8]>,
+{
+    status_line: StatusLine,
+    headers: HashMap<String, String>,
+    trailers: Vec<(String, String)>,
+    accepts_trailers: bool,
+    chunks: I,
+}
+
+impl<I: Iterator> StreamingResponse<I>
+where
+    I::Item: AsRef<[u8]>,
+{
+    pub fn new(status: StatusCode, chunks: I) -> Self {
+        StreamingResponse {
+            status_line: StatusLine::new_simple(status),
+            headers: HashMap::new(),
+            trailers: Vec::new(),
+            accepts_trailers: false,
+            chunks,
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers
+            .insert(name.into().to_ascii_lowercase(), value.into());
+        self
+    }
+
+    /// Adds a header sent after the last chunk, e.g. a checksum of the body.
+    pub fn trailer(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.trailers
+            .push((name.into().to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    /// Sends the trailers, for when the request `accepts_trailers`
+    pub fn accepts_trailers(mut self, accepts: bool) -> Self {
+        self.accepts_trailers = accepts;
+        self
+    }
+
+    pub fn trailers(&self) -> &[(String, String)] {
+        &self.trailers
+    }
+}
+
+impl<I> From<StreamingResponse<I>> for Response
+where
+    I: Iterator + Send + 'static,
+    I::Item: AsRef<[u8]>,
+{
+    fn from(streaming: StreamingResponse<I>) -> Self {
+        Response {
+            status_line: streaming.status_line,
+            headers: streaming.headers,
+            body: Some(ResponseBody::Chunked(Box::new(ChunkedBody {
+                chunks: Box::new(streaming.chunks.map(|chunk| chunk.as_ref().to_vec())),
+                trailers: streaming.trailers,
+                accepts_trailers: streaming.accepts_trailers,
+                close_delimited: false,
+            }))),
+        }
+    }
+}
+
+impl<W: std::io::Write, I: Iterator> StreamWritable<W> for StreamingResponse<I>
+where
+    I::Item: AsRef<[u8]>,
+{
+    fn write_to_stream(self, stream: &mut W) -> StreamResult {
+        let trailers = if self.accepts_trailers {
+            self.trailers
+        } else {
+            Vec::new()
+        };
+        self.status_line.write_to_stream(stream)?;
+        ChunkedBody::write_head(&trailers, stream)?;
+        for (name, val) in self.headers.into_iter() {
+            write!(stream, "{}:{}\r\n", name, val)?;
+        }
+        write!(stream, "\r\n")?;
+
+        ChunkedBody::write_chunks(self.chunks, trailers, stream)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::parsing::StrParser;
@@ -685,7 +1208,7 @@ mod tests {
     #[test]
     fn test_response() {
         let mut parser = StrParser::from_str(
-            "HTTP/1.1 200\r\ndate: Tue, 30 Dec 2025 12:06:15 GMT\r\ncontent-type: text/plain; charset=UTF-8\r\ncontent-length: 0\r\n",
+            "HTTP/1.1 200\r\ndate: Tue, 30 Dec 2025 12:06:15 GMT\r\ncontent-type: text/plain; charset=UTF-8\r\ncontent-length: 0\r\n\r\n",
         );
         let mut headers = HashMap::new();
         let header = ResponseHeaderType::GeneralHeader(GeneralHeader::Date(
@@ -714,4 +1237,214 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_write_round_trip() {
+        let headers = HashMap::from([(String::from("content-type"), String::from("text/plain"))]);
+        let response = Response::new(StatusCode::NotFound, headers, Some("missing".to_string()));
+        let mut written = Vec::new();
+        response.write_to_stream(&mut written).unwrap();
+        let parsed =
+            Response::parse(&mut Parser::<std::io::Cursor<&[u8]>>::from_bytes(&written)).unwrap();
+
+        let mut expected =
+            Response::new(StatusCode::NotFound, HashMap::new(), Some("missing".into()));
+        expected
+            .headers_mut()
+            .insert(String::from("content-type"), String::from("text/plain"));
+        expected
+            .headers_mut()
+            .insert(String::from("content-length"), String::from("7"));
+        assert_eq!(parsed, expected);
+        assert_eq!(parsed.status_line.reason_phrase.0, "Not Found");
+
+        let mut written = Vec::new();
+        Response::new_simple(StatusCode::ExtensionCode(599), None)
+            .write_to_stream(&mut written)
+            .unwrap();
+        assert!(written.starts_with(b"HTTP/1.1 599 \r\n"));
+        let parsed =
+            Response::parse(&mut Parser::<std::io::Cursor<&[u8]>>::from_bytes(&written)).unwrap();
+        assert_eq!(parsed.status_code(), &StatusCode::ExtensionCode(599));
+    }
+
+    #[test]
+    fn test_bodyless_status_codes() {
+        let written = |response: Response| {
+            let mut written = Vec::new();
+            response.write_to_stream(&mut written).unwrap();
+            String::from_utf8(written).unwrap()
+        };
+
+        for status in [
+            StatusCode::Continue,
+            StatusCode::SwitchingProtocols,
+            StatusCode::ExtensionCode(103),
+        ] {
+            let out = written(Response::new_simple(status, None));
+            assert!(!out.contains("content-length"), "{}", out);
+            assert!(out.ends_with("\r\n\r\n"));
+        }
+
+        // a 204 never gets a content-length or body, even one the handler set
+        let headers = HashMap::from([(String::from("content-length"), String::from("4"))]);
+        let out = written(Response::new(
+            StatusCode::NoContent,
+            headers,
+            Some("oops".into()),
+        ));
+        assert!(out.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!out.contains("content-length"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        // a 304 doesn't claim an empty representation, but may repeat its real length
+        let out = written(Response::new_simple(StatusCode::NotModified, None));
+        assert!(!out.contains("content-length"));
+        assert!(out.ends_with("\r\n\r\n"));
+        let headers = HashMap::from([(String::from("content-length"), String::from("1234"))]);
+        let out = written(Response::new(StatusCode::NotModified, headers, None));
+        assert!(out.contains("content-length:1234\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        assert!(StatusCode::OK.allows_body());
+        assert!(StatusCode::ExtensionCode(599).allows_body());
+    }
+
+    #[test]
+    fn test_modern_status_codes() {
+        let mut parser = StrParser::from_str("HTTP/1.1 429 Too Many Requests\r\n\r\n");
+        let response = Response::parse(&mut parser).unwrap();
+        assert_eq!(response.status_code(), &StatusCode::TooManyRequests);
+        assert_eq!(StatusCode::from_code(429), Ok(StatusCode::TooManyRequests));
+
+        let mut parser = StrParser::from_str("HTTP/1.1 308\r\nlocation: /new\r\n\r\n");
+        let response = Response::parse(&mut parser).unwrap();
+        assert_eq!(response.status_code(), &StatusCode::PermanentRedirect);
+        assert!(StatusCode::PermanentRedirect.is_redirect());
+        assert!(!StatusCode::NotModified.is_redirect());
+
+        let mut out = Vec::new();
+        Response::new_simple(StatusCode::PermanentRedirect, None)
+            .write_to_stream(&mut out)
+            .unwrap();
+        assert!(out.starts_with(b"HTTP/1.1 308 Permanent Redirect\r\n"));
+
+        for code in [308, 421, 426, 429, 431, 451] {
+            let status = StatusCode::from_code(code).unwrap();
+            assert_eq!(status.as_str(), Some(code.to_string().as_str()));
+            assert!(!status.reason_phrase().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_status_code_round_trip() {
+        let mut known = 0;
+        for code in 0..1000 {
+            let Ok(status) = StatusCode::from_code(code) else {
+                continue;
+            };
+            if let StatusCode::ExtensionCode(n) = status {
+                assert_eq!(n, code);
+                assert_eq!(status.reason_phrase(), "");
+                continue;
+            }
+            known += 1;
+            assert_eq!(status.as_code(), code);
+            assert!(!status.reason_phrase().is_empty());
+            assert_eq!(StatusCode::from_code(status.as_code()), Ok(status));
+        }
+        // every variant but `ExtensionCode`
+        assert_eq!(known, 46);
+        assert_eq!(StatusCode::NotFound.as_code(), 404);
+        assert_eq!(StatusCode::NotFound.reason_phrase(), "Not Found");
+        assert_eq!(StatusCode::ExtensionCode(599).as_code(), 599);
+    }
+
+    #[test]
+    fn test_age() {
+        let mut parser = StrParser::from_str("HTTP/1.1 200 OK\r\nAge: 60\r\n\r\n");
+        let response = Response::parse(&mut parser).unwrap();
+        assert_eq!(response.age(), Some(60));
+        assert_eq!(
+            response.headers().get("age").map(String::as_str),
+            Some("60")
+        );
+
+        let mut parser = StrParser::from_str("HTTP/1.1 200 OK\r\nAge: soon\r\n\r\n");
+        assert_eq!(
+            Response::parse(&mut parser),
+            Err(ParseErr::FailedToParseNum {
+                found: String::from("soon"),
+                radix: 10
+            })
+        );
+        assert!(ResponseHeader::parse_delta_seconds("-1").is_err());
+        assert!(ResponseHeader::parse_delta_seconds("").is_err());
+        assert_eq!(
+            ResponseHeader::parse_delta_seconds("99999999999999999999999"),
+            Ok(ResponseHeader::MAX_DELTA_SECONDS)
+        );
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("zero_from_file_{}", std::process::id()));
+        let contents: Vec<u8> = (0..ResponseBody::BLOCK_SIZE * 3 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let response = Response::from_file(&path).unwrap();
+        assert_eq!(
+            response.headers().get("content-length"),
+            Some(&contents.len().to_string())
+        );
+        let mut out = Vec::new();
+        response.write_to_stream(&mut out).unwrap();
+        let body_start = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(&out[body_start..], &contents[..]);
+
+        // the file shrinking after the response was built fails it before anything,
+        // headers included, is written
+        let response = Response::from_file(&path).unwrap();
+        std::fs::write(&path, &contents[..10]).unwrap();
+        let mut out = Vec::new();
+        assert!(response.write_to_stream(&mut out).is_err());
+        assert!(out.is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_streaming_trailers() {
+        let response = StreamingResponse::new(StatusCode::OK, ["hello", "", " world!"].into_iter())
+            .header("Content-Type", "text/plain")
+            .trailer("X-Checksum", "abc123")
+            .accepts_trailers(true);
+        assert_eq!(
+            response.trailers(),
+            &[(String::from("x-checksum"), String::from("abc123"))]
+        );
+
+        let mut out = Vec::new();
+        response.write_to_stream(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("transfer-encoding:chunked"));
+        assert!(head.contains("trailer:x-checksum"));
+        assert_eq!(
+            body,
+            "5\r\nhello\r\n7\r\n world!\r\n0\r\nx-checksum:abc123\r\n\r\n"
+        );
+
+        // trailers are dropped, names included, unless the request accepts them
+        let response = StreamingResponse::new(StatusCode::OK, ["hello"].into_iter())
+            .trailer("X-Checksum", "abc123");
+        let mut out = Vec::new();
+        response.write_to_stream(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        assert!(!head.contains("trailer:"));
+        assert_eq!(body, "5\r\nhello\r\n0\r\n\r\n");
+    }
 }
@@ -1,16 +1,30 @@
 use super::{
-    Body, HTTPVersion, ToBody,
-    request::{Method, Request, RequestBody, RequestHeaders},
-    response::{Response as FullResponse, ResponseHeaderType, StatusCode},
-    uri::{RequestQuery, URIPath},
+    Body, HTTPVersion, MediaType, ToBody,
+    cors::Cors,
+    rate_limit::RateLimiter,
+    request::{
+        Method, PeerAddr, Request, RequestBody, RequestHeader, RequestHeaderType, RequestHeaders,
+        RequestId,
+    },
+    response::{Response as FullResponse, ResponseHeaderType, StatusCode, StreamingResponse},
+    uri::{Authority, RequestQuery, Scheme, URI, URIPath},
+};
+use crate::{
+    errors::{DeserializeError, RouteConflict},
+    html::{CachedMarkup, Markup, Page},
+    http::ToMessageHeader,
+    parsing::{Parsable, ParseErr, StrParser},
+    serializer::Deserialize,
 };
-use crate::{html::Markup, http::ToMessageHeader, serializer::Deserialize};
 use std::{
+    cmp::Reverse,
     collections::{HashMap, HashSet},
     future::Future,
     marker::PhantomData,
+    net::IpAddr,
     pin::Pin,
-    sync::Arc,
+    str::FromStr,
+    sync::{Arc, RwLock},
 };
 
 /// Request + Instance wrapper function that makes code generation
@@ -18,12 +32,16 @@ use std::{
 #[derive(Debug, PartialEq, Eq)]
 pub struct InstanceRequest<T: Send + Sync> {
     instance: Arc<T>,
+    request_id: RequestId,
+    peer_addr: Option<PeerAddr>,
     method: Method,
     path: URIPath,
     query: RequestQuery,
     http_version: HTTPVersion,
     headers: RequestHeaders,
     body: RequestBody,
+    /// Whether the peer is one of `Router::trusted_proxies`
+    from_trusted_proxy: bool,
 }
 
 impl<T: Send + Sync> InstanceRequest<T> {
@@ -31,14 +49,70 @@ impl<T: Send + Sync> InstanceRequest<T> {
     pub fn from_request(instance: Arc<T>, r: Request) -> Self {
         InstanceRequest {
             instance,
+            request_id: r.request_id(),
+            peer_addr: r.peer_addr.map(PeerAddr),
             method: r.method,
             path: r.path,
             query: r.query,
             http_version: r.http_version,
             headers: r.headers,
             body: r.body,
+            from_trusted_proxy: false,
         }
     }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn path(&self) -> &URIPath {
+        &self.path
+    }
+
+    /// Request headers, keyed by lowercase name
+    pub fn headers(&self) -> &RequestHeaders {
+        &self.headers
+    }
+
+    /// Absolute URL of `path` on the host this request was sent to, e.g. for the
+    /// `Location` of a redirect. `path` is taken as decoded and percent-encoded as
+    /// needed.
+    ///
+    /// The server only speaks plain HTTP, so the scheme is `http` unless a proxy in
+    /// front of it says otherwise with `X-Forwarded-Proto`. That header is only read
+    /// on requests from one of `Router::trusted_proxies`, taking the entry the proxy
+    /// itself added, i.e. the last one. Without a valid `Host`
+    /// header, e.g. from an HTTP/1.0 client, the encoded path is returned alone, which
+    /// clients resolve against the URL they requested. Leading slashes are collapsed
+    /// into one, since clients would take the path `//evil.example` alone for a URL
+    /// on another host.
+    pub fn absolute_url(&self, path: &str) -> String {
+        let path = match path.strip_prefix("//") {
+            Some(rest) => URIPath::from_decoded(&format!("/{}", rest.trim_start_matches('/'))),
+            None => URIPath::from_decoded(path),
+        };
+        let authority = match self.headers.get("host") {
+            Some(RequestHeaderType::RequestHeader(RequestHeader::Host(host))) => {
+                Authority::from_host_header(host).ok()
+            }
+            _ => None,
+        };
+        let Some(authority) = authority else {
+            return path.to_string();
+        };
+        let scheme = match self.headers.get("x-forwarded-proto") {
+            Some(RequestHeaderType::ExtensionHeader(proto)) if self.from_trusted_proxy => {
+                let proto = proto.rsplit(',').next().unwrap_or_default().trim();
+                let mut parser = StrParser::from_str(proto);
+                Scheme::parse(&mut parser)
+                    .ok()
+                    .filter(|_| parser.peek().is_none())
+            }
+            _ => None,
+        };
+
+        URI::new(scheme.unwrap_or(Scheme::from("http")), authority, path).to_string()
+    }
 }
 
 /// Wrapper struct for the actual `::http::response::Response` struct so that fields can be optional
@@ -55,6 +129,73 @@ pub struct Response {
     status: Option<StatusCode>,
     headers: Option<HashMap<String, String>>,
     body: Option<String>,
+    /// Complete response sent as is, see `From<FullResponse>`
+    full: Option<Box<FullResponse>>,
+}
+
+impl Response {
+    /// Adds `name: value` to the response, replacing any previous value for `name`.
+    ///
+    /// Header names are case-insensitive and stored lowercased.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let headers = match &mut self.full {
+            Some(full) => full.headers_mut(),
+            None => self.headers.get_or_insert_with(HashMap::new),
+        };
+        headers.insert(name.into().to_ascii_lowercase(), value.into());
+        self
+    }
+
+    /// Builds the response from whichever arm `r` holds, e.g. for a handler ending in
+    /// `Ok(Response::from_result(lookup(id)))` where both arms convert to a response.
+    ///
+    /// The status is the one the arm's value converts with, so `Err(StatusCode::NotFound)`
+    /// gives a `404`. An `Err` whose value sets no status still gets a `500`, as if the
+    /// handler had returned it as its error.
+    pub fn from_result<T: Into<Response>, E: Into<Response>>(r: Result<T, E>) -> Self {
+        match r {
+            Ok(t) => t.into(),
+            Err(e) => {
+                let e: Response = e.into();
+                match (&e.status, &e.full) {
+                    (None, None) => e.with_status(StatusCode::InternalServerError),
+                    _ => e,
+                }
+            }
+        }
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        match &mut self.full {
+            Some(full) => full.set_status(status),
+            None => self.status = Some(status),
+        }
+        self
+    }
+}
+
+/// Hands a complete response to the server untouched, for handlers that need full
+/// control over it, e.g. to send a file body or a custom reason phrase.
+impl From<FullResponse> for Response {
+    fn from(full: FullResponse) -> Self {
+        Response {
+            status: None,
+            headers: None,
+            body: None,
+            full: Some(Box::new(full)),
+        }
+    }
+}
+
+/// Sends the body chunk by chunk, trailers included when the request `accepts_trailers`
+impl<I> From<StreamingResponse<I>> for Response
+where
+    I: Iterator + Send + 'static,
+    I::Item: AsRef<[u8]>,
+{
+    fn from(streaming: StreamingResponse<I>) -> Self {
+        FullResponse::from(streaming).into()
+    }
 }
 
 impl From<()> for Response {
@@ -63,6 +204,7 @@ impl From<()> for Response {
             status: None,
             headers: None,
             body: None,
+            full: None,
         }
     }
 }
@@ -73,6 +215,7 @@ impl From<StatusCode> for Response {
             status: Some(status),
             headers: None,
             body: None,
+            full: None,
         }
     }
 }
@@ -83,6 +226,7 @@ impl From<HashMap<String, String>> for Response {
             status: None,
             headers: Some(headers),
             body: None,
+            full: None,
         }
     }
 }
@@ -93,6 +237,7 @@ impl From<String> for Response {
             status: None,
             headers: None,
             body: Some(body),
+            full: None,
         }
     }
 }
@@ -103,6 +248,7 @@ impl From<&str> for Response {
             status: None,
             headers: None,
             body: Some(body.to_string()),
+            full: None,
         }
     }
 }
@@ -122,12 +268,36 @@ impl<'a> From<Markup<'a>> for Response {
     }
 }
 
+impl From<&CachedMarkup> for Response {
+    fn from(cached: &CachedMarkup) -> Self {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("content-type"), String::from("text/html"));
+        (headers, cached.get().to_string()).into()
+    }
+}
+
+impl<'a> From<Page<'a>> for Response {
+    fn from(page: Page<'a>) -> Self {
+        let mut headers = HashMap::new();
+
+        let header = ResponseHeaderType::EntityHeader(super::EntityHeader::ContentType(
+            String::from("text/html"),
+        ));
+
+        let header_map = header.to_msg_header();
+        let (k, v) = header_map.extract_name_val();
+        headers.insert(k, v);
+        (headers, page.render()).into()
+    }
+}
+
 impl From<(StatusCode, HashMap<String, String>)> for Response {
     fn from((status, headers): (StatusCode, HashMap<String, String>)) -> Self {
         Response {
             status: Some(status),
             headers: Some(headers),
             body: None,
+            full: None,
         }
     }
 }
@@ -138,6 +308,7 @@ impl From<(StatusCode, String)> for Response {
             status: Some(status),
             headers: None,
             body: Some(body),
+            full: None,
         }
     }
 }
@@ -148,6 +319,7 @@ impl<'a> From<(StatusCode, Markup<'a>)> for Response {
             status: Some(status),
             headers: None,
             body: Some(body.to_string()),
+            full: None,
         }
     }
 }
@@ -158,6 +330,7 @@ impl From<(HashMap<String, String>, String)> for Response {
             status: None,
             headers: Some(headers),
             body: Some(body),
+            full: None,
         }
     }
 }
@@ -168,6 +341,7 @@ impl From<(StatusCode, HashMap<String, String>, String)> for Response {
             status: Some(status),
             headers: Some(headers),
             body: Some(body),
+            full: None,
         }
     }
 }
@@ -178,12 +352,22 @@ impl<'a> From<(StatusCode, HashMap<String, String>, Markup<'a>)> for Response {
             status: Some(status),
             headers: Some(headers),
             body: Some(body.to_string()),
+            full: None,
         }
     }
 }
 
 impl From<Result<Response, Response>> for FullResponse {
     fn from(r: Result<Response, Response>) -> Self {
+        if let Ok(Response {
+            full: Some(full), ..
+        })
+        | Err(Response {
+            full: Some(full), ..
+        }) = r
+        {
+            return *full;
+        }
         let (status_code, headers, body) = match r {
             Ok(r) => match (r.status, r.headers, r.body) {
                 (Some(s), Some(h), b) => (s, h, b),
@@ -225,7 +409,7 @@ impl<F, A> Endpoint<F, A> {
     }
 }
 
-/// Used along with `FromRequest<T>` to implement A..G variadics for route functions
+/// Used along with `FromRequest<T>` to implement A..H variadics for route functions
 ///
 /// Both this trait and `FromRequest<T>` are mainly used for a bunch of code
 /// gen. See `impl_handler` within the source code if you are curious how this
@@ -236,7 +420,7 @@ pub trait Handler<A, T> {
     fn into_endpoint(self) -> Arc<dyn FromRequest<T>>;
 }
 
-/// Used along with `Handler<A,T>` to implement A..G variadics for route functions
+/// Used along with `Handler<A,T>` to implement A..H variadics for route functions
 ///
 /// Both this trait and `Handler<A,T>` are mainly used for a bunch of code
 /// gen. See `impl_handler` within the source code if you are curious how this
@@ -311,6 +495,7 @@ impl_handler!(A, B, C, D);
 impl_handler!(A, B, C, D, E);
 impl_handler!(A, B, C, D, E, F);
 impl_handler!(A, B, C, D, E, F, G);
+impl_handler!(A, B, C, D, E, F, G, H);
 
 /// This wrapper is just `Arc<T>` and allows for the instance to be shared
 /// across threads.
@@ -343,28 +528,58 @@ impl ToPath for HashSet<String> {
     }
 }
 
+/// Named access to the `:name` parameters captured by the route.
+impl ToPath for HashMap<String, String> {
+    fn into_path(path: URIPath) -> Result<Path<Self>, ()> {
+        Ok(Path(path.into_params().into_iter().collect()))
+    }
+}
+
+/// Positional access to the `:name` parameters captured by the route.
+///
+/// Tuple positions follow the order the parameters appear in the route, i.e. for
+/// `/users/:id/posts/:slug` the first element is `id` and the second is `slug`. The
+/// tuple must have exactly as many elements as the route has parameters, and every
+/// parameter must parse via `FromStr`, otherwise extraction fails and the request
+/// gets a `400 Bad Request`.
+macro_rules! impl_to_path_tuple {
+    ($($generic:ident),+) => {
+        impl<$($generic: FromStr),+> ToPath for ($($generic,)+) {
+            fn into_path(path: URIPath) -> Result<Path<Self>, ()> {
+                let mut params = path.into_params().into_iter().map(|(_, v)| v);
+                let tuple = ($(
+                    $generic::from_str(&params.next().ok_or(())?).map_err(|_| ())?,
+                )+);
+                if params.next().is_some() {
+                    return Err(());
+                }
+                Ok(Path(tuple))
+            }
+        }
+    }
+}
+
+impl_to_path_tuple!(A);
+impl_to_path_tuple!(A, B);
+impl_to_path_tuple!(A, B, C);
+impl_to_path_tuple!(A, B, C, D);
+
 pub struct Query<T: ToQuery>(pub T);
 
 pub trait ToQuery: Sized + Deserialize {
-    fn into_query(query: RequestQuery) -> Result<Query<Self>, ()>;
+    fn into_query(query: RequestQuery) -> Result<Query<Self>, DeserializeError>;
 }
 
+/// Query parameters are deserialized like a JSON object of strings, so any
+/// `Deserialize` type works, e.g. a derived struct with typed fields. Each value is
+/// parsed into its field's type, and a value that doesn't parse rejects the request.
+/// Repeated and single keys fit list and scalar fields, see `RequestQuery::deserialize`.
 impl<T: Deserialize> ToQuery for T {
-    fn into_query(query: RequestQuery) -> Result<Query<Self>, ()> {
-        let s = query.parameters;
-        match T::deserialize(s) {
-            Ok(t) => Ok(Query(t)),
-            Err(_) => Err(()),
-        }
+    fn into_query(query: RequestQuery) -> Result<Query<Self>, DeserializeError> {
+        query.deserialize().map(Query)
     }
 }
 
-// impl ToQuery for HashMap<String, String> {
-//     fn into_query(query: RequestQuery) -> Result<Query<Self>, ()> {
-//         Ok(Query(query.parameters))
-//     }
-// }
-
 /// This trait helps rust figure out how to extract different combintations of tuples.
 ///
 /// Outside of a few edge cases, implementations for this trait are mainly produced
@@ -376,32 +591,40 @@ impl<T: Deserialize> ToQuery for T {
 /// ordered combination via proc_macro with the following order:
 ///
 /// 1. Instance
-/// 2. Method
-/// 3. Path
-/// 4. Query
-/// 5. HTTPVersion
-/// 6. RequestHeaders
-/// 7. Body
+/// 2. RequestId
+/// 3. PeerAddr
+/// 4. Method
+/// 5. Path
+/// 6. Query
+/// 7. HTTPVersion
+/// 8. RequestHeaders
+/// 9. Body
 ///
 /// ## Valid Example
 ///
 /// ```rust
+/// # use std::collections::HashMap;
+/// # use zero::http::{Query, request::Method, routing::{ResponseResult, Router}};
 /// async fn some_valid_route(
 ///     method: Method,
-///     Query(s): Query(String)
-/// ) -> Result<(), ()> {
-///     Ok(())
+///     Query(s): Query<HashMap<String, String>>
+/// ) -> ResponseResult {
+///     Ok(().into())
 /// }
+/// # let router = Router::new(()).route(Method::Get, "/", some_valid_route);
 /// ```
 /// ## Invalid Example
 ///
-/// ```rust
+/// ```compile_fail
+/// # use std::collections::HashMap;
+/// # use zero::http::{Query, request::Method, routing::{ResponseResult, Router}};
 /// async fn some_invalid_route(
-///     Query(s): Query(String), // Query must come after method, path, etc.
+///     Query(s): Query<HashMap<String, String>>, // Query must come after method, path, etc.
 ///     method: Method
-/// ) -> Result<(), ()> {
-///     Ok(())
+/// ) -> ResponseResult {
+///     Ok(().into())
 /// }
+/// # let router = Router::new(()).route(Method::Get, "/", some_invalid_route);
 /// ```
 pub trait Extract<T, A, B>: Sized {
     fn from_request(_instance: PhantomData<T>, parts: A) -> Result<Self, ()>;
@@ -419,6 +642,19 @@ impl<T> Extract<T, Instance<T>, Instance<T>> for Instance<T> {
     }
 }
 
+impl<T> Extract<T, RequestId, RequestId> for RequestId {
+    fn from_request(_instance: PhantomData<T>, req: RequestId) -> Result<Self, ()> {
+        Ok(req)
+    }
+}
+
+/// Fails for requests that didn't come in over a socket.
+impl<T> Extract<T, Option<PeerAddr>, Option<PeerAddr>> for PeerAddr {
+    fn from_request(_instance: PhantomData<T>, req: Option<PeerAddr>) -> Result<Self, ()> {
+        req.ok_or(())
+    }
+}
+
 impl<T> Extract<T, Method, Method> for Method {
     fn from_request(_instance: PhantomData<T>, req: Method) -> Result<Self, ()> {
         Ok(req)
@@ -433,7 +669,7 @@ impl<T, A: ToPath> Extract<T, URIPath, URIPath> for Path<A> {
 
 impl<T, A: ToQuery> Extract<T, RequestQuery, RequestQuery> for Query<A> {
     fn from_request(_instance: PhantomData<T>, query: RequestQuery) -> Result<Self, ()> {
-        A::into_query(query)
+        A::into_query(query).map_err(|_| ())
     }
 }
 
@@ -459,16 +695,283 @@ macros::impl_extract_permutations!();
 
 type BoxFuture = Pin<Box<dyn Future<Output = ResponseResult> + Send>>;
 
+/// Route key, handler and captured `:name` parameters of a matched route
+type RouteMatch<K, T> = (K, Arc<dyn FromRequest<T>>, Vec<(String, String)>);
+
+/// Middleware wrapping route handlers, see `Router::layer` and `Group::layer`.
+type Layer<T> = Arc<dyn Fn(InstanceRequest<T>, Next<T>) -> BoxFuture + Send + Sync>;
+
+/// The rest of the middleware stack and the route handler, handed to each layer.
+pub struct Next<T: Send + Sync> {
+    layers: Arc<[Layer<T>]>,
+    idx: usize,
+    endpoint: Arc<dyn FromRequest<T>>,
+}
+
+impl<T: Send + Sync + 'static> Next<T> {
+    /// Passes `req` on to the next layer, or to the route handler after the last one.
+    ///
+    /// A request the handler cannot extract its arguments from gets a `400 Bad Request`,
+    /// same as a route without layers.
+    pub fn run(self, req: InstanceRequest<T>) -> impl Future<Output = ResponseResult> + Send {
+        self.into_future(req)
+    }
+
+    fn into_future(self, req: InstanceRequest<T>) -> BoxFuture {
+        let Some(layer) = self.layers.get(self.idx).cloned() else {
+            return match self.endpoint.apply_request(req) {
+                Ok(fut) => fut,
+                Err(()) => Box::pin(async { Err(StatusCode::BadRequest.into()) }),
+            };
+        };
+        let next = Next {
+            layers: self.layers,
+            idx: self.idx + 1,
+            endpoint: self.endpoint,
+        };
+        layer(req, next)
+    }
+}
+
+/// Route handler behind the layers of the `Group` it was registered in
+struct Layered<T: Send + Sync> {
+    layers: Arc<[Layer<T>]>,
+    endpoint: Arc<dyn FromRequest<T>>,
+}
+
+impl<T: Send + Sync + 'static> FromRequest<T> for Layered<T> {
+    fn apply_request(&self, req: InstanceRequest<T>) -> Result<BoxFuture, ()> {
+        let next = Next {
+            layers: self.layers.clone(),
+            idx: 0,
+            endpoint: self.endpoint.clone(),
+        };
+        Ok(next.into_future(req))
+    }
+}
+
+/// Routes sharing a path prefix and a middleware stack, added with `Router::group`.
+pub struct Group<T: Send + Sync> {
+    layers: Vec<Layer<T>>,
+    routes: Vec<(Method, &'static str, Arc<dyn FromRequest<T>>)>,
+}
+
+impl<T: Send + Sync + 'static> Default for Group<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + Sync + 'static> Group<T> {
+    pub fn new() -> Self {
+        Group {
+            layers: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Wraps every route of the group in `f`.
+    ///
+    /// `f` gets the request and the `Next` of the stack. It can answer by itself, e.g.
+    /// with a `401` for a missing `Authorization`, or call `next.run(req).await` and
+    /// post-process the response. Layers run in the order they were added, whatever the
+    /// order of the `route` calls, and never see requests for routes outside the group.
+    pub fn layer<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(InstanceRequest<T>, Next<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ResponseResult> + Send + 'static,
+    {
+        self.layers
+            .push(Arc::new(move |req, next| Box::pin(f(req, next))));
+        self
+    }
+
+    /// Same as `Router::route`, with `s` relative to the group's prefix. The path `/`
+    /// routes the prefix itself.
+    pub fn route<A>(
+        mut self,
+        method: impl Into<Method>,
+        s: &'static str,
+        f: impl Handler<A, T>,
+    ) -> Self {
+        self.routes.push((method.into(), s, f.into_endpoint()));
+        self
+    }
+}
+
 pub struct Router<T: Send + Sync> {
-    instance: Arc<T>,
+    instance: RwLock<Arc<T>>,
     routes: HashMap<(&'static Method, &'static str), Arc<dyn FromRequest<T>>>,
+    any_routes: HashMap<&'static str, Arc<dyn FromRequest<T>>>,
+    produces: HashMap<(&'static Method, &'static str), &'static [&'static str]>,
+    requires: HashMap<(&'static Method, &'static str), &'static [&'static str]>,
+    body_limits: HashMap<(&'static Method, &'static str), usize>,
+    streaming: HashSet<(&'static Method, &'static str)>,
+    /// Position each path was first registered at, the last tie-breaker between
+    /// parameterized routes
+    registered: HashMap<&'static str, usize>,
+    /// Routes registered again after their first handler, see `build`
+    duplicates: Vec<(&'static Method, &'static str)>,
+    fallback: Option<Arc<dyn FromRequest<T>>>,
+    layers: Arc<[Layer<T>]>,
+    bad_request: fn(ParseErr) -> FullResponse,
+    error_page: Option<fn(StatusCode, Option<&str>) -> Markup>,
+    rate_limiter: Option<RateLimiter>,
+    /// Proxies whose forwarding headers are believed, see `trusted_proxies`
+    trusted_proxies: Vec<IpAddr>,
+    /// Whether `If-None-Match` is answered with `304`s, see `conditional_get`
+    conditional_get: bool,
+    cors: Option<Cors>,
+}
+
+fn default_bad_request(_: ParseErr) -> FullResponse {
+    FullResponse::new_simple(StatusCode::BadRequest, None)
 }
 
 impl<T: Send + Sync> Router<T> {
+    /// Longest `Retry-After`, in seconds, sent by `rate_limit`
+    pub const MAX_RETRY_AFTER: u64 = 24 * 60 * 60;
+
     pub fn new(instance: T) -> Self {
         Router {
-            instance: instance.into(),
+            instance: RwLock::new(instance.into()),
             routes: HashMap::new(),
+            any_routes: HashMap::new(),
+            produces: HashMap::new(),
+            requires: HashMap::new(),
+            body_limits: HashMap::new(),
+            streaming: HashSet::new(),
+            registered: HashMap::new(),
+            duplicates: Vec::new(),
+            fallback: None,
+            layers: Arc::new([]),
+            bad_request: default_bad_request,
+            error_page: None,
+            rate_limiter: None,
+            trusted_proxies: Vec::new(),
+            conditional_get: false,
+            cors: None,
+        }
+    }
+
+    /// Sets the function used to build the response when a request fails to parse.
+    ///
+    /// By default the server answers with an empty 400.
+    pub fn bad_request_handler(mut self, f: fn(ParseErr) -> FullResponse) -> Self {
+        self.bad_request = f;
+        self
+    }
+
+    /// Sets the function rendering the body of 4xx and 5xx responses sent without one.
+    ///
+    /// It gets the status code and its reason phrase. The page is rendered by the server
+    /// right before the response is written, so it also covers responses the server
+    /// makes itself, e.g. for requests that fail to parse or handlers that panic.
+    pub fn error_page(mut self, f: fn(StatusCode, Option<&str>) -> Markup) -> Self {
+        self.error_page = Some(f);
+        self
+    }
+
+    /// Fills in the body of a bodiless error response with the `error_page`, if one is
+    /// set. Responses that already have a body are left alone.
+    pub fn render_error_page(&self, response: &mut FullResponse) {
+        let Some(error_page) = self.error_page else {
+            return;
+        };
+        let status = response.status_code();
+        if !status.is_error() || response.has_body() {
+            return;
+        }
+        let reason = Some(status.reason_phrase()).filter(|r| !r.is_empty());
+        let page = error_page(status.clone(), reason).to_string();
+        response
+            .headers_mut()
+            .insert(String::from("content-type"), String::from("text/html"));
+        response.set_body(page);
+    }
+
+    /// Limits every client to `per_second` requests a second, allowing bursts of up to
+    /// `burst` requests. Requests over the limit get a `429 Too Many Requests` with a
+    /// `Retry-After` header, without reaching a handler.
+    ///
+    /// Clients are told apart by `Request::client_ip`, i.e. by the address they
+    /// connected from unless that is one of the `trusted_proxies`.
+    pub fn rate_limit(mut self, per_second: u32, burst: u32) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(per_second, burst));
+        self
+    }
+
+    /// Proxies in front of the server, whose `X-Forwarded-*` headers are believed.
+    ///
+    /// Anyone can send those headers, so they are ignored on connections from any
+    /// other address. By default no proxy is trusted.
+    pub fn trusted_proxies(mut self, proxies: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.trusted_proxies = proxies.into_iter().collect();
+        self
+    }
+
+    /// Answers `GET` and `HEAD` requests whose `If-None-Match` lists the `ETag` of the
+    /// handler's `200` response with a bodiless `304 Not Modified`.
+    ///
+    /// The handler still runs, only the body isn't sent. Requests sent with
+    /// `Request::is_no_cache` always get the full response.
+    pub fn conditional_get(mut self) -> Self {
+        self.conditional_get = true;
+        self
+    }
+
+    /// Allows cross-origin requests as set out by `cors`.
+    ///
+    /// Responses from handlers to requests with an `Origin` get the
+    /// `Access-Control-Allow-Origin` header. CORS preflights for a routed path are
+    /// answered with a `204 No Content` listing the methods routed for the path, without
+    /// reaching a handler or layer.
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    fn check_rate_limit(&self, req: &Request) -> Result<(), FullResponse> {
+        let Some(limiter) = &self.rate_limiter else {
+            return Ok(());
+        };
+        let key = req
+            .client_ip(&self.trusted_proxies)
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+        limiter.check(&key).map_err(|wait| {
+            // a limiter that never refills waits forever, which is no use as a header
+            let secs = wait
+                .as_secs()
+                .saturating_add(u64::from(wait.subsec_nanos() > 0))
+                .clamp(1, Self::MAX_RETRY_AFTER);
+            let mut headers = HashMap::new();
+            headers.insert(String::from("retry-after"), secs.to_string());
+            FullResponse::new(StatusCode::TooManyRequests, headers, None)
+        })
+    }
+
+    pub fn handle_bad_request(&self, err: ParseErr) -> FullResponse {
+        (self.bad_request)(err)
+    }
+
+    /// Current instance shared with route handlers
+    pub fn instance(&self) -> Arc<T> {
+        match self.instance.read() {
+            Ok(instance) => instance.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Swaps out the instance handed to route handlers.
+    ///
+    /// Requests dispatched after the swap see the new instance. Requests already
+    /// being handled keep the `Arc` they were given.
+    pub fn set_instance(&self, instance: T) {
+        let instance = Arc::new(instance);
+        match self.instance.write() {
+            Ok(mut current) => *current = instance,
+            Err(poisoned) => *poisoned.into_inner() = instance,
         }
     }
 
@@ -480,11 +983,336 @@ impl<T: Send + Sync> Router<T> {
     const DELETE: &'static Method = &Method::Delete;
     const TRACE: &'static Method = &Method::Trace;
     const CONNECT: &'static Method = &Method::Connect;
+    const PATCH: &'static Method = &Method::Patch;
 
     /// This method is subject to change as role based
     /// routing is probably going to be a thing.
-    pub fn route<A>(mut self, method: Method, s: &'static str, f: impl Handler<A, T>) -> Self {
-        let m = match method {
+    ///
+    /// Paths are in origin-form, i.e. start with `/`. A path registered without the
+    /// leading `/` gets one, so `"users"` and `"/users"` are the same route.
+    ///
+    /// Segments of `s` starting with `:` match any single non-empty path segment,
+    /// e.g. `/users/:id`. A last segment of `*` matches the rest of the path, e.g.
+    /// `/static/*`, and is captured under the name `*`. The matched values are handed
+    /// to the handler via `Path`. A trailing `/` is ignored, on both routes and
+    /// requests, so `/users/` and `/users` are the same route.
+    ///
+    /// `method` can also be given by name, e.g. `"PURGE"` for an extension method.
+    pub fn route<A>(
+        mut self,
+        method: impl Into<Method>,
+        s: &'static str,
+        f: impl Handler<A, T>,
+    ) -> Self {
+        self.insert_route(
+            Self::method_key(&method.into()),
+            Self::normalize_path(s),
+            f.into_endpoint(),
+        );
+        self
+    }
+
+    /// Registering a route again replaces it along with the options it was added with,
+    /// e.g. a `route_with_limit` limit, so it only keeps those of the new registration.
+    fn insert_route(
+        &mut self,
+        method: &'static Method,
+        path: &'static str,
+        endpoint: Arc<dyn FromRequest<T>>,
+    ) {
+        if self.routes.insert((method, path), endpoint).is_some() {
+            self.duplicates.push((method, path));
+        }
+        self.register(path);
+        self.produces.remove(&(method, path));
+        self.requires.remove(&(method, path));
+        self.body_limits.remove(&(method, path));
+        self.streaming.remove(&(method, path));
+    }
+
+    /// Checks the routes once they are all registered, returning the router if none
+    /// of them conflict.
+    ///
+    /// A method and path routed twice would otherwise silently keep the last handler.
+    /// Parameterized routes for the same method conflict when some path matches both
+    /// with the same priority, e.g. `/users/:id` and `/users/:name`, or `/a/:x/c` and
+    /// `/a/b/:y`. A route without parameters always takes priority, so it never
+    /// conflicts with a parameterized one.
+    pub fn build(self) -> Result<Self, Vec<RouteConflict>> {
+        let mut conflicts: Vec<RouteConflict> = self
+            .duplicates
+            .iter()
+            .map(|(method, path)| RouteConflict::Duplicate {
+                method: (*method).clone(),
+                path,
+            })
+            .collect();
+
+        let mut param_routes: Vec<(&Method, &'static str)> = self
+            .routes
+            .keys()
+            .filter(|(_, r)| r.contains(':') || r.ends_with("/*"))
+            .map(|(m, r)| (*m, *r))
+            .collect();
+        param_routes.sort();
+        for (i, (method, a)) in param_routes.iter().enumerate() {
+            for (other, b) in &param_routes[i + 1..] {
+                if method == other && Self::ambiguous_routes(a, b) {
+                    conflicts.push(RouteConflict::Ambiguous {
+                        method: (*method).clone(),
+                        paths: (a, b),
+                    });
+                }
+            }
+        }
+
+        if conflicts.is_empty() {
+            Ok(self)
+        } else {
+            Err(conflicts)
+        }
+    }
+
+    /// Whether a path exists that both parameterized routes match with the same
+    /// priority, i.e. the same number of captures and both or neither ending with `*`,
+    /// leaving `best_param_match` to pick by literal prefix or registration order.
+    fn ambiguous_routes(a: &str, b: &str) -> bool {
+        let split = |route: &str| {
+            let mut segments: Vec<String> = route
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+            let wildcard = segments.last().is_some_and(|s| s == "*");
+            if wildcard {
+                segments.pop();
+            }
+            (segments, wildcard)
+        };
+        let (a, a_wildcard) = split(a);
+        let (b, b_wildcard) = split(b);
+        let params = |segments: &[String]| segments.iter().filter(|s| s.starts_with(':')).count();
+
+        // a `*` tail matches whatever segments the other route has left over
+        a_wildcard == b_wildcard
+            && (a_wildcard || a.len() == b.len())
+            && params(&a) == params(&b)
+            && a.iter()
+                .zip(&b)
+                .all(|(a, b)| a == b || a.starts_with(':') || b.starts_with(':'))
+    }
+
+    /// Canonical form of a registered path, see `route`.
+    ///
+    /// Routes are registered once while building the router, so leaking the rare
+    /// corrected path is cheaper than keying every lookup by an owned `String`.
+    fn normalize_path(s: &'static str) -> &'static str {
+        let s = match s.trim_end_matches('/') {
+            "" if s.starts_with('/') => "/",
+            trimmed => trimmed,
+        };
+        if s.starts_with('/') || s == "*" {
+            s
+        } else {
+            Box::leak(format!("/{}", s).into_boxed_str())
+        }
+    }
+
+    /// Routes every method for `s` to `f`, e.g. for proxies or handlers that dispatch on
+    /// the `Method` extractor themselves.
+    ///
+    /// A route registered for a specific method always takes priority, `f` only receives
+    /// the methods that have no route of their own for this path (including `OPTIONS`).
+    pub fn any<A>(mut self, s: &'static str, f: impl Handler<A, T>) -> Self {
+        let path = Self::normalize_path(s);
+        self.any_routes.insert(path, f.into_endpoint());
+        self.register(path);
+        self
+    }
+
+    /// Records the position `path` was first registered at.
+    fn register(&mut self, path: &'static str) {
+        let next = self.registered.len();
+        self.registered.entry(path).or_insert(next);
+    }
+
+    /// Handler for requests no route matches, e.g. to render a custom 404 page.
+    ///
+    /// Without a fallback such requests get an empty `404 Not Found`. Requests for a
+    /// path that is routed for other methods get a `405 Method Not Allowed` instead,
+    /// and never reach the fallback.
+    pub fn fallback<A>(mut self, f: impl Handler<A, T>) -> Self {
+        self.fallback = Some(f.into_endpoint());
+        self
+    }
+
+    /// Same as `route`, but declares the media types the handler can produce.
+    ///
+    /// Requests whose `Accept` header allows none of `types` get a `406 Not Acceptable`
+    /// without the handler being called.
+    pub fn route_producing<A>(
+        mut self,
+        method: impl Into<Method>,
+        s: &'static str,
+        types: &'static [&'static str],
+        f: impl Handler<A, T>,
+    ) -> Self {
+        let method = method.into();
+        let key = (Self::method_key(&method), Self::normalize_path(s));
+        self = self.route(method, s, f);
+        self.produces.insert(key, types);
+        self
+    }
+
+    /// Same as `route`, but requests missing any of the `headers` are rejected without
+    /// the handler being called.
+    ///
+    /// A missing header gets a `400 Bad Request`. A `Content-Type` entry can also name
+    /// the media type required, e.g. `"Content-Type: application/json"`, and a request
+    /// sending another one gets a `415 Unsupported Media Type`. Parameters such as the
+    /// `charset` aren't compared. Header names are case-insensitive.
+    pub fn route_requiring<A>(
+        mut self,
+        method: impl Into<Method>,
+        s: &'static str,
+        headers: &'static [&'static str],
+        f: impl Handler<A, T>,
+    ) -> Self {
+        let method = method.into();
+        let key = (Self::method_key(&method), Self::normalize_path(s));
+        self = self.route(method, s, f);
+        self.requires.insert(key, headers);
+        self
+    }
+
+    /// Same as `route`, but requests with a body over `bytes` get a `413 Payload Too
+    /// Large` without the body being read, in place of the server's `max_body`.
+    pub fn route_with_limit<A>(
+        mut self,
+        method: impl Into<Method>,
+        s: &'static str,
+        bytes: usize,
+        f: impl Handler<A, T>,
+    ) -> Self {
+        let method = method.into();
+        let key = (Self::method_key(&method), Self::normalize_path(s));
+        self = self.route(method, s, f);
+        self.body_limits.insert(key, bytes);
+        self
+    }
+
+    /// Same as `route_with_limit`, but the body is left on the connection for the handler
+    /// to read as a `BodyStream`, e.g. with a `Body<BodyStream>` argument, so uploads of
+    /// any size up to `bytes` are handled without being held in memory.
+    ///
+    /// Only bodies with a `Content-Length` are streamed, requests made without the server,
+    /// e.g. with `apply_request`, come with their body already read.
+    pub fn route_streaming<A>(
+        mut self,
+        method: impl Into<Method>,
+        s: &'static str,
+        bytes: usize,
+        f: impl Handler<A, T>,
+    ) -> Self {
+        let method = method.into();
+        let key = (Self::method_key(&method), Self::normalize_path(s));
+        self = self.route_with_limit(method, s, bytes, f);
+        self.streaming.insert(key);
+        self
+    }
+
+    /// Whether the route matching `method` and `path` was added with `route_streaming`
+    pub fn streams_body(&self, method: &Method, path: &URIPath) -> bool {
+        !self.streaming.is_empty()
+            && self
+                .matched_route(method, path)
+                .is_some_and(|route| self.streaming.contains(&(method, route)))
+    }
+
+    /// Body size limit set with `route_with_limit` for the route matching `method` and
+    /// `path`, if any. A route without a limit of its own has none, even when a
+    /// parameterized route that would also match `path` has one.
+    pub fn body_limit(&self, method: &Method, path: &URIPath) -> Option<usize> {
+        if self.body_limits.is_empty() {
+            return None;
+        }
+        let route = self.matched_route(method, path)?;
+        self.body_limits.get(&(method, route)).copied()
+    }
+
+    /// Route `apply_request` dispatches `method` and `path` to, a route without
+    /// parameters taking priority over the parameterized ones.
+    fn matched_route<'a>(&'a self, method: &'a Method, path: &'a URIPath) -> Option<&'a str> {
+        let trimmed = Self::trimmed_path(path);
+        if self.routes.contains_key(&(method, trimmed)) {
+            return Some(trimmed);
+        }
+        self.best_param_match(
+            self.routes
+                .keys()
+                .filter(|(m, _)| *m == method)
+                .map(|(_, r)| (*r, *r)),
+            path,
+        )
+        .map(|(route, _)| route)
+    }
+
+    /// Wraps every handler of the router, including the `fallback`, in `f`.
+    ///
+    /// `f` gets the request and the `Next` of the stack, and can answer by itself, e.g.
+    /// with a `401`, or call `next.run(req).await` and post-process the response. Layers
+    /// run in the order they were added, the first one being the outermost. Responses
+    /// the router makes without a handler, e.g. a `404` with no fallback or a `429` from
+    /// `rate_limit`, don't go through layers.
+    pub fn layer<F, Fut>(mut self, f: F) -> Self
+    where
+        T: 'static,
+        F: Fn(InstanceRequest<T>, Next<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ResponseResult> + Send + 'static,
+    {
+        let mut layers = self.layers.to_vec();
+        layers.push(Arc::new(move |req, next| Box::pin(f(req, next))));
+        self.layers = layers.into();
+        self
+    }
+
+    /// Registers the routes of `group` under `prefix`, behind the group's layers.
+    ///
+    /// Group layers only wrap the group's routes, so sibling routes registered on the
+    /// router directly, or in another group, are unaffected. Router-wide layers added
+    /// with `layer` wrap group layers, i.e. run before them on the way in. A route of
+    /// the group replaces a route already registered for the same method and path,
+    /// which `build` reports as a conflict.
+    pub fn group(mut self, prefix: &'static str, group: Group<T>) -> Self
+    where
+        T: 'static,
+    {
+        let prefix = Self::normalize_path(prefix).trim_end_matches('/');
+        let layers: Arc<[Layer<T>]> = group.layers.into();
+        for (method, s, endpoint) in group.routes {
+            let path = match Self::normalize_path(s) {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                s => format!("{}{}", prefix, s),
+            };
+            let endpoint: Arc<dyn FromRequest<T>> = if layers.is_empty() {
+                endpoint
+            } else {
+                Arc::new(Layered {
+                    layers: layers.clone(),
+                    endpoint,
+                })
+            };
+            self.insert_route(
+                Self::method_key(&method),
+                Box::leak(path.into_boxed_str()),
+                endpoint,
+            );
+        }
+        self
+    }
+
+    fn method_key(method: &Method) -> &'static Method {
+        match method {
             Method::Options => Self::OPTIONS,
             Method::Get => Self::GET,
             Method::Head => Self::HEAD,
@@ -493,9 +1321,10 @@ impl<T: Send + Sync> Router<T> {
             Method::Delete => Self::DELETE,
             Method::Trace => Self::TRACE,
             Method::Connect => Self::CONNECT,
-        };
-        self.routes.insert((m, s), f.into_endpoint());
-        self
+            Method::Patch => Self::PATCH,
+            // only called while registering routes, so this leaks a copy per registration
+            Method::Extension(_) => Box::leak(Box::new(method.clone())),
+        }
     }
     pub fn include_zero_js(self) -> Self {
         async fn include_zero() -> ResponseResult {
@@ -504,31 +1333,295 @@ impl<T: Send + Sync> Router<T> {
         self.route(Method::Get, "/zero.js", include_zero)
     }
 
-    pub async fn apply_request(&self, req: Request) -> FullResponse {
-        let handle = match self.routes.get(&req.method_path()) {
-            Some(handle) => handle.clone(),
-            None => return FullResponse::new_simple(StatusCode::NotFound, None),
+    /// Answers an `OPTIONS` request that has no route of its own.
+    ///
+    /// `OPTIONS *` lists every method the router has a route for, any other path
+    /// lists the methods routed for that path.
+    fn options_response(&self, req: &Request) -> FullResponse {
+        let Some(allow) = self.allow_header(req) else {
+            return FullResponse::new_simple(StatusCode::NotFound, None);
+        };
+        let headers = HashMap::from([(String::from("allow"), allow)]);
+        FullResponse::new(StatusCode::OK, headers, None)
+    }
+
+    /// Answers a request for a routed path with a method that has no route for it.
+    fn method_not_allowed(&self, req: &Request) -> Option<FullResponse> {
+        let allow = self.allow_header(req)?;
+        let headers = HashMap::from([(String::from("allow"), allow)]);
+        Some(FullResponse::new(
+            StatusCode::MethodNotAllowed,
+            headers,
+            None,
+        ))
+    }
+
+    /// `Allow` value listing the methods routed for the path of `req`, along with
+    /// `OPTIONS`, or every routed method for `OPTIONS *`. `None` if the path has no
+    /// routes at all.
+    fn allow_header(&self, req: &Request) -> Option<String> {
+        let path = Self::request_path(req);
+        let mut methods: Vec<&Method> = self
+            .routes
+            .keys()
+            .filter(|(_, p)| {
+                req.path.is_asterisk()
+                    || *p == path
+                    || (p.contains(':') || p.ends_with("/*"))
+                        && Self::match_route(p, req.path.segments()).is_some()
+            })
+            .map(|(m, _)| *m)
+            .collect();
+        if methods.is_empty() && !req.path.is_asterisk() {
+            return None;
+        }
+        methods.push(Self::OPTIONS);
+        methods.sort();
+        methods.dedup();
+
+        Some(
+            methods
+                .iter()
+                .map(|m| m.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+
+    /// Path of `req` as routes are registered, i.e. without a trailing `/`
+    fn request_path(req: &Request) -> &str {
+        Self::trimmed_path(&req.path)
+    }
+
+    fn trimmed_path(path: &URIPath) -> &str {
+        let path = path.entire_path().as_str();
+        match path.trim_end_matches('/') {
+            "" => path,
+            trimmed => trimmed,
+        }
+    }
+
+    /// Matches the segments of a request path against a route, returning the values
+    /// captured by its `:name` segments in route order.
+    fn match_route(route: &str, segments: &[String]) -> Option<Vec<(String, String)>> {
+        let mut captures = Vec::new();
+        let mut route_segments = route.split('/').filter(|s| !s.is_empty()).peekable();
+        let segments = match segments.split_last() {
+            Some((last, rest)) if last.is_empty() => rest,
+            _ => segments,
+        };
+        let mut segments = segments.iter();
+        loop {
+            if route_segments.next_if_eq(&"*").is_some() && route_segments.peek().is_none() {
+                let rest: Vec<&str> = segments.map(String::as_str).collect();
+                captures.push((String::from("*"), rest.join("/")));
+                return Some(captures);
+            }
+            match (route_segments.next(), segments.next()) {
+                (None, None) => return Some(captures),
+                (Some(r), Some(s))
+                    if let Some(name) = r.strip_prefix(':')
+                        && !s.is_empty() =>
+                {
+                    captures.push((name.to_string(), s.clone()));
+                }
+                (Some(r), Some(s)) if r == s => {}
+                _ => return None,
+            }
+        }
+    }
+
+    /// Finds the route with a `:name` or `*` segment that matches `req`, preferring
+    /// routes without a `*` tail, then the route with the fewest parameters, then the
+    /// one with the most literal segments before its first parameter, and finally the
+    /// one registered first.
+    fn find_param_route<'a, K>(
+        &self,
+        routes: impl Iterator<Item = (K, &'a str, &'a Arc<dyn FromRequest<T>>)>,
+        req: &Request,
+    ) -> Option<RouteMatch<K, T>>
+    where
+        T: 'a,
+    {
+        self.best_param_match(routes.map(|(key, r, h)| ((key, h), r)), &req.path)
+            .map(|((key, handle), captures)| (key, handle.clone(), captures))
+    }
+
+    /// Ranks the routes matching `path` as `find_param_route` does, returning the key
+    /// of the best one along with its captures.
+    fn best_param_match<'a, K>(
+        &self,
+        routes: impl Iterator<Item = (K, &'a str)>,
+        path: &URIPath,
+    ) -> Option<(K, Vec<(String, String)>)> {
+        routes
+            .filter(|(_, route)| route.contains(':') || route.ends_with("/*"))
+            .filter_map(|(key, route)| {
+                Self::match_route(route, path.segments()).map(|c| (key, route, c))
+            })
+            .min_by_key(|(_, route, captures)| {
+                let wildcard = captures.last().is_some_and(|(name, _)| name == "*");
+                let literal_prefix = route
+                    .split('/')
+                    .filter(|s| !s.is_empty())
+                    .take_while(|s| !s.starts_with(':') && *s != "*")
+                    .count();
+                let registered = self.registered.get(route).copied().unwrap_or(usize::MAX);
+                (
+                    wildcard,
+                    captures.len(),
+                    Reverse(literal_prefix),
+                    registered,
+                )
+            })
+            .map(|(key, _, captures)| (key, captures))
+    }
+
+    /// Status to reject `req` with when it doesn't send the header `required` by
+    /// `route_requiring`, or sends a `Content-Type` other than the one required.
+    fn check_required(req: &Request, required: &str) -> Option<StatusCode> {
+        let (name, media_type) = match required.split_once(':') {
+            Some((name, media_type)) => (name.trim(), Some(media_type)),
+            None => (required.trim(), None),
+        };
+        if !req.headers.contains_key(&name.to_ascii_lowercase()) {
+            return Some(StatusCode::BadRequest);
+        }
+        let expected = MediaType::parse(&mut StrParser::from_str(media_type?.trim())).ok()?;
+        let matches = req
+            .content_type()
+            .is_some_and(|sent| (sent.ty, sent.subtype) == (expected.ty, expected.subtype));
+        (!matches).then_some(StatusCode::UnsupportedMediaType)
+    }
+
+    /// Swaps a `200` response for `304 Not Modified` when its `etag` is listed in the
+    /// request's `If-None-Match`, using the weak comparison of rfc7232 Section 2.3.2.
+    ///
+    /// The `304` keeps the headers caches refresh their stored response with, rfc7232
+    /// Section 4.1. That includes the `Age`, re-written from `FullResponse::age` so a
+    /// malformed one is dropped rather than handed on to caches doing freshness math
+    /// with it.
+    fn not_modified(if_none_match: Option<&str>, response: FullResponse) -> FullResponse {
+        let (Some(if_none_match), StatusCode::OK) = (if_none_match, response.status_code()) else {
+            return response;
+        };
+        let Some(etag) = response.headers().get("etag") else {
+            return response;
+        };
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        let matched = if_none_match
+            .split(',')
+            .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag));
+        if !matched {
+            return response;
+        }
+
+        let mut headers: HashMap<String, String> = ["etag", "cache-control", "expires", "vary"]
+            .into_iter()
+            .filter_map(|name| Some((name.to_string(), response.headers().get(name)?.clone())))
+            .collect();
+        if let Some(age) = response.age() {
+            headers.insert(String::from("age"), age.to_string());
+        }
+        FullResponse::new(StatusCode::NotModified, headers, None)
+    }
+
+    pub async fn apply_request(&self, mut req: Request) -> FullResponse
+    where
+        T: 'static,
+    {
+        if let Err(response) = self.check_rate_limit(&req) {
+            return response;
+        }
+        if let Some(cors) = &self.cors
+            && req.is_cors_preflight()
+            && let Some(methods) = self.allow_header(&req)
+        {
+            return cors.preflight(&req, methods);
+        }
+        let path = Self::request_path(&req).to_string();
+        let (route, handle) = if let Some(handle) = self.routes.get(&(&req.method, path.as_str())) {
+            (Some(path.clone()), handle.clone())
+        } else if let Some((route, handle, captures)) = self.find_param_route(
+            self.routes
+                .iter()
+                .filter(|((m, _), _)| **m == req.method)
+                .map(|((_, r), h)| (*r, *r, h)),
+            &req,
+        ) {
+            req.path.set_params(captures);
+            (Some(route.to_string()), handle)
+        } else if let Some(handle) = self.any_routes.get(path.as_str()) {
+            (None, handle.clone())
+        } else if let Some(((), handle, captures)) =
+            self.find_param_route(self.any_routes.iter().map(|(r, h)| ((), *r, h)), &req)
+        {
+            req.path.set_params(captures);
+            (None, handle)
+        } else if req.method == Method::Options {
+            return self.options_response(&req);
+        } else if let Some(response) = self.method_not_allowed(&req) {
+            return response;
+        } else if let Some(fallback) = &self.fallback {
+            (None, fallback.clone())
+        } else {
+            return FullResponse::new_simple(StatusCode::NotFound, None);
         };
+        if let Some(route) = &route
+            && let Some(types) = self.produces.get(&(&req.method, route.as_str()))
+            && req.accept().best_match(types).is_none()
+        {
+            return FullResponse::new_simple(StatusCode::NotAcceptable, None);
+        }
+        if let Some(route) = &route
+            && let Some(headers) = self.requires.get(&(&req.method, route.as_str()))
+            && let Some(status) = headers.iter().find_map(|h| Self::check_required(&req, h))
+        {
+            return FullResponse::new_simple(status, None);
+        }
 
-        let req = InstanceRequest::from_request(self.instance.clone(), req);
+        // conditional GET, skipped when the client asked to bypass caches
+        let if_none_match = match req.method {
+            Method::Get | Method::Head if self.conditional_get && !req.is_no_cache() => {
+                req.if_none_match().map(str::to_string)
+            }
+            _ => None,
+        };
+        let origin = req.origin().map(str::to_string);
+        let from_trusted_proxy = req
+            .peer_addr
+            .is_some_and(|addr| self.trusted_proxies.contains(&addr.ip()));
+        let mut req = InstanceRequest::from_request(self.instance(), req);
+        req.from_trusted_proxy = from_trusted_proxy;
+        let handle: Arc<dyn FromRequest<T>> = if self.layers.is_empty() {
+            handle
+        } else {
+            Arc::new(Layered {
+                layers: self.layers.clone(),
+                endpoint: handle,
+            })
+        };
 
-        match handle.apply_request(req) {
+        let mut response = match handle.apply_request(req) {
             Ok(r) => {
                 eprintln!("hit");
-                r.await.into()
+                Self::not_modified(if_none_match.as_deref(), r.await.into())
             }
             Err(_) => {
                 eprintln!("hit2");
-                FullResponse::new_simple(StatusCode::InternalServerError, None)
+                FullResponse::new_simple(StatusCode::BadRequest, None)
             }
+        };
+        if let Some(cors) = &self.cors {
+            cors.apply(origin.as_deref(), &mut response);
         }
+        response
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parsing::{StrParser, prelude::*};
 
     #[test]
     fn test_router() {
@@ -566,9 +1659,9 @@ mod tests {
         //     }
         // }
         let _router = Router::new(1_usize)
-            .route(Method::Get, "some_route3", method_handler3)
-            .route(Method::Get, "some_route", method_handler)
-            .route(Method::Get, "some_route2", method_handler2);
+            .route(Method::Get, "/some_route3", method_handler3)
+            .route(Method::Get, "/some_route", method_handler)
+            .route(Method::Get, "/some_route2", method_handler2);
 
         let mut parser = StrParser::from_str(
             "GET /some_route?some=query HTTP/1.1\r\nHost: 127.0.0.1:8000\r\nUser-Agent: curl/8.14.1\r\nContent-Length: 14\r\nAccept: */*\r\n\r\nthis is a test    ",
@@ -580,4 +1673,983 @@ mod tests {
 
         // assert!(false);
     }
+
+    #[test]
+    fn test_set_instance() {
+        async fn version(instance: Arc<usize>) -> ResponseResult {
+            Ok(instance.to_string().into())
+        }
+        let router = Router::new(1_usize).route(Method::Get, "/version", version);
+        let request = || {
+            let mut parser = StrParser::from_str("GET /version HTTP/1.1\r\n\r\n");
+            Request::parse(&mut parser).unwrap()
+        };
+
+        let in_flight = router.instance();
+        let response = crate::async_runtime::run(router.apply_request(request()));
+        assert_eq!(response.body(), Some("1"));
+
+        router.set_instance(2);
+        let response = crate::async_runtime::run(router.apply_request(request()));
+        assert_eq!(response.body(), Some("2"));
+        assert_eq!(*in_flight, 1);
+    }
+
+    #[test]
+    fn test_options_asterisk() {
+        async fn ok() -> ResponseResult {
+            Ok(().into())
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/a", ok)
+            .route(Method::Post, "/b", ok)
+            .route(Method::Delete, "/b", ok);
+
+        let mut parser = StrParser::from_str("OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let req = Request::parse(&mut parser).unwrap();
+        assert!(req.path().is_asterisk());
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(response.status_code(), &StatusCode::OK);
+        assert_eq!(
+            response.headers().get("allow").map(String::as_str),
+            Some("OPTIONS, GET, POST, DELETE")
+        );
+
+        let mut parser = StrParser::from_str("OPTIONS /b HTTP/1.1\r\n\r\n");
+        let req = Request::parse(&mut parser).unwrap();
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(
+            response.headers().get("allow").map(String::as_str),
+            Some("OPTIONS, POST, DELETE")
+        );
+
+        let mut parser = StrParser::from_str("GET * HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            Request::parse(&mut parser),
+            Err(ParseErr::AsteriskFormNotOptions {
+                found: String::from("GET")
+            })
+        );
+    }
+
+    #[test]
+    fn test_not_acceptable() {
+        async fn json() -> ResponseResult {
+            Ok("{}".into())
+        }
+        let router =
+            Router::new(()).route_producing(Method::Get, "/data", &["application/json"], json);
+        let request = |accept: &str| {
+            let raw = format!("GET /data HTTP/1.1\r\nAccept: {}\r\n\r\n", accept);
+            Request::parse(&mut StrParser::from_str(&raw)).unwrap()
+        };
+
+        let response = crate::async_runtime::run(router.apply_request(request("text/html")));
+        assert_eq!(response.status_code(), &StatusCode::NotAcceptable);
+
+        let response = crate::async_runtime::run(router.apply_request(request("application/*")));
+        assert_eq!(response.status_code(), &StatusCode::OK);
+        assert_eq!(response.body(), Some("{}"));
+    }
+
+    #[test]
+    fn test_any_route() {
+        async fn get() -> ResponseResult {
+            Ok("get".into())
+        }
+        async fn any(method: Method) -> ResponseResult {
+            Ok(format!("any {}", method.as_str()).into())
+        }
+        let router = Router::new(()).route(Method::Get, "/x", get).any("/x", any);
+        let request = |method: &str| {
+            let raw = format!("{} /x HTTP/1.1\r\n\r\n", method);
+            Request::parse(&mut StrParser::from_str(&raw)).unwrap()
+        };
+
+        let response = crate::async_runtime::run(router.apply_request(request("DELETE")));
+        assert_eq!(response.body(), Some("any DELETE"));
+        let response = crate::async_runtime::run(router.apply_request(request("GET")));
+        assert_eq!(response.body(), Some("get"));
+
+        let raw = "DELETE /y HTTP/1.1\r\n\r\n";
+        let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(response.status_code(), &StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_typed_path_params() {
+        async fn post(Path((id, slug)): Path<(u64, String)>) -> ResponseResult {
+            Ok(format!("{} {}", id, slug).into())
+        }
+        async fn user(Path(params): Path<HashMap<String, String>>) -> ResponseResult {
+            Ok(params["id"].clone().into())
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/users/:id/posts/:slug", post)
+            .route(Method::Get, "/users/:id", user);
+        let request = |path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            Request::parse(&mut StrParser::from_str(&raw)).unwrap()
+        };
+
+        let response =
+            crate::async_runtime::run(router.apply_request(request("/users/7/posts/hello")));
+        assert_eq!(response.body(), Some("7 hello"));
+        let response = crate::async_runtime::run(router.apply_request(request("/users/abc")));
+        assert_eq!(response.body(), Some("abc"));
+
+        let response =
+            crate::async_runtime::run(router.apply_request(request("/users/abc/posts/hello")));
+        assert_eq!(response.status_code(), &StatusCode::BadRequest);
+        let response = crate::async_runtime::run(router.apply_request(request("/users/7/posts")));
+        assert_eq!(response.status_code(), &StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_path_params_trailing_slash_and_tail() {
+        async fn user(Path((id,)): Path<(String,)>) -> ResponseResult {
+            Ok(id.into())
+        }
+        async fn file(Path(params): Path<HashMap<String, String>>) -> ResponseResult {
+            Ok(format!("file {}", params["*"]).into())
+        }
+        async fn settings() -> ResponseResult {
+            Ok("settings".into())
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/users/:id", user)
+            .route(Method::Get, "/static/*", file)
+            .route(Method::Get, "/static/:name", user)
+            .route(Method::Get, "/settings/", settings);
+        let body = |path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            let req = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+            let response = crate::async_runtime::run(router.apply_request(req));
+            response.body().map(str::to_string)
+        };
+
+        assert_eq!(body("/users/7").as_deref(), Some("7"));
+        assert_eq!(body("/users/7/").as_deref(), Some("7"));
+        assert_eq!(body("/settings").as_deref(), Some("settings"));
+        assert_eq!(body("/settings/").as_deref(), Some("settings"));
+
+        assert_eq!(
+            body("/static/css/site.css").as_deref(),
+            Some("file css/site.css")
+        );
+        assert_eq!(body("/static").as_deref(), Some("file "));
+        // a `:name` segment is more specific than the `*` tail
+        assert_eq!(body("/static/logo.png").as_deref(), Some("logo.png"));
+    }
+
+    #[test]
+    fn test_path_params_tie_breaker() {
+        async fn first() -> ResponseResult {
+            Ok("first".into())
+        }
+        async fn second() -> ResponseResult {
+            Ok("second".into())
+        }
+        async fn prefix() -> ResponseResult {
+            Ok("prefix".into())
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/a/:x/c", first)
+            .route(Method::Get, "/a/b/:y", prefix)
+            .route(Method::Get, "/:x/z", first)
+            .route(Method::Get, "/y/:x", prefix)
+            .route(Method::Get, "/users/:id", first)
+            .route(Method::Get, "/users/:name", second);
+        let body = |path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            let req = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+            let response = crate::async_runtime::run(router.apply_request(req));
+            response.body().map(str::to_string)
+        };
+
+        // the longer literal prefix wins, whichever was registered first
+        assert_eq!(body("/a/b/c").as_deref(), Some("prefix"));
+        assert_eq!(body("/y/z").as_deref(), Some("prefix"));
+        // then the route registered first
+        assert_eq!(body("/users/7").as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_response_builders() {
+        let response: Response = "created".into();
+        let response = response
+            .with_header("X-Custom", "yes")
+            .with_status(StatusCode::Created);
+        let full: FullResponse = Ok(response).into();
+        assert_eq!(full.status_code(), &StatusCode::Created);
+        assert_eq!(full.body(), Some("created"));
+        assert_eq!(full.headers().get("x-custom"), Some(&String::from("yes")));
+    }
+
+    #[test]
+    fn test_route_without_leading_slash() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Get, "some_route", ok);
+        let req =
+            Request::parse(&mut StrParser::from_str("GET /some_route HTTP/1.1\r\n\r\n")).unwrap();
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(response.body(), Some("ok"));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let proxy = IpAddr::from([10, 0, 0, 254]);
+        let router = Router::new(())
+            .route(Method::Get, "/", ok)
+            .rate_limit(1, 3)
+            .trusted_proxies([proxy]);
+        let request = |peer: IpAddr, forwarded_for: &str| {
+            let raw = format!(
+                "GET / HTTP/1.1\r\nX-Forwarded-For: {}\r\n\r\n",
+                forwarded_for
+            );
+            let mut request = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+            request.peer_addr = Some(std::net::SocketAddr::new(peer, 51234));
+            request
+        };
+        let limited = |peer: IpAddr, forwarded_for: &str| {
+            let response =
+                crate::async_runtime::run(router.apply_request(request(peer, forwarded_for)));
+            let is_limited = response.status_code() == &StatusCode::TooManyRequests;
+            if is_limited {
+                assert_eq!(
+                    response.headers().get("retry-after"),
+                    Some(&String::from("1"))
+                );
+            }
+            is_limited
+        };
+
+        // a client connecting directly is keyed on its own address, whatever it forwards
+        let client = IpAddr::from([192, 168, 1, 20]);
+        let results: Vec<bool> = (0..5)
+            .map(|i| limited(client, &format!("10.0.1.{}", i)))
+            .collect();
+        assert_eq!(results, vec![false, false, false, true, true]);
+
+        // behind the proxy the rightmost hop it didn't add is the client, so entries the
+        // client prepended itself don't get it a new bucket
+        let results: Vec<bool> = (0..5)
+            .map(|i| limited(proxy, &format!("10.0.1.{}, 10.0.0.1, {}", i, proxy)))
+            .collect();
+        assert_eq!(results, vec![false, false, false, true, true]);
+        assert!(!limited(proxy, "10.0.0.2"));
+
+        // a bucket that never refills still gets a usable Retry-After
+        let router = Router::new(()).route(Method::Get, "/", ok).rate_limit(0, 1);
+        let peer = IpAddr::from([192, 168, 1, 21]);
+        let response = crate::async_runtime::run(router.apply_request(request(peer, "")));
+        assert_eq!(response.status_code(), &StatusCode::OK);
+        let response = crate::async_runtime::run(router.apply_request(request(peer, "")));
+        assert_eq!(response.status_code(), &StatusCode::TooManyRequests);
+        assert_eq!(
+            response.headers().get("retry-after"),
+            Some(&Router::<()>::MAX_RETRY_AFTER.to_string())
+        );
+    }
+
+    #[test]
+    fn test_peer_addr() {
+        async fn peer(PeerAddr(addr): PeerAddr, method: Method) -> ResponseResult {
+            Ok(format!("{} {}", method.as_str(), addr).into())
+        }
+        let router = Router::new(()).route(Method::Get, "/", peer);
+        let request =
+            || Request::parse(&mut StrParser::from_str("GET / HTTP/1.1\r\n\r\n")).unwrap();
+
+        let mut req = request();
+        req.peer_addr = Some("192.168.1.20:51234".parse().unwrap());
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(response.body(), Some("GET 192.168.1.20:51234"));
+
+        let response = crate::async_runtime::run(router.apply_request(request()));
+        assert_eq!(response.status_code(), &StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn test_not_modified_keeps_cache_headers() {
+        async fn cached(Path((age,)): Path<(String,)>) -> ResponseResult {
+            Ok(Response::from("body")
+                .with_header("ETag", "\"v1\"")
+                .with_header("Cache-Control", "max-age=60")
+                .with_header("Age", age)
+                .with_header("Content-Type", "text/plain"))
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/:age", cached)
+            .conditional_get();
+        let get = |age: &str| {
+            let raw = format!("GET /{} HTTP/1.1\r\nIf-None-Match: \"v1\"\r\n\r\n", age);
+            let req = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+        };
+
+        let response = get("30");
+        assert_eq!(response.status_code(), &StatusCode::NotModified);
+        let header = |name: &str| response.headers().get(name).map(String::as_str);
+        assert_eq!(header("etag"), Some("\"v1\""));
+        assert_eq!(header("cache-control"), Some("max-age=60"));
+        assert_eq!(header("age"), Some("30"));
+        assert_eq!(header("content-type"), None);
+
+        // a malformed age isn't passed on
+        let response = get("soon");
+        assert_eq!(response.status_code(), &StatusCode::NotModified);
+        assert_eq!(response.headers().get("age"), None);
+    }
+
+    #[test]
+    fn test_pragma_no_cache() {
+        async fn tagged() -> ResponseResult {
+            Ok(Response::from("body").with_header("ETag", "\"v1\""))
+        }
+        let conditional = Router::new(())
+            .route(Method::Get, "/page", tagged)
+            .conditional_get();
+        let plain = Router::new(()).route(Method::Get, "/page", tagged);
+        let not_modified = |router: &Router<()>, extra: &str| {
+            let raw = format!(
+                "GET /page HTTP/1.0\r\nIf-None-Match: W/\"v1\"\r\n{}\r\n",
+                extra
+            );
+            let req = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+            let response = crate::async_runtime::run(router.apply_request(req));
+            response.status_code() == &StatusCode::NotModified
+        };
+        let status = |extra: &str| not_modified(&conditional, extra);
+
+        // routers only answer conditionally once asked to
+        assert!(!not_modified(&plain, ""));
+        assert!(status(""));
+        assert!(!status("Cache-Control: no-cache\r\n"));
+        assert!(!status("Pragma: no-cache\r\n"));
+        // Cache-Control takes precedence over Pragma when both are sent
+        assert!(status("Cache-Control: max-age=60\r\nPragma: no-cache\r\n"));
+    }
+
+    #[test]
+    fn test_route_requiring() {
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        async fn upload() -> ResponseResult {
+            CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok("stored".into())
+        }
+        let router = Router::new(())
+            .route_requiring(Method::Post, "/upload", &["Content-Type"], upload)
+            .route_requiring(Method::Post, "/api", &["x-api-key"], upload)
+            .route_requiring(
+                Method::Post,
+                "/json",
+                &["Content-Type: application/json"],
+                upload,
+            );
+        let status = |raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+                .status_code()
+                .clone()
+        };
+
+        assert_eq!(
+            status("POST /upload HTTP/1.1\r\nContent-Length: 0\r\n\r\n"),
+            StatusCode::BadRequest
+        );
+        assert_eq!(
+            status("POST /json HTTP/1.1\r\nContent-Length: 0\r\n\r\n"),
+            StatusCode::BadRequest
+        );
+        assert_eq!(
+            status("POST /json HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n"),
+            StatusCode::UnsupportedMediaType
+        );
+        assert_eq!(
+            status("POST /api HTTP/1.1\r\nContent-Length: 0\r\n\r\n"),
+            StatusCode::BadRequest
+        );
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        assert_eq!(
+            status("POST /upload HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("POST /api HTTP/1.1\r\nX-Api-Key: secret\r\n\r\n"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("POST /json HTTP/1.1\r\nContent-Type: Application/JSON; charset=utf-8\r\n\r\n"),
+            StatusCode::OK
+        );
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_route_conflicts() {
+        async fn handler() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let conflicts = |router: Router<()>| router.build().err().unwrap_or_default();
+
+        let router = Router::new(())
+            .route(Method::Get, "/x", handler)
+            .route(Method::Post, "/x", handler)
+            .route(Method::Get, "x/", handler);
+        assert_eq!(
+            conflicts(router),
+            vec![RouteConflict::Duplicate {
+                method: Method::Get,
+                path: "/x",
+            }]
+        );
+
+        let router = Router::new(())
+            .route(Method::Get, "/users/:id", handler)
+            .route(Method::Get, "/users/:name", handler)
+            .route(Method::Get, "/a/:x/c", handler)
+            .route(Method::Get, "/a/b/:y", handler)
+            .route(Method::Get, "/static/*", handler)
+            .route(Method::Get, "/static/css/*", handler);
+        assert_eq!(
+            conflicts(router),
+            vec![
+                RouteConflict::Ambiguous {
+                    method: Method::Get,
+                    paths: ("/a/:x/c", "/a/b/:y"),
+                },
+                RouteConflict::Ambiguous {
+                    method: Method::Get,
+                    paths: ("/static/*", "/static/css/*"),
+                },
+                RouteConflict::Ambiguous {
+                    method: Method::Get,
+                    paths: ("/users/:id", "/users/:name"),
+                },
+            ]
+        );
+
+        // routes that always resolve the same way are fine
+        let router = Router::new(())
+            .route(Method::Get, "/users/:id", handler)
+            .route(Method::Post, "/users/:name", handler)
+            .route(Method::Get, "/users/me", handler)
+            .route(Method::Get, "/users/:id/posts/:post", handler)
+            .route(Method::Get, "/files/:name", handler)
+            .route(Method::Get, "/files/*", handler)
+            .route(Method::Get, "/a/:x/c", handler)
+            .route(Method::Get, "/a/:x/d", handler)
+            .build();
+        assert!(router.is_ok());
+
+        let duplicate = Router::new(())
+            .route(Method::Get, "/x", handler)
+            .group("/", Group::new().route(Method::Get, "/x", handler))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(duplicate[0].to_string(), "GET /x is routed more than once");
+    }
+
+    #[test]
+    fn test_group_layers() {
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        async fn ok() -> ResponseResult {
+            CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok("ok".into())
+        }
+        async fn auth(req: InstanceRequest<()>, next: Next<()>) -> ResponseResult {
+            if !req.headers().contains_key("authorization") {
+                return Err(StatusCode::Unauthorized.into());
+            }
+            next.run(req).await
+        }
+        let router = Router::new(()).route(Method::Get, "/public", ok).group(
+            "/admin",
+            Group::new()
+                .route(Method::Get, "/", ok)
+                .layer(auth)
+                .route(Method::Get, "users", ok),
+        );
+        let status = |raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+                .status_code()
+                .clone()
+        };
+
+        assert_eq!(status("GET /public HTTP/1.1\r\n\r\n"), StatusCode::OK);
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(
+            status("GET /admin HTTP/1.1\r\n\r\n"),
+            StatusCode::Unauthorized
+        );
+        assert_eq!(
+            status("GET /admin/users HTTP/1.1\r\n\r\n"),
+            StatusCode::Unauthorized
+        );
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        assert_eq!(
+            status("GET /admin/users HTTP/1.1\r\nAuthorization: Bearer t\r\n\r\n"),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("GET /admin/other HTTP/1.1\r\n\r\n"),
+            StatusCode::NotFound
+        );
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_full_response_handler() {
+        fn teapot() -> FullResponse {
+            let headers = HashMap::from([(String::from("x-brew"), String::from("earl grey"))]);
+            FullResponse::new(
+                StatusCode::ExtensionCode(418),
+                headers,
+                Some("short and stout".into()),
+            )
+        }
+        async fn full() -> ResponseResult {
+            Ok(teapot().into())
+        }
+        async fn full_err() -> ResponseResult {
+            Err(Response::from(teapot()).with_status(StatusCode::ServiceUnavailable))
+        }
+        let router =
+            Router::new(())
+                .route(Method::Get, "/full", full)
+                .route(Method::Get, "/err", full_err);
+        let response = |raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+        };
+
+        assert_eq!(response("GET /full HTTP/1.1\r\n\r\n"), teapot());
+        let mut expected = teapot();
+        expected.set_status(StatusCode::ServiceUnavailable);
+        assert_eq!(response("GET /err HTTP/1.1\r\n\r\n"), expected);
+    }
+
+    #[test]
+    fn test_extension_methods() {
+        async fn patch(method: Method) -> ResponseResult {
+            Ok(format!("{} applied", method.as_str()).into())
+        }
+        let router = Router::new(())
+            .route(Method::Patch, "/doc", patch)
+            .route("PURGE", "/cache", patch);
+        let response = |raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+        };
+
+        let patched = response("PATCH /doc HTTP/1.1\r\n\r\n");
+        assert_eq!(patched.status_code(), &StatusCode::OK);
+        assert_eq!(patched.body(), Some("PATCH applied"));
+        let purged = response("PURGE /cache HTTP/1.1\r\n\r\n");
+        assert_eq!(purged.body(), Some("PURGE applied"));
+        assert_eq!(
+            response("purge /cache HTTP/1.1\r\n\r\n").status_code(),
+            &StatusCode::MethodNotAllowed
+        );
+
+        let allow = response("OPTIONS /doc HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            allow.headers().get("allow").map(String::as_str),
+            Some("OPTIONS, PATCH")
+        );
+    }
+
+    #[test]
+    fn test_json_array_body() {
+        use crate::serializer::Json;
+
+        #[derive(crate::Deserialize, Debug)]
+        struct Item {
+            a: u8,
+        }
+        async fn sum(Body(Json(items)): Body<Json<Vec<Item>>>) -> ResponseResult {
+            assert_eq!(items.len(), 2);
+            Ok(items
+                .iter()
+                .map(|i| i.a as u32)
+                .sum::<u32>()
+                .to_string()
+                .into())
+        }
+        let router = Router::new(()).route(Method::Post, "/items", sum);
+
+        let body = r#"[{"a":1},{"a":2}]"#;
+        let raw = format!(
+            "POST /items HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let req = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(response.status_code(), &StatusCode::OK);
+        assert_eq!(response.body(), Some("3"));
+
+        let raw = "POST /items HTTP/1.1\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+        let response = crate::async_runtime::run(router.apply_request(req));
+        assert_eq!(response.status_code(), &StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn test_cached_markup_route() {
+        static RENDERS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        fn render() -> Markup<'static> {
+            RENDERS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            crate::html! { P(){ "static page" } }
+        }
+        static PAGE: CachedMarkup = CachedMarkup::new(render);
+        async fn page() -> ResponseResult {
+            Ok((&PAGE).into())
+        }
+        let router = Router::new(()).route(Method::Get, "/page", page);
+        let get = || {
+            let req = Request::parse(&mut StrParser::from_str("GET /page HTTP/1.1\r\n\r\n"));
+            crate::async_runtime::run(router.apply_request(req.unwrap()))
+        };
+
+        let first = get();
+        let second = get();
+        assert_eq!(first.body(), Some("<p>static page</p>"));
+        assert_eq!(second.body(), first.body());
+        assert_eq!(
+            second.headers().get("content-type").map(String::as_str),
+            Some("text/html")
+        );
+        assert_eq!(RENDERS.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        PAGE.invalidate();
+        assert_eq!(get().body(), Some("<p>static page</p>"));
+        assert_eq!(RENDERS.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_fallback_and_method_not_allowed() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        async fn not_found(Path(path): Path<String>) -> ResponseResult {
+            Err((StatusCode::NotFound, format!("nothing at {}", path)).into())
+        }
+        let request = |raw: &str| Request::parse(&mut StrParser::from_str(raw)).unwrap();
+        let router = Router::new(())
+            .route(Method::Get, "/items", ok)
+            .route(Method::Post, "/items", ok)
+            .route(Method::Delete, "/items/:id", ok);
+
+        let response = crate::async_runtime::run(
+            router.apply_request(request("GET /missing HTTP/1.1\r\n\r\n")),
+        );
+        assert_eq!(response.status_code(), &StatusCode::NotFound);
+        assert!(!response.has_body());
+
+        let router = router.fallback(not_found);
+        let response = crate::async_runtime::run(
+            router.apply_request(request("GET /missing HTTP/1.1\r\n\r\n")),
+        );
+        assert_eq!(response.status_code(), &StatusCode::NotFound);
+        assert_eq!(response.body(), Some("nothing at /missing"));
+
+        let response =
+            crate::async_runtime::run(router.apply_request(request("PUT /items HTTP/1.1\r\n\r\n")));
+        assert_eq!(response.status_code(), &StatusCode::MethodNotAllowed);
+        assert_eq!(
+            response.headers().get("allow").map(String::as_str),
+            Some("OPTIONS, GET, POST")
+        );
+        let response = crate::async_runtime::run(
+            router.apply_request(request("GET /items/3 HTTP/1.1\r\n\r\n")),
+        );
+        assert_eq!(response.status_code(), &StatusCode::MethodNotAllowed);
+        assert_eq!(
+            response.headers().get("allow").map(String::as_str),
+            Some("OPTIONS, DELETE")
+        );
+    }
+
+    #[test]
+    fn test_router_layers() {
+        static ORDER: std::sync::Mutex<Vec<&str>> = std::sync::Mutex::new(Vec::new());
+        async fn ok() -> ResponseResult {
+            ORDER.lock().unwrap().push("handler");
+            Ok("ok".into())
+        }
+        async fn auth(req: InstanceRequest<()>, next: Next<()>) -> ResponseResult {
+            ORDER.lock().unwrap().push("auth");
+            if !req.headers().contains_key("authorization") {
+                return Err(StatusCode::Unauthorized.into());
+            }
+            next.run(req).await
+        }
+        async fn timing(req: InstanceRequest<()>, next: Next<()>) -> ResponseResult {
+            ORDER.lock().unwrap().push("timing");
+            Ok(next.run(req).await?.with_header("x-layer", "timing"))
+        }
+        async fn group_layer(req: InstanceRequest<()>, next: Next<()>) -> ResponseResult {
+            ORDER.lock().unwrap().push("group");
+            next.run(req).await
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/", ok)
+            .layer(auth)
+            .group(
+                "/g",
+                Group::new().layer(group_layer).route(Method::Get, "/", ok),
+            )
+            .layer(timing);
+        let response = |raw: &str| {
+            ORDER.lock().unwrap().clear();
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+        };
+
+        let rejected = response("GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(rejected.status_code(), &StatusCode::Unauthorized);
+        assert_eq!(*ORDER.lock().unwrap(), ["auth"]);
+
+        let accepted = response("GET / HTTP/1.1\r\nAuthorization: Bearer t\r\n\r\n");
+        assert_eq!(accepted.status_code(), &StatusCode::OK);
+        assert_eq!(
+            accepted.headers().get("x-layer").map(String::as_str),
+            Some("timing")
+        );
+        assert_eq!(*ORDER.lock().unwrap(), ["auth", "timing", "handler"]);
+
+        response("GET /g HTTP/1.1\r\nAuthorization: Bearer t\r\n\r\n");
+        assert_eq!(
+            *ORDER.lock().unwrap(),
+            ["auth", "timing", "group", "handler"]
+        );
+    }
+
+    #[test]
+    fn test_cors_preflight() {
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        async fn ok() -> ResponseResult {
+            CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok("ok".into())
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/items", ok)
+            .route(Method::Post, "/items", ok)
+            .route(Method::Options, "/items", ok)
+            .cors(
+                Cors::new()
+                    .allow_origins(&["https://example.com"])
+                    .max_age(600),
+            );
+        let response = |raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+        };
+        let header = |r: &FullResponse, name: &str| r.headers().get(name).cloned();
+
+        let preflight = response(
+            "OPTIONS /items HTTP/1.1\r\nOrigin: https://example.com\r\n\
+             Access-Control-Request-Method: POST\r\n\
+             Access-Control-Request-Headers: content-type, x-token\r\n\r\n",
+        );
+        assert_eq!(preflight.status_code(), &StatusCode::NoContent);
+        assert_eq!(
+            header(&preflight, "access-control-allow-origin").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            header(&preflight, "access-control-allow-methods").as_deref(),
+            Some("OPTIONS, GET, POST")
+        );
+        assert_eq!(
+            header(&preflight, "access-control-allow-headers").as_deref(),
+            Some("content-type, x-token")
+        );
+        assert_eq!(
+            header(&preflight, "access-control-max-age").as_deref(),
+            Some("600")
+        );
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // plain OPTIONS still reaches its handler
+        let options = response("OPTIONS /items HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n");
+        assert_eq!(options.status_code(), &StatusCode::OK);
+        assert_eq!(CALLS.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let other = response(
+            "OPTIONS /items HTTP/1.1\r\nOrigin: https://evil.example\r\n\
+             Access-Control-Request-Method: POST\r\n\r\n",
+        );
+        assert_eq!(other.status_code(), &StatusCode::NoContent);
+        assert_eq!(header(&other, "access-control-allow-methods"), None);
+
+        let get = response("GET /items HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n");
+        assert_eq!(
+            header(&get, "access-control-allow-origin").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(header(&get, "vary").as_deref(), Some("Origin"));
+    }
+
+    #[test]
+    fn test_cors_keeps_vary() {
+        async fn compressed() -> ResponseResult {
+            Ok(Response::from("ok").with_header("Vary", "Accept-Encoding"))
+        }
+        async fn by_origin() -> ResponseResult {
+            Ok(Response::from("ok").with_header("Vary", "origin"))
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/compressed", compressed)
+            .route(Method::Get, "/by-origin", by_origin)
+            .cors(Cors::new().allow_origins(&["https://example.com"]));
+        let vary = |path: &str| {
+            let raw = format!(
+                "GET {} HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n",
+                path
+            );
+            let req = Request::parse(&mut StrParser::from_str(&raw)).unwrap();
+            let response = crate::async_runtime::run(router.apply_request(req));
+            response.headers().get("vary").cloned()
+        };
+
+        assert_eq!(
+            vary("/compressed").as_deref(),
+            Some("Accept-Encoding, Origin")
+        );
+        // already listed, in any case
+        assert_eq!(vary("/by-origin").as_deref(), Some("origin"));
+    }
+
+    #[test]
+    fn test_typed_query() {
+        #[derive(crate::Deserialize, Debug)]
+        struct Search {
+            page: u32,
+            q: String,
+        }
+        async fn search(Query(s): Query<Search>) -> ResponseResult {
+            Ok(format!("{} {}", s.page, s.q).into())
+        }
+        let router = Router::new(()).route(Method::Get, "/search", search);
+        let response = |raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            crate::async_runtime::run(router.apply_request(req))
+        };
+
+        let found = response("GET /search?page=2&q=hello HTTP/1.1\r\n\r\n");
+        assert_eq!(found.status_code(), &StatusCode::OK);
+        assert_eq!(found.body(), Some("2 hello"));
+
+        let bad = response("GET /search?page=two&q=hello HTTP/1.1\r\n\r\n");
+        assert_eq!(bad.status_code(), &StatusCode::BadRequest);
+        let missing = response("GET /search?q=hello HTTP/1.1\r\n\r\n");
+        assert_eq!(missing.status_code(), &StatusCode::BadRequest);
+
+        let query = RequestQuery::parse(&mut StrParser::from_str("q=hello")).unwrap();
+        assert_eq!(
+            Search::into_query(query).map(|_| ()),
+            Err(DeserializeError::missing("page"))
+        );
+    }
+
+    #[test]
+    fn test_response_from_result() {
+        let full = |r: Response| FullResponse::from(Ok::<_, Response>(r));
+
+        let created: Result<_, StatusCode> = Ok((StatusCode::Created, String::from("made")));
+        let response = full(Response::from_result(created));
+        assert_eq!(response.status_code(), &StatusCode::Created);
+        assert_eq!(response.body(), Some("made"));
+
+        let missing: Result<String, _> = Err(StatusCode::NotFound);
+        let response = full(Response::from_result(missing));
+        assert_eq!(response.status_code(), &StatusCode::NotFound);
+
+        // arms without a status keep the defaults of a handler's `Ok` and `Err`
+        assert_eq!(
+            full(Response::from_result(Ok::<_, String>("ok"))).status_code(),
+            &StatusCode::OK
+        );
+        assert_eq!(
+            full(Response::from_result(Err::<(), _>("failed"))).status_code(),
+            &StatusCode::InternalServerError
+        );
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        async fn login_redirect(req: InstanceRequest<()>, _: Next<()>) -> ResponseResult {
+            let location = req.absolute_url("/log in");
+            Ok(Response::from(StatusCode::Found).with_header("location", location))
+        }
+        let proxy = IpAddr::from([10, 0, 0, 254]);
+        let router = Router::new(())
+            .route(Method::Get, "/private", ok)
+            .layer(login_redirect)
+            .trusted_proxies([proxy]);
+        let location_from = |peer: IpAddr, raw: &str| {
+            let mut req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            req.peer_addr = Some(std::net::SocketAddr::new(peer, 51234));
+            let response = crate::async_runtime::run(router.apply_request(req));
+            assert_eq!(response.status_code(), &StatusCode::Found);
+            response.headers().get("location").cloned().unwrap()
+        };
+        let location = |raw: &str| location_from(IpAddr::from([192, 168, 1, 20]), raw);
+
+        assert_eq!(
+            location("GET /private HTTP/1.1\r\nHost: Example.com:8080\r\n\r\n"),
+            "http://example.com:8080/log%20in"
+        );
+        let forwarded =
+            "GET /private HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-Proto: https\r\n\r\n";
+        assert_eq!(
+            location_from(proxy, forwarded),
+            "https://example.com/log%20in"
+        );
+        // clients can't pick the scheme themselves
+        assert_eq!(location(forwarded), "http://example.com/log%20in");
+        // nor by sending the header ahead of the proxy's own
+        assert_eq!(
+            location_from(
+                proxy,
+                "GET /private HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-Proto: https, http\r\n\r\n"
+            ),
+            "http://example.com/log%20in"
+        );
+        assert_eq!(location("GET /private HTTP/1.0\r\n\r\n"), "/log%20in");
+
+        // a path starting with `//` can't point clients to another host
+        let redirect = |path: &str, raw: &str| {
+            let req = Request::parse(&mut StrParser::from_str(raw)).unwrap();
+            InstanceRequest::from_request(Arc::new(()), req).absolute_url(path)
+        };
+        let no_host = "GET /private HTTP/1.0\r\n\r\n";
+        assert_eq!(redirect("//evil.example/x", no_host), "/evil.example/x");
+        assert_eq!(redirect("///evil.example", no_host), "/evil.example");
+        assert_eq!(redirect("/\\evil.example", no_host), "/%5Cevil.example");
+        assert_eq!(
+            redirect(
+                "//evil.example",
+                "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+            ),
+            "http://example.com/evil.example"
+        );
+    }
 }
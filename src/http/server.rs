@@ -1,47 +1,1282 @@
 use super::response::{Response, StatusCode};
 use super::routing::Router;
-use crate::stream_writer::StreamWritable;
-use crate::{errors::ZeroErr, http::request::Request, parsing::StreamParser};
-use std::net::TcpListener;
-use std::sync::Arc;
+use crate::async_runtime;
+use crate::parsing::{BufferPool, ParseErr, Parser};
+use crate::stream_writer::{CountingWriter, StreamWritable};
+use crate::{
+    errors::ZeroErr,
+    http::request::{BodyStream, Method, Request, RequestBody, RequestId},
+};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, atomic::Ordering, mpsc};
+use std::time::Duration;
 
 pub struct HttpServer<T: Send + Sync + 'static> {
     router: Arc<Router<T>>,
+    buffer_pool: Option<Arc<BufferPool>>,
+    workers: usize,
+    max_connections: usize,
+    limits: Limits,
+}
+
+/// Per connection limits, socket options and logging, see the matching `HttpServer`
+/// builders
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_body: usize,
+    max_headers: usize,
+    max_requests: usize,
+    idle_timeout: Option<Duration>,
+    tcp_nodelay: bool,
+    access_log: Option<fn(&AccessLog)>,
+}
+
+/// A request the server answered, handed to the `HttpServer::access_log` callback
+/// once its response has been written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLog {
+    pub request_id: RequestId,
+    pub method: Method,
+    /// Path the request was sent to, without its query
+    pub path: String,
+    pub status: StatusCode,
+    /// Bytes written for the response, status line and headers included
+    pub bytes_written: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_body: HttpServer::<()>::DEFAULT_MAX_BODY,
+            max_headers: HttpServer::<()>::DEFAULT_MAX_HEADERS,
+            max_requests: usize::MAX,
+            idle_timeout: Some(HttpServer::<()>::DEFAULT_IDLE_TIMEOUT),
+            tcp_nodelay: true,
+            access_log: None,
+        }
+    }
+}
+
+/// Connections being served on threads of their own, see `HttpServer::max_connections`
+struct ConnectionSlots {
+    max: usize,
+    open: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// Frees its connection's slot when dropped, once the connection is done with
+struct ConnectionSlot(Arc<ConnectionSlots>);
+
+impl ConnectionSlots {
+    fn new(max: usize) -> Arc<Self> {
+        Arc::new(ConnectionSlots {
+            max: max.max(1),
+            open: Mutex::new(0),
+            freed: Condvar::new(),
+        })
+    }
+
+    /// Takes a slot, waiting for one to be freed while all `max` are taken
+    fn acquire(self: &Arc<Self>) -> ConnectionSlot {
+        let mut open = match self.open.lock() {
+            Ok(open) => open,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        while *open >= self.max {
+            open = match self.freed.wait(open) {
+                Ok(open) => open,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+        }
+        *open += 1;
+        ConnectionSlot(self.clone())
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open = match self.0.open.lock() {
+            Ok(open) => open,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *open -= 1;
+        self.0.freed.notify_one();
+    }
 }
 
 // type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
 
 impl<T: Send + Sync> HttpServer<T> {
+    /// Default for `max_body`
+    pub const DEFAULT_MAX_BODY: usize = 8 * 1024 * 1024;
+    /// Default for `max_headers`
+    pub const DEFAULT_MAX_HEADERS: usize = 100;
+    /// Default for `max_connections`
+    pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+    /// How long a connection may sit idle unless `keep_alive` says otherwise
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
     pub fn from_router(router: Router<T>) -> Self {
         HttpServer {
             router: router.into(),
+            buffer_pool: None,
+            workers: 0,
+            max_connections: Self::DEFAULT_MAX_CONNECTIONS,
+            limits: Limits::default(),
         }
     }
+
+    /// Handle to the router being served, e.g. to call `Router::set_instance`
+    /// while the server is running.
+    pub fn router(&self) -> Arc<Router<T>> {
+        self.router.clone()
+    }
+
+    /// Recycles parser read buffers across connections instead of allocating
+    /// a fresh one for every request. See `BufferPool`.
+    pub fn with_buffer_pool(mut self) -> Self {
+        self.buffer_pool = Some(Arc::new(BufferPool::new()));
+        self
+    }
+
+    /// Hands accepted connections off to `n` worker threads named
+    /// `zero-worker-0` through `zero-worker-{n-1}`.
+    ///
+    /// With the default of `0` every connection gets a thread of its own, named
+    /// `zero-connection`, so a slow handler only holds up its own client. How many of
+    /// those there are at once is capped by `max_connections`.
+    pub fn workers(mut self, n: usize) -> Self {
+        self.workers = n;
+        self
+    }
+
+    /// Most connections served at once when they each get a thread, see `workers`.
+    ///
+    /// Once that many are open, new ones wait in the listen backlog until one closes.
+    pub fn max_connections(mut self, n: usize) -> Self {
+        self.max_connections = n;
+        self
+    }
+
+    /// Largest request body in bytes the server will read off a connection, unless
+    /// the route sets its own with `Router::route_with_limit`.
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.limits.max_body = bytes;
+        self
+    }
+
+    /// Most header fields a request may have. Requests with more are answered with
+    /// `431 Request Header Fields Too Large` and the connection is closed.
+    pub fn max_headers(mut self, count: usize) -> Self {
+        self.limits.max_headers = count;
+        self
+    }
+
+    /// Closes a persistent connection after it has served `max_requests` requests,
+    /// the last response carrying `Connection: close`, or once the client has been
+    /// silent for `idle_timeout`. Without it connections are dropped after
+    /// `DEFAULT_IDLE_TIMEOUT`, and serve any number of requests.
+    ///
+    /// The timeout applies to every read off the connection, so a client stalling in
+    /// the middle of a request is dropped as well.
+    pub fn keep_alive(mut self, max_requests: usize, idle_timeout: Duration) -> Self {
+        self.limits.max_requests = max_requests;
+        self.limits.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Calls `log` with every request answered, along with the size of its response,
+    /// e.g. to write an access log. Requests rejected before they could be parsed,
+    /// e.g. with a `400`, aren't logged.
+    pub fn access_log(mut self, log: fn(&AccessLog)) -> Self {
+        self.limits.access_log = Some(log);
+        self
+    }
+
+    /// Whether accepted connections disable Nagle's algorithm, on by default.
+    ///
+    /// Responses are written as a small header block followed by the body, which
+    /// Nagle would otherwise hold back waiting for an ACK.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.limits.tcp_nodelay = nodelay;
+        self
+    }
+
     pub async fn serve<IP>(&mut self, ip: IP) -> Result<(), ZeroErr>
     where
         IP: std::fmt::Display,
     {
         let listener = TcpListener::bind(ip.to_string()).map_err(|_| ZeroErr::FailedToOpen)?;
+        self.serve_listener(listener).await
+    }
+
+    async fn serve_listener(&mut self, listener: TcpListener) -> Result<(), ZeroErr> {
+        let sender = if self.workers > 0 {
+            Some(self.spawn_workers()?)
+        } else {
+            None
+        };
+
+        let slots = ConnectionSlots::new(self.max_connections);
 
         for stream in listener.incoming() {
             match stream {
-                Ok(mut stream) => {
-                    let router = self.router.clone();
-                    match Request::parse_from_stream(&mut stream) {
-                        Ok(request) => {
-                            let response = router.apply_request(request).await;
-                            let _ = response.write_to_stream(&mut stream);
-                        }
-                        Err(_) => {
-                            let response = Response::new_simple(StatusCode::BadRequest, None);
-                            let _ = response.write_to_stream(&mut stream);
+                Ok(stream) => match &sender {
+                    Some(sender) => {
+                        let _ = sender.send(stream);
+                    }
+                    None => {
+                        let slot = slots.acquire();
+                        let router = self.router.clone();
+                        let buffer_pool = self.buffer_pool.clone();
+                        let limits = self.limits;
+                        let spawned = std::thread::Builder::new()
+                            .name(String::from("zero-connection"))
+                            .spawn(move || {
+                                Self::serve_stream(&router, &buffer_pool, limits, stream);
+                                drop(slot);
+                            });
+                        if let Err(e) = spawned {
+                            eprintln!("failed to spawn connection thread: {}", e);
                         }
                     }
-                }
+                },
                 Err(e) => eprintln!("connection failed: {}", e),
             }
         }
 
         Ok(())
     }
+
+    fn spawn_workers(&self) -> Result<mpsc::Sender<TcpStream>, ZeroErr> {
+        let (sender, receiver) = mpsc::channel::<TcpStream>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for i in 0..self.workers {
+            let receiver = receiver.clone();
+            let router = self.router.clone();
+            let buffer_pool = self.buffer_pool.clone();
+            let limits = self.limits;
+            std::thread::Builder::new()
+                .name(format!("zero-worker-{}", i))
+                .spawn(move || {
+                    loop {
+                        let stream = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => break,
+                        };
+                        let Ok(stream) = stream else {
+                            break;
+                        };
+                        Self::serve_stream(&router, &buffer_pool, limits, stream);
+                    }
+                })
+                .map_err(|_| ZeroErr::FailedToSpawn)?;
+        }
+
+        Ok(sender)
+    }
+
+    /// Serves an accepted connection to completion on the current thread
+    fn serve_stream(
+        router: &Router<T>,
+        buffer_pool: &Option<Arc<BufferPool>>,
+        limits: Limits,
+        stream: TcpStream,
+    ) {
+        // the parser owns its half so a streamed body can outlive the borrow of `stream`
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let parser = Self::parser_for(buffer_pool, reader, limits);
+        let mut writer = &stream;
+        async_runtime::run(Self::serve_connection(
+            router,
+            Arc::new(Mutex::new(parser)),
+            &mut writer,
+            limits,
+            stream.peer_addr().ok(),
+        ));
+    }
+
+    fn parser_for(
+        buffer_pool: &Option<Arc<BufferPool>>,
+        stream: TcpStream,
+        limits: Limits,
+    ) -> Parser<TcpStream> {
+        // a timed out read surfaces as an io error, which ends the connection
+        let _ = stream.set_read_timeout(limits.idle_timeout);
+        let _ = stream.set_nodelay(limits.tcp_nodelay);
+        match buffer_pool {
+            Some(pool) => Parser::from_stream_pooled(stream, pool.clone()),
+            None => Parser::from_stream(stream),
+        }
+    }
+
+    fn lock<R: Read>(parser: &Mutex<Parser<R>>) -> MutexGuard<'_, Parser<R>> {
+        match parser.lock() {
+            Ok(parser) => parser,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Parses the next request, reading its body unless the route streams it, in which
+    /// case the body is a `BodyStream` reading through `parser`.
+    fn read_request<R: Read + Send + 'static>(
+        router: &Router<T>,
+        parser: &Arc<Mutex<Parser<R>>>,
+        limits: Limits,
+    ) -> Result<Request, ParseErr> {
+        let mut locked = Self::lock(parser);
+        let (mut request, body_len) = Request::parse_head(&mut locked, limits.max_headers)?;
+        let Some(body_len) = body_len else {
+            return Ok(request);
+        };
+
+        let max_body = router
+            .body_limit(&request.method, &request.path)
+            .unwrap_or(limits.max_body);
+        if !router.streams_body(&request.method, &request.path) {
+            request.read_body(&mut locked, body_len, max_body)?;
+        } else if body_len > max_body {
+            return Err(ParseErr::BodyTooLarge {
+                requested: body_len,
+                max: max_body,
+            });
+        } else {
+            let source: Arc<Mutex<dyn Read + Send>> = parser.clone();
+            request.body = RequestBody::Stream(BodyStream::new(source, body_len));
+        }
+
+        Ok(request)
+    }
+
+    /// Writes `response`, returning the number of bytes written
+    fn write_response<W: Write>(
+        router: &Router<T>,
+        mut response: Response,
+        writer: &mut W,
+    ) -> std::io::Result<u64> {
+        router.render_error_page(&mut response);
+        let mut writer = CountingWriter::new(writer);
+        response.write_to_stream(&mut writer)?;
+        Ok(writer.count())
+    }
+
+    /// Serves requests off of a connection until it is closed or stops being persistent.
+    ///
+    /// Whether the connection persists follows `Request::is_persistent`, and every
+    /// response says so in its `Connection` header when it isn't implied by the version:
+    /// `close` on the last response, `keep-alive` for persistent HTTP/1.0 clients. A
+    /// client closing the connection between requests or in the middle of one just ends
+    /// the connection.
+    ///
+    /// A handler that panics is answered with a `500` and the panic is logged,
+    /// leaving the calling thread free to serve the next connection. Chunked bodies
+    /// nobody read are drained, up to `max_body` bytes, and so is what a handler left
+    /// unread of a streamed body, so the next request on the connection starts at the
+    /// right place.
+    async fn serve_connection<R: Read + Send + 'static, W: Write>(
+        router: &Router<T>,
+        parser: Arc<Mutex<Parser<R>>>,
+        writer: &mut W,
+        limits: Limits,
+        peer_addr: Option<SocketAddr>,
+    ) {
+        let mut served = 0_usize;
+        while Self::lock(&parser).peek().is_some() {
+            let mut request = match Self::read_request(router, &parser, limits) {
+                Ok(request) => request,
+                // the connection is broken or the client went away mid-request,
+                // there is nobody to send a 400 to
+                Err(ParseErr::IoError { .. } | ParseErr::UnexpectedEof) => return,
+                Err(ParseErr::TooManyHeaders { .. }) => {
+                    let response =
+                        Response::new_simple(StatusCode::RequestHeaderFieldsTooLarge, None);
+                    let _ = Self::write_response(router, response, writer);
+                    return;
+                }
+                Err(ParseErr::BodyTooLarge { .. }) => {
+                    let response = Response::new_simple(StatusCode::RequestEntityTooLarge, None);
+                    let _ = Self::write_response(router, response, writer);
+                    return;
+                }
+                Err(err) => {
+                    let response = router.handle_bad_request(err);
+                    let _ = Self::write_response(router, response, writer);
+                    return;
+                }
+            };
+            request.peer_addr = peer_addr;
+            let request_id = request.ensure_request_id();
+            served += 1;
+            let last = served >= limits.max_requests;
+            let mut persistent = request.is_persistent() && !last;
+            let http_10 = !request.http_version.at_least(1, 1);
+            let chunked = request.is_chunked();
+            let accepts_trailers = request.accepts_trailers();
+            let logged = limits.access_log.map(|log| {
+                let path = request.path.entire_path().to_string();
+                (log, request_id.clone(), request.method.clone(), path)
+            });
+            let streamed = match &request.body {
+                RequestBody::Stream(stream) => Some(stream.remaining_handle()),
+                _ => None,
+            };
+
+            let mut response =
+                match async_runtime::catch_unwind(router.apply_request(request)).await {
+                    Ok(response) => response,
+                    Err(payload) => {
+                        eprintln!(
+                            "[{}] handler panicked on thread {}: {}",
+                            request_id,
+                            std::thread::current().name().unwrap_or("<unnamed>"),
+                            async_runtime::panic_message(payload.as_ref())
+                        );
+                        Response::new_simple(StatusCode::InternalServerError, None)
+                    }
+                };
+            // whatever of a streamed body the handler didn't read is ours to skip
+            let unread = streamed.map_or(0, |remaining| remaining.swap(0, Ordering::AcqRel));
+            response.accept_trailers(accepts_trailers);
+            // HTTP/1.0 clients can't decode chunks, the body ends when the connection does
+            if http_10 && response.is_chunked() {
+                response.close_delimit();
+                persistent = false;
+            }
+            // HTTP/1.1 connections persist by default, older clients have to be told
+            let announce_keep_alive = persistent && http_10;
+            response
+                .headers_mut()
+                .insert(RequestId::HEADER.to_string(), request_id.0);
+            if !persistent {
+                response
+                    .headers_mut()
+                    .insert(String::from("connection"), String::from("close"));
+            } else if announce_keep_alive {
+                response
+                    .headers_mut()
+                    .insert(String::from("connection"), String::from("keep-alive"));
+            }
+            let status = response.status_code().clone();
+            let Ok(bytes_written) = Self::write_response(router, response, writer) else {
+                return;
+            };
+            if let Some((log, request_id, method, path)) = logged {
+                log(&AccessLog {
+                    request_id,
+                    method,
+                    path,
+                    status,
+                    bytes_written,
+                });
+            }
+            if !persistent {
+                return;
+            }
+            let mut parser = Self::lock(&parser);
+            if unread > 0 && parser.skip_n(unread) < unread {
+                return;
+            }
+            if chunked && RequestBody::drain_chunked(&mut parser, limits.max_body).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Markup;
+    use crate::http::Body;
+    use crate::http::request::Method;
+    use crate::http::response::StreamingResponse;
+    use crate::http::routing::{Path, ResponseResult};
+    use crate::parsing::Parsable;
+    use std::net::Shutdown;
+
+    fn send(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_worker_panic_isolation() {
+        async fn panics() -> ResponseResult {
+            panic!("handler exploded");
+        }
+        async fn ok() -> ResponseResult {
+            Ok("still alive".into())
+        }
+        async fn thread_name() -> ResponseResult {
+            Ok(std::thread::current().name().unwrap_or("").into())
+        }
+
+        let router = Router::new(())
+            .route(Method::Get, "/panic", panics)
+            .route(Method::Get, "/ok", ok)
+            .route(Method::Get, "/thread", thread_name);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut server = HttpServer::from_router(router).workers(1);
+            async_runtime::run(server.serve_listener(listener))
+        });
+
+        assert!(send(addr, "/panic").starts_with("HTTP/1.1 500"));
+        let response = send(addr, "/ok");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("still alive"));
+        assert!(send(addr, "/panic").starts_with("HTTP/1.1 500"));
+        assert!(send(addr, "/thread").ends_with("zero-worker-0"));
+    }
+
+    #[test]
+    fn test_concurrent_connections() {
+        async fn slow() -> ResponseResult {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok("slow".into())
+        }
+        async fn fast() -> ResponseResult {
+            Ok("fast".into())
+        }
+        let router =
+            Router::new(())
+                .route(Method::Get, "/slow", slow)
+                .route(Method::Get, "/fast", fast);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut server = HttpServer::from_router(router);
+            async_runtime::run(server.serve_listener(listener))
+        });
+
+        let slow_client = std::thread::spawn(move || send(addr, "/slow"));
+        // give the slow request a head start so it is being handled first
+        std::thread::sleep(Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        assert!(send(addr, "/fast").ends_with("fast"));
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        assert!(slow_client.join().unwrap().ends_with("slow"));
+    }
+
+    #[test]
+    fn test_max_connections() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Get, "/", ok);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut server = HttpServer::from_router(router).max_connections(1);
+            async_runtime::run(server.serve_listener(listener))
+        });
+
+        // an idle connection holds the only slot
+        let idle = TcpStream::connect(addr).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let mut waiting = TcpStream::connect(addr).unwrap();
+        write!(waiting, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        waiting
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0_u8; 1];
+        assert!(waiting.read(&mut buf).is_err());
+
+        drop(idle);
+        waiting.set_read_timeout(None).unwrap();
+        let mut response = String::new();
+        waiting.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("ok"));
+    }
+
+    #[test]
+    fn test_streaming_upload() {
+        use crate::db::{BufferedRW, TableRecord, ToDatabaseBytes};
+        use crate::http::routing::Instance;
+
+        #[derive(Debug, PartialEq, crate::ZeroTable)]
+        struct Upload {
+            name: String,
+        }
+        /// Passes reads through, remembering the largest one asked of it
+        struct Tracked<R> {
+            inner: R,
+            largest_read: usize,
+        }
+        impl<R: Read> Read for Tracked<R> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.largest_read = self.largest_read.max(buf.len());
+                self.inner.read(buf)
+            }
+        }
+        struct Uploads {
+            db: Arc<Mutex<BufferedRW>>,
+            started: Mutex<mpsc::Sender<()>>,
+        }
+        async fn upload(
+            uploads: Instance<Uploads>,
+            Path((name,)): Path<(String,)>,
+            Body(body): Body<BodyStream>,
+        ) -> ResponseResult {
+            fn failed<E>(_: E) -> Response {
+                Response::new_simple(StatusCode::InternalServerError, None)
+            }
+            let _ = uploads.started.lock().unwrap().send(());
+            let len = body.remaining();
+            let row = TableRecord::new_system_record(Upload { name }).map_err(failed)?;
+            let mut body = Tracked {
+                inner: body,
+                largest_read: 0,
+            };
+            let record = row.to_db_bytes().to_record_stream(len, &mut body);
+            let written = uploads
+                .db
+                .lock()
+                .unwrap()
+                .write_stream(1 << 12, record)
+                .map_err(failed)?;
+            Ok(format!("{} {}", written, body.largest_read).into())
+        }
+
+        let path = std::env::temp_dir().join(format!("zero_stream_upload_{}", std::process::id()));
+        let db = BufferedRW::new(path.to_str().unwrap()).unwrap();
+        let db = Arc::new(Mutex::new(db));
+        let (started, handler_started) = mpsc::channel();
+        let uploads = Uploads {
+            db: db.clone(),
+            started: Mutex::new(started),
+        };
+        let router = Router::new(uploads).route_streaming(
+            Method::Post,
+            "/upload/:name",
+            2 * 1024 * 1024,
+            upload,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            // far below the upload, only the route's own limit applies
+            let mut server = HttpServer::from_router(router).max_body(64 * 1024);
+            async_runtime::run(server.serve_listener(listener))
+        });
+
+        let data: Vec<u8> = (0..1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /upload/blob HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+            data.len()
+        )
+        .unwrap();
+        stream.write_all(&data[..64 * 1024]).unwrap();
+        // the handler is already running while most of the body is still with the client
+        handler_started
+            .recv_timeout(Duration::from_secs(5))
+            .expect("handler should run before the whole body is sent");
+        stream.write_all(&data[64 * 1024..]).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let (written, largest_read) = body.split_once(' ').unwrap();
+        let written: usize = written.parse().unwrap();
+        // the body was read a page at a time, never collected whole
+        assert!(largest_read.parse::<usize>().unwrap() <= 4096);
+
+        let mut raw = Vec::new();
+        db.lock()
+            .unwrap()
+            .read_stream(1 << 12, written, &mut raw)
+            .unwrap();
+        let mut bytes = crate::db::DatabaseBytes::from_record_bytes(&raw).unwrap();
+        let (row, blob) = <(TableRecord<Upload>, Vec<u8>)>::from_db_bytes(&mut bytes).unwrap();
+        assert_eq!(
+            row.row(),
+            &Upload {
+                name: String::from("blob")
+            }
+        );
+        assert!(blob == data);
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("zero_wal"));
+    }
+
+    #[test]
+    fn test_streamed_body_left_unread() {
+        async fn peek(Body(mut body): Body<BodyStream>) -> ResponseResult {
+            let mut start = [0_u8; 4];
+            body.read_exact(&mut start)
+                .map_err(|_| StatusCode::BadRequest)?;
+            Ok(String::from_utf8_lossy(&start).into_owned().into())
+        }
+        async fn second() -> ResponseResult {
+            Ok("second".into())
+        }
+        let router = Router::new(())
+            .route_streaming(Method::Post, "/peek", 16, peek)
+            .route(Method::Get, "/second", second);
+        let serve = |raw: &'static str| {
+            let parser = Parser::from_stream(std::io::Cursor::new(raw));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits::default(),
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        // the rest of the body is skipped before the next request is read
+        let out = serve(concat!(
+            "POST /peek HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789",
+            "GET /second HTTP/1.1\r\n\r\n",
+        ));
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2);
+        assert!(out.contains("\r\n\r\n0123HTTP/1.1"));
+        assert!(out.ends_with("second"));
+
+        let out = serve("POST /peek HTTP/1.1\r\nContent-Length: 17\r\n\r\n01234567890123456");
+        assert!(out.starts_with("HTTP/1.1 413"));
+
+        // the connection closing mid-body fails the handler's read
+        let out = serve("POST /peek HTTP/1.1\r\nContent-Length: 10\r\n\r\n01");
+        assert!(out.starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn test_pipelined_unread_bodies() {
+        async fn ignores_body() -> ResponseResult {
+            Ok("ignored".into())
+        }
+        async fn second() -> ResponseResult {
+            Ok("second".into())
+        }
+        let router = Router::new(())
+            .route(Method::Post, "/upload", ignores_body)
+            .route(Method::Get, "/second", second);
+
+        let raw = concat!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+            "GET /second HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        let parser = Parser::from_stream(std::io::Cursor::new(raw));
+        let mut out = Vec::new();
+        async_runtime::run(HttpServer::serve_connection(
+            &router,
+            Arc::new(Mutex::new(parser)),
+            &mut out,
+            Limits::default(),
+            None,
+        ));
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 3);
+        assert_eq!(out.matches("ignored").count(), 2);
+        assert!(out.ends_with("second"));
+    }
+
+    #[test]
+    fn test_drain_respects_max_body() {
+        async fn ignores_body() -> ResponseResult {
+            Ok("ignored".into())
+        }
+        let router = Router::new(()).route(Method::Post, "/upload", ignores_body);
+
+        let raw = concat!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "10\r\n0123456789abcdef\r\n0\r\n\r\n",
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        let parser = Parser::from_stream(std::io::Cursor::new(raw));
+        let mut out = Vec::new();
+        async_runtime::run(HttpServer::serve_connection(
+            &router,
+            Arc::new(Mutex::new(parser)),
+            &mut out,
+            Limits {
+                max_body: 8,
+                ..Limits::default()
+            },
+            None,
+        ));
+
+        // the connection is dropped instead of parsing the rest of the oversized body
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 1);
+    }
+
+    #[test]
+    fn test_content_length_over_max_body() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Post, "/upload", ok);
+
+        let raw = "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 999999999\r\n\r\n";
+        let parser = Parser::from_stream(std::io::Cursor::new(raw));
+        let mut out = Vec::new();
+        async_runtime::run(HttpServer::serve_connection(
+            &router,
+            Arc::new(Mutex::new(parser)),
+            &mut out,
+            Limits {
+                max_body: 1024,
+                ..Limits::default()
+            },
+            None,
+        ));
+
+        assert!(String::from_utf8(out).unwrap().starts_with("HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn test_route_body_limit() {
+        async fn echo(Body(body): Body<RequestBody>) -> ResponseResult {
+            match body {
+                RequestBody::Bytes(body) => Ok(String::from_utf8_lossy(&body).into_owned().into()),
+                _ => Ok("".into()),
+            }
+        }
+        let router = Router::new(())
+            .route_with_limit(Method::Post, "/small", 10, echo)
+            .route_with_limit(Method::Post, "/upload/:name", 1024, echo)
+            .route(Method::Post, "/upload/big", echo)
+            .route_with_limit(Method::Post, "/replaced", 1024, echo)
+            .route(Method::Post, "/replaced", echo);
+        let serve = |path: &str, max_body: usize| {
+            let raw = format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 16\r\n\r\n\
+                 0123456789abcdef",
+                path
+            );
+            let parser = Parser::from_stream(std::io::Cursor::new(raw));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits {
+                    max_body,
+                    ..Limits::default()
+                },
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        assert!(serve("/small", 1024).starts_with("HTTP/1.1 413"));
+        // the route limit overrides the server's, in both directions
+        let accepted = serve("/upload/a.txt", 8);
+        assert!(accepted.starts_with("HTTP/1.1 200"));
+        assert!(accepted.ends_with("0123456789abcdef"));
+        assert!(serve("/other", 8).starts_with("HTTP/1.1 413"));
+
+        // a static route doesn't take the limit of a parameterized one it shadows, and
+        // registering a route again without a limit drops the old one
+        assert!(serve("/upload/big", 8).starts_with("HTTP/1.1 413"));
+        assert!(serve("/replaced", 8).starts_with("HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn test_error_page() {
+        async fn fails() -> ResponseResult {
+            Err(StatusCode::InternalServerError.into())
+        }
+        async fn explains() -> ResponseResult {
+            Err((StatusCode::InternalServerError, String::from("db is down")).into())
+        }
+        fn error_page(status: StatusCode, reason: Option<&str>) -> Markup<'_> {
+            format!(
+                "{} {}",
+                status.as_str().unwrap_or("???"),
+                reason.unwrap_or("")
+            )
+            .into()
+        }
+        let router = Router::new(())
+            .route(Method::Get, "/fails", fails)
+            .route(Method::Get, "/explains", explains)
+            .error_page(error_page);
+        let serve = |path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let parser = Parser::from_stream(std::io::Cursor::new(raw));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits::default(),
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = serve("/fails");
+        assert!(out.starts_with("HTTP/1.1 500"));
+        assert!(out.contains("content-type:text/html\r\n"));
+        assert!(out.ends_with("\r\n\r\n500 Internal Server Error"));
+
+        // bodies set by the handler are kept, the router's own 404 gets the page too
+        assert!(serve("/explains").ends_with("\r\n\r\ndb is down"));
+        assert!(serve("/missing").ends_with("\r\n\r\n404 Not Found"));
+    }
+
+    #[test]
+    fn test_custom_bad_request_handler() {
+        fn bad_request(err: ParseErr) -> Response {
+            let reason = match err {
+                ParseErr::Expected { label, .. } => format!("expected {}", label),
+                _ => String::from("other"),
+            };
+            Response::new_simple(StatusCode::BadRequest, Some(reason))
+        }
+        let router = Router::new(()).bad_request_handler(bad_request);
+
+        let raw = "GET / HTTP/1.1\r\nHost localhost\r\n\r\n";
+        let parser = Parser::from_stream(std::io::Cursor::new(raw));
+        let mut out = Vec::new();
+        async_runtime::run(HttpServer::serve_connection(
+            &router,
+            Arc::new(Mutex::new(parser)),
+            &mut out,
+            Limits::default(),
+            None,
+        ));
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 400"));
+        assert!(out.ends_with("\r\n\r\nexpected header name separator"));
+    }
+
+    #[test]
+    fn test_request_id() {
+        async fn echo_id(id: RequestId) -> ResponseResult {
+            Ok(id.0.into())
+        }
+        let router = Router::new(()).route(Method::Get, "/id", echo_id);
+        let serve = |raw: &str| {
+            let parser = Parser::from_stream(std::io::Cursor::new(raw.to_string()));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits::default(),
+                None,
+            ));
+            let mut parser = Parser::from_stream(std::io::Cursor::new(out));
+            Response::parse(&mut parser).unwrap()
+        };
+
+        let response = serve("GET /id HTTP/1.1\r\n\r\n");
+        let id = response.headers().get("x-request-id").unwrap();
+        assert!(!id.is_empty());
+        // the handler sees the same id that is echoed back
+        assert_eq!(response.body(), Some(id.as_str()));
+
+        let response = serve("GET /id HTTP/1.1\r\nX-Request-Id: abc-123\r\n\r\n");
+        assert_eq!(
+            response.headers().get("x-request-id").map(String::as_str),
+            Some("abc-123")
+        );
+        assert_eq!(response.body(), Some("abc-123"));
+    }
+
+    #[test]
+    fn test_max_headers() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Get, "/", ok);
+        let limits = Limits {
+            max_headers: 3,
+            ..Limits::default()
+        };
+        let serve = |headers: usize| {
+            let mut raw = String::from("GET / HTTP/1.1\r\n");
+            for i in 0..headers {
+                raw.push_str(&format!("X-Header-{}: {}\r\n", i, i));
+            }
+            raw.push_str("\r\n");
+            let parser = Parser::from_stream(std::io::Cursor::new(raw));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                limits,
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        assert!(serve(3).starts_with("HTTP/1.1 200"));
+        assert!(serve(4).starts_with("HTTP/1.1 431"));
+    }
+
+    #[test]
+    fn test_reject_smuggling() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Post, "/", ok);
+        let serve = |headers: &str| {
+            let raw = format!("POST / HTTP/1.1\r\n{}\r\nhello", headers);
+            let parser = Parser::from_stream(std::io::Cursor::new(raw));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits::default(),
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        assert!(serve("Content-Length: 5\r\n").starts_with("HTTP/1.1 200"));
+        assert!(serve("Content-Length: 5\r\nContent-Length: 5\r\n").starts_with("HTTP/1.1 200"));
+        assert!(
+            serve("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n")
+                .starts_with("HTTP/1.1 400")
+        );
+        assert!(serve("Content-Length: 5\r\nContent-Length: 4\r\n").starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn test_keep_alive_max_requests() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Get, "/", ok);
+        let limits = Limits {
+            max_requests: 2,
+            ..Limits::default()
+        };
+        let raw = "GET / HTTP/1.1\r\n\r\n".repeat(3);
+        let parser = Arc::new(Mutex::new(Parser::from_stream(std::io::Cursor::new(raw))));
+        let mut out = Vec::new();
+        async_runtime::run(HttpServer::serve_connection(
+            &router,
+            parser.clone(),
+            &mut out,
+            limits,
+            None,
+        ));
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2);
+        assert_eq!(out.matches("connection:close").count(), 1);
+        assert!(out.trim_end().ends_with("ok"));
+        // the third request is left unread on the closed connection
+        assert!(parser.lock().unwrap().peek().is_some());
+    }
+
+    #[test]
+    fn test_streaming_response_trailers() {
+        async fn checksummed() -> ResponseResult {
+            let chunks = ["hello", " world!"].into_iter();
+            Ok(StreamingResponse::new(StatusCode::OK, chunks)
+                .trailer("X-Checksum", "abc123")
+                .into())
+        }
+        let router = Router::new(()).route(Method::Get, "/", checksummed);
+        let serve = |raw: &str| {
+            let parser = Parser::from_stream(std::io::Cursor::new(raw.to_string()));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits::default(),
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        // without `TE: trailers` the client may not expect them, so they're dropped
+        let out = serve("GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("transfer-encoding:chunked"));
+        assert!(!head.contains("content-length"));
+        assert!(!head.contains("trailer:"));
+        assert_eq!(body, "5\r\nhello\r\n7\r\n world!\r\n0\r\n\r\n");
+
+        let out = serve("GET / HTTP/1.1\r\nTE: trailers\r\nConnection: close, TE\r\n\r\n");
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("trailer:x-checksum"));
+        assert_eq!(
+            body,
+            "5\r\nhello\r\n7\r\n world!\r\n0\r\nx-checksum:abc123\r\n\r\n"
+        );
+
+        // HTTP/1.0 has no chunked coding, the body is ended by closing the connection
+        let out = serve(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\nTE: trailers\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+        );
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        assert!(!head.contains("transfer-encoding"));
+        assert!(!head.contains("content-length"));
+        assert!(!head.contains("trailer:"));
+        assert!(head.contains("connection:close"));
+        assert_eq!(body, "hello world!");
+    }
+
+    #[test]
+    fn test_access_log() {
+        static LOGGED: Mutex<Vec<AccessLog>> = Mutex::new(Vec::new());
+        fn log(entry: &AccessLog) {
+            LOGGED.lock().unwrap().push(entry.clone());
+        }
+        async fn path(Path(p): Path<String>) -> ResponseResult {
+            Ok(p.into())
+        }
+        let router = Router::new(()).route(Method::Get, "/first", path);
+        let raw = concat!(
+            "GET /first?x=1 HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n",
+            "POST /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let parser = Parser::from_stream(std::io::Cursor::new(raw));
+        let mut out = Vec::new();
+        async_runtime::run(HttpServer::serve_connection(
+            &router,
+            Arc::new(Mutex::new(parser)),
+            &mut out,
+            Limits {
+                access_log: Some(log),
+                ..Limits::default()
+            },
+            None,
+        ));
+
+        let logged = LOGGED.lock().unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[0].request_id, RequestId(String::from("abc")));
+        assert_eq!(
+            (
+                &logged[0].method,
+                logged[0].path.as_str(),
+                &logged[0].status
+            ),
+            (&Method::Get, "/first", &StatusCode::OK)
+        );
+        assert_eq!(
+            (
+                &logged[1].method,
+                logged[1].path.as_str(),
+                &logged[1].status
+            ),
+            (&Method::Post, "/missing", &StatusCode::NotFound)
+        );
+        // the sizes add up to everything written to the connection
+        let written: u64 = logged.iter().map(|entry| entry.bytes_written).sum();
+        assert_eq!(written, out.len() as u64);
+    }
+
+    #[test]
+    fn test_keep_alive_pipelined() {
+        async fn path(Path(p): Path<String>) -> ResponseResult {
+            Ok(p.into())
+        }
+        let router =
+            Router::new(())
+                .route(Method::Get, "/first", path)
+                .route(Method::Get, "/second", path);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut server = HttpServer::from_router(router);
+            async_runtime::run(server.serve_listener(listener))
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            concat!(
+                "GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "GET /second HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+        )
+        .unwrap();
+        // the server closes the connection after the second response
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+
+        let responses: Vec<&str> = out.split("HTTP/1.1 200").skip(1).collect();
+        assert_eq!(responses.len(), 2);
+        assert!(!responses[0].contains("connection:"));
+        assert!(responses[0].ends_with("/first"));
+        assert!(responses[1].contains("connection:close"));
+        assert!(responses[1].ends_with("/second"));
+    }
+
+    #[test]
+    fn test_keep_alive_http_10_and_eof() {
+        async fn ok() -> ResponseResult {
+            Ok("ok".into())
+        }
+        let router = Router::new(()).route(Method::Get, "/", ok);
+        let serve = |raw: &str| {
+            let parser = Parser::from_stream(std::io::Cursor::new(raw.to_string()));
+            let mut out = Vec::new();
+            async_runtime::run(HttpServer::serve_connection(
+                &router,
+                Arc::new(Mutex::new(parser)),
+                &mut out,
+                Limits::default(),
+                None,
+            ));
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = serve(concat!(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            "GET / HTTP/1.0\r\n\r\n",
+        ));
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2);
+        assert_eq!(out.matches("connection:keep-alive").count(), 1);
+        assert!(out.trim_end().ends_with("ok"));
+        assert_eq!(out.matches("connection:close").count(), 1);
+
+        // the client went away in the middle of the second request
+        let out = serve("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nHost: loc");
+        assert_eq!(out.matches("HTTP/1.1").count(), 1);
+    }
+
+    #[test]
+    fn test_tcp_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let _parser =
+            HttpServer::<()>::parser_for(&None, stream.try_clone().unwrap(), Limits::default());
+        assert!(stream.nodelay().unwrap());
+
+        let limits = Limits {
+            tcp_nodelay: false,
+            ..Limits::default()
+        };
+        let _parser = HttpServer::<()>::parser_for(&None, stream.try_clone().unwrap(), limits);
+        assert!(!stream.nodelay().unwrap());
+    }
 }
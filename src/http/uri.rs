@@ -1,6 +1,7 @@
 use crate::{
+    errors::{DeserializeError, DeserializeReason},
     parsing::{Parsable, ParseErr, ParseResult, Parser, StrParser},
-    serializer::DataHolder,
+    serializer::{DataHolder, Deserialize},
 };
 use std::{cmp::Ordering, collections::HashMap, fmt::Display, io::Read};
 
@@ -25,6 +26,12 @@ impl Scheme {
     }
 }
 
+impl Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl<R: Read> Parsable<R> for Scheme {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         if parser.matches(|c| c.is_ascii_alphabetic()) {
@@ -49,11 +56,23 @@ impl PctEncoding {
     pub fn from(c: char) -> Self {
         Self(c)
     }
+
+    /// `pct_encode` preset for path segments, keeping every `pchar` of rfc3986
+    /// Section 3.3 except `%` itself.
+    pub fn keep_in_segment(c: u8) -> bool {
+        URIPath::is_valid_segment(c)
+    }
+
+    /// `pct_encode` preset for query keys and values, keeping only unreserved
+    /// characters so `&`, `=`, `+` and `#` can't be mistaken for query syntax.
+    pub fn keep_in_query(c: u8) -> bool {
+        URI::is_unreserved(c)
+    }
 }
 
 impl<R: Read> Parsable<R> for PctEncoding {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
-        parser.consume_or_err(|c| c == b'%')?;
+        parser.consume_expect(b'%', "percent encoding")?;
         let b1 = parser.consume_or_err(|c| c.is_ascii_hexdigit())?;
         let b2 = parser.consume_or_err(|c| c.is_ascii_hexdigit())?;
         let hex = format!("{}{}", b1 as char, b2 as char);
@@ -68,6 +87,54 @@ impl<R: Read> Parsable<R> for PctEncoding {
     }
 }
 
+/// Percent-encodes every byte of `input` that doesn't pass `keep`, see rfc3986
+/// Section 2.1, using uppercase hex digits as the RFC recommends. `%` and non-ASCII
+/// bytes are always encoded, so the result decodes back to `input`.
+pub fn pct_encode(input: &str, keep: fn(u8) -> bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for b in input.bytes() {
+        if b.is_ascii() && b != b'%' && keep(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// Percent-decodes an already collected string, see rfc3986 Section 2.1.
+///
+/// Decoded bytes are reassembled before being checked as UTF-8, so multibyte
+/// characters like `%C3%A9` come out as a single `é`. `+` is left alone, it only
+/// means space in form encoded queries.
+pub fn pct_decode(input: &str) -> ParseResult<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3).unwrap_or(&input[i + 1..]);
+            if hex.len() != 2 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return Err(ParseErr::InvalidPctEncoding {
+                    found: format!("%{}", hex),
+                });
+            }
+            let radix = 16;
+            let byte = u8::from_str_radix(hex, radix).map_err(|_| ParseErr::FailedToParseNum {
+                found: hex.to_string(),
+                radix,
+            })?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| ParseErr::InvalidUTF8)
+}
+
 /// Based on rfc3986 Section 3.2.1
 ///
 /// # Augmented Backus-Naur Form
@@ -83,6 +150,13 @@ impl UserInfo {
     }
 }
 
+impl Display for UserInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keep = |c| URI::is_unreserved(c) || URI::is_sub_delim(c) || c == b':';
+        write!(f, "{}", pct_encode(&self.0, keep))
+    }
+}
+
 impl<R: Read> Parsable<R> for UserInfo {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         let mut s = String::new();
@@ -112,9 +186,13 @@ impl<R: Read> Parsable<R> for UserInfo {
 
 /// Based on rfc3986 Section 3.2.2
 ///
+/// IPv6 and IPvFuture hosts are kept as written, lowercased and without the brackets.
+///
 /// # Augmented Backus-Naur Form
 /// ```text
-/// userinfo    = *( unreserved / pct-encoded / sub-delims / ":" )
+/// host        = IP-literal / IPv4address / reg-name
+/// IP-literal  = "[" ( IPv6address / IPvFuture  ) "]"
+/// IPvFuture   = "v" 1*HEXDIG "." 1*( unreserved / sub-delims / ":" )
 /// ```
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Host {
@@ -125,10 +203,88 @@ pub enum Host {
 }
 
 impl Host {
-    fn parse_ip_lit<R: Read>(_parser: &mut Parser<R>) -> ParseResult<Self> {
-        unimplemented!("Haven't worked on ipv6 yet, sorry")
-        // parser.consume_or_err(|c| c == b'[')?;
-        // parser.consume_or_err(|c| c == b']')?;
+    fn parse_ip_lit<R: Read>(parser: &mut Parser<R>) -> ParseResult<Self> {
+        parser.consume_expect(b'[', "start of IP literal")?;
+        let host = if parser.matches(|c| c == b'v' || c == b'V') {
+            parser.consume();
+            let version = parser.consume_while_lower(|p| p.is_hex_digit());
+            let has_dot = parser.matches(|c| c == b'.');
+            if has_dot {
+                parser.consume();
+            }
+            let address = parser.consume_while_lower(|p| {
+                p.matches(|c| URI::is_unreserved(c) || URI::is_sub_delim(c) || c == b':')
+            });
+            if version.is_empty() || !has_dot || address.is_empty() {
+                return Err(ParseErr::InvalidIPvFuture {
+                    found: format!("v{}.{}", version, address),
+                });
+            }
+            Self::IPvFuture(format!("v{}.{}", version, address))
+        } else {
+            let address = parser.consume_while_lower(|p| {
+                p.matches(|c| c.is_ascii_hexdigit() || c == b':' || c == b'.')
+            });
+            if !Self::is_ipv6(&address) {
+                return Err(ParseErr::InvalidIPv6 { found: address });
+            }
+            Self::IPv6(address)
+        };
+        parser.consume_expect(b']', "end of IP literal")?;
+
+        Ok(host)
+    }
+
+    /// Based on rfc3986 Section 3.2.2
+    ///
+    /// Eight 16 bit groups, the last two may be written as an IPv4 address. One run of
+    /// groups may be compressed to `::`.
+    ///
+    /// # Augmented Backus-Naur Form
+    /// ```text
+    /// IPv6address =                            6( h16 ":" ) ls32
+    ///             /                       "::" 5( h16 ":" ) ls32
+    ///             / [               h16 ] "::" 4( h16 ":" ) ls32
+    ///             / [ *1( h16 ":" ) h16 ] "::" 3( h16 ":" ) ls32
+    ///             / [ *2( h16 ":" ) h16 ] "::" 2( h16 ":" ) ls32
+    ///             / [ *3( h16 ":" ) h16 ] "::"    h16 ":"   ls32
+    ///             / [ *4( h16 ":" ) h16 ] "::"              ls32
+    ///             / [ *5( h16 ":" ) h16 ] "::"              h16
+    ///             / [ *6( h16 ":" ) h16 ] "::"
+    /// h16         = 1*4HEXDIG
+    /// ls32        = ( h16 ":" h16 ) / IPv4address
+    /// ```
+    fn is_ipv6(s: &str) -> bool {
+        let (head, tail, compressed) = match s.split_once("::") {
+            Some((head, tail)) => (head, tail, true),
+            None => (s, "", false),
+        };
+        if tail.contains("::") {
+            return false;
+        }
+
+        let groups: Vec<&str> = [head, tail]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .flat_map(|part| part.split(':'))
+            .collect();
+        let mut count = 0;
+        for (i, group) in groups.iter().enumerate() {
+            if group.contains('.') {
+                // ls32 as an IPv4 address, only allowed as the last group
+                if i + 1 != groups.len() || Self::ipv4_from_str(group).is_err() {
+                    return false;
+                }
+                count += 2;
+            } else if (1..=4).contains(&group.len()) && group.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                count += 1;
+            } else {
+                return false;
+            }
+        }
+
+        if compressed { count < 8 } else { count == 8 }
     }
 
     fn ipv4_from_str(s: &str) -> ParseResult<Self> {
@@ -194,6 +350,19 @@ impl Host {
     }
 }
 
+impl Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Host::IPvFuture(address) | Host::IPv6(address) => write!(f, "[{}]", address),
+            Host::IPv4((a, b, c, d)) => write!(f, "{}.{}.{}.{}", a, b, c, d),
+            Host::Domain(name) => {
+                let keep = |c| URI::is_unreserved(c) || URI::is_sub_delim(c);
+                write!(f, "{}", pct_encode(name, keep))
+            }
+        }
+    }
+}
+
 impl<R: Read> Parsable<R> for Host {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         if parser.matches(|c| c == b'[') {
@@ -215,7 +384,7 @@ pub struct Port(u16);
 
 impl<R: Read> Parsable<R> for Port {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
-        parser.consume_or_err(|c| c == b':')?;
+        parser.consume_expect(b':', "port separator")?;
         let port_num_str = parser.consume_while(|p| p.is_digit());
         let radix = 10;
         let port = u16::from_str_radix(port_num_str.as_str(), radix).map_err(|_| {
@@ -241,6 +410,47 @@ pub struct Authority {
     port: Option<Port>,
 }
 
+impl Authority {
+    /// Parses the value of a `Host` header, i.e. `host [":" port]` without userinfo,
+    /// see rfc7230 Section 5.4.
+    pub fn from_host_header(value: &str) -> ParseResult<Self> {
+        let mut parser = StrParser::from_str(value.trim());
+        let host = Host::parse(&mut parser)?;
+        let port = if parser.matches(|c| c == b':') {
+            Some(Port::parse(&mut parser)?)
+        } else {
+            None
+        };
+        if let Some(c) = parser.peek() {
+            return Err(ParseErr::Expected {
+                expected: Vec::new(),
+                label: "end of host",
+                found: Some(c),
+                at: parser.offset(),
+            });
+        }
+
+        Ok(Authority {
+            user_info: None,
+            host,
+            port,
+        })
+    }
+}
+
+impl Display for Authority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(user_info) = &self.user_info {
+            write!(f, "{}@", user_info)?;
+        }
+        write!(f, "{}", self.host)?;
+        if let Some(Port(port)) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
 impl<R: Read> Parsable<R> for Authority {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         let (user_info, host) = match UserInfo::parse(parser) {
@@ -248,6 +458,11 @@ impl<R: Read> Parsable<R> for Authority {
                 let host = Host::parse(parser)?;
                 (Some(user_info), host)
             }
+            Err(ParseErr::NotUserInfo {
+                presumed_host: host_str,
+            }) if host_str.is_empty() && parser.matches(|c| c == b'[') => {
+                (None, Host::parse(parser)?)
+            }
             Err(ParseErr::NotUserInfo {
                 presumed_host: host_str,
             }) => {
@@ -276,6 +491,8 @@ impl<R: Read> Parsable<R> for Authority {
 pub enum PathType {
     Relative,
     Absolute,
+    /// The `*` request target, only valid for server wide `OPTIONS` requests (rfc7230 section 5.3.4)
+    Asterisk,
 }
 
 /// Based on rfc3986 Section 3.2
@@ -306,6 +523,7 @@ pub struct URIPath {
     ty: PathType,
     segments: Vec<String>,
     entire_path: String,
+    params: Vec<(String, String)>,
 }
 
 impl URIPath {
@@ -318,16 +536,74 @@ impl URIPath {
         &self.ty
     }
 
+    pub fn is_asterisk(&self) -> bool {
+        self.ty == PathType::Asterisk
+    }
+
     pub fn entire_path(&self) -> &String {
         &self.entire_path
     }
 
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// `:name` parameters captured by the route this path was matched against, in the
+    /// order they appear in the route.
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn set_params(&mut self, params: Vec<(String, String)>) {
+        self.params = params;
+    }
+
+    /// Path made of an already decoded `path`, e.g. `/files/my report.pdf`, which is
+    /// percent-encoded again when the path is written out.
+    pub fn from_decoded(path: &str) -> Self {
+        let (ty, rest) = match path.strip_prefix('/') {
+            Some(rest) => (PathType::Absolute, rest),
+            None => (PathType::Relative, path),
+        };
+        let mut segments: Vec<String> = rest.split('/').map(str::to_string).collect();
+        if segments.last().is_some_and(|s| s.is_empty()) {
+            segments.pop();
+        }
+
+        URIPath {
+            ty,
+            segments,
+            entire_path: path.to_string(),
+            params: Vec::new(),
+        }
+    }
+
     pub fn into_segments(self) -> Vec<String> {
         self.segments
     }
     pub fn into_entire_path(self) -> String {
         self.entire_path
     }
+    pub fn into_params(self) -> Vec<(String, String)> {
+        self.params
+    }
+}
+
+impl Display for URIPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_asterisk() {
+            return write!(f, "*");
+        }
+        let keep = |c| PctEncoding::keep_in_segment(c) || c == b'/';
+        write!(f, "{}", pct_encode(&self.entire_path, keep))
+    }
 }
 
 impl<R: Read> Parsable<R> for URIPath {
@@ -343,6 +619,19 @@ impl<R: Read> Parsable<R> for URIPath {
         };
 
         let mut s = String::new();
+        if ty == PathType::Relative && parser.matches(|c| c == b'*') {
+            parser.consume();
+            if parser.peek().is_none_or(|c| c.is_ascii_whitespace()) {
+                return Ok(URIPath {
+                    ty: PathType::Asterisk,
+                    segments: Vec::new(),
+                    entire_path: String::from("*"),
+                    params: Vec::new(),
+                });
+            }
+            s.push('*');
+            entire_path.push('*');
+        }
         while let Some(c) = parser.peek() {
             if URIPath::is_valid_segment(c) {
                 s.push(c as char);
@@ -370,6 +659,7 @@ impl<R: Read> Parsable<R> for URIPath {
             ty,
             segments,
             entire_path,
+            params: Vec::new(),
         })
     }
 }
@@ -384,7 +674,7 @@ impl<R: Read> Parsable<R> for URIPath {
 /// This struct assumes standardization of query parameters which is technically not true.
 ///
 /// For defensive reasons, this will error if parameter is invalid, even if RFC says otherwise when accounting for more "raw" querries.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct RequestQuery {
     pub parameters: DataHolder,
 }
@@ -397,19 +687,31 @@ impl Default for RequestQuery {
     }
 }
 
+/// Writes the query as `key=value&key2=value2`, without the leading `?`, so it parses
+/// back into the same parameters.
+///
+/// Keys are sorted for a deterministic output. Keys and values are percent-encoded,
+/// spaces as `+`. Lists are written as a repeated key, other nested values have no query
+/// form and are skipped.
 impl Display for RequestQuery {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.parameters {
-            DataHolder::Primitive(_) => {}
-            DataHolder::Struct(ref s) => {
-                for (k, v) in s.iter() {
-                    match v {
-                        DataHolder::Primitive(s) => {
-                            writeln!(f, "{}:{},", k, s)?;
-                        }
-                        _ => {}
-                    }
+        // every `%` left after encoding starts an escape, so `%20` is always a space
+        let encode = |s: &str| pct_encode(s, PctEncoding::keep_in_query).replace("%20", "+");
+        let mut first = true;
+        for (k, v) in self.sorted_pairs() {
+            let values = match v {
+                DataHolder::List(list) => list.as_slice(),
+                v => std::slice::from_ref(v),
+            };
+            for v in values {
+                let DataHolder::Primitive(v) = v else {
+                    continue;
+                };
+                if !first {
+                    write!(f, "&")?;
                 }
+                first = false;
+                write!(f, "{}={}", encode(k), encode(v))?;
             }
         }
 
@@ -418,78 +720,223 @@ impl Display for RequestQuery {
 }
 
 impl RequestQuery {
-    fn sorted_keys(&self) -> Vec<&String> {
+    /// Deserializes the parameters into `T`, reading them the way a form is sent.
+    ///
+    /// A query doesn't say which keys are lists, so a key is reshaped when `T` rejects
+    /// the shape it came in: a key sent more than once for a single value, e.g.
+    /// `?page=1&page=2`, reads as its last value, and a key sent once for a list reads
+    /// as a list of one. Other `Deserialize` sources, e.g. JSON bodies, stay strict.
+    pub fn deserialize<T: Deserialize>(self) -> Result<T, DeserializeError> {
+        let mut parameters = self.parameters;
+        let mut reshaped = Vec::new();
+        loop {
+            let err = match T::deserialize(parameters.clone()) {
+                Ok(t) => return Ok(t),
+                Err(err) => err,
+            };
+            let DataHolder::Struct(map) = &mut parameters else {
+                return Err(err);
+            };
+            // each key is reshaped at most once, so this ends after one pass over them
+            let Some(value) = map
+                .get_mut(&err.field)
+                .filter(|_| !reshaped.contains(&err.field))
+            else {
+                return Err(err);
+            };
+            *value = match (
+                err.reason.clone(),
+                std::mem::replace(value, DataHolder::Null),
+            ) {
+                (DeserializeReason::ExpectedPrimitive, DataHolder::List(mut list))
+                    if list.iter().all(|v| matches!(v, DataHolder::Primitive(_))) =>
+                {
+                    match list.pop() {
+                        Some(last) => last,
+                        None => DataHolder::List(list),
+                    }
+                }
+                (DeserializeReason::ExpectedList, v @ DataHolder::Primitive(_)) => {
+                    DataHolder::List(vec![v])
+                }
+                (_, v) => {
+                    *value = v;
+                    return Err(err);
+                }
+            };
+            reshaped.push(err.field);
+        }
+    }
+
+    fn sorted_pairs(&self) -> Vec<(&String, &DataHolder)> {
         match &self.parameters {
-            DataHolder::Primitive(_) => Vec::new(),
-            DataHolder::Struct(s) => {
-                let mut keys: Vec<&String> = s.keys().collect();
-                keys.sort();
-                keys
-            }
+            DataHolder::Primitive(_) | DataHolder::List(_) | DataHolder::Null => Vec::new(),
+            DataHolder::Struct(s) => DataHolder::sorted_entries(s),
         }
     }
 }
 
 impl<R: Read> Parsable<R> for RequestQuery {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
-        // parser.consume_or_err(|c| c == b'?')?;
-        let mut parameters = HashMap::new();
+        let mut parameters: HashMap<String, DataHolder> = HashMap::new();
+        for (k, v) in Params::QUERY.parse_pairs(parser)? {
+            let v = DataHolder::Primitive(v);
+            match parameters.remove(&k) {
+                None => parameters.insert(k, v),
+                Some(DataHolder::List(mut list)) => {
+                    list.push(v);
+                    parameters.insert(k, DataHolder::List(list))
+                }
+                Some(first) => parameters.insert(k, DataHolder::List(vec![first, v])),
+            };
+        }
+
+        Ok(RequestQuery {
+            parameters: DataHolder::Struct(parameters),
+        })
+    }
+}
+
+/// Rules for a separated list of `key=value` parameters.
+///
+/// Queries and header parameters share the same shape but differ in how keys and values
+/// are written:
+/// - `Params::QUERY`: `&` separated and form encoded (`+` is a space, `%XX` is decoded),
+///   ending at `#` or whitespace. `;` is also taken as a separator, as older clients
+///   send `a=1;b=2`
+/// - `Params::HEADER`: `;` separated tokens where values may also be quoted strings,
+///   with optional whitespace around separators and `=`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Params {
+    separator: u8,
+    quoted_values: bool,
+    form_encoded: bool,
+}
+
+impl Params {
+    pub const QUERY: Self = Params {
+        separator: b'&',
+        quoted_values: false,
+        form_encoded: true,
+    };
+    pub const HEADER: Self = Params {
+        separator: b';',
+        quoted_values: true,
+        form_encoded: false,
+    };
+
+    pub fn new(separator: u8, quoted_values: bool, form_encoded: bool) -> Self {
+        Params {
+            separator,
+            quoted_values,
+            form_encoded,
+        }
+    }
 
+    fn at_end<R: Read>(&self, parser: &mut Parser<R>) -> bool {
+        match parser.peek() {
+            None => true,
+            Some(c) if self.form_encoded => c == b'#' || parser.is_linear_whitespace(),
+            Some(_) => false,
+        }
+    }
+
+    fn is_separator(&self, c: u8) -> bool {
+        c == self.separator || (self.form_encoded && c == b';')
+    }
+
+    fn skip_whitespace<R: Read>(&self, parser: &mut Parser<R>) {
+        if !self.form_encoded {
+            parser.skip_whitespace();
+        }
+    }
+
+    /// Form encoded keys end at `=`, values also end at any sub-delim other than `+`
+    fn consume_form_encoded<R: Read>(
+        &self,
+        parser: &mut Parser<R>,
+        is_value: bool,
+    ) -> ParseResult<String> {
+        let mut s = String::new();
         while let Some(c) = parser.peek()
-            && c != b'#'
+            && c != b'='
+            && !self.is_separator(c)
+            && !(is_value && URI::is_sub_delim(c) && c != b'+')
             && !parser.is_linear_whitespace()
         {
-            let mut key = String::new();
-            while let Some(c) = parser.peek()
-                && c != b'='
-                && !parser.is_linear_whitespace()
-            {
-                if c == b'+' {
-                    key.push(' ');
-                    parser.consume();
-                } else if URIPath::is_valid_segment(c) || c == b'/' || c == b'?' {
-                    key.push(c as char);
-                    parser.consume();
-                } else if c == b'%' {
-                    let pct = PctEncoding::parse(parser)?;
-                    key.push(pct.0);
-                } else {
-                    break;
-                }
+            if c == b'+' {
+                s.push(' ');
+                parser.consume();
+            } else if URIPath::is_valid_segment(c) || c == b'/' || c == b'?' {
+                s.push(c as char);
+                parser.consume();
+            } else if c == b'%' {
+                let pct = PctEncoding::parse(parser)?;
+                s.push(pct.0);
+            } else {
+                break;
             }
+        }
 
-            parser.consume_or_err(|c| c == b'=')?;
-            let mut val = String::new();
+        Ok(s)
+    }
 
-            while let Some(c) = parser.peek()
-                && !(URI::is_sub_delim(c) && c != b'+')
-                && !parser.is_linear_whitespace()
-            {
-                if c == b'+' {
-                    val.push(' ');
-                    parser.consume();
-                } else if URIPath::is_valid_segment(c) || c == b'/' || c == b'?' {
-                    val.push(c as char);
-                    parser.consume();
-                } else if c == b'%' {
-                    let pct = PctEncoding::parse(parser)?;
-                    val.push(pct.0);
-                } else {
-                    break;
-                }
-            }
+    fn consume_key<R: Read>(&self, parser: &mut Parser<R>) -> ParseResult<String> {
+        if self.form_encoded {
+            self.consume_form_encoded(parser, false)
+        } else {
+            Ok(parser.consume_while(|p| p.is_token_char()))
+        }
+    }
 
-            parameters.insert(key, DataHolder::Primitive(val));
-            if parser.matches(|c| c == b'#' || c.is_ascii_whitespace()) {
-                break;
-            } else {
+    fn consume_value<R: Read>(&self, parser: &mut Parser<R>) -> ParseResult<String> {
+        if self.quoted_values && parser.matches(|c| c == b'"') {
+            Ok(parser.consume_str_lit())
+        } else if self.form_encoded {
+            self.consume_form_encoded(parser, true)
+        } else {
+            Ok(parser.consume_while(|p| p.is_token_char()))
+        }
+    }
+
+    /// Parses parameters until the list ends or something other than the separator
+    /// follows a value. A later key replaces an earlier one.
+    pub fn parse<R: Read>(&self, parser: &mut Parser<R>) -> ParseResult<HashMap<String, String>> {
+        Ok(self.parse_pairs(parser)?.into_iter().collect())
+    }
+
+    /// Same as `parse`, but keeps every parameter in order, repeated keys included.
+    pub fn parse_pairs<R: Read>(
+        &self,
+        parser: &mut Parser<R>,
+    ) -> ParseResult<Vec<(String, String)>> {
+        let mut params = Vec::new();
+
+        self.skip_whitespace(parser);
+        while !self.at_end(parser) {
+            // empty parameters, e.g. `a=1;;b=2` or a trailing separator, are skipped
+            if parser.matches(|c| self.is_separator(c)) {
                 parser.consume();
+                self.skip_whitespace(parser);
+                continue;
+            }
+            let key = self.consume_key(parser)?;
+            self.skip_whitespace(parser);
+            parser.consume_expect(b'=', "parameter value")?;
+            self.skip_whitespace(parser);
+            let val = self.consume_value(parser)?;
+            params.push((key, val));
+
+            self.skip_whitespace(parser);
+            if parser.matches(|c| self.is_separator(c)) {
+                parser.consume();
+                self.skip_whitespace(parser);
+            } else {
+                break;
             }
         }
 
-        Ok(RequestQuery {
-            parameters: DataHolder::Struct(parameters),
-        })
+        Ok(params)
     }
 }
 
@@ -501,7 +948,7 @@ impl PartialOrd for RequestQuery {
 
 impl Ord for RequestQuery {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.sorted_keys().cmp(&other.sorted_keys())
+        self.sorted_pairs().cmp(&other.sorted_pairs())
     }
 }
 
@@ -514,9 +961,16 @@ impl Ord for RequestQuery {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Fragment(String);
 
+impl Display for Fragment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keep = |c| PctEncoding::keep_in_segment(c) || c == b'/' || c == b'?';
+        write!(f, "#{}", pct_encode(&self.0, keep))
+    }
+}
+
 impl<R: Read> Parsable<R> for Fragment {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
-        parser.consume_or_err(|c| c == b'#')?;
+        parser.consume_expect(b'#', "fragment")?;
         let mut fragment = String::new();
         while let Some(c) = parser.peek() {
             if URIPath::is_valid_segment(c) || c == b'/' || c == b'?' {
@@ -565,6 +1019,16 @@ pub struct URI {
 }
 
 impl URI {
+    pub fn new(scheme: Scheme, authority: Authority, path: URIPath) -> Self {
+        URI {
+            scheme,
+            authority,
+            path,
+            query: None,
+            fragment: None,
+        }
+    }
+
     /// Based on See rfc3986 section 3.3
     pub fn is_unreserved(c: u8) -> bool {
         c.is_ascii_alphanumeric() || c == b'-' || c == b'.' || c == b'_' || c == b'~'
@@ -592,6 +1056,25 @@ impl URI {
     }
 }
 
+/// Writes the URI back out, percent-encoding whatever parsing decoded.
+impl Display for URI {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", self.scheme, self.authority)?;
+        if self.path.path_type() == &PathType::Relative && !self.path.entire_path().is_empty() {
+            // a path following an authority has to start with `/`
+            write!(f, "/")?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
 impl<R: Read> Parsable<R> for URI {
     fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
         let scheme = Scheme::parse(parser)?;
@@ -600,7 +1083,7 @@ impl<R: Read> Parsable<R> for URI {
         let path = URIPath::parse(parser)?;
 
         let query = if parser.matches(|c| c == b'?') {
-            parser.consume_or_err(|c| c == b'?')?;
+            parser.consume_expect(b'?', "query")?;
             Some(RequestQuery::parse(parser)?)
         } else {
             None
@@ -642,6 +1125,55 @@ mod tests {
         assert_eq!(Scheme::parse(&mut parser), Err(ParseErr::InvalidScheme));
     }
 
+    #[test]
+    fn test_pct_decode() {
+        assert_eq!(pct_decode("a%20b"), Ok(String::from("a b")));
+        assert_eq!(pct_decode("caf%C3%A9"), Ok(String::from("café")));
+        assert_eq!(pct_decode("a+b"), Ok(String::from("a+b")));
+        assert_eq!(
+            pct_decode("100%"),
+            Err(ParseErr::InvalidPctEncoding {
+                found: String::from("%")
+            })
+        );
+        assert_eq!(pct_decode("%C3"), Err(ParseErr::InvalidUTF8));
+    }
+
+    #[test]
+    fn test_pct_encode() {
+        assert_eq!(
+            pct_encode("a b&c=d+é", PctEncoding::keep_in_query),
+            "a%20b%26c%3Dd%2B%C3%A9"
+        );
+        assert_eq!(
+            pct_encode("a b:c@d=e/f%", PctEncoding::keep_in_segment),
+            "a%20b:c@d=e%2Ff%25"
+        );
+        assert_eq!(
+            pct_decode(&pct_encode("100% é", |_| true)),
+            Ok(String::from("100% é"))
+        );
+
+        let map = HashMap::from([
+            (String::from("q"), String::from("fish & chips")),
+            (String::from("a=b"), String::from("1+1 #2")),
+            (String::from("empty"), String::new()),
+        ]);
+        let query = map
+            .iter()
+            .map(|(k, v)| {
+                format!(
+                    "{}={}",
+                    pct_encode(k, PctEncoding::keep_in_query),
+                    pct_encode(v, PctEncoding::keep_in_query)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let mut parser = StrParser::from_str(&query);
+        assert_eq!(Params::QUERY.parse(&mut parser), Ok(map));
+    }
+
     #[test]
     fn test_pct_encoding() {
         let mut parser = StrParser::from_str("%3D");
@@ -687,6 +1219,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ip_literal_host() {
+        for (raw, host) in [
+            ("[::1]", Host::IPv6(String::from("::1"))),
+            ("[::]", Host::IPv6(String::from("::"))),
+            ("[2001:DB8::1]", Host::IPv6(String::from("2001:db8::1"))),
+            (
+                "[1:2:3:4:5:6:7:8]",
+                Host::IPv6(String::from("1:2:3:4:5:6:7:8")),
+            ),
+            (
+                "[::ffff:192.0.2.1]",
+                Host::IPv6(String::from("::ffff:192.0.2.1")),
+            ),
+            (
+                "[v1.fe80::a+en1]",
+                Host::IPvFuture(String::from("v1.fe80::a+en1")),
+            ),
+        ] {
+            assert_eq!(
+                Host::parse(&mut StrParser::from_str(raw)),
+                Ok(host),
+                "{}",
+                raw
+            );
+        }
+
+        for raw in [
+            "[1:2:3:4:5:6:7]",
+            "[1:2:3:4:5:6:7:8:9]",
+            "[1::2::3]",
+            "[12345::]",
+            "[::1.2.3.4:1]",
+            "[:1]",
+        ] {
+            assert!(
+                matches!(
+                    Host::parse(&mut StrParser::from_str(raw)),
+                    Err(ParseErr::InvalidIPv6 { .. })
+                ),
+                "{}",
+                raw
+            );
+        }
+        assert!(matches!(
+            Host::parse(&mut StrParser::from_str("[v.1]")),
+            Err(ParseErr::InvalidIPvFuture { .. })
+        ));
+        assert_eq!(
+            Host::parse(&mut StrParser::from_str("[::1")),
+            Err(ParseErr::UnexpectedEof)
+        );
+        assert!(matches!(
+            Host::parse(&mut StrParser::from_str("[::1/")),
+            Err(ParseErr::Expected { .. })
+        ));
+
+        let mut parser = StrParser::from_str("[2001:db8::1]:8080/");
+        assert_eq!(
+            Authority::parse(&mut parser),
+            Ok(Authority {
+                user_info: None,
+                host: Host::IPv6(String::from("2001:db8::1")),
+                port: Some(Port(8080)),
+            })
+        );
+    }
+
     #[test]
     fn test_valid_domain_host() {
         let mut parser = StrParser::from_str("www.example.com");
@@ -709,6 +1309,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_uri_display() {
+        let raw = "http://user@Example.com:8080/a%20b/c?q=x+y#top";
+        let uri = URI::parse(&mut StrParser::from_str(raw)).unwrap();
+        assert_eq!(
+            uri.to_string(),
+            "http://user@example.com:8080/a%20b/c?q=x+y#top"
+        );
+
+        let authority = Authority::from_host_header("[::1]:3000").unwrap();
+        let uri = URI::new(
+            Scheme::from("https"),
+            authority,
+            URIPath::from_decoded("/files/my report.pdf"),
+        );
+        assert_eq!(uri.to_string(), "https://[::1]:3000/files/my%20report.pdf");
+
+        assert!(Authority::from_host_header("example.com/path").is_err());
+        assert!(Authority::from_host_header("example.com:port").is_err());
+    }
+
     #[test]
     fn test_valid_authority_pct() {
         let mut parser = StrParser::from_str("some%3duser@some%3demaildomain.com:8000");
@@ -729,7 +1350,8 @@ mod tests {
             Ok(URIPath {
                 ty: PathType::Absolute,
                 segments: vec![],
-                entire_path: String::from("/")
+                entire_path: String::from("/"),
+                params: Vec::new(),
             })
         );
     }
@@ -742,7 +1364,8 @@ mod tests {
             Ok(URIPath {
                 ty: PathType::Absolute,
                 segments: vec![String::from("somerandompath"), String::from("ye=p")],
-                entire_path: String::from("/somerandompath/ye=p")
+                entire_path: String::from("/somerandompath/ye=p"),
+                params: Vec::new(),
             })
         );
 
@@ -752,7 +1375,8 @@ mod tests {
             Ok(URIPath {
                 ty: PathType::Relative,
                 segments: vec![String::from("somerandompath"), String::from("ye=p")],
-                entire_path: String::from("somerandompath/ye=p")
+                entire_path: String::from("somerandompath/ye=p"),
+                params: Vec::new(),
             })
         );
     }
@@ -817,6 +1441,134 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_params() {
+        let mut parser = StrParser::from_str("a=one+two&b=%41%42&c=#frag");
+        let params = Params::QUERY.parse(&mut parser).unwrap();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["a"], "one two");
+        assert_eq!(params["b"], "AB");
+        assert_eq!(params["c"], "");
+        assert_eq!(parser.peek(), Some(b'#'));
+
+        let mut parser = StrParser::from_str("a=1;b=2&c=3");
+        let params = Params::QUERY.parse(&mut parser).unwrap();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["a"], "1");
+        assert_eq!(params["b"], "2");
+        assert_eq!(params["c"], "3");
+
+        let mut parser = StrParser::from_str("name=\"a b;c.txt\" ; size = 10;plus=a+b\r\n");
+        let params = Params::HEADER.parse(&mut parser).unwrap();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["name"], "a b;c.txt");
+        assert_eq!(params["size"], "10");
+        assert_eq!(params["plus"], "a+b");
+        assert_eq!(parser.peek(), Some(b'\r'));
+
+        let mut parser = StrParser::from_str("novalue;b=1");
+        assert!(Params::HEADER.parse(&mut parser).is_err());
+    }
+
+    #[test]
+    fn test_query_display_round_trip() {
+        let raw = "q=fish+%26+chips&page=2&a%3Db=1%2B1&empty=&path=/a/b";
+        let query = RequestQuery::parse(&mut StrParser::from_str(raw)).unwrap();
+        let displayed = query.to_string();
+        assert_eq!(
+            displayed,
+            "a%3Db=1%2B1&empty=&page=2&path=%2Fa%2Fb&q=fish+%26+chips"
+        );
+
+        let reparsed = RequestQuery::parse(&mut StrParser::from_str(&displayed)).unwrap();
+        assert_eq!(reparsed.parameters, query.parameters);
+        assert_eq!(RequestQuery::default().to_string(), "");
+    }
+
+    #[test]
+    fn test_repeated_query_keys() {
+        let parse = |raw: &str| RequestQuery::parse(&mut StrParser::from_str(raw)).unwrap();
+        let list = |values: &[&str]| {
+            DataHolder::List(
+                values
+                    .iter()
+                    .map(|v| DataHolder::Primitive(v.to_string()))
+                    .collect(),
+            )
+        };
+
+        let query = parse("tag=a&page=1&tag=b");
+        let DataHolder::Struct(params) = &query.parameters else {
+            panic!("query parameters are a struct");
+        };
+        assert_eq!(params["tag"], list(&["a", "b"]));
+        assert_eq!(params["page"], DataHolder::Primitive(String::from("1")));
+
+        let query = parse("tag=a&tag=b&tag=c+d");
+        let DataHolder::Struct(params) = &query.parameters else {
+            panic!("query parameters are a struct");
+        };
+        assert_eq!(params["tag"], list(&["a", "b", "c d"]));
+        assert_eq!(query.to_string(), "tag=a&tag=b&tag=c+d");
+
+        #[derive(crate::Deserialize, Debug)]
+        struct Filter {
+            tag: Vec<String>,
+        }
+        let filter: Filter = query.deserialize().unwrap();
+        assert_eq!(filter.tag, ["a", "b", "c d"]);
+
+        // a key sent once is still a list of one
+        let filter: Filter = parse("tag=a").deserialize().unwrap();
+        assert_eq!(filter.tag, ["a"]);
+
+        // and a scalar sent more than once keeps its last value
+        #[derive(crate::Deserialize, Debug)]
+        struct Page {
+            page: u32,
+            sort: String,
+        }
+        let page: Page = parse("page=1&sort=name&page=2&sort=date")
+            .deserialize()
+            .unwrap();
+        assert_eq!((page.page, page.sort.as_str()), (2, "date"));
+
+        // values that don't parse still reject the query
+        let err = parse("page=1&sort=name&page=x")
+            .deserialize::<Page>()
+            .unwrap_err();
+        assert_eq!(err.field, "page");
+        assert_eq!(
+            err.reason,
+            DeserializeReason::InvalidValue {
+                found: String::from("x")
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_ordering() {
+        let mut parser = StrParser::from_str("a=1&b=2");
+        let query = RequestQuery::parse(&mut parser).unwrap();
+        let mut parser = StrParser::from_str("a=1&b=3");
+        let other = RequestQuery::parse(&mut parser).unwrap();
+        assert_ne!(query.cmp(&other), Ordering::Equal);
+        assert_eq!(query.cmp(&other), Ordering::Less);
+
+        let mut parser = StrParser::from_str("b=2&a=1");
+        let reordered = RequestQuery::parse(&mut parser).unwrap();
+        assert_eq!(query.cmp(&reordered), Ordering::Equal);
+
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        let hash = |q: &RequestQuery| {
+            let mut hasher = DefaultHasher::new();
+            q.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&query), hash(&reordered));
+        assert_ne!(hash(&query), hash(&other));
+    }
+
     #[test]
     fn test_valid_fragment() {
         let mut parser = StrParser::from_str("#some_param=some_val");
@@ -849,7 +1601,8 @@ mod tests {
                 path: URIPath {
                     ty: PathType::Absolute,
                     segments: vec![String::from("apath")],
-                    entire_path: String::from("/apath")
+                    entire_path: String::from("/apath"),
+                    params: Vec::new(),
                 },
                 query: Some(RequestQuery { parameters }),
                 fragment: Some(Fragment(String::from("some_param=some_val")))
@@ -15,7 +15,8 @@ pub mod variadics;
 /// This macro is written pretty badly right now. See the macros workspace for implementation details
 ///
 /// # Example Usage
-/// ```rust
+/// ```rust,no_run
+/// # use zero::http::{routing::Router, server::HttpServer};
 /// #[zero::main]
 /// async fn main() -> Result<(), ()> {  // allows async usage
 ///    let router = Router::new(());
@@ -35,5 +36,5 @@ pub mod variadics;
 ///
 /// Additionally, this macro expects the crate to have a name of "zero". Anything
 /// else will break the macro.
-pub use macros::{Deserialize, ToDatabaseBytes, ZeroTable, html, main};
+pub use macros::{Deserialize, Serialize, ToDatabaseBytes, ZeroTable, html, main};
 pub use uuid::UUID;
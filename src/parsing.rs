@@ -1,4 +1,11 @@
-use std::io::{BufReader, Cursor, Read};
+use crate::errors::DeserializeError;
+use std::{
+    io::{Cursor, Read},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
 pub mod prelude {
     pub use super::{Parsable, ParseErr, ParseResult, Parser};
@@ -55,6 +62,12 @@ pub enum ParseErr {
     InvalidIPv4Len {
         found: usize,
     },
+    InvalidIPv6 {
+        found: String,
+    },
+    InvalidIPvFuture {
+        found: String,
+    },
     InvalidPath,
     InvalidUserInfo,
     BlankHeaderFieldName,
@@ -78,8 +91,187 @@ pub enum ParseErr {
         tried_seeking_to: usize,
     },
     ZeroLenDispositionTy,
+    InvalidJson {
+        found: Option<u8>,
+    },
+    InvalidJsonEscape {
+        found: Option<u8>,
+    },
+    /// Arrays and objects were nested more than `max` levels deep
+    JsonTooDeep {
+        max: usize,
+    },
+    FailedToDeserialize(DeserializeError),
+    InvalidContentRange {
+        start: u64,
+        end: u64,
+        total: Option<u64>,
+    },
+    BodyTooLarge {
+        requested: usize,
+        max: usize,
+    },
+    UnsupportedCharset {
+        found: String,
+    },
+    InvalidQValue {
+        found: String,
+    },
+    InvalidMediaType {
+        found: String,
+    },
+    AsteriskFormNotOptions {
+        found: String,
+    },
+    /// The stream ended cleanly in the middle of something being parsed
+    UnexpectedEof,
+    /// Reading from the stream failed, e.g. the connection was reset
+    IoError {
+        kind: std::io::ErrorKind,
+    },
+    TooManyHeaders {
+        max: usize,
+    },
+    /// A specific byte, or one of a set of bytes, was required. `label` says what it
+    /// was for, e.g. `"header name separator"`.
+    Expected {
+        expected: Vec<u8>,
+        label: &'static str,
+        found: Option<u8>,
+        /// Offset of `found` in the stream, see `Parser::offset`
+        at: usize,
+    },
+    /// Both `Content-Length` and `Transfer-Encoding` were sent, see rfc7230 section 3.3.3
+    ContentLengthWithTransferEncoding,
+    ConflictingContentLength {
+        found: Vec<usize>,
+    },
 }
 
+impl std::fmt::Display for ParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// Printable form of a byte, escaping anything that isn't visible ascii
+        fn byte(b: u8) -> String {
+            if b.is_ascii_graphic() || b == b' ' {
+                format!("'{}'", b as char)
+            } else {
+                format!("0x{:02x}", b)
+            }
+        }
+        fn found(b: &Option<u8>) -> String {
+            b.map_or(String::from("end of input"), byte)
+        }
+
+        match self {
+            Self::InvalidStatusCode { found } => write!(f, "{} is not a valid status code", found),
+            Self::InvalidStatusCodeStr { found } => {
+                write!(f, "found {} in status code, expected a digit", byte(*found))
+            }
+            Self::ExpectedStatusCode => write!(f, "expected a status code"),
+            Self::InvalidRequestOption { found } => write!(f, "'{}' is not a known method", found),
+            Self::InvalidUTF8 => write!(f, "text is not valid utf-8"),
+            Self::InvalidScheme => write!(f, "invalid uri scheme"),
+            Self::InvalidPctEncoding { found } => {
+                write!(f, "'{}' is not a valid percent encoding", found)
+            }
+            Self::InvalidIPv4Num { found } => {
+                write!(f, "'{}' is not a valid ipv4 address number", found)
+            }
+            Self::InvalidIPv4Char { found } => {
+                write!(f, "found '{}' in ipv4 address", found.escape_default())
+            }
+            Self::InvalidIPv4Len { found } => {
+                write!(f, "ipv4 address has {} numbers, expected 4", found)
+            }
+            Self::InvalidIPv6 { found } => write!(f, "'{}' is not a valid ipv6 address", found),
+            Self::InvalidIPvFuture { found } => {
+                write!(f, "'{}' is not a valid IPvFuture address", found)
+            }
+            Self::InvalidPath => write!(f, "invalid path"),
+            Self::InvalidUserInfo => write!(f, "invalid user info"),
+            Self::BlankHeaderFieldName => write!(f, "header field name is empty"),
+            Self::NotUserInfo { presumed_host } => {
+                write!(f, "'{}' is not user info", presumed_host)
+            }
+            Self::EmptyStack => write!(f, "stack is empty"),
+            Self::ExpectedStr { expected, at, .. } => {
+                write!(
+                    f,
+                    "expected \"{}\" at byte {}",
+                    expected.escape_default(),
+                    at
+                )
+            }
+            Self::FailedToConsume { found: b } => write!(f, "unexpected {}", found(b)),
+            Self::FailedToParseNum { found, radix } => {
+                write!(f, "failed to parse '{}' as base-{} number", found, radix)
+            }
+            Self::FailedToSeekDuringPop { tried_seeking_to } => {
+                write!(f, "failed to seek to {}", tried_seeking_to)
+            }
+            Self::ZeroLenDispositionTy => write!(f, "content disposition type is empty"),
+            Self::InvalidJson { found: b } => write!(f, "invalid json, found {}", found(b)),
+            Self::InvalidJsonEscape { found: b } => {
+                write!(f, "invalid json escape, found {}", found(b))
+            }
+            Self::JsonTooDeep { max } => {
+                write!(f, "json nested more than {} levels deep", max)
+            }
+            Self::FailedToDeserialize(e) => write!(f, "failed to deserialize: {}", e),
+            Self::InvalidContentRange { start, end, total } => match total {
+                Some(total) => write!(f, "invalid content range {}-{}/{}", start, end, total),
+                None => write!(f, "invalid content range {}-{}/*", start, end),
+            },
+            Self::BodyTooLarge { requested, max } => {
+                write!(
+                    f,
+                    "body of {} bytes is over the {} byte limit",
+                    requested, max
+                )
+            }
+            Self::UnsupportedCharset { found } => write!(f, "unsupported charset '{}'", found),
+            Self::InvalidQValue { found } => write!(f, "'{}' is not a valid qvalue", found),
+            Self::InvalidMediaType { found } => {
+                write!(f, "'{}' is not a valid media type", found)
+            }
+            Self::AsteriskFormNotOptions { found } => {
+                write!(
+                    f,
+                    "'*' request target used with {}, only OPTIONS allows it",
+                    found
+                )
+            }
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::IoError { kind } => write!(f, "failed to read input: {}", kind),
+            Self::TooManyHeaders { max } => write!(f, "more than {} header fields", max),
+            Self::Expected {
+                expected,
+                label,
+                found: b,
+                at,
+            } => {
+                let expected: Vec<String> = expected.iter().map(|b| byte(*b)).collect();
+                write!(
+                    f,
+                    "expected {} ({}) at byte {}, found {}",
+                    expected.join(" or "),
+                    label,
+                    at,
+                    found(b)
+                )
+            }
+            Self::ContentLengthWithTransferEncoding => {
+                write!(f, "content-length sent along with transfer-encoding")
+            }
+            Self::ConflictingContentLength { found } => {
+                write!(f, "conflicting content-length values {:?}", found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseErr {}
+
 /// Result type for Parsable trait
 pub type ParseResult<T> = Result<T, ParseErr>;
 
@@ -88,6 +280,70 @@ pub type ParseResult<T> = Result<T, ParseErr>;
 /// This type is mainly used for testing parsable structs
 pub type StrParser<'a> = Parser<Cursor<&'a str>>;
 
+/// Shared pool of read buffers that can be handed to a `Parser`.
+///
+/// Every parser normally allocates its own read buffer. When a pool is used,
+/// the buffer is taken from the pool when the parser is created and handed back
+/// when the parser is dropped, so buffers get recycled across connections
+/// instead of churning the allocator.
+///
+/// Buffers are handed out based on size. `take` returns the smallest pooled
+/// buffer that can hold the requested capacity and only allocates when none fit.
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    allocations: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Max number of idle buffers held by the pool. Anything past this is just dropped.
+    pub const MAX_POOLED: usize = 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a buffer with at least `min_capacity` capacity out of the pool,
+    /// allocating a new one if no pooled buffer is large enough.
+    pub fn take(&self, min_capacity: usize) -> Vec<u8> {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            let best = buffers
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.capacity() >= min_capacity)
+                .min_by_key(|(_, b)| b.capacity())
+                .map(|(i, _)| i);
+
+            if let Some(i) = best {
+                return buffers.swap_remove(i);
+            }
+        }
+
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        Vec::with_capacity(min_capacity)
+    }
+
+    /// Hands a buffer back to the pool so it can be reused.
+    pub fn give(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        if let Ok(mut buffers) = self.buffers.lock()
+            && buffers.len() < Self::MAX_POOLED
+        {
+            buffers.push(buf);
+        }
+    }
+
+    /// Number of buffers this pool has had to allocate.
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    /// Number of idle buffers currently held by the pool.
+    pub fn idle(&self) -> usize {
+        self.buffers.lock().map(|b| b.len()).unwrap_or(0)
+    }
+}
+
 /// Used by Parsable trait
 ///
 /// This Parser contains a variety of methods that
@@ -95,26 +351,138 @@ pub type StrParser<'a> = Parser<Cursor<&'a str>>;
 /// are some protocol specific parsing methods, adding
 /// non-universal methods should be avoided.
 pub struct Parser<R: Read> {
-    reader: BufReader<R>,
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
     idx: usize,
     peek: Option<u8>,
+    pool: Option<Arc<BufferPool>>,
+    io_error: Option<std::io::Error>,
 }
 
 impl<R: Read> Parser<R> {
+    /// Size of the read buffer used by each parser
+    pub const BUF_SIZE: usize = 8 * 1024;
+
     pub fn from_str(s: &str) -> Parser<Cursor<&str>> {
         let stream = Cursor::new(s);
-        Parser {
-            reader: BufReader::new(stream),
-            idx: 0,
-            peek: None,
-        }
+        Parser::from_buf(stream, Vec::with_capacity(Self::BUF_SIZE), None)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Parser<Cursor<&[u8]>> {
+        Parser::from_buf(Cursor::new(bytes), Vec::with_capacity(Self::BUF_SIZE), None)
     }
 
     pub fn from_stream(stream: R) -> Parser<R> {
+        Parser::from_buf(stream, Vec::with_capacity(Self::BUF_SIZE), None)
+    }
+
+    /// Same as `from_stream` but the read buffer is taken from `pool` and
+    /// handed back to it once the parser is dropped.
+    pub fn from_stream_pooled(stream: R, pool: Arc<BufferPool>) -> Parser<R> {
+        let buf = pool.take(Self::BUF_SIZE);
+        Parser::from_buf(stream, buf, Some(pool))
+    }
+
+    fn from_buf(reader: R, mut buf: Vec<u8>, pool: Option<Arc<BufferPool>>) -> Parser<R> {
+        buf.resize(Self::BUF_SIZE, 0);
         Parser {
-            reader: BufReader::new(stream),
+            reader,
+            buf,
+            pos: 0,
+            filled: 0,
             idx: 0,
             peek: None,
+            pool,
+            io_error: None,
+        }
+    }
+
+    /// Refills the read buffer from the underlying reader if it has been fully read.
+    ///
+    /// Returns false if there is nothing left to read. Read errors are kept in
+    /// `io_error` and stop any further reads.
+    fn fill_buf(&mut self) -> bool {
+        if self.pos < self.filled {
+            return true;
+        }
+        if self.io_error.is_some() {
+            return false;
+        }
+
+        self.pos = 0;
+        self.filled = 0;
+        loop {
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.filled = n;
+                    return true;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.io_error = Some(e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Makes sure at least `n` unread bytes are buffered, moving the unread ones to the
+    /// front of the buffer to make room. Returns false if the stream ends first.
+    fn fill_at_least(&mut self, n: usize) -> bool {
+        while self.filled - self.pos < n {
+            if self.io_error.is_some() {
+                return false;
+            }
+            if self.pos > 0 {
+                self.buf.copy_within(self.pos..self.filled, 0);
+                self.filled -= self.pos;
+                self.pos = 0;
+            }
+            match self.reader.read(&mut self.buf[self.filled..]) {
+                Ok(0) => return false,
+                Ok(read) => self.filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.io_error = Some(e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Pulls the next byte out of the read buffer, refilling it from the
+    /// underlying reader when empty.
+    fn read_byte(&mut self) -> Option<u8> {
+        if !self.fill_buf() {
+            return None;
+        }
+
+        let b = self.buf[self.pos];
+        self.pos += 1;
+        Some(b)
+    }
+
+    /// Looks at the byte right after the one returned by `peek` without consuming either.
+    pub(crate) fn peek_second(&mut self) -> Option<u8> {
+        self.peek()?;
+        if self.fill_buf() {
+            Some(self.buf[self.pos])
+        } else {
+            None
+        }
+    }
+
+    /// Looks at the byte after the one returned by `peek_second` without consuming any.
+    fn peek_third(&mut self) -> Option<u8> {
+        self.peek()?;
+        if self.fill_at_least(2) {
+            Some(self.buf[self.pos + 1])
+        } else {
+            None
         }
     }
 
@@ -123,33 +491,46 @@ impl<R: Read> Parser<R> {
     /// conditions
     pub fn peek(&mut self) -> Option<u8> {
         if self.peek.is_none() {
-            let mut buf = [0; 1];
-            match self.reader.read_exact(&mut buf) {
-                Ok(_) => {
-                    self.peek = Some(buf[0]);
-                    self.peek
-                }
-                Err(_) => None,
-            }
-        } else {
-            self.peek
+            self.peek = self.read_byte();
+        }
+        self.peek
+    }
+
+    /// Same as `peek`, but tells a clean end of stream (`Ok(None)`) apart from a failed read.
+    pub fn peek_result(&mut self) -> std::io::Result<Option<u8>> {
+        match self.peek() {
+            Some(c) => Ok(Some(c)),
+            None => match &self.io_error {
+                Some(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Number of bytes consumed so far
+    pub fn offset(&self) -> usize {
+        self.idx
+    }
+
+    /// Error to report when the stream ran out while parsing:
+    /// `IoError` if a read failed, otherwise `UnexpectedEof`.
+    pub fn eof_err(&self) -> ParseErr {
+        match &self.io_error {
+            Some(e) => ParseErr::IoError { kind: e.kind() },
+            None => ParseErr::UnexpectedEof,
         }
     }
 
     /// Reads the value under the seeking head, moves the seeking head forward by 1, then returns the value.
     pub fn consume(&mut self) -> Option<u8> {
-        if self.peek.is_none() {
-            let mut buf = [0; 1];
-            match self.reader.read_exact(&mut buf) {
-                Ok(_) => {
-                    self.idx += 1;
-                    Some(buf[0])
-                }
-                Err(_) => None,
-            }
-        } else {
-            self.peek.take()
+        let b = match self.peek.take() {
+            Some(b) => Some(b),
+            None => self.read_byte(),
+        };
+        if b.is_some() {
+            self.idx += 1;
         }
+        b
     }
 
     /// Builds a string while the value under the seeking head is found to meet conditions provided by the closure `f`.
@@ -167,7 +548,7 @@ impl<R: Read> Parser<R> {
         s
     }
     pub fn consume_n(&mut self, n: usize) -> String {
-        let mut s = String::new();
+        let mut s = String::with_capacity(n.min(Self::BUF_SIZE));
 
         for _ in 0..n {
             if let Some(c) = self.consume() {
@@ -180,6 +561,80 @@ impl<R: Read> Parser<R> {
         s
     }
 
+    /// Same as `consume_n` but keeps the raw bytes instead of casting each one to a `char`.
+    pub fn consume_n_bytes(&mut self, n: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(n.min(Self::BUF_SIZE));
+
+        for _ in 0..n {
+            if let Some(c) = self.consume() {
+                bytes.push(c);
+            } else {
+                break;
+            }
+        }
+
+        bytes
+    }
+
+    /// Same as `consume_n`, but fails with `ParseErr::BodyTooLarge` without reading
+    /// anything if `n` is over `max`.
+    pub fn consume_n_capped(&mut self, n: usize, max: usize) -> ParseResult<String> {
+        if n > max {
+            return Err(ParseErr::BodyTooLarge { requested: n, max });
+        }
+        Ok(self.consume_n(n))
+    }
+
+    /// Same as `consume_n_bytes`, but fails with `ParseErr::BodyTooLarge` without
+    /// reading anything if `n` is over `max`.
+    pub fn consume_n_bytes_capped(&mut self, n: usize, max: usize) -> ParseResult<Vec<u8>> {
+        if n > max {
+            return Err(ParseErr::BodyTooLarge { requested: n, max });
+        }
+        Ok(self.consume_n_bytes(n))
+    }
+
+    /// Reads exactly `n` bytes, failing if the stream ends first.
+    pub fn consume_bytes(&mut self, n: usize) -> ParseResult<Vec<u8>> {
+        let bytes = self.consume_n_bytes(n);
+        if bytes.len() < n {
+            return Err(self.eof_err());
+        }
+        Ok(bytes)
+    }
+
+    pub fn read_u32_le(&mut self) -> ParseResult<u32> {
+        let mut buf = [0_u8; 4];
+        let bytes = self.consume_bytes(buf.len())?;
+        buf.copy_from_slice(&bytes);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64_le(&mut self) -> ParseResult<u64> {
+        let mut buf = [0_u8; 8];
+        let bytes = self.consume_bytes(buf.len())?;
+        buf.copy_from_slice(&bytes);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Discards up to `n` bytes without collecting them.
+    ///
+    /// Returns how many bytes were skipped, which is only less than `n` if the stream ended.
+    pub fn skip_n(&mut self, n: usize) -> usize {
+        let mut skipped = 0;
+        if n > 0 && self.peek.take().is_some() {
+            self.idx += 1;
+            skipped += 1;
+        }
+        while skipped < n && self.fill_buf() {
+            let take = (self.filled - self.pos).min(n - skipped);
+            self.pos += take;
+            self.idx += take;
+            skipped += take;
+        }
+        skipped
+    }
+
     pub fn consume_while_lower<F: Fn(&mut Self) -> bool>(&mut self, f: F) -> String {
         let mut s = String::new();
 
@@ -194,12 +649,71 @@ impl<R: Read> Parser<R> {
         s
     }
 
+    /// Checks if the seeking head is at a line terminator, either `\r\n` or a bare `\n`.
+    pub fn peek_is_crlf(&mut self) -> bool {
+        match self.peek() {
+            Some(b'\n') => true,
+            Some(b'\r') => self.peek_second() == Some(b'\n'),
+            _ => false,
+        }
+    }
+
+    /// Reads up to the end of the current line, consuming the line terminator
+    /// (`\r\n` or a bare `\n`). The returned string does not include the terminator.
+    ///
+    /// If the stream ends before a terminator is found, everything up to the end is returned.
+    pub fn consume_line(&mut self) -> String {
+        let mut s = String::new();
+
+        while !self.peek_is_crlf() {
+            match self.consume() {
+                Some(c) => s.push(c as char),
+                None => return s,
+            }
+        }
+
+        if self.matches(|c| c == b'\r') {
+            self.consume();
+        }
+        self.consume();
+
+        s
+    }
+
     pub fn skip_whitespace(&mut self) {
         while self.is_linear_whitespace() {
             self.consume();
         }
     }
 
+    /// Whether the head is on an obs-fold, i.e. a line terminator followed by a space
+    /// or tab, see rfc7230 Section 3.2.4
+    pub fn peek_is_fold(&mut self) -> bool {
+        let is_wsp = |c: Option<u8>| c == Some(b' ') || c == Some(b'\t');
+        match self.peek() {
+            Some(b'\n') => is_wsp(self.peek_second()),
+            Some(b'\r') => self.peek_second() == Some(b'\n') && is_wsp(self.peek_third()),
+            _ => false,
+        }
+    }
+
+    /// Same as `skip_whitespace`, but also skips obs-folds, so a value continued on the
+    /// next line reads as one line. Returns whether anything was skipped, for callers
+    /// replacing the whole run with a single space as rfc7230 Section 3.2.4 asks.
+    pub fn skip_linear_whitespace_and_folds(&mut self) -> bool {
+        let mut skipped = false;
+        loop {
+            if self.is_linear_whitespace() {
+                self.consume();
+            } else if self.peek_is_fold() {
+                self.consume_line();
+            } else {
+                return skipped;
+            }
+            skipped = true;
+        }
+    }
+
     pub fn consume_escaped<F: Fn(&mut Self) -> bool, FF: Fn(&mut Self) -> bool>(
         &mut self,
         is_escape: FF,
@@ -225,8 +739,17 @@ impl<R: Read> Parser<R> {
         s
     }
 
+    /// Consumes a quoted string (opening and closing quotes included) and returns its
+    /// unescaped contents
     pub fn consume_str_lit(&mut self) -> String {
-        self.consume_escaped(|c| c.matches(|c| c == b'\\'), |c| c.matches(|c| c == b'"'))
+        if self.is_dquote() {
+            self.consume();
+        }
+        let s = self.consume_escaped(|c| c.matches(|c| c == b'\\'), |c| c.matches(|c| c != b'"'));
+        if self.is_dquote() {
+            self.consume();
+        }
+        s
     }
 
     // HTTP spec section 2.2
@@ -340,11 +863,34 @@ impl<R: Read> Parser<R> {
     }
 
     pub fn consume_or_err<F: Fn(u8) -> bool>(&mut self, f: F) -> ParseResult<u8> {
-        let peek = self.peek();
-        if peek.is_some_and(f) {
-            Ok(self.consume().unwrap())
-        } else {
-            Err(ParseErr::FailedToConsume { found: peek })
+        match self.peek() {
+            Some(c) if f(c) => Ok(self.consume().unwrap()),
+            Some(c) => Err(ParseErr::FailedToConsume { found: Some(c) }),
+            None => Err(self.eof_err()),
+        }
+    }
+
+    /// Same as `consume_or_err` for a single byte, but the error names `byte` and what it
+    /// was for.
+    pub fn consume_expect(&mut self, byte: u8, label: &'static str) -> ParseResult<u8> {
+        self.expect_one_of_labeled(&[byte], label)
+    }
+
+    /// Consumes the next byte if it is one of `set`, the error listing all of `set`.
+    pub fn expect_one_of(&mut self, set: &[u8]) -> ParseResult<u8> {
+        self.expect_one_of_labeled(set, "one of")
+    }
+
+    fn expect_one_of_labeled(&mut self, set: &[u8], label: &'static str) -> ParseResult<u8> {
+        match self.peek() {
+            Some(c) if set.contains(&c) => Ok(self.consume().unwrap()),
+            Some(c) => Err(ParseErr::Expected {
+                expected: set.to_vec(),
+                label,
+                found: Some(c),
+                at: self.idx,
+            }),
+            None => Err(self.eof_err()),
         }
     }
 
@@ -362,7 +908,234 @@ impl<R: Read> Parser<R> {
         Ok(())
     }
 
+    /// Consumes a line terminator, either `\r\n` or a bare `\n`.
+    ///
+    /// rfc7230 Section 3.5 recommends accepting a bare `\n` wherever a CRLF is expected,
+    /// same as `consume_line` and `peek_is_crlf` do.
     pub fn expect_crlf(&mut self) -> ParseResult<()> {
-        self.expect_str("\r\n")
+        if self.matches(|c| c == b'\r') {
+            self.consume();
+        }
+        self.consume_expect(b'\n', "line terminator").map(|_| ())
+    }
+}
+
+/// Reads on from wherever parsing stopped, buffered bytes first, e.g. to hand a request
+/// body to something that takes a `Read` without collecting it.
+impl<R: Read> Read for Parser<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+
+        let mut n = 0;
+        if let Some(b) = self.peek.take() {
+            out[0] = b;
+            n = 1;
+        }
+        // only block on the reader when nothing was buffered at all
+        if n == 0 && !self.fill_buf() {
+            return match &self.io_error {
+                Some(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+                None => Ok(0),
+            };
+        }
+        let take = (self.filled - self.pos).min(out.len() - n);
+        out[n..n + take].copy_from_slice(&self.buf[self.pos..self.pos + take]);
+        self.pos += take;
+        n += take;
+        self.idx += n;
+
+        Ok(n)
+    }
+}
+
+impl<R: Read> Drop for Parser<R> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::{Method, Request};
+
+    #[test]
+    fn test_read_after_parsing() {
+        let mut parser = StrParser::from_str("head\r\nbody bytes");
+        assert_eq!(parser.consume_line(), "head");
+        assert_eq!(parser.peek(), Some(b'b'));
+
+        let mut body = String::new();
+        parser.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "body bytes");
+        assert_eq!(parser.offset(), "head\r\nbody bytes".len());
+        assert_eq!(parser.read(&mut [0; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_consume_line_crlf() {
+        let mut parser = StrParser::from_str("first line\r\nsecond");
+        assert!(!parser.peek_is_crlf());
+        assert_eq!(parser.consume_line(), "first line");
+        assert_eq!(parser.peek(), Some(b's'));
+    }
+
+    #[test]
+    fn test_consume_line_bare_lf() {
+        let mut parser = StrParser::from_str("first line\nsecond");
+        assert_eq!(parser.consume_line(), "first line");
+        assert_eq!(parser.peek(), Some(b's'));
+
+        // a lone CR is not a line terminator
+        let mut parser = StrParser::from_str("a\rb\n");
+        assert_eq!(parser.consume_line(), "a\rb");
+    }
+
+    #[test]
+    fn test_skip_linear_whitespace_and_folds() {
+        let mut parser = StrParser::from_str(" \t\r\n \n\tnext\r\nend");
+        assert!(parser.skip_linear_whitespace_and_folds());
+        assert_eq!(parser.peek(), Some(b'n'));
+        parser.consume_n(4);
+        // a terminator not followed by whitespace ends the line
+        assert!(!parser.skip_linear_whitespace_and_folds());
+        assert!(parser.peek_is_crlf());
+
+        // the lookahead for `\r\n ` spans buffer refills
+        let mut input = "a".repeat(Parser::<Cursor<&[u8]>>::BUF_SIZE - 2);
+        input.push_str("\r\n b");
+        let mut parser = StrParser::from_str(&input);
+        parser.consume_n(input.len() - 4);
+        assert!(parser.peek_is_fold());
+        assert!(parser.skip_linear_whitespace_and_folds());
+        assert_eq!(parser.peek(), Some(b'b'));
+    }
+
+    #[test]
+    fn test_consume_line_eof() {
+        let mut parser = StrParser::from_str("last line");
+        assert_eq!(parser.consume_line(), "last line");
+        assert_eq!(parser.peek(), None);
+        assert_eq!(parser.consume_line(), "");
+    }
+
+    #[test]
+    fn test_parse_err_display() {
+        let err = ParseErr::FailedToParseNum {
+            found: String::from("abc"),
+            radix: 16,
+        };
+        assert_eq!(err.to_string(), "failed to parse 'abc' as base-16 number");
+
+        let mut parser = StrParser::from_str("ab\r\nx");
+        parser.consume_n(2);
+        parser.peek();
+        assert_eq!(parser.offset(), 2);
+        parser.expect_crlf().unwrap();
+        let err = parser.consume_expect(b':', "separator").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected ':' (separator) at byte 4, found 'x'"
+        );
+
+        fn boxed() -> Result<(), Box<dyn std::error::Error>> {
+            Err(ParseErr::UnexpectedEof)?
+        }
+        assert_eq!(boxed().unwrap_err().to_string(), "unexpected end of input");
+    }
+
+    #[test]
+    fn test_length_prefixed_blob() {
+        let blob = [0xff_u8, 0x00, b'\r', b'\n', 0x80];
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&blob);
+        raw.extend_from_slice(&u64::MAX.to_le_bytes());
+        raw.extend_from_slice(&9_u32.to_le_bytes());
+        raw.extend_from_slice(b"short");
+
+        let mut parser = Parser::<Cursor<&[u8]>>::from_bytes(&raw);
+        let len = parser.read_u32_le().unwrap() as usize;
+        assert_eq!(parser.consume_bytes(len).unwrap(), blob);
+        assert_eq!(parser.read_u64_le(), Ok(u64::MAX));
+
+        let len = parser.read_u32_le().unwrap() as usize;
+        assert_eq!(parser.consume_bytes(len), Err(ParseErr::UnexpectedEof));
+        assert_eq!(parser.read_u32_le(), Err(ParseErr::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_buffer_pool_reuse() {
+        let pool = Arc::new(BufferPool::new());
+        let raw = "GET /somepath?some=query HTTP/1.1\r\nHost: 127.0.0.1:8000\r\n\r\n";
+
+        for _ in 0..100 {
+            let mut parser = Parser::from_stream_pooled(Cursor::new(raw), pool.clone());
+            let request = Request::parse(&mut parser).unwrap();
+            assert_eq!(request.method(), &Method::Get);
+        }
+
+        assert_eq!(pool.allocations(), 1);
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[test]
+    fn test_buffer_pool_size_aware() {
+        let pool = BufferPool::new();
+        pool.give(Vec::with_capacity(16));
+        pool.give(Vec::with_capacity(1024));
+
+        let buf = pool.take(512);
+        assert!(buf.capacity() >= 1024);
+        assert_eq!(pool.allocations(), 0);
+
+        let buf = pool.take(4096);
+        assert!(buf.capacity() >= 4096);
+        assert_eq!(pool.allocations(), 1);
+    }
+
+    /// Hands out `data` and then fails every read with `kind`
+    struct FailingReader {
+        data: Cursor<&'static [u8]>,
+        kind: std::io::ErrorKind,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.data.read(buf)? {
+                0 => Err(std::io::Error::new(self.kind, "reader failed")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn test_peek_result() {
+        let mut parser = StrParser::from_str("a");
+        assert_eq!(parser.peek_result().unwrap(), Some(b'a'));
+        parser.consume();
+        assert_eq!(parser.peek_result().unwrap(), None);
+        assert_eq!(parser.eof_err(), ParseErr::UnexpectedEof);
+
+        let reader = FailingReader {
+            data: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n"),
+            kind: std::io::ErrorKind::ConnectionReset,
+        };
+        let mut parser = Parser::from_stream(reader);
+        assert_eq!(
+            Request::parse(&mut parser),
+            Err(ParseErr::IoError {
+                kind: std::io::ErrorKind::ConnectionReset
+            })
+        );
+        let err = parser.peek_result().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+
+        let mut parser = StrParser::from_str("GET / HTTP/1.1\r\nHost: localhost\r\n");
+        assert_eq!(Request::parse(&mut parser), Err(ParseErr::UnexpectedEof));
     }
 }
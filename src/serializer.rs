@@ -1,11 +1,293 @@
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    io::Read,
+    str::FromStr,
+};
 
-use crate::parsing::Parsable;
+use crate::{
+    errors::{DeserializeError, DeserializeReason},
+    parsing::{Parsable, ParseErr, ParseResult, Parser},
+};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum DataHolder {
     Primitive(String),
     Struct(HashMap<String, DataHolder>),
+    List(Vec<DataHolder>),
+    /// JSON `null` and `Option::None`, kept apart from a `"null"` string
+    Null,
+}
+
+impl DataHolder {
+    /// Struct entries sorted by key. Used so comparing and hashing doesn't
+    /// depend on `HashMap` iteration order.
+    pub fn sorted_entries(map: &HashMap<String, DataHolder>) -> Vec<(&String, &DataHolder)> {
+        let mut entries: Vec<(&String, &DataHolder)> = map.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    fn variant_rank(&self) -> u8 {
+        match self {
+            Self::Primitive(_) => 0,
+            Self::Struct(_) => 1,
+            Self::List(_) => 2,
+            Self::Null => 3,
+        }
+    }
+}
+
+/// Primitives print as quoted strings, structs as maps sorted by key and lists as
+/// lists. The alternate flag (`{:#?}`) puts every entry on its own indented line.
+impl std::fmt::Debug for DataHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Primitive(s) => std::fmt::Debug::fmt(s, f),
+            Self::Struct(map) => f.debug_map().entries(Self::sorted_entries(map)).finish(),
+            Self::List(list) => f.debug_list().entries(list).finish(),
+            Self::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl PartialOrd for DataHolder {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Primitives order before structs, structs before lists and lists before null. Structs are compared
+/// entry by entry in key order.
+impl Ord for DataHolder {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Primitive(a), Self::Primitive(b)) => a.cmp(b),
+            (Self::Struct(a), Self::Struct(b)) => {
+                Self::sorted_entries(a).cmp(&Self::sorted_entries(b))
+            }
+            (Self::List(a), Self::List(b)) => a.cmp(b),
+            _ => self.variant_rank().cmp(&other.variant_rank()),
+        }
+    }
+}
+
+impl Hash for DataHolder {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.variant_rank().hash(state);
+        match self {
+            Self::Primitive(s) => s.hash(state),
+            Self::Struct(map) => Self::sorted_entries(map).hash(state),
+            Self::List(list) => list.hash(state),
+            Self::Null => {}
+        }
+    }
+}
+
+/// JSON parsing straight off of a `Parser`.
+///
+/// Values are read byte by byte from the underlying stream, so a request body
+/// never has to be collected into a `String` before it can be parsed. Strings,
+/// numbers and booleans become `Primitive`, objects become `Struct` and arrays
+/// become `List`. `null` becomes `Null`, so it isn't mistaken for a `"null"` string.
+///
+/// Nothing past the end of the value is consumed, which keeps the parser from
+/// blocking on a socket waiting for trailing whitespace that never comes.
+///
+/// Arrays and objects nested more than `MAX_JSON_DEPTH` levels deep fail with
+/// `ParseErr::JsonTooDeep`, before they can overflow the stack.
+impl DataHolder {
+    pub const MAX_JSON_DEPTH: usize = 128;
+
+    pub fn parse_json<R: Read>(parser: &mut Parser<R>) -> ParseResult<Self> {
+        Self::parse_json_at(parser, 0)
+    }
+
+    /// Parses a value nested inside `depth` arrays and objects
+    fn parse_json_at<R: Read>(parser: &mut Parser<R>, depth: usize) -> ParseResult<Self> {
+        Self::skip_json_whitespace(parser);
+        if matches!(parser.peek(), Some(b'{' | b'[')) && depth >= Self::MAX_JSON_DEPTH {
+            return Err(ParseErr::JsonTooDeep {
+                max: Self::MAX_JSON_DEPTH,
+            });
+        }
+        match parser.peek() {
+            Some(b'{') => Self::parse_json_object(parser, depth + 1),
+            Some(b'[') => Self::parse_json_array(parser, depth + 1),
+            Some(b'"') => Ok(DataHolder::Primitive(Self::parse_json_string(parser)?)),
+            Some(b't') => Self::parse_json_literal(parser, "true"),
+            Some(b'f') => Self::parse_json_literal(parser, "false"),
+            Some(b'n') => {
+                parser.expect_str("null")?;
+                Ok(DataHolder::Null)
+            }
+            Some(c) if c == b'-' || c.is_ascii_digit() => Self::parse_json_number(parser),
+            found => Err(ParseErr::InvalidJson { found }),
+        }
+    }
+
+    fn skip_json_whitespace<R: Read>(parser: &mut Parser<R>) {
+        while parser.matches(|c| c == b' ' || c == b'\t' || c == b'\r' || c == b'\n') {
+            parser.consume();
+        }
+    }
+
+    fn parse_json_literal<R: Read>(parser: &mut Parser<R>, literal: &str) -> ParseResult<Self> {
+        parser.expect_str(literal)?;
+        Ok(DataHolder::Primitive(String::from(literal)))
+    }
+
+    fn parse_json_number<R: Read>(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let num = parser.consume_while(|p| {
+            p.matches(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E'))
+        });
+        if f64::from_str(num.as_str()).is_ok() {
+            Ok(DataHolder::Primitive(num))
+        } else {
+            Err(ParseErr::FailedToParseNum {
+                found: num,
+                radix: 10,
+            })
+        }
+    }
+
+    fn parse_json_hex4<R: Read>(parser: &mut Parser<R>) -> ParseResult<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let c = parser.consume();
+            let digit = c
+                .and_then(|c| (c as char).to_digit(16))
+                .ok_or(ParseErr::InvalidJsonEscape { found: c })?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_json_unicode_escape<R: Read>(parser: &mut Parser<R>) -> ParseResult<char> {
+        let high = Self::parse_json_hex4(parser)?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            parser.expect_str("\\u")?;
+            let low = Self::parse_json_hex4(parser)?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(ParseErr::InvalidJsonEscape { found: None });
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or(ParseErr::InvalidJsonEscape { found: None })
+    }
+
+    fn parse_json_string<R: Read>(parser: &mut Parser<R>) -> ParseResult<String> {
+        parser.consume_or_err(|c| c == b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match parser.consume() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match parser.consume() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => Self::parse_json_unicode_escape(parser)?,
+                        found => return Err(ParseErr::InvalidJsonEscape { found }),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(c) if c >= 0x20 => bytes.push(c),
+                found => return Err(ParseErr::InvalidJson { found }),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| ParseErr::InvalidUTF8)
+    }
+
+    fn parse_json_object<R: Read>(parser: &mut Parser<R>, depth: usize) -> ParseResult<Self> {
+        parser.consume_or_err(|c| c == b'{')?;
+        let mut map = HashMap::new();
+        Self::skip_json_whitespace(parser);
+        if parser.matches(|c| c == b'}') {
+            parser.consume();
+            return Ok(DataHolder::Struct(map));
+        }
+
+        loop {
+            Self::skip_json_whitespace(parser);
+            if !parser.is_dquote() {
+                return Err(ParseErr::InvalidJson {
+                    found: parser.peek(),
+                });
+            }
+            let key = Self::parse_json_string(parser)?;
+            Self::skip_json_whitespace(parser);
+            parser.consume_or_err(|c| c == b':')?;
+            let value = Self::parse_json_at(parser, depth)?;
+            map.insert(key, value);
+
+            Self::skip_json_whitespace(parser);
+            match parser.consume() {
+                Some(b',') => continue,
+                Some(b'}') => break,
+                found => return Err(ParseErr::InvalidJson { found }),
+            }
+        }
+
+        Ok(DataHolder::Struct(map))
+    }
+
+    fn parse_json_array<R: Read>(parser: &mut Parser<R>, depth: usize) -> ParseResult<Self> {
+        parser.consume_or_err(|c| c == b'[')?;
+        let mut list = Vec::new();
+        Self::skip_json_whitespace(parser);
+        if parser.matches(|c| c == b']') {
+            parser.consume();
+            return Ok(DataHolder::List(list));
+        }
+
+        loop {
+            list.push(Self::parse_json_at(parser, depth)?);
+
+            Self::skip_json_whitespace(parser);
+            match parser.consume() {
+                Some(b',') => continue,
+                Some(b']') => break,
+                found => return Err(ParseErr::InvalidJson { found }),
+            }
+        }
+
+        Ok(DataHolder::List(list))
+    }
+}
+
+/// Wrapper for parsing a `Deserialize` type out of a JSON stream.
+///
+/// # Example Usage
+/// ```rust
+/// use zero::parsing::{Parsable, StrParser};
+/// use zero::serializer::Json;
+/// use std::collections::HashMap;
+///
+/// let mut parser = StrParser::from_str(r#"{"a": "1", "b": "2"}"#);
+/// let Json(map) = Json::<HashMap<String, u8>>::parse(&mut parser).unwrap();
+/// assert_eq!(map.get("b"), Some(&2));
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<R: Read, T: Deserialize> Parsable<R> for Json<T> {
+    fn parse(parser: &mut Parser<R>) -> ParseResult<Self> {
+        let dh = DataHolder::parse_json(parser)?;
+        T::deserialize(dh)
+            .map(Json)
+            .map_err(ParseErr::FailedToDeserialize)
+    }
 }
 
 // impl DataHolder {
@@ -14,6 +296,28 @@ pub enum DataHolder {
 
 pub trait Serialize {
     fn serialize(self) -> DataHolder;
+
+    /// Value stored for a struct field by the derive, `None` leaves the key out
+    fn serialize_field(self) -> Option<DataHolder>
+    where
+        Self: Sized,
+    {
+        Some(self.serialize())
+    }
+}
+
+/// `None` serializes to `Null`, or is skipped entirely when it is a struct field.
+impl<T: Serialize> Serialize for Option<T> {
+    fn serialize(self) -> DataHolder {
+        match self {
+            Some(t) => t.serialize(),
+            None => DataHolder::Null,
+        }
+    }
+
+    fn serialize_field(self) -> Option<DataHolder> {
+        self.map(Serialize::serialize)
+    }
 }
 
 macro_rules! impl_primitive_serialize {
@@ -50,30 +354,55 @@ impl_primitive_serialize!(usize);
 impl_primitive_serialize!(String);
 
 pub trait Deserialize: Sized {
-    fn deserialize(dh: DataHolder) -> Result<Self, ()>;
+    fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError>;
+
+    /// Value the derive uses when a struct field is absent, `None` makes it required
+    fn deserialize_missing() -> Option<Self> {
+        None
+    }
+}
+
+/// Both `Null` and a missing struct field deserialize to `None`. A `"null"` string is
+/// left to `T`, so `Some(String::from("null"))` round-trips.
+impl<T: Deserialize> Deserialize for Option<T> {
+    fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError> {
+        match dh {
+            DataHolder::Null => Ok(None),
+            dh => T::deserialize(dh).map(Some),
+        }
+    }
+
+    fn deserialize_missing() -> Option<Self> {
+        Some(None)
+    }
 }
 
 macro_rules! impl_primitive_deserialize {
     ($t:ty) => {
         impl Deserialize for $t {
-            fn deserialize(dh: DataHolder) -> Result<Self, ()> {
+            fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError> {
                 match dh {
                     DataHolder::Primitive(s) => match Self::from_str(&s) {
-                        Ok(s) => Ok(s),
-                        Err(_) => Err(()),
+                        Ok(v) => Ok(v),
+                        Err(_) => Err(DeserializeError::new(DeserializeReason::InvalidValue {
+                            found: s,
+                        })),
                     },
-                    _ => Err(()),
+                    _ => Err(DeserializeError::new(DeserializeReason::ExpectedPrimitive)),
                 }
             }
         }
         impl Deserialize for HashMap<String, $t> {
-            fn deserialize(dh: DataHolder) -> Result<Self, ()> {
+            fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError> {
                 match dh {
                     DataHolder::Struct(map) => map
                         .into_iter()
-                        .map(|(k, v)| Ok((k, <$t>::deserialize(v)?)))
+                        .map(|(k, v)| {
+                            let v = <$t>::deserialize(v).map_err(|e| e.in_field(&k))?;
+                            Ok((k, v))
+                        })
                         .collect(),
-                    _ => Err(()),
+                    _ => Err(DeserializeError::new(DeserializeReason::ExpectedStruct)),
                 }
             }
         }
@@ -97,22 +426,322 @@ impl_primitive_deserialize!(u64);
 impl_primitive_deserialize!(u128);
 impl_primitive_deserialize!(usize);
 
+/// Errors name the offending element by its index, e.g. `items.1.name`.
+impl<T: Deserialize> Deserialize for Vec<T> {
+    fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError> {
+        match dh {
+            DataHolder::List(list) => list
+                .into_iter()
+                .enumerate()
+                .map(|(i, dh)| T::deserialize(dh).map_err(|e| e.in_field(&i.to_string())))
+                .collect(),
+            _ => Err(DeserializeError::new(DeserializeReason::ExpectedList)),
+        }
+    }
+}
+
 impl Deserialize for String {
-    fn deserialize(dh: DataHolder) -> Result<Self, ()> {
+    fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError> {
         match dh {
             DataHolder::Primitive(s) => Ok(s),
-            _ => Err(()),
+            _ => Err(DeserializeError::new(DeserializeReason::ExpectedPrimitive)),
         }
     }
 }
 impl Deserialize for HashMap<String, String> {
-    fn deserialize(dh: DataHolder) -> Result<Self, ()> {
+    fn deserialize(dh: DataHolder) -> Result<Self, DeserializeError> {
         match dh {
             DataHolder::Struct(map) => map
                 .into_iter()
-                .map(|(k, v)| Ok((k, String::deserialize(v)?)))
+                .map(|(k, v)| {
+                    let v = String::deserialize(v).map_err(|e| e.in_field(&k))?;
+                    Ok((k, v))
+                })
                 .collect(),
-            _ => Err(()),
+            _ => Err(DeserializeError::new(DeserializeReason::ExpectedStruct)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::StrParser;
+
+    /// Hands out one byte per read, like a slow socket would.
+    struct TrickleReader<'a>(&'a [u8]);
+
+    impl Read for TrickleReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.split_first() {
+                Some((first, rest)) if !buf.is_empty() => {
+                    buf[0] = *first;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
         }
     }
+
+    fn primitive(s: &str) -> DataHolder {
+        DataHolder::Primitive(String::from(s))
+    }
+
+    #[test]
+    fn test_data_holder_debug() {
+        let holder = DataHolder::Struct(HashMap::from([
+            (
+                String::from("name"),
+                DataHolder::Primitive(String::from("zero")),
+            ),
+            (
+                String::from("tags"),
+                DataHolder::List(vec![
+                    DataHolder::Primitive(String::from("a")),
+                    DataHolder::Primitive(String::from("b")),
+                ]),
+            ),
+            (
+                String::from("inner"),
+                DataHolder::Struct(HashMap::from([(
+                    String::from("id"),
+                    DataHolder::Primitive(String::from("7")),
+                )])),
+            ),
+        ]));
+
+        assert_eq!(
+            format!("{:?}", holder),
+            r#"{"inner": {"id": "7"}, "name": "zero", "tags": ["a", "b"]}"#
+        );
+        assert_eq!(
+            format!("{:#?}", holder),
+            concat!(
+                "{\n",
+                "    \"inner\": {\n",
+                "        \"id\": \"7\",\n",
+                "    },\n",
+                "    \"name\": \"zero\",\n",
+                "    \"tags\": [\n",
+                "        \"a\",\n",
+                "        \"b\",\n",
+                "    ],\n",
+                "}",
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_nested_stream() {
+        let json = br#"{
+            "name": "zero \"server\"\n\u00e9\ud83d\ude00",
+            "version": 1.5e2,
+            "tags": ["http", [], {}, null, true],
+            "owner": {"id": -42, "active": false}
+        } trailing"#;
+        let mut parser = Parser::from_stream(TrickleReader(json));
+        let dh = DataHolder::parse_json(&mut parser).unwrap();
+
+        let mut owner = HashMap::new();
+        owner.insert(String::from("id"), primitive("-42"));
+        owner.insert(String::from("active"), primitive("false"));
+        let mut expected = HashMap::new();
+        expected.insert(
+            String::from("name"),
+            primitive("zero \"server\"\n\u{e9}\u{1f600}"),
+        );
+        expected.insert(String::from("version"), primitive("1.5e2"));
+        expected.insert(
+            String::from("tags"),
+            DataHolder::List(vec![
+                primitive("http"),
+                DataHolder::List(Vec::new()),
+                DataHolder::Struct(HashMap::new()),
+                DataHolder::Null,
+                primitive("true"),
+            ]),
+        );
+        expected.insert(String::from("owner"), DataHolder::Struct(owner));
+
+        assert_eq!(dh, DataHolder::Struct(expected));
+        // only the value itself is consumed
+        assert_eq!(parser.peek(), Some(b' '));
+    }
+
+    #[test]
+    fn test_json_invalid() {
+        let mut parser = StrParser::from_str(r#"{"a": 1,}"#);
+        assert_eq!(
+            DataHolder::parse_json(&mut parser),
+            Err(ParseErr::InvalidJson { found: Some(b'}') })
+        );
+        let mut parser = StrParser::from_str(r#""\x""#);
+        assert_eq!(
+            DataHolder::parse_json(&mut parser),
+            Err(ParseErr::InvalidJsonEscape { found: Some(b'x') })
+        );
+        let mut parser = StrParser::from_str(r#"[1, 2"#);
+        assert_eq!(
+            DataHolder::parse_json(&mut parser),
+            Err(ParseErr::InvalidJson { found: None })
+        );
+    }
+
+    #[test]
+    fn test_json_too_deep() {
+        let max = DataHolder::MAX_JSON_DEPTH;
+        let nested = format!("{}{}", "[".repeat(max), "]".repeat(max));
+        let mut parser = StrParser::from_str(&nested);
+        assert!(DataHolder::parse_json(&mut parser).is_ok());
+
+        let nested = format!(
+            r#"{}{{"a": []}}{}"#,
+            "[".repeat(max - 1),
+            "]".repeat(max - 1)
+        );
+        let mut parser = StrParser::from_str(&nested);
+        assert_eq!(
+            DataHolder::parse_json(&mut parser),
+            Err(ParseErr::JsonTooDeep { max })
+        );
+
+        // deep enough to overflow the stack without the limit
+        let nested = "[".repeat(1_000_000);
+        let mut parser = StrParser::from_str(&nested);
+        assert_eq!(
+            DataHolder::parse_json(&mut parser),
+            Err(ParseErr::JsonTooDeep { max })
+        );
+    }
+
+    #[test]
+    fn test_json_deserialize() {
+        let mut parser = StrParser::from_str(r#"{"a": 1, "b": 2}"#);
+        let Json(map) = Json::<HashMap<String, u8>>::parse(&mut parser).unwrap();
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.get("b"), Some(&2));
+
+        let mut parser = StrParser::from_str(r#"{"a": "not a number"}"#);
+        let err = DeserializeError::new(DeserializeReason::InvalidValue {
+            found: String::from("not a number"),
+        })
+        .in_field("a");
+        assert_eq!(err.to_string(), "field 'a' invalid value 'not a number'");
+        assert_eq!(
+            Json::<HashMap<String, u8>>::parse(&mut parser),
+            Err(ParseErr::FailedToDeserialize(err))
+        );
+    }
+
+    #[derive(crate::Deserialize, Debug)]
+    struct Account {
+        name: String,
+        age: u8,
+    }
+
+    #[test]
+    fn test_deserialize_list() {
+        let mut parser = StrParser::from_str(r#"[{"name":"a","age":1},{"name":"b","age":"x"}]"#);
+        let dh = DataHolder::parse_json(&mut parser).unwrap();
+        let err = Vec::<Account>::deserialize(dh).unwrap_err();
+        assert_eq!(err.field, "1.age");
+
+        let mut parser = StrParser::from_str("[1, 2, 3]");
+        let Json(list) = Json::<Vec<u8>>::parse(&mut parser).unwrap();
+        assert_eq!(list, vec![1, 2, 3]);
+        assert_eq!(
+            Vec::<u8>::deserialize(primitive("1")).unwrap_err().reason,
+            DeserializeReason::ExpectedList
+        );
+        // json stays strict, a list is not read as one of its values
+        let mut parser = StrParser::from_str(r#"[1, 2]"#);
+        assert_eq!(
+            Json::<u8>::parse(&mut parser),
+            Err(ParseErr::FailedToDeserialize(DeserializeError::new(
+                DeserializeReason::ExpectedPrimitive
+            )))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_error_field() {
+        let mut map = HashMap::new();
+        map.insert(String::from("name"), primitive("zero"));
+        let err = Account::deserialize(DataHolder::Struct(map)).unwrap_err();
+        assert_eq!(err, DeserializeError::missing("age"));
+        assert_eq!(err.field, "age");
+
+        let mut map = HashMap::new();
+        map.insert(String::from("name"), primitive("zero"));
+        map.insert(String::from("age"), primitive("old"));
+        let err = Account::deserialize(DataHolder::Struct(map)).unwrap_err();
+        assert_eq!(err.field, "age");
+        assert_eq!(
+            err.reason,
+            DeserializeReason::InvalidValue {
+                found: String::from("old")
+            }
+        );
+
+        let account = Account::deserialize(DataHolder::Struct(HashMap::from([
+            (String::from("name"), primitive("zero")),
+            (String::from("age"), primitive("3")),
+        ])))
+        .unwrap();
+        assert_eq!((account.name.as_str(), account.age), ("zero", 3));
+    }
+
+    #[derive(crate::Serialize, crate::Deserialize, Debug, PartialEq)]
+    struct Profile {
+        name: String,
+        nickname: Option<String>,
+    }
+
+    #[test]
+    fn test_serialize_skips_none() {
+        let profile = Profile {
+            name: String::from("zero"),
+            nickname: None,
+        };
+        let DataHolder::Struct(map) = profile.serialize() else {
+            panic!("expected a struct");
+        };
+        assert_eq!(map.get("name"), Some(&primitive("zero")));
+        assert!(!map.contains_key("nickname"));
+        assert_eq!(
+            Profile::deserialize(DataHolder::Struct(map)),
+            Ok(Profile {
+                name: String::from("zero"),
+                nickname: None
+            })
+        );
+
+        let profile = Profile {
+            name: String::from("zero"),
+            nickname: Some(String::from("z")),
+        };
+        let DataHolder::Struct(map) = profile.serialize() else {
+            panic!("expected a struct");
+        };
+        assert_eq!(map.get("nickname"), Some(&primitive("z")));
+
+        // a "null" string is a value like any other, not a missing one
+        let profile = Profile {
+            name: String::from("zero"),
+            nickname: Some(String::from("null")),
+        };
+        let DataHolder::Struct(map) = profile.serialize() else {
+            panic!("expected a struct");
+        };
+        assert_eq!(
+            Profile::deserialize(DataHolder::Struct(map)),
+            Ok(Profile {
+                name: String::from("zero"),
+                nickname: Some(String::from("null"))
+            })
+        );
+        assert_eq!(None::<String>.serialize(), DataHolder::Null);
+        assert_eq!(Option::<String>::deserialize(DataHolder::Null), Ok(None));
+    }
 }
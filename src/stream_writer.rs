@@ -1,7 +1,7 @@
 use std::io::Write;
 
 pub mod prelude {
-    pub use super::{StreamResult, StreamWritable};
+    pub use super::{CountingWriter, StreamResult, StreamWritable};
 }
 
 pub type StreamResult = Result<(), std::io::Error>;
@@ -11,3 +11,51 @@ pub type StreamResult = Result<(), std::io::Error>;
 pub trait StreamWritable<W: Write>: Sized {
     fn write_to_stream(self, stream: &mut W) -> StreamResult;
 }
+
+/// Writer keeping count of the bytes written through it, e.g. to report response sizes.
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+
+    /// Bytes written so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_writer() {
+        let mut writer = CountingWriter::new(Vec::new());
+        writer.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+        write!(writer, "content-length:{}\r\n\r\n", 0).unwrap();
+        let written = writer.count();
+        assert_eq!(written, writer.into_inner().len() as u64);
+        assert_eq!(written, 37);
+    }
+}
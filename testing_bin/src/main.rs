@@ -76,7 +76,7 @@ pub async fn index() -> ResponseResult {
 // }
 
 // #[zero::main]
-fn main() -> Result<(), ()> {
+fn main() -> Result<(), zero::errors::DbError> {
     // let router = Router::new(())
     //     .route(Method::Get, "/", index)
     //     .route(Method::Get, "/content", content)
@@ -96,7 +96,7 @@ fn main() -> Result<(), ()> {
         println!("reading: {}", page[0]);
         let duration = Duration::from_secs(u64::rand().unwrap() % 10);
         println!("waiting: {:#?}", duration);
-        let r = <[u8; 4096]>::rand().map_err(|_| ())?;
+        let r = <[u8; 4096]>::rand().unwrap();
         println!("writing: {}", r[0]);
         buf_rw.write_page(&0, r)?;
         buf_rw.write_page(&4096, [42; 4096])?;
@@ -84,7 +84,7 @@ impl UUID {
         Ok(UUID::default().encode_time(t_ms).encode_id(table_hash))
     }
 
-    /// Decodes a table hash from a UUID. Version check must be done by user.
+    /// Decodes a table hash from a UUID. Version check must be done by user, see `is_v7`.
     pub fn as_table_hash(&self) -> u64 {
         u64::from_le_bytes(self.data_4)
     }
@@ -124,7 +124,8 @@ impl UUID {
     /// See rand module to see how random nums are generated
     pub fn rand_v7() -> Result<Self, ()> {
         let t_ms = Self::current_time()?;
-        let rand_a = u16::rand().map_err(|_| ())?;
+        // rand_a is only 12 bits, the top nibble holds the version
+        let rand_a = u16::rand().map_err(|_| ())? & 0x0fff;
         let version: u16 = 0x7 << 12;
         let data_3 = version | rand_a;
 
@@ -147,6 +148,15 @@ impl UUID {
         self
     }
 
+    /// Version field, the top 4 bits of `data_3`. See RFC 9562, section 4.2
+    pub fn version(&self) -> u8 {
+        (self.data_3 >> 12) as u8
+    }
+
+    pub fn is_v7(&self) -> bool {
+        self.version() == 7
+    }
+
     pub fn extract_timestamp(&self) -> u64 {
         ((self.data_1 as u64) << 16) | (self.data_2 as u64)
     }
@@ -184,7 +194,10 @@ impl std::fmt::Display for UUID {
             self.data_1, self.data_2, self.data_3,
         )?;
 
-        for b in self.data_4 {
+        for (i, b) in self.data_4.iter().enumerate() {
+            if i == 2 {
+                write!(f, "-")?;
+            }
             write!(f, "{:02x}", b)?;
         }
 
@@ -192,8 +205,23 @@ impl std::fmt::Display for UUID {
     }
 }
 
+impl UUID {
+    /// Hex digits in each dash separated group of the canonical form
+    const GROUPS: [usize; 5] = [8, 4, 4, 4, 12];
+    /// Length of the canonical form, hex digits plus the dashes between groups
+    const CANONICAL_LEN: usize = 32 + Self::GROUPS.len() - 1;
+    /// Groups of the form `Display` used to write, without the dash in the last 8 bytes
+    const LEGACY_GROUPS: [usize; 4] = [8, 4, 4, 16];
+    const LEGACY_LEN: usize = 32 + Self::LEGACY_GROUPS.len() - 1;
+}
+
 /// See RFC 9562, section 4
 ///
+/// Accepts the canonical form, optionally wrapped in `{}` or prefixed with
+/// `urn:uuid:` (prefix is case-insensitive). The 35 character form older versions
+/// wrote, missing the dash before the last group, is still read so stored ids keep
+/// parsing.
+///
 /// # ABNF
 /// ```text
 /// UUID     = 4hexOctet "-"
@@ -209,38 +237,52 @@ impl FromStr for UUID {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 35 {
-            return Err(());
-        }
+        const URN: &str = "urn:uuid:";
+        let s = match s.get(..URN.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(URN) => &s[URN.len()..],
+            _ => s,
+        };
+        let s = match s.strip_prefix('{') {
+            Some(inner) => inner.strip_suffix('}').ok_or(())?,
+            None => s,
+        };
 
-        fn is_dash(c: &str) -> Result<(), ()> {
-            if c == "-" { Ok(()) } else { Err(()) }
-        }
+        let groups: &[usize] = match s.len() {
+            Self::CANONICAL_LEN => &Self::GROUPS,
+            Self::LEGACY_LEN => &Self::LEGACY_GROUPS,
+            _ => return Err(()),
+        };
 
-        let (s_data_1, remainder) = s.split_at(8);
-        let (dash, remainder) = remainder.split_at(1);
-        is_dash(dash)?; //should we even care about this check?
-        let (s_data_2, remainder) = remainder.split_at(4);
-        let (dash, remainder) = remainder.split_at(1);
-        is_dash(dash)?; //should we even care about this check?
-        let (s_data_3, remainder) = remainder.split_at(4);
-        let (dash, remainder) = remainder.split_at(1);
-        is_dash(dash)?; //should we even care about this check?
-        let (s_data_4, _) = remainder.split_at(16);
-
-        let data_1 = u32::from_str_radix(s_data_1, 16).map_err(|_| ())?;
-        let data_2 = u16::from_str_radix(s_data_2, 16).map_err(|_| ())?;
-        let data_3 = u16::from_str_radix(s_data_3, 16).map_err(|_| ())?;
+        let mut hex = [0_u8; 16];
+        let mut idx = 0;
+        for (group, &len) in s.split('-').zip(groups) {
+            if group.len() != len {
+                return Err(());
+            }
+            for pair in group.as_bytes().chunks(2) {
+                let pair = std::str::from_utf8(pair).map_err(|_| ())?;
+                // from_str_radix allows a leading '+'
+                if !pair.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(());
+                }
+                hex[idx] = u8::from_str_radix(pair, 16).map_err(|_| ())?;
+                idx += 1;
+            }
+        }
+        if idx != hex.len() {
+            return Err(());
+        }
 
+        let data_1 = u32::from_be_bytes([hex[0], hex[1], hex[2], hex[3]]);
+        let data_2 = u16::from_be_bytes([hex[4], hex[5]]);
+        let data_3 = u16::from_be_bytes([hex[6], hex[7]]);
         let mut data_4 = [0_u8; 8];
-        for idx in 0..data_4.len() {
-            let s = &s_data_4[idx * 2..(idx * 2) + 2];
-            data_4[idx] = u8::from_str_radix(s, 16).map_err(|_| ())?;
-        }
+        data_4.copy_from_slice(&hex[8..]);
 
         Ok(UUID::new(data_1, data_2, data_3, data_4))
     }
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,10 +300,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_version() {
+        let uuid = UUID::rand_v7().unwrap();
+        assert_eq!(uuid.version(), 7);
+        assert!(uuid.is_v7());
+        assert!(UUID::default().encode_id(42).is_v7());
+
+        assert_eq!(UUID::default().version(), 0);
+        assert!(!UUID::default().is_v7());
+    }
+
     #[test]
     fn test_time_encoding() {
         let t_ms = 12093472938478;
         let uuid = UUID::rand_v7().unwrap().encode_time(t_ms);
         assert_eq!(t_ms, uuid.extract_timestamp());
     }
+
+    #[test]
+    fn test_uuid_from_str() {
+        let uuid = UUID::new(0x0123_4567, 0x89ab, 0x7def, [0x80, 1, 2, 3, 4, 5, 6, 0xff]);
+        let canonical = "01234567-89ab-7def-8001-0203040506ff";
+        assert_eq!(uuid.to_string(), canonical);
+
+        assert_eq!(UUID::from_str(canonical), Ok(uuid.clone()));
+        assert_eq!(
+            UUID::from_str("{01234567-89AB-7DEF-8001-0203040506FF}"),
+            Ok(uuid.clone())
+        );
+        assert_eq!(
+            UUID::from_str("URN:UUID:01234567-89ab-7def-8001-0203040506ff"),
+            Ok(uuid.clone())
+        );
+        assert_eq!(
+            UUID::from_str("urn:uuid:{01234567-89ab-7def-8001-0203040506ff}"),
+            Ok(uuid.clone())
+        );
+        // written by older versions
+        assert_eq!(
+            UUID::from_str("01234567-89ab-7def-80010203040506ff"),
+            Ok(uuid)
+        );
+
+        for bad in [
+            "",
+            "01234567-89ab-7def-80010203040506f",
+            "01234567-89ab-7def8-0010203040506ff",
+            "01234567-89ab-7def-8001-0203040506f",
+            "01234567-89ab-7def-8001-0203040506fff",
+            "0123456-789ab-7def-8001-0203040506ff",
+            "01234567-89ab-7def-8001-0203040506fg",
+            "01234567-89ab-7def-+001-0203040506ff",
+            "{01234567-89ab-7def-8001-0203040506ff",
+            "01234567-89ab-7def-8001-0203040506ff}",
+            "uuid:01234567-89ab-7def-8001-0203040506ff",
+        ] {
+            assert_eq!(UUID::from_str(bad), Err(()), "{}", bad);
+        }
+    }
 }